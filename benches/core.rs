@@ -0,0 +1,143 @@
+//! Benchmarks for the database, index and vector store on generated data.
+//!
+//! Run with `cargo bench`. Everything runs inside a temporary directory, so the
+//! `Indefx/` and `hashes/` folders `HashIndex` creates never touch real sessions.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use redru::{HashIndex, InMemoryDB, VectorDB};
+use redru::vector_db::{VectorFormat, VectorStorage};
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+const CITIES: [&str; 4] = ["Oslo", "Lima", "Pune", "Kyiv"];
+
+fn record(i: usize) -> Value {
+    json!({
+        "name": format!("user{}", i),
+        "age": i % 90,
+        "city": CITIES[i % 4],
+        "active": i.is_multiple_of(3),
+    })
+}
+
+fn populated_db(size: usize) -> InMemoryDB {
+    let mut db = InMemoryDB::new();
+    for i in 0..size {
+        db.add(&format!("key{}", i), record(i)).expect("in-memory add");
+    }
+    db
+}
+
+fn bench_insert_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("db");
+    for size in SIZES {
+        group.bench_with_input(BenchmarkId::new("insert", size), &size, |b, &size| {
+            b.iter_batched(
+                || populated_db(size),
+                |mut db| db.insert("new_key", record(size)).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+        let db = populated_db(size);
+        group.bench_with_input(BenchmarkId::new("get", size), &size, |b, &size| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % size;
+                black_box(db.get(&format!("key{}", i)));
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_save_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("persistence");
+    group.sample_size(20);
+    for size in SIZES {
+        let path = format!("bench_{}.json", size);
+        let db = populated_db(size);
+        group.bench_with_input(BenchmarkId::new("save", size), &size, |b, _| {
+            b.iter(|| db.save_to_file_with_path(&path).unwrap());
+        });
+        group.bench_with_input(BenchmarkId::new("load", size), &size, |b, _| {
+            b.iter(|| black_box(InMemoryDB::load_from_file_path(&path).unwrap()));
+        });
+    }
+    group.finish();
+}
+
+fn bench_index_lookups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash_index");
+    for size in SIZES {
+        let storage: HashMap<String, Value> = (0..size).map(|i| (format!("key{}", i), record(i))).collect();
+        let index_name = format!("bench_{}", size);
+        let mut index = HashIndex::new();
+        index.create_index(&index_name);
+        index.rebuild_index(&index_name, &storage);
+
+        group.bench_with_input(BenchmarkId::new("find_by_value", size), &size, |b, _| {
+            b.iter(|| black_box(index.find_by_value(&index_name, &record(size / 2))));
+        });
+        group.bench_with_input(BenchmarkId::new("find_range_scan", size), &size, |b, _| {
+            b.iter(|| black_box(index.find_range(&index_name, "age", 10.0, 20.0, &storage)));
+        });
+        group.bench_with_input(BenchmarkId::new("find_multi_scan", size), &size, |b, _| {
+            let query = [("city".to_string(), json!("Lima")), ("age".to_string(), json!(5))];
+            b.iter(|| black_box(index.find_multi(&index_name, &query, &storage)));
+        });
+
+        index.index_field(&index_name, "age", &storage);
+        index.index_field(&index_name, "city", &storage);
+        group.bench_with_input(BenchmarkId::new("find_range_indexed", size), &size, |b, _| {
+            b.iter(|| black_box(index.find_range(&index_name, "age", 10.0, 20.0, &storage)));
+        });
+        group.bench_with_input(BenchmarkId::new("find_multi_indexed", size), &size, |b, _| {
+            let query = [("city".to_string(), json!("Lima")), ("age".to_string(), json!(5))];
+            b.iter(|| black_box(index.find_multi(&index_name, &query, &storage)));
+        });
+    }
+    group.finish();
+}
+
+fn bench_query_similar(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vector_db");
+    group.sample_size(20);
+    let mut rng = StdRng::seed_from_u64(7);
+    for size in SIZES {
+        let path = format!("bench_vectors_{}.bin", size);
+        let _ = std::fs::remove_file(&path);
+        let mut vectors = VectorDB::new(&path, VectorFormat::Binary).unwrap();
+        for _ in 0..size {
+            vectors.add_vector((0..128).map(|_| rng.r#gen::<f64>()).collect()).unwrap();
+        }
+        let query: Vec<f64> = (0..128).map(|_| rng.r#gen::<f64>()).collect();
+        group.bench_with_input(BenchmarkId::new("query_similar_cosine", size), &size, |b, _| {
+            b.iter(|| black_box(vectors.query_similar(&query, true).len()));
+        });
+        let int8_path = format!("bench_vectors_int8_{}.bin", size);
+        let _ = std::fs::remove_file(&int8_path);
+        let mut int8 = VectorDB::with_storage(&int8_path, VectorFormat::Binary, VectorStorage::Int8).unwrap();
+        int8.set_vectors(vectors.list_vectors().into_owned()).unwrap();
+        group.bench_with_input(BenchmarkId::new("query_similar_cosine_int8", size), &size, |b, _| {
+            b.iter(|| black_box(int8.query_similar(&query, true).len()));
+        });
+    }
+    group.finish();
+}
+
+fn in_temp_dir(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    bench_insert_get(c);
+    bench_save_load(c);
+    bench_index_lookups(c);
+    bench_query_similar(c);
+}
+
+criterion_group!(benches, in_temp_dir);
+criterion_main!(benches);
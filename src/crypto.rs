@@ -0,0 +1,120 @@
+use std::fmt;
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use rand::Rng;
+use rand::rngs::OsRng;
+use crate::error::{RedruError, Result};
+
+/// First bytes of everything `DataKey::seal` produces.
+pub const SEALED_MAGIC: &[u8; 8] = b"REDRUENC";
+const SEALED_VERSION: u8 = 1;
+const HEADER_LEN: usize = SEALED_MAGIC.len() + 1;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Random key that a protected session's database, backups and write-ahead log are
+/// encrypted with. It is only ever stored wrapped by a key derived from the session
+/// password, so changing the password rewraps this key and leaves the data as it is.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DataKey([u8; KEY_LEN]);
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DataKey(..)")
+    }
+}
+
+impl DataKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill(&mut key);
+        DataKey(key)
+    }
+
+    /// Encrypts `plaintext` as `magic | version | nonce | ciphertext`, with the header
+    /// authenticated along with the payload.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill(&mut nonce);
+        let mut sealed = SEALED_MAGIC.to_vec();
+        sealed.push(SEALED_VERSION);
+        let ciphertext = self.cipher()
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &sealed })
+            .map_err(|_| RedruError::Serialization("Encryption failed".to_string()))?;
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Reverses `seal`. A wrong key and tampered or truncated bytes are all refused.
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if !is_sealed(sealed) || sealed.len() < HEADER_LEN + NONCE_LEN {
+            return Err(RedruError::Integrity("Not an encrypted redru file".to_string()));
+        }
+        let version = sealed[SEALED_MAGIC.len()];
+        if version != SEALED_VERSION {
+            return Err(RedruError::Integrity(format!(
+                "Unsupported encryption version {} (expected {})", version, SEALED_VERSION
+            )));
+        }
+        let (header, rest) = sealed.split_at(HEADER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| RedruError::Auth("Wrong key or corrupted encrypted data".to_string()))
+    }
+
+    /// This key encrypted under `password`, as hex of `salt | nonce | ciphertext`.
+    pub fn wrap(&self, password: &str) -> Result<String> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill(&mut salt);
+        OsRng.fill(&mut nonce);
+        let ciphertext = password_cipher(password, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), self.0.as_slice())
+            .map_err(|_| RedruError::Serialization("Key wrapping failed".to_string()))?;
+        Ok([&salt[..], &nonce[..], &ciphertext[..]].concat().iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Recovers a key written by `wrap`; fails with `Auth` for the wrong password.
+    pub fn unwrap(wrapped: &str, password: &str) -> Result<Self> {
+        let bytes = from_hex(wrapped)
+            .filter(|bytes| bytes.len() > SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| RedruError::Integrity("Stored session key is malformed".to_string()))?;
+        let (salt, rest) = bytes.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let key = password_cipher(password, salt)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| RedruError::Auth("Wrong password for the session key".to_string()))?;
+        let key: [u8; KEY_LEN] = key.try_into()
+            .map_err(|_| RedruError::Integrity("Stored session key has the wrong length".to_string()))?;
+        Ok(DataKey(key))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+/// Whether `bytes` start like something `DataKey::seal` wrote.
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(SEALED_MAGIC)
+}
+
+fn password_cipher(password: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| RedruError::Serialization(format!("Key derivation error: {}", e)))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
@@ -1,10 +1,51 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Write, BufWriter, BufReader, BufRead};
 use std::path::{Path, PathBuf};
+use rand::seq::{IteratorRandom, SliceRandom};
+use serde::Serialize;
 use serde_json::{Value, json};
 use std::time::SystemTime;
-use crate::hash_index::{HashIndex, hash_value, hash_field_value, calculate_data_hash};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::crypto::{self, DataKey};
+use crate::disk;
+use crate::error::{RedruError, Result};
+use crate::wal::{Wal, WalRecord};
+use crate::hash_index::{extract_field_value, extract_field_value_mut, read_json_file, values_match, HashIndex, MatchMode, hash_value, hash_field_value, calculate_data_hash};
+
+const MSGPACK_MAGIC: &[u8; 8] = b"REDRUMPK";
+
+/// On-disk encoding of the persistence file. `storage` is always a JSON `Value` map in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageFormat {
+    /// Pretty-printed JSON, no header.
+    Json,
+    /// `REDRUMPK` magic followed by the MessagePack-encoded map.
+    MessagePack,
+}
+
+impl StorageFormat {
+    /// `.msgpack`/`.mpk` files use MessagePack; everything else defaults to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("msgpack") | Some("mpk") => StorageFormat::MessagePack,
+            _ => StorageFormat::Json,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(StorageFormat::Json),
+            "msgpack" | "messagepack" | "mpk" => Some(StorageFormat::MessagePack),
+            _ => None,
+        }
+    }
+}
 
 pub struct InMemoryDB {
     storage: HashMap<String, Value>,
@@ -12,6 +53,68 @@ pub struct InMemoryDB {
     auto_save: bool,
     backup_enabled: bool,
     hash_index: HashIndex,
+    /// Set by every change and cleared once the persistence file matches memory again.
+    dirty: AtomicBool,
+    format: StorageFormat,
+    /// Indent JSON saves; compact output is roughly half the size.
+    pretty: bool,
+    limits: RecordLimits,
+    /// Backups older than this are deleted after each new backup.
+    backup_max_age: Option<Duration>,
+    /// Only this many of the newest backups are kept after each new backup.
+    backup_max_count: Option<usize>,
+    /// Changes since the last save, replayed by `enable_wal` after a crash.
+    wal: Option<Wal>,
+    /// Set for password-protected sessions: saves, and the write-ahead log, are encrypted.
+    key: Option<DataKey>,
+    /// The file this database last loaded or saved, as it looked then. A save to that file
+    /// is refused once it looks different, since another process must have written it.
+    seen_file: Mutex<Option<(PathBuf, Option<FileStamp>)>>,
+    /// Refuses every change and save; see `set_read_only`.
+    read_only: bool,
+    /// Makes `delete` and `delete_key` move records to `trash`; see `set_soft_delete`.
+    soft_delete: bool,
+    /// Deleted records, by key, until restored or purged. Saved beside the file.
+    trash: HashMap<String, Value>,
+    /// Problems met while loading that did not stop it; see `take_warnings`.
+    warnings: Vec<String>,
+}
+
+/// Upper bounds `insert` and `update` enforce on each record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLimits {
+    /// Longest key accepted, in bytes.
+    pub max_key_len: usize,
+    /// Largest value accepted, measured as compact JSON bytes.
+    pub max_value_size: usize,
+}
+
+impl Default for RecordLimits {
+    fn default() -> Self {
+        RecordLimits { max_key_len: 1024, max_value_size: 16 * 1024 * 1024 }
+    }
+}
+
+impl RecordLimits {
+    pub fn check(&self, key: &str, value: &Value) -> Result<()> {
+        if key.len() > self.max_key_len {
+            return Err(RedruError::Limit(format!(
+                "key is {} bytes; the limit is {}", key.len(), self.max_key_len)));
+        }
+        let mut counter = ByteCounter(0);
+        serde_json::to_writer(&mut counter, value)?;
+        if counter.0 > self.max_value_size {
+            return Err(RedruError::Limit(format!(
+                "value for '{}' is {} bytes; the limit is {}", key, counter.0, self.max_value_size)));
+        }
+        Ok(())
+    }
+}
+
+impl Default for InMemoryDB {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InMemoryDB {
@@ -22,10 +125,30 @@ impl InMemoryDB {
             auto_save: true,
             backup_enabled: false,
             hash_index: HashIndex::new(),
+            dirty: AtomicBool::new(false),
+            format: StorageFormat::Json,
+            pretty: true,
+            limits: RecordLimits::default(),
+            backup_max_age: None,
+            backup_max_count: None,
+            wal: None,
+            key: None,
+            seen_file: Mutex::new(None),
+            read_only: false,
+            soft_delete: false,
+            trash: HashMap::new(),
+            warnings: Vec::new(),
         }
     }
 
-    pub fn new_with_persistence<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+    pub fn new_with_persistence<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let format = StorageFormat::from_path(file_path.as_ref());
+        Self::new_with_format(file_path, format)
+    }
+
+    /// Like `new_with_persistence`, but saves in `format`. Existing files are read in
+    /// whatever format their header says, so this also converts a database on first save.
+    pub fn new_with_format<P: AsRef<Path>>(file_path: P, format: StorageFormat) -> Result<Self> {
         let path_buf = file_path.as_ref().to_path_buf();
 
         let mut db = InMemoryDB {
@@ -34,6 +157,19 @@ impl InMemoryDB {
             auto_save: true,
             backup_enabled: true,
             hash_index: HashIndex::new(),
+            dirty: AtomicBool::new(false),
+            format,
+            pretty: true,
+            limits: RecordLimits::default(),
+            backup_max_age: None,
+            backup_max_count: None,
+            wal: None,
+            key: None,
+            seen_file: Mutex::new(None),
+            read_only: false,
+            soft_delete: false,
+            trash: HashMap::new(),
+            warnings: Vec::new(),
         };
 
         if let Some(parent) = path_buf.parent() {
@@ -48,16 +184,55 @@ impl InMemoryDB {
             }
         }
 
-        db.hash_index.load_all_indexes()?;
+        for (name, e) in db.hash_index.load_all_indexes()? {
+            db.warnings.push(format!("Index '{}' is unreadable: {}", name, e));
+        }
+        for name in db.hash_index.outdated_indexes() {
+            db.hash_index.repair_index(&name, &db.storage)?;
+        }
 
         Ok(db)
     }
 
-    pub fn new_persistent(file_name: &str) -> io::Result<Self> {
+    pub fn new_persistent(file_name: &str) -> Result<Self> {
         let stpers_path = PathBuf::from("stpers").join(file_name);
         Self::new_with_persistence(stpers_path)
     }
 
+    /// Encrypts every later save with `key`, or stops encrypting with `None`. The change
+    /// takes effect on disk with the next save, so the database is marked dirty.
+    pub fn set_encryption_key(&mut self, key: Option<DataKey>) {
+        if self.key != key {
+            self.key = key;
+            self.mark_dirty();
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Makes every change (`insert`, `add`, `update`, `delete`, `delete_key`, `clear`, merges,
+    /// imports, restores) and every save fail with `ReadOnly` without touching memory or
+    /// disk, and turns off auto-save.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+        if read_only {
+            self.auto_save = false;
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(RedruError::ReadOnly("the database was opened read-only".to_string()));
+        }
+        Ok(())
+    }
+
     pub fn set_auto_save(&mut self, enabled: bool) {
         self.auto_save = enabled;
     }
@@ -66,6 +241,72 @@ impl InMemoryDB {
         self.backup_enabled = enabled;
     }
 
+    /// Deletes backups older than `max_age` after every new backup; `None` keeps them all.
+    pub fn set_backup_retention(&mut self, max_age: Option<Duration>) {
+        self.backup_max_age = max_age;
+    }
+
+    pub fn backup_retention(&self) -> Option<Duration> {
+        self.backup_max_age
+    }
+
+    /// Keeps only the newest `max_count` backups after every new backup; `None` keeps them all.
+    /// Combined with `set_backup_retention`, a backup is deleted if either rule removes it.
+    pub fn set_max_backups(&mut self, max_count: Option<usize>) {
+        self.backup_max_count = max_count;
+    }
+
+    pub fn max_backups(&self) -> Option<usize> {
+        self.backup_max_count
+    }
+
+    /// Deletes backups of the persistence file whose timestamp (from the file name) is more
+    /// than `age` ago, along with their hash files. Returns how many were deleted.
+    pub fn prune_backups_older_than(&self, age: Duration) -> Result<usize> {
+        match self.persistence_file {
+            Some(ref path) => prune_backups(path, Some(age), None),
+            None => Ok(0),
+        }
+    }
+
+    pub fn format(&self) -> StorageFormat {
+        self.format
+    }
+
+    /// Changes the encoding used by the next save.
+    pub fn set_format(&mut self, format: StorageFormat) {
+        if self.format != format {
+            self.format = format;
+            self.mark_dirty();
+        }
+    }
+
+    pub fn is_pretty(&self) -> bool {
+        self.pretty
+    }
+
+    /// Chooses indented (the default) or compact JSON for the next save. Both load the same;
+    /// MessagePack saves ignore this.
+    pub fn set_pretty(&mut self, pretty: bool) {
+        if self.pretty != pretty {
+            self.pretty = pretty;
+            self.mark_dirty();
+        }
+    }
+
+    pub fn limits(&self) -> RecordLimits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: RecordLimits) {
+        self.limits = limits;
+    }
+
+    /// Rejects a key or value larger than the configured `RecordLimits`.
+    pub fn check_limits(&self, key: &str, value: &Value) -> Result<()> {
+        self.limits.check(key, value)
+    }
+
     pub fn create_index(&mut self, index_name: &str) {
         self.hash_index.create_index(index_name);
         for (key, value) in &self.storage {
@@ -77,6 +318,11 @@ impl InMemoryDB {
         self.hash_index.drop_index(index_name);
     }
 
+    /// See `HashIndex::index_field`. Kept current by `insert`, `update` and `delete`.
+    pub fn index_field(&mut self, index_name: &str, field: &str) -> bool {
+        self.hash_index.index_field(index_name, field, &self.storage)
+    }
+
     pub fn rebuild_index(&mut self, index_name: &str) {
         self.hash_index.rebuild_index(index_name, &self.storage);
     }
@@ -112,23 +358,25 @@ impl InMemoryDB {
         self.hash_index.list_indexes()
     }
 
+    /// Whether the records in memory still hash to what the last save of the persistence
+    /// file recorded (see `data_hash_path`). Always true without a persistence file.
     pub fn verify_data_integrity(&self) -> bool {
-        if let Some(ref path) = self.persistence_file {
-            if let Some(filename) = path.file_stem() {
-                if let Some(filename_str) = filename.to_str() {
-                    return self.hash_index.verify_data_integrity(filename_str, &self.storage);
-                }
-            }
+        match self.persistence_file {
+            Some(ref path) => matches!(stored_data_hash(path), Ok(Some(hash)) if hash == calculate_data_hash(&self.storage)),
+            None => true,
         }
-        true
     }
 
-    pub fn insert(&mut self, key: &str, value: Value) -> io::Result<()> {
+    pub fn insert(&mut self, key: &str, value: Value) -> Result<()> {
+        self.check_writable()?;
+        self.check_limits(key, &value)?;
+        self.log_change(|| WalRecord::Insert { key: key.to_string(), value: value.clone() })?;
         for index_name in self.hash_index.list_indexes() {
             self.hash_index.add_to_index(&index_name, key, &value);
         }
         
         self.storage.insert(key.to_string(), value);
+        self.mark_dirty();
 
         if self.auto_save && self.persistence_file.is_some() {
             self.save_to_file()?;
@@ -141,7 +389,13 @@ impl InMemoryDB {
         self.storage.get(key)
     }
 
-    pub fn delete(&mut self, key: &str) -> io::Result<()> {
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        if self.soft_delete {
+            self.soft_delete(key)?;
+            return Ok(());
+        }
+        self.check_writable()?;
+        self.log_change(|| WalRecord::Delete { key: key.to_string() })?;
         if let Some(value) = self.storage.get(key) {
             for index_name in self.hash_index.list_indexes() {
                 self.hash_index.remove_from_index(&index_name, key, value);
@@ -149,6 +403,7 @@ impl InMemoryDB {
         }
         
         self.storage.remove(key);
+        self.mark_dirty();
 
         if self.auto_save && self.persistence_file.is_some() {
             self.save_to_file()?;
@@ -157,8 +412,11 @@ impl InMemoryDB {
         Ok(())
     }
 
-    pub fn update(&mut self, key: &str, value: Value) -> io::Result<bool> {
+    pub fn update(&mut self, key: &str, value: Value) -> Result<bool> {
+        self.check_writable()?;
         if self.storage.contains_key(key) {
+            self.check_limits(key, &value)?;
+            self.log_change(|| WalRecord::Insert { key: key.to_string(), value: value.clone() })?;
             if let Some(old_value) = self.storage.get(key) {
                 for index_name in self.hash_index.list_indexes() {
                     self.hash_index.remove_from_index(&index_name, key, old_value);
@@ -167,6 +425,7 @@ impl InMemoryDB {
             }
             
             self.storage.insert(key.to_string(), value);
+            self.mark_dirty();
 
             if self.auto_save && self.persistence_file.is_some() {
                 self.save_to_file()?;
@@ -178,6 +437,128 @@ impl InMemoryDB {
         }
     }
 
+    /// Makes `delete` and `delete_key` move records to the trash instead of dropping them,
+    /// so `restore_deleted` can bring them back. Off by default.
+    pub fn set_soft_delete(&mut self, enabled: bool) {
+        self.soft_delete = enabled;
+    }
+
+    pub fn is_soft_delete(&self) -> bool {
+        self.soft_delete
+    }
+
+    /// Moves `key` into the trash, replacing an older deleted record with the same key.
+    /// Returns whether the key existed. The move is in the write-ahead log, so a crash
+    /// before the next save still leaves the record restorable.
+    pub fn soft_delete(&mut self, key: &str) -> Result<bool> {
+        self.check_writable()?;
+        let Some(value) = self.storage.get(key) else {
+            return Ok(false);
+        };
+        self.log_change(|| WalRecord::Trash { key: key.to_string(), value: value.clone() })?;
+        if let Some(value) = self.storage.remove(key) {
+            for index_name in self.hash_index.list_indexes() {
+                self.hash_index.remove_from_index(&index_name, key, &value);
+            }
+            self.trash.insert(key.to_string(), value);
+        }
+        self.mark_dirty();
+
+        if self.auto_save && self.persistence_file.is_some() {
+            self.save_to_file()?;
+        }
+        Ok(true)
+    }
+
+    /// Moves `key` back out of the trash. Returns `false` if it is not in the trash, and
+    /// fails with `Conflict` if a record with that key has been added since.
+    pub fn restore_deleted(&mut self, key: &str) -> Result<bool> {
+        self.check_writable()?;
+        let Some(value) = self.trash.get(key).cloned() else {
+            return Ok(false);
+        };
+        if self.storage.contains_key(key) {
+            return Err(RedruError::Conflict(format!(
+                "'{}' exists again; delete or rename it before restoring the deleted record", key)));
+        }
+        self.check_limits(key, &value)?;
+        self.log_change(|| WalRecord::Restore { key: key.to_string(), value: value.clone() })?;
+        self.trash.remove(key);
+        for index_name in self.hash_index.list_indexes() {
+            self.hash_index.add_to_index(&index_name, key, &value);
+        }
+        self.storage.insert(key.to_string(), value);
+        self.mark_dirty();
+
+        if self.auto_save && self.persistence_file.is_some() {
+            self.save_to_file()?;
+        }
+        Ok(true)
+    }
+
+    /// Permanently drops everything in the trash; returns how many records that was.
+    pub fn empty_trash(&mut self) -> Result<usize> {
+        self.check_writable()?;
+        let purged = self.trash.len();
+        if purged == 0 {
+            return Ok(0);
+        }
+        self.log_change(|| WalRecord::EmptyTrash)?;
+        self.trash.clear();
+        self.mark_dirty();
+
+        if self.auto_save && self.persistence_file.is_some() {
+            self.save_to_file()?;
+        }
+        Ok(purged)
+    }
+
+    /// Keys in the trash, sorted.
+    pub fn trashed_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.trash.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn get_trashed(&self, key: &str) -> Option<&Value> {
+        self.trash.get(key)
+    }
+
+    /// Writes the trash to `trash_path(path)`, or removes that file once the trash is empty.
+    fn save_trash(&self, path: &Path) -> Result<()> {
+        let trash_file = trash_path(path);
+        if self.trash.is_empty() {
+            return match fs::remove_file(&trash_file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        write_storage_atomically(&trash_file, &self.trash, StorageFormat::Json, false, self.key.as_ref())
+    }
+
+    /// Reads the trash saved beside `path`. One that does not read is copied aside with
+    /// `save_corrupt_copy` rather than failing the load, since the records come first.
+    fn load_trash(&mut self, path: &Path) {
+        let trash_file = trash_path(path);
+        match read_storage(&trash_file, self.key.as_ref()) {
+            Ok(data) => self.trash = data.map(|(data, _)| data).unwrap_or_default(),
+            Err(RedruError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                let mut warning = format!("Could not read the trash at {}: {}", trash_file.display(), e);
+                if let Ok(copy) = save_corrupt_copy(&trash_file) {
+                    warning.push_str(&format!(". A copy was kept at {}.", copy.display()));
+                }
+                self.warnings.push(warning);
+            }
+        }
+    }
+
+    /// Problems met while loading that did not stop it, such as an unreadable trash, for
+    /// the caller to show. Each is handed over once.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
     pub fn exists(&self, key: &str) -> bool {
         self.storage.contains_key(key)
     }
@@ -194,8 +575,11 @@ impl InMemoryDB {
         self.storage.is_empty()
     }
 
-    pub fn clear(&mut self) -> io::Result<()> {
+    pub fn clear(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.log_change(|| WalRecord::Clear)?;
         self.storage.clear();
+        self.mark_dirty();
         
         for index_name in self.hash_index.list_indexes() {
             self.hash_index.clear_index(&index_name);
@@ -208,42 +592,30 @@ impl InMemoryDB {
         Ok(())
     }
 
-    fn create_backup(&self, path: &Path) -> io::Result<()> {
+    fn create_backup(&self, path: &Path) -> Result<()> {
         if !self.backup_enabled || !path.exists() {
             return Ok(());
         }
 
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-
-        let backup_path = path.with_extension(format!("backup.{}", timestamp));
-
-        fs::copy(path, &backup_path)?;
+        let backup_path = new_backup_path(path, None);
+        copy_backup(path, &backup_path)?;
+        copy_data_hash(path, &backup_path);
 
-        if let Some(filename) = path.file_stem() {
-            if let Some(filename_str) = filename.to_str() {
-                let hash_file = PathBuf::from("hashes").join(format!("{}.hash", filename_str));
-                if hash_file.exists() {
-                    let backup_hash_path = PathBuf::from("hashes")
-                        .join(format!("{}.backup.{}.hash", filename_str, timestamp));
-                    let _ = fs::copy(&hash_file, &backup_hash_path);
-                }
-            }
+        if self.backup_max_age.is_some() || self.backup_max_count.is_some() {
+            prune_backups(path, self.backup_max_age, self.backup_max_count)?;
         }
-
         Ok(())
     }
 
-    pub fn save_to_file(&self) -> io::Result<()> {
+    pub fn save_to_file(&self) -> Result<()> {
+        self.check_writable()?;
         if let Some(ref path) = self.persistence_file {
-            self.create_backup(path)?;
-
-            let json_data = serde_json::to_string_pretty(&self.storage)
-                .map_err(|e| {
-                    io::Error::new(io::ErrorKind::InvalidData, format!("JSON serialization error: {}", e))
-                })?;
+            self.check_not_changed_elsewhere(path)?;
+            // A save with nothing new rewrites what the file already holds, and a backup of
+            // that would only push an older version out of the kept backups.
+            if self.is_dirty() {
+                self.create_backup(path)?;
+            }
 
             if let Some(parent) = path.parent() {
                 if !parent.exists() {
@@ -251,50 +623,113 @@ impl InMemoryDB {
                 }
             }
 
-            let temp_path = path.with_extension("tmp");
-            
-            {
-                let file = File::create(&temp_path)?;
-                let mut writer = BufWriter::new(file);
-                writer.write_all(json_data.as_bytes())?;
-                writer.flush()?;
-            }
+            write_storage_atomically(path, &self.storage, self.format, self.pretty, self.key.as_ref())?;
+            self.remember_file(path);
+            save_data_hash(path, &self.storage);
+            self.save_trash(path)?;
+            self.dirty.store(false, Ordering::SeqCst);
+            self.reset_wal()?;
+        }
+        Ok(())
+    }
 
-            fs::rename(&temp_path, path).map_err(|e| {
-                let _ = fs::remove_file(&temp_path);
-                e
-            })?;
+    /// Whether there are changes the persistence file does not have yet.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::SeqCst)
+    }
 
-            if let Some(filename) = path.file_stem() {
-                if let Some(filename_str) = filename.to_str() {
-                    let data_hash = calculate_data_hash(&self.storage);
-                    let _ = self.hash_index.save_data_hash(filename_str, &data_hash);
-                }
+    /// Saves only if something changed since the last save; returns whether it wrote.
+    pub fn save_if_dirty(&self) -> Result<bool> {
+        if self.persistence_file.is_none() || !self.is_dirty() {
+            return Ok(false);
+        }
+        self.save_to_file()?;
+        Ok(true)
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Fails with `Conflict` when `path` is the file this database last loaded or saved
+    /// and its modification time or size has changed since.
+    fn check_not_changed_elsewhere(&self, path: &Path) -> Result<()> {
+        let seen = self.seen_file.lock().unwrap_or_else(|e| e.into_inner());
+        match &*seen {
+            Some((seen_path, stamp)) if seen_path == path && *stamp != file_stamp(path) => Err(changed_elsewhere(path)),
+            _ => Ok(()),
+        }
+    }
+
+    fn remember_file(&self, path: &Path) {
+        *self.seen_file.lock().unwrap_or_else(|e| e.into_inner()) = Some((path.to_path_buf(), file_stamp(path)));
+    }
+
+    /// Stops checking for changes by other processes until the next load or save.
+    fn forget_file(&self) {
+        *self.seen_file.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// Logs every later change to a write-ahead log at `path` until the next save, so a
+    /// crash between saves loses nothing. Changes a previous run logged but never saved
+    /// are replayed first; returns how many there were.
+    pub fn enable_wal<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        // Opening the log may truncate a torn tail.
+        self.check_writable()?;
+        let (wal, records) = Wal::open(path, self.key.clone())?;
+        let replayed = records.len();
+        for record in records {
+            record.apply(&mut self.storage, &mut self.trash);
+        }
+        if replayed > 0 {
+            self.mark_dirty();
+            for index_name in self.hash_index.list_indexes() {
+                self.rebuild_index(&index_name);
             }
         }
-        Ok(())
+        self.wal = Some(wal);
+        Ok(replayed)
+    }
+
+    /// Appends a change to the write-ahead log, if there is one, before memory changes.
+    fn log_change(&self, record: impl FnOnce() -> WalRecord) -> Result<()> {
+        match &self.wal {
+            Some(wal) => wal.append(&record()),
+            None => Ok(()),
+        }
+    }
+
+    /// Logs `data` as the whole map, before a bulk change swaps it in.
+    fn log_replace(&self, data: &HashMap<String, Value>) -> Result<()> {
+        self.log_change(|| WalRecord::Replace { data: data.clone() })
     }
 
+    /// Empties the write-ahead log after a save has made its records redundant.
+    fn reset_wal(&self) -> Result<()> {
+        match &self.wal {
+            Some(wal) => wal.reset(),
+            None => Ok(()),
+        }
+    }
 
-    fn load_from_file(&mut self) -> io::Result<()> {
+
+    fn load_from_file(&mut self) -> Result<()> {
         if let Some(ref path) = self.persistence_file {
+            let stamp = file_stamp(path);
             if !path.exists() {
                 return Ok(());
             }
 
-            let content = fs::read_to_string(path)?;
-
-            if content.trim().is_empty() {
+            let Some((data, _)) = read_storage(path, self.key.as_ref())? else {
                 self.storage = HashMap::new();
                 return Ok(());
-            }
-
-            let data: HashMap<String, Value> = serde_json::from_str(&content)
-                .map_err(|e| {
-                    io::Error::new(io::ErrorKind::InvalidData, format!("JSON parsing error: {}", e))
-                })?;
+            };
 
+            *self.seen_file.lock().unwrap_or_else(|e| e.into_inner()) = Some((path.clone(), stamp));
             self.storage = data;
+            let path = path.clone();
+            self.load_trash(&path);
+            self.dirty.store(false, Ordering::SeqCst);
             
             for index_name in self.hash_index.list_indexes() {
                 self.rebuild_index(&index_name);
@@ -303,79 +738,84 @@ impl InMemoryDB {
         Ok(())
     }
 
-    pub fn save(&self) -> io::Result<()> {
+    pub fn save(&self) -> Result<()> {
         self.save_to_file()
     }
 
-    pub fn reload(&mut self) -> io::Result<()> {
+    pub fn reload(&mut self) -> Result<()> {
         self.load_from_file()
     }
 
-    pub fn validate_file_integrity(&self) -> io::Result<bool> {
+    pub fn validate_file_integrity(&self) -> Result<bool> {
         if let Some(ref path) = self.persistence_file {
             if !path.exists() {
                 return Ok(false);
             }
             
-            let content = fs::read_to_string(path)?;
-            if content.trim().is_empty() {
-                return Ok(true);
+            match read_storage(path, self.key.as_ref()) {
+                Ok(_) => Ok(true),
+                Err(RedruError::Io(e)) => Err(e.into()),
+                Err(_) => Ok(false),
             }
-
-            serde_json::from_str::<HashMap<String, Value>>(&content)
-                .map(|_| true)
-                .or(Ok(false))
         } else {
             Ok(true)
         }
     }
 
-    pub fn repair_file(&mut self) -> io::Result<()> {
-        if let Some(ref path) = self.persistence_file {
-            let parent = path.parent().unwrap_or(Path::new("."));
-            let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
-            
-            let mut backup_files: Vec<_> = fs::read_dir(parent)?
-                .filter_map(|entry| entry.ok())
-                .filter(|entry| {
-                    entry.file_name().to_string_lossy().starts_with(&format!("{}.backup.", file_stem))
-                })
-                .collect();
-
-            backup_files.sort_by_key(|entry| {
-                entry.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH)
-            });
-            backup_files.reverse();
-
-            for backup_entry in backup_files {
-                let backup_path = backup_entry.path();
-                
-                if let Ok(content) = fs::read_to_string(&backup_path) {
-                    if let Ok(data) = serde_json::from_str::<HashMap<String, Value>>(&content) {
-                        let backup_filename = backup_path.file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or(&file_stem);
-                        
-                        let hash_dir = PathBuf::from("hashes");
-                        let backup_hash_file = hash_dir.join(format!("{}.hash", backup_filename));
-                        
-                        if backup_hash_file.exists() {
-                            if self.hash_index.verify_data_integrity(backup_filename, &data) {
-                                self.storage = data;
-                                for index_name in self.hash_index.list_indexes() {
-                                    self.rebuild_index(&index_name);
-                                }
-                                self.save_to_file()?;
-                                return Ok(());
-                            }
-                        }
+    /// Restores the newest backup that parses and matches its integrity hash, then saves.
+    /// An unreadable persistence file is copied aside first (see `save_corrupt_copy`) so
+    /// the save never destroys the only copy of data someone may still recover by hand.
+    pub fn repair_file(&mut self) -> Result<RepairReport> {
+        self.check_writable()?;
+        let mut report = RepairReport::default();
+        if let Some(path) = self.persistence_file.clone() {
+            for (backup_path, _) in backup_files(&path)? {
+                let data = match read_storage(&backup_path, self.key.as_ref()) {
+                    Ok(Some((data, _))) => data,
+                    Ok(None) => {
+                        report.rejected.push((backup_path, "empty file".to_string()));
+                        continue;
                     }
+                    Err(e) => {
+                        report.rejected.push((backup_path, e.to_string()));
+                        continue;
+                    }
+                };
+                // `create_backup` copies the file's hash beside the backup along with it.
+                let hash = match stored_data_hash(&backup_path) {
+                    Ok(Some(hash)) => hash,
+                    Ok(None) => {
+                        report.rejected.push((backup_path, "no integrity hash".to_string()));
+                        continue;
+                    }
+                    Err(e) => {
+                        report.rejected.push((backup_path, e.to_string()));
+                        continue;
+                    }
+                };
+                if hash != calculate_data_hash(&data) {
+                    report.rejected.push((backup_path, "integrity hash mismatch".to_string()));
+                } else {
+                    if file_is_unreadable(&path, self.key.as_ref()) {
+                        report.corrupt_copy = Some(save_corrupt_copy(&path)?);
+                    }
+                    // Replacing the file is the point of a repair, whoever wrote it last.
+                    self.forget_file();
+                    self.storage = data;
+                    self.mark_dirty();
+                    for index_name in self.hash_index.list_indexes() {
+                        self.rebuild_index(&index_name);
+                    }
+                    self.save_to_file()?;
+                    report.restored_from = Some(backup_path);
+                    return Ok(report);
                 }
             }
 
-            return Err(io::Error::new(io::ErrorKind::NotFound, "No valid backup found. Database was not modified."));
+            return Err(RedruError::NotFound(format!(
+                "No valid backup found{}. Database was not modified.", report.describe_rejections())));
         }
-        Ok(())
+        Ok(report)
     }
 
     pub fn persistence_file(&self) -> Option<&PathBuf> {
@@ -386,6 +826,10 @@ impl InMemoryDB {
         self.hash_index.find_partial(index_name, field, substring, &self.storage)
     }
 
+    pub fn find_partial_matching(&self, index_name: &str, field: &str, query: &str, mode: MatchMode) -> Vec<String> {
+        self.hash_index.find_partial_matching(index_name, field, query, mode, &self.storage)
+    }
+
     pub fn find_range(&self, index_name: &str, field: &str, min: f64, max: f64) -> Vec<String> {
         self.hash_index.find_range(index_name, field, min, max, &self.storage)
     }
@@ -399,7 +843,16 @@ impl InMemoryDB {
     }
 
     // Additional public methods for main.rs compatibility
-    pub fn save_to_file_with_path(&self, file_path: &str) -> io::Result<()> {
+    /// Fails with `Conflict`, writing nothing, if another process changed `file_path` since
+    /// this database loaded or saved it; `overwrite_file_with_path` saves anyway.
+    pub fn save_to_file_with_path(&self, file_path: &str) -> Result<()> {
+        self.check_not_changed_elsewhere(Path::new(file_path))?;
+        self.overwrite_file_with_path(file_path)
+    }
+
+    /// `save_to_file_with_path` without the check for changes made by other processes.
+    pub fn overwrite_file_with_path(&self, file_path: &str) -> Result<()> {
+        self.check_writable()?;
         let path = PathBuf::from(file_path);
         if let Some(parent) = path.parent() {
             if !parent.exists() {
@@ -407,156 +860,640 @@ impl InMemoryDB {
             }
         }
         
-        let json_data = serde_json::to_string_pretty(&self.storage)
-            .map_err(|e| {
-                io::Error::new(io::ErrorKind::InvalidData, format!("JSON serialization error: {}", e))
-            })?;
+        write_storage_atomically(&path, &self.storage, self.format, self.pretty, self.key.as_ref())?;
+        self.remember_file(&path);
+        self.save_trash(&path)?;
+        save_data_hash(&path, &self.storage);
+        self.reset_wal()
+    }
 
-        let temp_path = path.with_extension("tmp");
-        
-        {
-            let file = File::create(&temp_path)?;
-            let mut writer = BufWriter::new(file);
-            writer.write_all(json_data.as_bytes())?;
-            writer.flush()?;
+    /// Checks what is saved at `file_path`, not what is in memory: that the file reads
+    /// with this database's key, and that its records still hash to what the last save
+    /// recorded beside it (see `data_hash_path`).
+    pub fn verify_file_with_path(&self, file_path: &str) -> Result<FileVerification> {
+        let path = Path::new(file_path);
+        if !path.exists() {
+            return Ok(FileVerification::default());
         }
+        let data = match read_storage(path, self.key.as_ref()) {
+            Ok(data) => data.map(|(data, _)| data).unwrap_or_default(),
+            Err(RedruError::Io(e)) => return Err(e.into()),
+            Err(e) => return Ok(FileVerification { unreadable: Some(e.to_string()), hash_matches: None }),
+        };
+        let hash_matches = stored_data_hash(path)?.map(|stored| stored == calculate_data_hash(&data));
+        Ok(FileVerification { unreadable: None, hash_matches })
+    }
 
-        fs::rename(&temp_path, &path).map_err(|e| {
-            let _ = fs::remove_file(&temp_path);
-            e
-        })?;
-
-        Ok(())
+    pub fn load_from_file_path(file_path: &str) -> Result<Self> {
+        Self::load_from_file_path_with_key(file_path, None)
     }
 
-    pub fn load_from_file_path(file_path: &str) -> io::Result<Self> {
+    /// Like `load_from_file_path` for a file encrypted with `key`. The database keeps the
+    /// key, so its saves are encrypted too. An unencrypted file still loads, and is
+    /// encrypted by the next save.
+    pub fn load_from_file_path_with_key(file_path: &str, key: Option<DataKey>) -> Result<Self> {
         let path = PathBuf::from(file_path);
         let mut db = InMemoryDB::new();
+        db.key = key;
+        // Taken before reading, so a write racing the read shows up as a change later.
+        db.remember_file(&path);
         
         if !path.exists() {
             return Ok(db);
         }
 
-        let content = fs::read_to_string(&path)?;
-
-        if content.trim().is_empty() {
-            return Ok(db);
+        if let Some((data, format)) = read_storage(&path, db.key.as_ref())? {
+            db.storage = data;
+            db.format = format;
         }
-
-        let data: HashMap<String, Value> = serde_json::from_str(&content)
-            .map_err(|e| {
-                io::Error::new(io::ErrorKind::InvalidData, format!("JSON parsing error: {}", e))
-            })?;
-
-        db.storage = data;
+        db.load_trash(&path);
         Ok(db)
     }
 
-    pub fn create_backup_with_path(&self, file_path: &str) -> io::Result<()> {
+    /// Copies `file_path` to `<stem>.backup.<timestamp>`, or `<stem>.backup.<label>.<timestamp>`
+    /// when a `label` is given, and returns the backup's path (`None` if there is no file yet).
+    /// The file's hash is copied too, so `repair_file` can restore from the backup.
+    pub fn create_backup_with_path(&self, file_path: &str, label: Option<&str>) -> Result<Option<PathBuf>> {
+        if let Some(label) = label {
+            check_backup_label(label).map_err(|problem| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid backup label '{}': {}", label, problem))
+            })?;
+        }
         let path = PathBuf::from(file_path);
         if !path.exists() {
-            return Ok(());
+            return Ok(None);
         }
 
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        let backup_path = new_backup_path(&path, label);
+        copy_backup(&path, &backup_path)?;
+        copy_data_hash(&path, &backup_path);
+        if self.backup_max_age.is_some() || self.backup_max_count.is_some() {
+            prune_backups(&path, self.backup_max_age, self.backup_max_count)?;
+        }
+        Ok(Some(backup_path))
+    }
 
-        let backup_path = path.with_extension(format!("backup.{}", timestamp));
-        fs::copy(&path, &backup_path)?;
-        Ok(())
+    /// `prune_backups_older_than` for backups of `file_path`.
+    pub fn prune_backups_with_path(&self, file_path: &str, age: Duration) -> Result<usize> {
+        prune_backups(Path::new(file_path), Some(age), None)
     }
 
-    pub fn restore_from_backup_path(&mut self, file_path: &str) -> io::Result<()> {
-        let path = PathBuf::from(file_path);
-        let parent = path.parent().unwrap_or(Path::new("."));
-        let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
-        
-        let mut backup_files: Vec<_> = fs::read_dir(parent)?
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| {
-                entry.file_name().to_string_lossy().starts_with(&format!("{}.backup.", file_stem))
-            })
-            .collect();
+    /// Reclaims the space kept for `file_path`: rewrites it compactly (later saves stay
+    /// compact, see `set_pretty`), deletes all but the newest `keep_backups` unlabelled
+    /// backups and empties the write-ahead log, which the rewrite makes redundant. Labelled
+    /// backups are kept. Fails with `Conflict`, changing nothing, if another process wrote
+    /// `file_path` since this database loaded or saved it.
+    pub fn vacuum_with_path(&mut self, file_path: &str, keep_backups: usize) -> Result<VacuumReport> {
+        self.check_writable()?;
+        let path = Path::new(file_path);
+        self.check_not_changed_elsewhere(path)?;
+        let data_files = [path.to_path_buf(), trash_path(path)];
+        let mut report = VacuumReport {
+            data_bytes_before: data_files.iter().map(|file| file_len(file)).sum(),
+            wal_bytes: match &self.wal {
+                Some(wal) => wal.size()?,
+                None => 0,
+            },
+            ..VacuumReport::default()
+        };
 
-        backup_files.sort_by_key(|entry| {
-            entry.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH)
-        });
-        backup_files.reverse();
+        self.set_pretty(false);
+        self.overwrite_file_with_path(file_path)?;
+        report.data_bytes_after = data_files.iter().map(|file| file_len(file)).sum();
 
-        for backup_entry in backup_files {
-            let backup_path = backup_entry.path();
-            
-            if let Ok(content) = fs::read_to_string(&backup_path) {
-                if let Ok(data) = serde_json::from_str::<HashMap<String, Value>>(&content) {
+        let unlabelled = backup_files(path)?.into_iter().filter(|(backup_path, _)| backup_label(backup_path).is_none());
+        for (backup_path, _) in unlabelled.skip(keep_backups) {
+            report.backup_bytes += file_len(&backup_path);
+            remove_backup(&backup_path)?;
+            report.backups_removed += 1;
+        }
+        Ok(report)
+    }
+
+    /// Loads the newest backup of `file_path` that parses. Only memory changes; the file
+    /// itself is overwritten by the next save.
+    pub fn restore_from_backup_path(&mut self, file_path: &str) -> Result<RepairReport> {
+        self.check_writable()?;
+        let mut report = RepairReport::default();
+        for (backup_path, _) in backup_files(Path::new(file_path))? {
+            match read_storage(&backup_path, self.key.as_ref()) {
+                Ok(Some((data, _))) => {
+                    self.log_replace(&data)?;
                     self.storage = data;
-                    return Ok(());
+                    self.mark_dirty();
+                    report.restored_from = Some(backup_path);
+                    return Ok(report);
                 }
+                Ok(None) => report.rejected.push((backup_path, "empty file".to_string())),
+                Err(e) => report.rejected.push((backup_path, e.to_string())),
             }
         }
 
-        Err(io::Error::new(io::ErrorKind::NotFound, "No valid backup found"))
+        Err(RedruError::NotFound(format!("No valid backup found{}", report.describe_rejections())))
     }
 
-    pub fn repair_corrupted_database(&mut self, file_path: &str) -> io::Result<()> {
-        self.restore_from_backup_path(file_path)
+    /// Backups of the persistence file, newest first.
+    pub fn list_backups(&self) -> Vec<(PathBuf, SystemTime)> {
+        match self.persistence_file {
+            Some(ref path) => backup_files(path).unwrap_or_default(),
+            None => Vec::new(),
+        }
     }
 
-    pub fn get_statistics(&self) -> DatabaseStats {
-        let total_records = self.storage.len();
-        let total_size = serde_json::to_string(&self.storage)
-            .map(|s| s.len())
-            .unwrap_or(0);
-        let average_record_size = if total_records > 0 {
-            total_size as f64 / total_records as f64
-        } else {
-            0.0
-        };
-        let last_modified = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs()
-            .to_string();
+    /// Backups of `file_path`, newest first.
+    pub fn list_backups_with_path(&self, file_path: &str) -> Result<Vec<(PathBuf, SystemTime)>> {
+        backup_files(Path::new(file_path))
+    }
 
-        DatabaseStats {
-            total_records,
-            total_size,
-            average_record_size,
-            last_modified,
+    /// Replaces the current data with a specific backup file, leaving it untouched if the
+    /// backup does not parse. Persistent databases are saved and reindexed afterwards.
+    pub fn restore_backup<P: AsRef<Path>>(&mut self, backup_path: P) -> Result<()> {
+        self.check_writable()?;
+        let data = read_storage(backup_path.as_ref(), self.key.as_ref())?.map(|(data, _)| data).unwrap_or_default();
+        self.log_replace(&data)?;
+        self.storage = data;
+        self.mark_dirty();
+        if self.persistence_file.is_some() {
+            for index_name in self.hash_index.list_indexes() {
+                self.rebuild_index(&index_name);
+            }
+            self.save_to_file()?;
         }
+        Ok(())
     }
 
-    pub fn enable_auto_save(&mut self) {
-        self.auto_save = true;
+    /// Merges the keys of another database file (JSON or MessagePack) into this one.
+    ///
+    /// With `ConflictPolicy::Error` nothing is merged if any key already exists. Indexes are
+    /// rebuilt and, when the database has a persistence file, it is saved once at the end.
+    pub fn merge_from_file<P: AsRef<Path>>(&mut self, path: P, conflict: ConflictPolicy) -> Result<MergeReport> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let incoming = read_storage(path, self.key.as_ref())?
+            .map(|(data, _)| data)
+            .ok_or_else(|| RedruError::NotFound(format!("'{}' contains no database", path.display())))?;
+
+        if conflict == ConflictPolicy::Error {
+            let mut clashes: Vec<&String> = incoming.keys().filter(|key| self.storage.contains_key(*key)).collect();
+            if !clashes.is_empty() {
+                clashes.sort();
+                let shown: Vec<&str> = clashes.iter().take(5).map(|key| key.as_str()).collect();
+                let more = if clashes.len() > shown.len() { ", ..." } else { "" };
+                return Err(RedruError::Conflict(format!(
+                    "{} key(s) already exist: {}{}", clashes.len(), shown.join(", "), more
+                )));
+            }
+        }
+
+        let mut report = MergeReport::default();
+        let mut merged = Vec::new();
+        for (key, value) in incoming {
+            match self.storage.get(&key) {
+                None => report.added += 1,
+                Some(_) if conflict == ConflictPolicy::Overwrite => report.overwritten += 1,
+                Some(_) => {
+                    report.skipped += 1;
+                    continue;
+                }
+            }
+            merged.push((key, value));
+        }
+
+        if !merged.is_empty() {
+            self.log_change(|| WalRecord::InsertMany { records: merged.clone() })?;
+            self.storage.extend(merged);
+            self.mark_dirty();
+            for index_name in self.hash_index.list_indexes() {
+                self.rebuild_index(&index_name);
+            }
+            if self.persistence_file.is_some() {
+                self.save_to_file()?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Writes every record to `path` as one JSON object, regardless of the storage format.
+    /// Returns the number of records written.
+    pub fn export_all<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        write_storage_atomically(path.as_ref(), &self.storage, StorageFormat::Json, self.pretty, None)?;
+        Ok(self.storage.len())
+    }
+
+    /// Loads a JSON object written by `export_all`. With `replace` it becomes the whole
+    /// database; otherwise its records are added, overwriting keys that already exist.
+    /// Nothing changes if any record breaks the `RecordLimits`. Returns the number of
+    /// records imported.
+    pub fn import_all<P: AsRef<Path>>(&mut self, path: P, replace: bool) -> Result<usize> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let incoming: HashMap<String, Value> = read_json_file(path)?
+            .ok_or_else(|| RedruError::NotFound(format!("'{}' contains no records", path.display())))?;
+        for (key, value) in &incoming {
+            self.check_limits(key, value)?;
+        }
+
+        let count = incoming.len();
+        if replace {
+            self.log_replace(&incoming)?;
+            self.storage = incoming;
+        } else {
+            self.log_change(|| WalRecord::InsertMany {
+                records: incoming.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+            })?;
+            self.storage.extend(incoming);
+        }
+        self.mark_dirty();
+        for index_name in self.hash_index.list_indexes() {
+            self.rebuild_index(&index_name);
+        }
+        if self.persistence_file.is_some() {
+            self.save_to_file()?;
+        }
+        Ok(count)
+    }
+
+    /// Adds the records in a JSON Lines file, one JSON object per line, reading a line at a
+    /// time. With `key_field` (which may be a dotted path) each record is stored under that
+    /// field's value, which must be a string or a number; without it records are numbered
+    /// from one past the largest numeric key already present. Existing keys are overwritten.
+    /// Lines that do not parse, are not objects, lack the key field or break the
+    /// `RecordLimits` are skipped and listed in the report; blank lines are ignored.
+    ///
+    /// Records are logged and added `JSONL_IMPORT_BATCH` at a time, so the write-ahead log
+    /// never holds more than one batch beyond what is in memory. A failure part way keeps
+    /// the batches already added.
+    pub fn import_jsonl<P: AsRef<Path>>(&mut self, path: P, key_field: Option<&str>) -> Result<JsonlImportReport> {
+        self.check_writable()?;
+        let reader = BufReader::new(File::open(path.as_ref())?);
+        let mut next_key = self.storage.keys().filter_map(|key| key.parse::<u64>().ok()).max().map_or(1, |max| max + 1);
+        let mut report = JsonlImportReport::default();
+        let mut batch = Vec::new();
+        for (i, line) in reader.split(b'\n').enumerate() {
+            let line = line?;
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let value = match serde_json::from_slice::<Value>(&line) {
+                Ok(value @ Value::Object(_)) => value,
+                Ok(other) => {
+                    report.skipped.push((i + 1, format!("expected a JSON object, found {}", json_type_name(&other))));
+                    continue;
+                }
+                Err(e) => {
+                    report.skipped.push((i + 1, format!("invalid JSON: {}", e)));
+                    continue;
+                }
+            };
+            let key = match key_field {
+                None => next_key.to_string(),
+                Some(field) => match extract_field_value(&value, field) {
+                    Some(Value::String(key)) => key.clone(),
+                    Some(Value::Number(key)) => key.to_string(),
+                    Some(other) => {
+                        report.skipped.push((i + 1, format!("'{}' must be a string or number, not {}", field, json_type_name(other))));
+                        continue;
+                    }
+                    None => {
+                        report.skipped.push((i + 1, format!("no '{}' field", field)));
+                        continue;
+                    }
+                },
+            };
+            if let Err(e) = self.check_limits(&key, &value) {
+                report.skipped.push((i + 1, e.to_string()));
+                continue;
+            }
+            if key_field.is_none() {
+                next_key += 1;
+            }
+            batch.push((key, value));
+            if batch.len() == JSONL_IMPORT_BATCH {
+                report.imported += self.add_logged(std::mem::take(&mut batch))?;
+            }
+        }
+        report.imported += self.add_logged(batch)?;
+
+        if report.imported > 0 {
+            for index_name in self.hash_index.list_indexes() {
+                self.rebuild_index(&index_name);
+            }
+            if self.persistence_file.is_some() {
+                self.save_to_file()?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Writes every record to `path` as JSON Lines, one record per line in key order. With
+    /// `key_field` each record's key is written into that field, so `import_jsonl` with the
+    /// same field restores the keys; every record must then be an object. A dotted field
+    /// is written as nested objects, the way `import_jsonl` reads it, and fails for a record
+    /// where part of the path is not an object. Without `key_field` only the values are
+    /// written. Returns the number of records written.
+    pub fn export_jsonl<P: AsRef<Path>>(&self, path: P, key_field: Option<&str>) -> Result<usize> {
+        let mut keys: Vec<&String> = self.storage.keys().collect();
+        keys.sort();
+        if let Some(field) = key_field
+            && let Some(key) = keys.iter().find(|key| !self.storage[**key].is_object()) {
+            return Err(RedruError::Serialization(format!(
+                "record '{}' is not an object, so it cannot hold its key in '{}'", key, field)));
+        }
+
+        let path = path.as_ref();
+        let temp_path = path.with_extension("tmp");
+        let written = File::create(&temp_path)
+            .map_err(RedruError::from)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                for key in &keys {
+                    let value = &self.storage[*key];
+                    match key_field {
+                        Some(field) => {
+                            let mut record = value.clone();
+                            if !set_field_path(&mut record, field, Value::String((*key).clone())) {
+                                return Err(RedruError::Serialization(format!(
+                                    "record '{}' has no object at '{}' to hold its key", key, field)));
+                            }
+                            serde_json::to_writer(&mut writer, &record)?;
+                        }
+                        None => serde_json::to_writer(&mut writer, value)?,
+                    }
+                    writer.write_all(b"\n")?;
+                }
+                writer.flush()?;
+                Ok(())
+            });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        fs::rename(&temp_path, path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })?;
+        Ok(keys.len())
+    }
+
+    /// Sets `field` (which may be a dotted path) to `to` in every record where it matches
+    /// `from`, compared as `find` compares (`30` matches `30.0` and `"30"`). Nothing changes
+    /// if any updated record would break the `RecordLimits`. Indexes are rebuilt and, when
+    /// the database has a persistence file, it is saved once. Returns the records changed.
+    pub fn replace_field_value(&mut self, field: &str, from: &Value, to: Value) -> Result<usize> {
+        self.check_writable()?;
+        let mut updated = Vec::new();
+        for (key, value) in &self.storage {
+            if !extract_field_value(value, field).is_some_and(|v| values_match(v, from)) {
+                continue;
+            }
+            let mut value = value.clone();
+            if let Some(slot) = extract_field_value_mut(&mut value, field) {
+                *slot = to.clone();
+            }
+            self.check_limits(key, &value)?;
+            updated.push((key.clone(), value));
+        }
+        if updated.is_empty() {
+            return Ok(0);
+        }
+
+        let count = updated.len();
+        for (key, value) in updated {
+            self.log_change(|| WalRecord::Insert { key: key.clone(), value: value.clone() })?;
+            self.storage.insert(key, value);
+        }
+        self.mark_dirty();
+        for index_name in self.hash_index.list_indexes() {
+            self.rebuild_index(&index_name);
+        }
+        if self.persistence_file.is_some() {
+            self.save_to_file()?;
+        }
+        Ok(count)
+    }
+
+    pub fn repair_corrupted_database(&mut self, file_path: &str) -> Result<RepairReport> {
+        self.restore_from_backup_path(file_path)
+    }
+
+    pub fn get_statistics(&self) -> DatabaseStats {
+        let total_records = self.storage.len();
+        let total_size = serde_json::to_string(&self.storage)
+            .map(|s| s.len())
+            .unwrap_or(0);
+        let mut counter = ByteCounter(0);
+        let pretty_size = serde_json::to_writer_pretty(&mut counter, &self.storage)
+            .map(|_| counter.0)
+            .unwrap_or(0);
+        let average_record_size = if total_records > 0 {
+            total_size as f64 / total_records as f64
+        } else {
+            0.0
+        };
+        let last_modified = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        let mut largest_record: Option<LargestRecord> = None;
+        let mut value_types = BTreeMap::new();
+        for (key, value) in &self.storage {
+            *value_types.entry(json_type_name(value).to_string()).or_insert(0) += 1;
+            let size = serde_json::to_string(value).map(|s| s.len()).unwrap_or(0);
+            // Ties go to the smaller key, so the answer does not depend on map order.
+            if largest_record.as_ref().is_none_or(|l| size > l.size || (size == l.size && *key < l.key)) {
+                largest_record = Some(LargestRecord { key: key.clone(), size });
+            }
+        }
+
+        DatabaseStats {
+            schema_version: STATS_SCHEMA_VERSION,
+            total_records,
+            total_size,
+            pretty_size,
+            average_record_size,
+            last_modified,
+            pretty_saves: self.pretty,
+            largest_record,
+            value_types,
+        }
+    }
+
+    pub fn enable_auto_save(&mut self) {
+        self.auto_save = true;
     }
 
     pub fn disable_auto_save(&mut self) {
         self.auto_save = false;
     }
 
-    pub fn add(&mut self, key: &str, value: Value) {
+    /// Inserts or replaces `key` without checking the `RecordLimits`, indexing or saving.
+    /// Fails, changing nothing, when read-only or when the write-ahead log cannot be written.
+    pub fn add(&mut self, key: &str, value: Value) -> Result<()> {
+        self.check_writable()?;
+        self.log_change(|| WalRecord::Insert { key: key.to_string(), value: value.clone() })?;
         self.storage.insert(key.to_string(), value);
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Adds many records at once: one write-ahead log record covers the whole batch, and a
+    /// database with a persistence file is saved once at the end. Records that break the
+    /// `RecordLimits` are skipped and reported by their position in `records`.
+    pub fn add_batch(&mut self, records: Vec<(String, Value)>) -> Result<BatchAddReport> {
+        self.check_writable()?;
+        let mut report = BatchAddReport::default();
+        let mut accepted = Vec::with_capacity(records.len());
+        for (row, (key, value)) in records.into_iter().enumerate() {
+            match self.check_limits(&key, &value) {
+                Ok(()) => accepted.push((key, value)),
+                Err(e) => report.rejected.push((row, e.to_string())),
+            }
+        }
+        if accepted.is_empty() {
+            return Ok(report);
+        }
+
+        self.log_change(|| WalRecord::InsertMany { records: accepted.clone() })?;
+        report.added = accepted.len();
+        for (key, value) in accepted {
+            if let Some(old) = self.storage.get(&key) {
+                for index_name in self.hash_index.list_indexes() {
+                    self.hash_index.remove_from_index(&index_name, &key, old);
+                }
+            }
+            for index_name in self.hash_index.list_indexes() {
+                self.hash_index.add_to_index(&index_name, &key, &value);
+            }
+            self.storage.insert(key, value);
+        }
+        self.mark_dirty();
+        if self.persistence_file.is_some() {
+            self.save_to_file()?;
+        }
+        Ok(report)
     }
 
-    pub fn delete_key(&mut self, key: &str) -> bool {
-        self.storage.remove(key).is_some()
+    /// Logs `records` as one write-ahead log record, then adds them and marks the database
+    /// dirty. Indexes are left for the caller to rebuild. Returns how many were added.
+    fn add_logged(&mut self, records: Vec<(String, Value)>) -> Result<usize> {
+        if records.is_empty() {
+            return Ok(0);
+        }
+        self.log_change(|| WalRecord::InsertMany { records: records.clone() })?;
+        let added = records.len();
+        self.storage.extend(records);
+        self.mark_dirty();
+        Ok(added)
+    }
+
+    /// Removes `key` (into the trash with `set_soft_delete`) without indexing or saving.
+    /// Returns whether it existed. Fails, changing nothing, like `add`.
+    pub fn delete_key(&mut self, key: &str) -> Result<bool> {
+        self.check_writable()?;
+        let Some(value) = self.storage.get(key) else {
+            return Ok(false);
+        };
+        if self.soft_delete {
+            self.log_change(|| WalRecord::Trash { key: key.to_string(), value: value.clone() })?;
+        } else {
+            self.log_change(|| WalRecord::Delete { key: key.to_string() })?;
+        }
+        if let Some(value) = self.storage.remove(key)
+            && self.soft_delete
+        {
+            self.trash.insert(key.to_string(), value);
+        }
+        self.mark_dirty();
+        Ok(true)
     }
 
     pub fn list_keys(&self) -> Vec<String> {
         self.storage.keys().cloned().collect()
     }
 
-    pub fn search_by_field(&self, field: &str, value: &str) -> Vec<String> {
-        let mut results = Vec::new();
-        for (key, val) in &self.storage {
-            if let Some(field_value) = val.get(field) {
-                if field_value.to_string() == value {
-                    results.push(key.clone());
-                }
+    /// Up to `n` distinct records, each equally likely to be picked, in random order.
+    /// Asking for more records than there are returns all of them.
+    pub fn sample(&self, n: usize) -> Vec<(String, &Value)> {
+        let mut rng = rand::thread_rng();
+        let mut picked = self.storage.iter().choose_multiple(&mut rng, n);
+        picked.shuffle(&mut rng);
+        picked.into_iter().map(|(key, value)| (key.clone(), value)).collect()
+    }
+
+    /// A hash of every record's value by key, small enough to keep for the length of a
+    /// session and compare against later with `changes_since`.
+    pub fn fingerprint(&self) -> HashMap<String, u64> {
+        self.storage.iter().map(|(key, value)| (key.clone(), hash_value(value))).collect()
+    }
+
+    /// Records added, changed and removed since `earlier` was taken by `fingerprint`.
+    /// A record added and then deleted again counts as neither.
+    pub fn changes_since(&self, earlier: &HashMap<String, u64>) -> ChangeCounts {
+        let mut changes = ChangeCounts::default();
+        for (key, value) in &self.storage {
+            match earlier.get(key) {
+                None => changes.added += 1,
+                Some(&hash) if hash != hash_value(value) => changes.updated += 1,
+                Some(_) => {}
             }
         }
-        results
+        changes.deleted = earlier.keys().filter(|key| !self.storage.contains_key(*key)).count();
+        changes
+    }
+
+    /// Every key with its score: the sum of each weighted field's value times its weight.
+    /// Fields may be dotted paths; a field that is missing or not a number counts as 0.
+    /// Sorted by score, highest first when `descending`, with ties in key order.
+    pub fn rank_by(&self, weights: &[(String, f64)], descending: bool) -> Vec<(String, f64)> {
+        let mut scored: Vec<(String, f64)> = self.storage.iter()
+            .map(|(key, value)| {
+                let score = weights.iter()
+                    .map(|(field, weight)| extract_field_value(value, field).and_then(Value::as_f64).unwrap_or(0.0) * weight)
+                    .sum();
+                (key.clone(), score)
+            })
+            .collect();
+        scored.sort_by(|(a_key, a), (b_key, b)| {
+            let by_score = if descending { b.total_cmp(a) } else { a.total_cmp(b) };
+            by_score.then_with(|| a_key.cmp(b_key))
+        });
+        scored
+    }
+
+    /// Exact match on a field, which may be a dotted path (`address.city`, `items.0.id`).
+    /// Strings match either their raw text or their JSON form, so `Alice` and `"Alice"`
+    /// both find `{"name": "Alice"}`; other values match when `value` parses to the same
+    /// JSON (`30`, `true`, `{"a": 1}`).
+    pub fn search_by_field(&self, field: &str, value: &str) -> Vec<String> {
+        self.search_by_field_matching(field, value, MatchMode::Exact)
+    }
+
+    /// Like `search_by_field`, but compares string fields using `mode`. With `Contains`
+    /// and `Prefix`, non-string values are compared by their JSON text, so prefix `12`
+    /// finds `123`.
+    pub fn search_by_field_matching(&self, field: &str, value: &str, mode: MatchMode) -> Vec<String> {
+        let parsed = serde_json::from_str::<Value>(value).ok();
+        self.storage.iter()
+            .filter(|(_, val)| field_matches(val, field, value, parsed.as_ref(), mode))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Keys of records where `field` (which may be a dotted path) exists, including records
+    /// where it is `null`.
+    pub fn keys_with_field(&self, field: &str) -> Vec<String> {
+        self.storage.iter()
+            .filter(|(_, value)| extract_field_value(value, field).is_some())
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Keys of records where `field` does not exist at all; a `null` field is not missing.
+    pub fn keys_missing_field(&self, field: &str) -> Vec<String> {
+        self.storage.iter()
+            .filter(|(_, value)| extract_field_value(value, field).is_none())
+            .map(|(key, _)| key.clone())
+            .collect()
     }
 
     pub fn get_all_data(&self) -> &HashMap<String, Value> {
@@ -564,10 +1501,901 @@ impl InMemoryDB {
     }
 }
 
-#[derive(Debug)]
+/// What `InMemoryDB::changes_since` counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeCounts {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// Outcome of `InMemoryDB::add_batch`.
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchAddReport {
+    pub added: usize,
+    /// Position in the batch and reason for each record that was not added.
+    pub rejected: Vec<(usize, String)>,
+}
+
+/// Records `InMemoryDB::import_jsonl` logs and adds at a time.
+pub const JSONL_IMPORT_BATCH: usize = 1000;
+
+/// Outcome of `InMemoryDB::import_jsonl`.
+#[derive(Debug, Default, PartialEq)]
+pub struct JsonlImportReport {
+    pub imported: usize,
+    /// Line number (from 1) and reason for each line that was skipped.
+    pub skipped: Vec<(usize, String)>,
+}
+
+/// What `merge_from_file` does with a key that exists in both databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Replace the current value with the incoming one.
+    Overwrite,
+    /// Keep the current value.
+    Skip,
+    /// Refuse the whole merge if any key clashes.
+    Error,
+}
+
+impl ConflictPolicy {
+    /// Parses `overwrite`, `skip` or `error`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "skip" => Some(ConflictPolicy::Skip),
+            "error" => Some(ConflictPolicy::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Key counts from `merge_from_file`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergeReport {
+    pub added: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+}
+
+/// Bumped whenever a field of `DatabaseStats` is renamed, removed or changes meaning,
+/// so scrapers of `stats --json` can tell. Adding a field does not bump it.
+pub const STATS_SCHEMA_VERSION: u32 = 1;
+
+/// Serializes (for `stats --json`) with the field names below as its keys.
+#[derive(Debug, Serialize)]
 pub struct DatabaseStats {
+    pub schema_version: u32,
     pub total_records: usize,
+    /// Size of the data as compact JSON.
     pub total_size: usize,
+    /// Size of the data as indented JSON, for comparison with `total_size`.
+    pub pretty_size: usize,
     pub average_record_size: f64,
     pub last_modified: String,
-}
\ No newline at end of file
+    /// Whether saves write indented JSON.
+    pub pretty_saves: bool,
+    /// `None` for an empty database.
+    pub largest_record: Option<LargestRecord>,
+    /// Record count per top-level JSON type (`object`, `array`, `string`, `number`,
+    /// `boolean`, `null`), named as in `FieldProfile::type_counts`; types with no records are left out.
+    pub value_types: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LargestRecord {
+    pub key: String,
+    /// Size of the value as compact JSON.
+    pub size: usize,
+}
+
+/// What `InMemoryDB::verify_file_with_path` found. A file that does not exist passes:
+/// nothing has been saved yet.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileVerification {
+    /// Why the file could not be read, if it could not.
+    pub unreadable: Option<String>,
+    /// Whether the records match the hash saved with them; `None` when no hash was saved
+    /// or the file could not be read.
+    pub hash_matches: Option<bool>,
+}
+
+impl FileVerification {
+    pub fn passed(&self) -> bool {
+        self.unreadable.is_none() && self.hash_matches != Some(false)
+    }
+}
+
+/// Where every save records the hash of the records it wrote: `<file>.hash`, next to the
+/// file. `hashes/<stem>.hash` would be shared by every session's `database.json`. Backups
+/// get a copy of the hash beside them, which `repair_file` checks them against.
+pub fn data_hash_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".hash");
+    PathBuf::from(name)
+}
+
+/// The hash the last save of `path` recorded at its `data_hash_path`, if there is one.
+fn stored_data_hash(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(data_hash_path(path)) {
+        Ok(stored) => Ok(Some(stored.trim().to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes the `data_hash_path` of `path` after `data` was saved there. Every save path
+/// calls this, so `verify_file_with_path` checks a file against whoever wrote it last.
+fn save_data_hash(path: &Path, data: &HashMap<String, Value>) {
+    let _ = fs::write(data_hash_path(path), calculate_data_hash(data));
+}
+
+/// Where the trash of the database saved at `path` is kept: `<file>.trash`, next to it.
+pub fn trash_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".trash");
+    PathBuf::from(name)
+}
+
+/// What a restore from backups looked at. Backups are tried newest first.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Backups that could not be used, with the reason.
+    pub rejected: Vec<(PathBuf, String)>,
+    /// The backup the data now comes from.
+    pub restored_from: Option<PathBuf>,
+    /// Where an unreadable persistence file was copied before being overwritten.
+    pub corrupt_copy: Option<PathBuf>,
+}
+
+impl RepairReport {
+    pub fn tried(&self) -> usize {
+        self.rejected.len() + usize::from(self.restored_from.is_some())
+    }
+
+    /// `" (tried N: a: reason; b: reason)"`, or `" (no backups exist)"`, for error messages.
+    fn describe_rejections(&self) -> String {
+        if self.rejected.is_empty() {
+            return " (no backups exist)".to_string();
+        }
+        let reasons: Vec<String> = self.rejected.iter()
+            .map(|(path, reason)| format!("{}: {}", path.display(), reason))
+            .collect();
+        format!(" (tried {}: {})", self.rejected.len(), reasons.join("; "))
+    }
+}
+
+/// Unlabelled backups `vacuum` keeps unless told otherwise.
+pub const DEFAULT_VACUUM_BACKUPS: usize = 3;
+
+/// What `vacuum_with_path` did. Sizes are in bytes, as found on disk.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VacuumReport {
+    /// The database file and its trash, before and after the rewrite.
+    pub data_bytes_before: u64,
+    pub data_bytes_after: u64,
+    pub backups_removed: usize,
+    pub backup_bytes: u64,
+    /// The write-ahead log that was emptied.
+    pub wal_bytes: u64,
+}
+
+impl VacuumReport {
+    /// Space freed overall. Unsaved changes written by the rewrite count against it.
+    pub fn reclaimed(&self) -> u64 {
+        (self.data_bytes_before + self.backup_bytes + self.wal_bytes).saturating_sub(self.data_bytes_after)
+    }
+}
+
+/// Whether `path` exists but does not parse in either storage format. An encrypted file
+/// counts as readable when no key is given, since only the key can tell.
+pub fn file_is_unreadable(path: &Path, key: Option<&DataKey>) -> bool {
+    if key.is_none() && is_encrypted_file(path) {
+        return false;
+    }
+    path.exists() && read_storage(path, key).is_err()
+}
+
+/// Rewrites the backups of `file_path` that `from` can read so they are encrypted with
+/// `to`, or stored plain for `None`, as a session's protection changes. Backups already in
+/// that form or that do not read are left alone. They are rewritten oldest first, which
+/// keeps their order by modification time. Returns how many were rewritten.
+pub fn convert_backups(file_path: &str, from: Option<&DataKey>, to: Option<&DataKey>) -> Result<usize> {
+    let mut converted = 0;
+    for (backup_path, _) in backup_files(Path::new(file_path))?.into_iter().rev() {
+        if is_encrypted_file(&backup_path) == to.is_some() {
+            continue;
+        }
+        let Ok(Some((data, format))) = read_storage(&backup_path, from) else {
+            continue;
+        };
+        write_storage_atomically(&backup_path, &data, format, true, to)?;
+        converted += 1;
+    }
+    Ok(converted)
+}
+
+/// Whether `path` was written encrypted (see `InMemoryDB::set_encryption_key`).
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut magic = [0u8; crypto::SEALED_MAGIC.len()];
+    File::open(path).and_then(|mut file| io::Read::read_exact(&mut file, &mut magic)).is_ok()
+        && crypto::is_sealed(&magic)
+}
+
+/// Copies `path` byte for byte to `<name>.corrupt` (or `<name>.corrupt.<timestamp>` if that
+/// is taken) so it can be recovered by hand after a repair overwrites it.
+pub fn save_corrupt_copy(path: &Path) -> Result<PathBuf> {
+    let mut copy = path.with_extension("corrupt");
+    if copy.exists() {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        copy = path.with_extension(format!("corrupt.{}", timestamp));
+    }
+    disk::ensure_space(&copy, fs::metadata(path)?.len())?;
+    fs::copy(path, &copy)?;
+    Ok(copy)
+}
+
+/// Cloneable, thread-safe handle to an `InMemoryDB`.
+///
+/// Readers share the lock; writers are serialized, and a write's auto-save happens before
+/// the lock is released so no other thread can observe or change half-saved state.
+#[derive(Clone)]
+pub struct SharedDB {
+    inner: Arc<RwLock<InMemoryDB>>,
+}
+
+impl SharedDB {
+    pub fn new(db: InMemoryDB) -> Self {
+        SharedDB {
+            inner: Arc::new(RwLock::new(db)),
+        }
+    }
+
+    /// Runs `f` under the read lock.
+    pub fn read<R>(&self, f: impl FnOnce(&InMemoryDB) -> R) -> R {
+        f(&self.read_guard())
+    }
+
+    /// Runs `f` under the write lock, so multi-step changes are applied atomically.
+    pub fn write<R>(&self, f: impl FnOnce(&mut InMemoryDB) -> R) -> R {
+        f(&mut self.write_guard())
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.read_guard().get(key).cloned()
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.read_guard().exists(key)
+    }
+
+    pub fn list_keys(&self) -> Vec<String> {
+        self.read_guard().list_keys()
+    }
+
+    pub fn search_by_field(&self, field: &str, value: &str) -> Vec<String> {
+        self.read_guard().search_by_field(field, value)
+    }
+
+    pub fn search_by_field_matching(&self, field: &str, value: &str, mode: MatchMode) -> Vec<String> {
+        self.read_guard().search_by_field_matching(field, value, mode)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read_guard().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read_guard().is_empty()
+    }
+
+    pub fn insert(&self, key: &str, value: Value) -> Result<()> {
+        self.write_guard().insert(key, value)
+    }
+
+    pub fn update(&self, key: &str, value: Value) -> Result<bool> {
+        self.write_guard().update(key, value)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<()> {
+        self.write_guard().delete(key)
+    }
+
+    /// Starts a background thread that saves every `interval` if anything changed.
+    ///
+    /// Dropping the returned handle stops the thread after one last flush, so at most one
+    /// interval of changes can be lost. Only databases with a persistence file are saved.
+    /// Failed saves are kept for `PeriodicSave::take_failures`.
+    pub fn enable_periodic_save(&self, interval: Duration) -> PeriodicSave {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let db = self.clone();
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let failed = Arc::clone(&failures);
+        let handle = thread::spawn(move || {
+            loop {
+                let finished = !matches!(stopped.recv_timeout(interval), Err(mpsc::RecvTimeoutError::Timeout));
+                if let Err(e) = db.read(|db| db.save_if_dirty()) {
+                    failed.lock().unwrap_or_else(|e| e.into_inner()).push(e);
+                }
+                if finished {
+                    break;
+                }
+            }
+        });
+        PeriodicSave {
+            stop: Some(stop),
+            handle: Some(handle),
+            failures,
+        }
+    }
+
+    // A panic while holding the lock leaves the data as the last completed write left it,
+    // so a poisoned lock is recovered rather than propagated.
+    fn read_guard(&self) -> RwLockReadGuard<'_, InMemoryDB> {
+        self.inner.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write_guard(&self) -> RwLockWriteGuard<'_, InMemoryDB> {
+        self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Handle for the thread started by `SharedDB::enable_periodic_save`; dropping it stops the thread.
+pub struct PeriodicSave {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    failures: Arc<Mutex<Vec<RedruError>>>,
+}
+
+impl PeriodicSave {
+    /// Errors from saves that failed since the last call, oldest first. The changes stay
+    /// unsaved, so the next interval tries again.
+    pub fn take_failures(&self) -> Vec<RedruError> {
+        std::mem::take(&mut *self.failures.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl Drop for PeriodicSave {
+    fn drop(&mut self) {
+        // Closing the channel wakes the thread immediately for its final flush.
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Number of shards in a `ShardedDB`; a power of two well above the server's worker count.
+const SHARD_COUNT: usize = 16;
+
+/// Cloneable, thread-safe key-value store whose keys are spread over `RwLock` shards by
+/// hash, so writers to keys in different shards never wait for each other and readers only
+/// wait for a writer in their own shard.
+///
+/// Unlike `SharedDB` there is no single `InMemoryDB` underneath, so indexes, backups and
+/// per-write auto-save are not available. Persistence works on snapshots instead:
+/// `snapshot` read-locks every shard in order (writers hold at most one shard, so this
+/// cannot deadlock) and copies them out while all locks are held, giving a point-in-time
+/// view. `save` writes such a snapshot outside the shard locks, and saves are serialized
+/// among themselves, so writes keep going while a file is being written.
+#[derive(Clone)]
+pub struct ShardedDB {
+    inner: Arc<Shards>,
+}
+
+struct Shards {
+    shards: Vec<RwLock<HashMap<String, Value>>>,
+    persistence_file: Option<PathBuf>,
+    format: StorageFormat,
+    key: Option<DataKey>,
+    limits: RecordLimits,
+    /// Set after every change; cleared by the save whose snapshot will include it.
+    dirty: AtomicBool,
+    save_lock: Mutex<()>,
+    /// The persistence file as this database last loaded or saved it; see `FileStamp`.
+    seen_file: Mutex<Option<FileStamp>>,
+}
+
+impl ShardedDB {
+    pub fn new() -> Self {
+        Self::from_storage(HashMap::new(), None, StorageFormat::Json, None)
+    }
+
+    /// Loads `file_path` (if it exists) and saves back to it in the same format.
+    pub fn open(file_path: &str) -> Result<Self> {
+        Self::open_with_key(file_path, None)
+    }
+
+    /// Like `open` for a file encrypted with `key`, which later saves use as well.
+    pub fn open_with_key(file_path: &str, key: Option<DataKey>) -> Result<Self> {
+        // Taken before reading, as in `InMemoryDB::load_from_file_path_with_key`.
+        let stamp = file_stamp(Path::new(file_path));
+        let db = InMemoryDB::load_from_file_path_with_key(file_path, key)?;
+        let sharded = Self::from_storage(db.storage, Some(PathBuf::from(file_path)), db.format, db.key);
+        *sharded.inner.seen_file.lock().unwrap_or_else(|e| e.into_inner()) = stamp;
+        Ok(sharded)
+    }
+
+    fn from_storage(storage: HashMap<String, Value>, persistence_file: Option<PathBuf>, format: StorageFormat, key: Option<DataKey>) -> Self {
+        let shards: Vec<_> = (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect();
+        for (key, value) in storage {
+            write_shard(&shards[shard_for(&key)]).insert(key, value);
+        }
+        ShardedDB {
+            inner: Arc::new(Shards {
+                shards,
+                persistence_file,
+                format,
+                key,
+                limits: RecordLimits::default(),
+                dirty: AtomicBool::new(false),
+                save_lock: Mutex::new(()),
+                seen_file: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn limits(&self) -> RecordLimits {
+        self.inner.limits
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        read_shard(self.shard(key)).get(key).cloned()
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        read_shard(self.shard(key)).contains_key(key)
+    }
+
+    /// Inserts or replaces `key`; returns whether it already existed.
+    pub fn insert(&self, key: &str, value: Value) -> Result<bool> {
+        self.inner.limits.check(key, &value)?;
+        let existed = write_shard(self.shard(key)).insert(key.to_string(), value).is_some();
+        self.inner.dirty.store(true, Ordering::SeqCst);
+        Ok(existed)
+    }
+
+    /// Returns whether `key` was present.
+    pub fn delete(&self, key: &str) -> bool {
+        let removed = write_shard(self.shard(key)).remove(key).is_some();
+        if removed {
+            self.inner.dirty.store(true, Ordering::SeqCst);
+        }
+        removed
+    }
+
+    /// Counts shard by shard, so concurrent writes may or may not be included.
+    pub fn len(&self) -> usize {
+        self.inner.shards.iter().map(|shard| read_shard(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Collected shard by shard, like `len`.
+    pub fn list_keys(&self) -> Vec<String> {
+        self.inner.shards.iter().flat_map(|shard| read_shard(shard).keys().cloned().collect::<Vec<_>>()).collect()
+    }
+
+    /// Same matching as `InMemoryDB::search_by_field_matching`, one shard at a time.
+    pub fn search_by_field_matching(&self, field: &str, value: &str, mode: MatchMode) -> Vec<String> {
+        let parsed = serde_json::from_str::<Value>(value).ok();
+        let mut results = Vec::new();
+        for shard in &self.inner.shards {
+            let shard = read_shard(shard);
+            results.extend(
+                shard.iter()
+                    .filter(|(_, val)| field_matches(val, field, value, parsed.as_ref(), mode))
+                    .map(|(key, _)| key.clone()),
+            );
+        }
+        results
+    }
+
+    /// Every record as of a single moment; see the type docs for how this is kept consistent.
+    pub fn snapshot(&self) -> HashMap<String, Value> {
+        let guards: Vec<_> = self.inner.shards.iter().map(read_shard).collect();
+        guards.iter().flat_map(|shard| shard.iter().map(|(k, v)| (k.clone(), v.clone()))).collect()
+    }
+
+    /// Writes a snapshot to the persistence file if anything changed since the last save.
+    ///
+    /// A write that returns before this is called is always in the saved file: either this
+    /// call saves it, or a save still in progress already took it. Databases without a
+    /// persistence file are never saved. Like `InMemoryDB::save_to_file_with_path`, fails
+    /// with `Conflict`, writing nothing, if another process changed the file since this
+    /// database loaded or saved it; the changes stay in memory for a later save.
+    pub fn save_if_dirty(&self) -> Result<bool> {
+        let Some(path) = &self.inner.persistence_file else {
+            return Ok(false);
+        };
+        let _saving = self.inner.save_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Cleared before the snapshot, so any write that sets it again afterwards is either in
+        // this snapshot or picked up by the next save.
+        if !self.inner.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let mut seen = self.inner.seen_file.lock().unwrap_or_else(|e| e.into_inner());
+        if *seen != file_stamp(path) {
+            self.inner.dirty.store(true, Ordering::SeqCst);
+            return Err(changed_elsewhere(path));
+        }
+        let snapshot = self.snapshot();
+        let written = match path.parent() {
+            Some(parent) if !parent.exists() => fs::create_dir_all(parent).map_err(RedruError::from),
+            _ => Ok(()),
+        }
+        .and_then(|_| write_storage_atomically(path, &snapshot, self.inner.format, true, self.inner.key.as_ref()));
+        match written {
+            Ok(()) => {
+                *seen = file_stamp(path);
+                save_data_hash(path, &snapshot);
+            }
+            Err(_) => self.inner.dirty.store(true, Ordering::SeqCst),
+        }
+        written.map(|_| true)
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<HashMap<String, Value>> {
+        &self.inner.shards[shard_for(key)]
+    }
+}
+
+impl Default for ShardedDB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn shard_for(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % SHARD_COUNT
+}
+
+// As in `SharedDB`, a poisoned shard still holds the last completed write, so it is recovered.
+fn read_shard(shard: &RwLock<HashMap<String, Value>>) -> RwLockReadGuard<'_, HashMap<String, Value>> {
+    shard.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write_shard(shard: &RwLock<HashMap<String, Value>>) -> RwLockWriteGuard<'_, HashMap<String, Value>> {
+    shard.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Whether `val` has a `field` (a literal top-level key, else a dotted path) matching
+/// `value` under `mode`. `parsed` is `value` read as JSON, for exact typed matches.
+fn field_matches(val: &Value, field: &str, value: &str, parsed: Option<&Value>, mode: MatchMode) -> bool {
+    // A literal top-level key wins over path resolution, so keys containing dots still work.
+    let Some(field_value) = val.get(field).or_else(|| extract_field_value(val, field)) else {
+        return false;
+    };
+    parsed == Some(field_value)
+        || match field_value {
+            Value::String(s) => mode.matches_str(s, value),
+            _ => matches!(mode, MatchMode::Contains | MatchMode::Prefix)
+                && mode.matches_str(&field_value.to_string(), value),
+        }
+}
+
+/// Reads a persistence file in either format, detected from the header. Empty or
+/// whitespace-only files yield `None`. Encrypted files need `key`.
+fn read_storage(path: &Path, key: Option<&DataKey>) -> Result<Option<(HashMap<String, Value>, StorageFormat)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if crypto::is_sealed(reader.fill_buf()?) {
+        let key = key.ok_or_else(|| RedruError::Auth(format!(
+            "'{}' is encrypted; open it through its password-protected session", path.display()
+        )))?;
+        let mut sealed = Vec::new();
+        io::Read::read_to_end(&mut reader, &mut sealed)?;
+        return decode_storage(&key.open(&sealed)?);
+    }
+    if reader.fill_buf()?.starts_with(MSGPACK_MAGIC) {
+        reader.consume(MSGPACK_MAGIC.len());
+        let data = rmp_serde::from_read(reader)
+            .map_err(|e| RedruError::Serialization(format!("MessagePack parsing error: {}", e)))?;
+        return Ok(Some((data, StorageFormat::MessagePack)));
+    }
+    Ok(read_json_file(path)?.map(|data| (data, StorageFormat::Json)))
+}
+
+/// `read_storage` for bytes already in memory.
+fn decode_storage(bytes: &[u8]) -> Result<Option<(HashMap<String, Value>, StorageFormat)>> {
+    if let Some(body) = bytes.strip_prefix(MSGPACK_MAGIC.as_slice()) {
+        let data = rmp_serde::from_slice(body)
+            .map_err(|e| RedruError::Serialization(format!("MessagePack parsing error: {}", e)))?;
+        return Ok(Some((data, StorageFormat::MessagePack)));
+    }
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    serde_json::from_slice(bytes)
+        .map(|data| Some((data, StorageFormat::Json)))
+        .map_err(|e| RedruError::Serialization(format!("JSON parsing error: {}", e)))
+}
+
+/// Encodes `storage` as a save would, so sizing and saving cannot disagree.
+fn encode_storage(writer: &mut impl Write, storage: &HashMap<String, Value>, format: StorageFormat, pretty: bool) -> Result<()> {
+    match format {
+        StorageFormat::Json => {
+            let written = if pretty {
+                serde_json::to_writer_pretty(&mut *writer, storage)
+            } else {
+                serde_json::to_writer(&mut *writer, storage)
+            };
+            written.map_err(|e| {
+                if e.is_io() {
+                    RedruError::from(e)
+                } else {
+                    RedruError::Serialization(format!("JSON serialization error: {}", e))
+                }
+            })
+        }
+        StorageFormat::MessagePack => {
+            writer.write_all(MSGPACK_MAGIC)?;
+            rmp_serde::encode::write(writer, storage).map_err(|e| {
+                RedruError::Serialization(format!("MessagePack serialization error: {}", e))
+            })
+        }
+    }
+}
+
+/// Below this much free space (beyond a few times the current file), a save measures its
+/// encoded size before writing.
+const TIGHT_SPACE: u64 = 64 * 1024 * 1024;
+
+/// Fails before anything is written when the save cannot fit. The temp file and the old
+/// file both exist until the rename, so the whole encoded size must be free. Measuring
+/// means encoding twice, so it is only done when space is tight.
+fn ensure_storage_space(path: &Path, storage: &HashMap<String, Value>, format: StorageFormat, pretty: bool) -> Result<()> {
+    let Some(available) = disk::available_space(path) else {
+        return Ok(());
+    };
+    let current = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if available > current.saturating_mul(4).saturating_add(TIGHT_SPACE) {
+        return Ok(());
+    }
+    let mut counter = ByteCounter(0);
+    encode_storage(&mut counter, storage, format, pretty)?;
+    disk::ensure_space(path, counter.0 as u64)?;
+    Ok(())
+}
+
+/// `<stem>.backup.[<label>.]<timestamp>` for a new backup of `path`. Names go to the second,
+/// so a backup made in the same second as an earlier one takes the next free second
+/// instead of overwriting it.
+fn new_backup_path(path: &Path, label: Option<&str>) -> PathBuf {
+    let mut timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    loop {
+        let backup_path = match label {
+            Some(label) => path.with_extension(format!("backup.{}.{}", label, timestamp)),
+            None => path.with_extension(format!("backup.{}", timestamp)),
+        };
+        if !backup_path.exists() {
+            return backup_path;
+        }
+        timestamp += 1;
+    }
+}
+
+/// Copies the persistence file to `backup_path`, checking for space first and removing a
+/// partial copy if the copy fails.
+fn copy_backup(path: &Path, backup_path: &Path) -> Result<()> {
+    disk::ensure_space(backup_path, fs::metadata(path)?.len())?;
+    fs::copy(path, backup_path).map_err(|e| {
+        let _ = fs::remove_file(backup_path);
+        RedruError::from(e)
+    })?;
+    Ok(())
+}
+
+/// Copies the `data_hash_path` of `path` to that of its backup at `backup_path`: the
+/// backup holds what the last save wrote, so it has the same hash.
+fn copy_data_hash(path: &Path, backup_path: &Path) {
+    let hash_file = data_hash_path(path);
+    if hash_file.exists() {
+        let _ = fs::copy(&hash_file, data_hash_path(backup_path));
+    }
+}
+
+/// Modification time and size of a file, compared to notice writes by other processes.
+/// `None` (from `file_stamp`) stands for a file that does not exist.
+#[derive(Debug, Clone, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// The `Conflict` a save refuses with when `path` changed since it was loaded or saved.
+fn changed_elsewhere(path: &Path) -> RedruError {
+    RedruError::Conflict(format!(
+        "{} was changed by another process since it was loaded; saving would discard those changes",
+        path.display()))
+}
+
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileStamp { modified: metadata.modified().ok(), len: metadata.len() })
+}
+
+/// Sets the dotted path `field` in `record` to `value`, creating objects along the way.
+/// Returns `false`, changing nothing, if part of the path holds something other than an object.
+fn set_field_path(record: &mut Value, field: &str, value: Value) -> bool {
+    let (parents, last) = match field.rsplit_once('.') {
+        Some((parents, last)) => (Some(parents), last),
+        None => (None, field),
+    };
+    // Checked first, so a path that fails part way leaves no new objects behind.
+    let mut found = Some(&*record);
+    for part in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        match found {
+            Some(current) if current.is_object() => found = current.get(part),
+            Some(_) => return false,
+            None => break,
+        }
+    }
+    if found.is_some_and(|current| !current.is_object()) {
+        return false;
+    }
+
+    let mut current = record;
+    for part in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        let Value::Object(object) = current else { return false };
+        current = object.entry(part).or_insert_with(|| Value::Object(serde_json::Map::new()));
+    }
+    let Value::Object(object) = current else { return false };
+    object.insert(last.to_string(), value);
+    true
+}
+
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Counts bytes written to it, for sizing output without buffering it.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams `storage` into a temp file next to `path`, then renames it into place, so the
+/// encoded bytes never have to exist in memory all at once (except when encrypting with
+/// `key`, which seals the whole encoding). A failed write (including a disk filling up) or
+/// rename removes the temp file, and the old file is left untouched.
+fn write_storage_atomically(path: &Path, storage: &HashMap<String, Value>, format: StorageFormat, pretty: bool, key: Option<&DataKey>) -> Result<()> {
+    ensure_storage_space(path, storage, format, pretty)?;
+    let temp_path = path.with_extension("tmp");
+    let written = File::create(&temp_path)
+        .map_err(RedruError::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            match key {
+                Some(key) => {
+                    let mut plaintext = Vec::new();
+                    encode_storage(&mut plaintext, storage, format, pretty)?;
+                    writer.write_all(&key.seal(&plaintext)?)?;
+                }
+                None => encode_storage(&mut writer, storage, format, pretty)?,
+            }
+            writer.flush()?;
+            Ok(())
+        });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })?;
+    Ok(())
+}
+
+const MAX_BACKUP_LABEL_LEN: usize = 64;
+
+/// Why `label` cannot name a backup, if it cannot. Labels are kept to letters, digits, `-`
+/// and `_` so they stay readable in a file name and never swallow the timestamp after them.
+pub fn check_backup_label(label: &str) -> Result<(), &'static str> {
+    if label.is_empty() {
+        Err("it cannot be empty")
+    } else if label.len() > MAX_BACKUP_LABEL_LEN {
+        Err("it must be at most 64 characters")
+    } else if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Err("it can only contain letters, digits, '-' and '_'")
+    } else {
+        Ok(())
+    }
+}
+
+/// The label of a backup written by `create_backup_with_path`, if it was given one.
+pub fn backup_label(backup_path: &Path) -> Option<&str> {
+    let name = backup_path.file_name()?.to_str()?;
+    let (_, rest) = name.split_once(".backup.")?;
+    let (label, timestamp) = rest.rsplit_once('.')?;
+    timestamp.parse::<u64>().ok()?;
+    Some(label)
+}
+
+/// Deletes backups of `path` that are older than `max_age` or beyond the newest `max_count`,
+/// plus their hash files. Age comes from the timestamp in the file name, falling back
+/// to the modification time. Returns how many backups were deleted.
+fn prune_backups(path: &Path, max_age: Option<Duration>, max_count: Option<usize>) -> Result<usize> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for (i, (backup_path, modified)) in backup_files(path)?.into_iter().enumerate() {
+        let name = backup_path.file_name().unwrap_or_default();
+        let created = name.to_string_lossy().rsplit('.').next()
+            .and_then(|secs| secs.parse().ok())
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap_or(modified);
+        let too_old = max_age.is_some_and(|max_age| now.duration_since(created).unwrap_or_default() > max_age);
+        let too_many = max_count.is_some_and(|max_count| i >= max_count);
+        if too_old || too_many {
+            remove_backup(&backup_path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Deletes a backup along with its hash file.
+fn remove_backup(backup_path: &Path) -> Result<()> {
+    fs::remove_file(backup_path)?;
+    let _ = fs::remove_file(data_hash_path(backup_path));
+    Ok(())
+}
+
+/// Size of `path`, or 0 if it does not exist.
+fn file_len(path: &Path) -> u64 {
+    fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+/// Lists `<stem>.backup.[<label>.]<timestamp>` siblings of `path`, newest first, leaving
+/// out the backups' hash files.
+fn backup_files(path: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // Compared as raw bytes: a lossy stem could match some other file's mangled name.
+    let mut prefix = path.file_stem().unwrap_or_default().to_os_string();
+    prefix.push(".backup.");
+
+    let mut backups: Vec<(PathBuf, SystemTime)> = fs::read_dir(parent)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            name.as_encoded_bytes().starts_with(prefix.as_encoded_bytes()) && !name.as_encoded_bytes().ends_with(b".hash")
+        })
+        .map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+            (entry.path(), modified)
+        })
+        .collect();
+
+    backups.sort_by_key(|(_, modified)| *modified);
+    backups.reverse();
+    Ok(backups)
+}
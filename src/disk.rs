@@ -0,0 +1,53 @@
+use std::io;
+use std::path::Path;
+
+/// Headroom kept on top of an estimate, so a write never runs the disk completely full.
+const SPACE_MARGIN: u64 = 1024 * 1024;
+
+/// Bytes an unprivileged process may still write on the filesystem holding `path`, which
+/// need not exist yet. `None` when the platform cannot tell.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(Path::new("."));
+    let existing = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+    platform_available_space(existing)
+}
+
+#[cfg(unix)]
+fn platform_available_space(path: &Path) -> Option<u64> {
+    let stats = rustix::fs::statvfs(path).ok()?;
+    Some(stats.f_bavail.saturating_mul(stats.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn platform_available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Fails with `StorageFull` when writing `needed` bytes near `path` would leave less than
+/// a small margin free. Passes when free space is unknown.
+pub fn ensure_space(path: &Path, needed: u64) -> io::Result<()> {
+    match available_space(path) {
+        Some(available) if available < needed.saturating_add(SPACE_MARGIN) => Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Not enough disk space for {}: about {} needed plus {} headroom, {} available",
+                path.display(), format_bytes(needed), format_bytes(SPACE_MARGIN), format_bytes(available)
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
@@ -0,0 +1,152 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use console::{measure_text_width, style, Term};
+use serde_json::Value;
+
+/// Space between columns and before the first one.
+const GUTTER: usize = 2;
+/// Keys longer than this get a line to themselves in preview mode instead of pushing
+/// every preview to the right.
+const PREVIEW_KEY_MAX: usize = 32;
+/// Preview width when stdout is not a terminal.
+const PREVIEW_DEFAULT_WIDTH: usize = 80;
+
+/// Set by `--no-emoji` or `NO_COLOR`: ASCII markers instead of emoji, and no color.
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// `emoji` as is, or an ASCII marker in plain mode so minimal terminals and log parsers
+/// see text instead of boxes.
+pub fn icon(emoji: &'static str) -> &'static str {
+    if !plain() {
+        return emoji;
+    }
+    match emoji {
+        "✅" => "[ok]",
+        "❌" => "[error]",
+        "⚠️" => "[warn]",
+        "🔒" => "[locked]",
+        "🔓" => "[unlocked]",
+        "🔐" => "[auth]",
+        "📂" => "[open]",
+        "🌐" => "[http]",
+        "ℹ️" => "[info]",
+        _ => "*",
+    }
+}
+
+/// Width of the terminal stdout is attached to, or `None` when output is piped, in which
+/// case lists keep the one-key-per-line layout scripts expect. `COLUMNS` is used when the
+/// terminal does not report a size.
+pub fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    Term::stdout().size_checked()
+        .map(|(_, cols)| cols as usize)
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+}
+
+/// Color only for a terminal, and never in plain mode or when `NO_COLOR` is set to
+/// anything non-empty.
+pub fn use_color() -> bool {
+    !plain() && std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+/// Lays `items` out in columns that fit `width`, filled top to bottom like `ls`. Each row
+/// is indented by the gutter; a single item wider than `width` still gets its own row.
+pub fn format_columns(items: &[String], width: usize) -> Vec<String> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let widths: Vec<usize> = items.iter().map(|item| measure_text_width(item)).collect();
+    // Try the most columns first (each needs a character plus the gutter); the first
+    // layout whose rows fit wins.
+    for columns in (1..=items.len().min(width / (GUTTER + 1)).max(1)).rev() {
+        let rows = items.len().div_ceil(columns);
+        let columns = items.len().div_ceil(rows);
+        let column_widths: Vec<usize> = (0..columns)
+            .map(|c| widths[c * rows..((c + 1) * rows).min(items.len())].iter().copied().max().unwrap_or(0))
+            .collect();
+        let total: usize = column_widths.iter().map(|w| w + GUTTER).sum();
+        if total <= width || columns == 1 {
+            return (0..rows)
+                .map(|r| {
+                    let mut line = " ".repeat(GUTTER);
+                    for (c, column_width) in column_widths.iter().enumerate() {
+                        let Some(item) = items.get(c * rows + r) else { break };
+                        line.push_str(item);
+                        if items.get((c + 1) * rows + r).is_some() {
+                            line.push_str(&" ".repeat(column_width - widths[c * rows + r] + GUTTER));
+                        }
+                    }
+                    line
+                })
+                .collect();
+        }
+    }
+    unreachable!("a single column always fits")
+}
+
+/// `value` as compact JSON, cut to at most `max` characters with a trailing `…`.
+pub fn truncate_preview(value: &Value, max: usize) -> String {
+    let text = value.to_string();
+    if text.chars().count() <= max {
+        return text;
+    }
+    let mut cut: String = text.chars().take(max.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// Prints keys sorted, in columns on a terminal and one per line otherwise.
+pub fn print_keys(keys: &[String]) {
+    let mut keys = keys.to_vec();
+    keys.sort();
+    match terminal_width() {
+        Some(width) => {
+            for line in format_columns(&keys, width) {
+                println!("{}", line);
+            }
+        }
+        None => {
+            for key in keys {
+                println!("  {}", key);
+            }
+        }
+    }
+}
+
+/// Prints each key followed by a one-line preview of its value, cut to the terminal width.
+pub fn print_previews<'a>(keys: &[String], value_of: impl Fn(&str) -> Option<&'a Value>) {
+    let mut keys = keys.to_vec();
+    keys.sort();
+    let width = terminal_width().unwrap_or(PREVIEW_DEFAULT_WIDTH);
+    let key_width = keys.iter()
+        .map(|key| measure_text_width(key))
+        .filter(|&w| w <= PREVIEW_KEY_MAX)
+        .max()
+        .unwrap_or(0);
+    let color = use_color();
+    for key in &keys {
+        let Some(value) = value_of(key) else { continue };
+        let key_len = measure_text_width(key);
+        let shown_key = if color { style(key).cyan().to_string() } else { key.clone() };
+        let indent = GUTTER + key_width + GUTTER;
+        if key_len > key_width {
+            println!("{}{}", " ".repeat(GUTTER), shown_key);
+            print!("{}", " ".repeat(indent));
+        } else {
+            print!("{}{}{}", " ".repeat(GUTTER), shown_key, " ".repeat(key_width - key_len + GUTTER));
+        }
+        let preview = truncate_preview(value, width.saturating_sub(indent).max(16));
+        println!("{}", if color { style(preview).dim().to_string() } else { preview });
+    }
+}
@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Error type shared by the database, index and password APIs.
+#[derive(Debug)]
+pub enum RedruError {
+    /// Reading or writing a file (or the terminal) failed.
+    Io(io::Error),
+    /// Data could not be encoded or decoded: JSON, password hashes, vault contents.
+    Serialization(String),
+    /// Stored data failed a hash or format check.
+    Integrity(String),
+    /// A password or passphrase was rejected.
+    Auth(String),
+    /// The requested key, backup, index or record does not exist.
+    NotFound(String),
+    /// An operation would overwrite existing data it was told to leave alone.
+    Conflict(String),
+    /// A key or value is larger than the configured limit.
+    Limit(String),
+    /// A change was attempted on a database opened read-only.
+    ReadOnly(String),
+}
+
+pub type Result<T, E = RedruError> = std::result::Result<T, E>;
+
+impl fmt::Display for RedruError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedruError::Io(e) => write!(f, "I/O error: {}", e),
+            RedruError::Serialization(msg) => write!(f, "Serialization error: {}", msg),
+            RedruError::Integrity(msg) => write!(f, "Integrity error: {}", msg),
+            RedruError::Auth(msg) => write!(f, "Authentication error: {}", msg),
+            RedruError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            RedruError::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            RedruError::Limit(msg) => write!(f, "Limit exceeded: {}", msg),
+            RedruError::ReadOnly(msg) => write!(f, "Read-only: {}", msg),
+        }
+    }
+}
+
+impl Error for RedruError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RedruError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RedruError {
+    fn from(e: io::Error) -> Self {
+        RedruError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RedruError {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            RedruError::Io(e.into())
+        } else {
+            RedruError::Serialization(e.to_string())
+        }
+    }
+}
+
+// Lets the interactive shell, which works in `io::Result`, keep using `?` on library calls.
+impl From<RedruError> for io::Error {
+    fn from(e: RedruError) -> Self {
+        let kind = match e {
+            RedruError::Io(inner) => return inner,
+            RedruError::Serialization(_) | RedruError::Integrity(_) => io::ErrorKind::InvalidData,
+            RedruError::Auth(_) | RedruError::ReadOnly(_) => io::ErrorKind::PermissionDenied,
+            RedruError::NotFound(_) => io::ErrorKind::NotFound,
+            RedruError::Conflict(_) => io::ErrorKind::AlreadyExists,
+            RedruError::Limit(_) => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
@@ -1,16 +1,180 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
 use std::fs::{self, File};
-use std::io::{self, Write, BufWriter, BufReader, BufRead};
+use std::io::{Write, BufWriter, BufReader, BufRead};
 use std::path::{Path, PathBuf};
-use serde_json::Value;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
 use sha2::{Sha256, Digest};
+use crate::error::{RedruError, Result};
+
+/// How query text is compared against string field values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Case-sensitive equality; the default for `search`.
+    Exact,
+    /// Case-insensitive equality.
+    IgnoreCase,
+    /// Case-insensitive substring; the default for `partial`.
+    Contains,
+    /// Case-insensitive prefix.
+    Prefix,
+}
+
+impl MatchMode {
+    /// Parses a mode name: `exact`, `icase`, `contains` or `prefix`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "exact" => Some(MatchMode::Exact),
+            "icase" => Some(MatchMode::IgnoreCase),
+            "contains" => Some(MatchMode::Contains),
+            "prefix" => Some(MatchMode::Prefix),
+            _ => None,
+        }
+    }
+
+    /// Parses a shell flag such as `--icase`.
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        flag.strip_prefix("--").and_then(MatchMode::parse)
+    }
+
+    pub fn matches_str(self, text: &str, query: &str) -> bool {
+        match self {
+            MatchMode::Exact => text == query,
+            MatchMode::IgnoreCase => text.to_lowercase() == query.to_lowercase(),
+            MatchMode::Contains => text.to_lowercase().contains(&query.to_lowercase()),
+            MatchMode::Prefix => text.to_lowercase().starts_with(&query.to_lowercase()),
+        }
+    }
+}
 
 pub struct HashIndex {
     indexes: HashMap<String, HashMap<u64, Vec<String>>>,
+    /// Per-index field lookups built by `index_field`. Kept in memory only.
+    fields: HashMap<String, HashMap<String, FieldIndex>>,
     index_dir: PathBuf,
     hash_dir: PathBuf,
+    /// Previous versions of each index file kept before it is rewritten whole; 0 keeps none.
+    backups_kept: usize,
+    /// Indexes whose file was written in an older format; loaded empty until rebuilt.
+    outdated: HashSet<String>,
+}
+
+/// Version written into every index file. Bump it whenever `hash_value` changes, so files
+/// bucketed by an older build are rebuilt instead of trusted.
+pub const INDEX_FORMAT_VERSION: u32 = 1;
+
+/// An index file as saved: buckets of keys under the `hash_value` of their records.
+#[derive(Serialize)]
+struct IndexFile<'a> {
+    format: u32,
+    buckets: BTreeMap<u64, Vec<&'a String>>,
+}
+
+/// An index file as read back. Files from before versioning hold the bare bucket map, whose
+/// numeric keys are ignored here, so they read as having no format.
+#[derive(Deserialize)]
+struct StoredIndex {
+    #[serde(default)]
+    format: Option<u32>,
+    #[serde(default)]
+    buckets: HashMap<u64, Vec<String>>,
+}
+
+impl StoredIndex {
+    /// The buckets, or `None` if they were written in another format than this build's.
+    fn current(self) -> Option<HashMap<u64, Vec<String>>> {
+        (self.format == Some(INDEX_FORMAT_VERSION)).then_some(self.buckets)
+    }
+}
+
+/// Index versions kept by default before an index file is overwritten.
+pub const DEFAULT_INDEX_BACKUPS: usize = 3;
+
+/// Lookup structures for one field, used by the `find_*` queries instead of scanning storage.
+/// Entries are candidates: queries still check each key against its stored value.
+#[derive(Default)]
+struct FieldIndex {
+    /// Lowercased match text of the field value (see `match_texts`) -> keys.
+    by_text: BTreeMap<String, Vec<String>>,
+    /// Numeric field values in ascending order.
+    numeric: Vec<(f64, String)>,
+}
+
+impl FieldIndex {
+    fn build(field: &str, storage: &HashMap<String, Value>) -> Self {
+        let mut index = FieldIndex::default();
+        for (key, value) in storage {
+            if let Some(field_value) = extract_field_value(value, field) {
+                for text in match_texts(field_value) {
+                    index.by_text.entry(text).or_default().push(key.clone());
+                }
+                if let Some(n) = field_value.as_f64() {
+                    index.numeric.push((n, key.clone()));
+                }
+            }
+        }
+        index.numeric.sort_by(|a, b| a.0.total_cmp(&b.0));
+        index
+    }
+
+    fn insert(&mut self, key: &str, field_value: &Value) {
+        for text in match_texts(field_value) {
+            self.by_text.entry(text).or_default().push(key.to_string());
+        }
+        if let Some(n) = field_value.as_f64() {
+            let pos = self.numeric.partition_point(|(m, _)| m.total_cmp(&n).is_lt());
+            self.numeric.insert(pos, (n, key.to_string()));
+        }
+    }
+
+    fn remove(&mut self, key: &str, field_value: &Value) {
+        for text in match_texts(field_value) {
+            if let Some(keys) = self.by_text.get_mut(&text) {
+                keys.retain(|k| k != key);
+                if keys.is_empty() {
+                    self.by_text.remove(&text);
+                }
+            }
+        }
+        if field_value.is_number() {
+            self.numeric.retain(|(_, k)| k != key);
+        }
+    }
+
+    /// Keys whose value may equal `expected` under `values_match`.
+    fn equal_candidates(&self, expected: &Value) -> impl Iterator<Item = &String> {
+        let text = match_texts(expected).into_iter().next().unwrap_or_default();
+        self.by_text.get(&text).into_iter().flatten()
+    }
+
+    /// Keys whose value may satisfy `mode` against `query`.
+    fn text_candidates<'a>(&'a self, query: &str, mode: MatchMode) -> Box<dyn Iterator<Item = &'a String> + 'a> {
+        let query = query.to_lowercase();
+        match mode {
+            MatchMode::Exact | MatchMode::IgnoreCase => Box::new(self.by_text.get(&query).into_iter().flatten()),
+            MatchMode::Prefix => Box::new(self.by_text.range(query.clone()..)
+                .take_while(move |(text, _)| text.starts_with(&query))
+                .flat_map(|(_, keys)| keys)),
+            MatchMode::Contains => Box::new(self.by_text.iter()
+                .filter(move |(text, _)| text.contains(&query))
+                .flat_map(|(_, keys)| keys)),
+        }
+    }
+
+    fn range(&self, min: f64, max: f64) -> impl Iterator<Item = &String> {
+        let start = self.numeric.partition_point(|(n, _)| *n < min);
+        let end = self.numeric.partition_point(|(n, _)| *n <= max);
+        self.numeric[start..end.max(start)].iter().map(|(_, key)| key)
+    }
+}
+
+impl Default for HashIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HashIndex {
@@ -27,29 +191,53 @@ impl HashIndex {
         
         HashIndex {
             indexes: HashMap::new(),
+            fields: HashMap::new(),
             index_dir,
             hash_dir,
+            backups_kept: DEFAULT_INDEX_BACKUPS,
+            outdated: HashSet::new(),
         }
     }
 
+    /// How many previous versions of each index file are kept, newest first. A version is
+    /// kept each time an index is rebuilt, cleared, repaired or restored; creating one, or
+    /// adding or removing one key, saves the index without keeping one.
+    pub fn set_index_backups(&mut self, count: usize) {
+        self.backups_kept = count;
+    }
+
+    pub fn index_backups(&self) -> usize {
+        self.backups_kept
+    }
+
     pub fn create_index(&mut self, index_name: &str) {
         self.indexes.insert(index_name.to_string(), HashMap::new());
-        self.save_index(index_name).unwrap_or(());
+        self.fields.remove(index_name);
+        self.outdated.remove(index_name);
+        self.save_index(index_name, false).unwrap_or(());
     }
 
     pub fn drop_index(&mut self, index_name: &str) {
         self.indexes.remove(index_name);
+        self.fields.remove(index_name);
+        self.outdated.remove(index_name);
         let index_file = self.index_dir.join(format!("{}.json", index_name));
         let hash_file = self.hash_dir.join(format!("{}.hash", index_name));
         let _ = fs::remove_file(index_file);
         let _ = fs::remove_file(hash_file);
+        let _ = self.prune_index_backups(index_name, 0);
     }
 
     pub fn add_to_index(&mut self, index_name: &str, key: &str, value: &Value) {
         if let Some(index) = self.indexes.get_mut(index_name) {
             let hash = hash_value(value);
             index.entry(hash).or_insert_with(Vec::new).push(key.to_string());
-            self.save_index(index_name).unwrap_or(());
+            for (field, field_index) in self.fields.entry(index_name.to_string()).or_default() {
+                if let Some(field_value) = extract_field_value(value, field) {
+                    field_index.insert(key, field_value);
+                }
+            }
+            self.save_index(index_name, false).unwrap_or(());
         }
     }
 
@@ -62,7 +250,12 @@ impl HashIndex {
                     index.remove(&hash);
                 }
             }
-            self.save_index(index_name).unwrap_or(());
+            for (field, field_index) in self.fields.entry(index_name.to_string()).or_default() {
+                if let Some(field_value) = extract_field_value(value, field) {
+                    field_index.remove(key, field_value);
+                }
+            }
+            self.save_index(index_name, false).unwrap_or(());
         }
     }
 
@@ -98,14 +291,48 @@ impl HashIndex {
                 let hash = hash_value(value);
                 index.entry(hash).or_insert_with(Vec::new).push(key.clone());
             }
-            self.save_index(index_name).unwrap_or(());
+            for (field, field_index) in self.fields.entry(index_name.to_string()).or_default() {
+                *field_index = FieldIndex::build(field, storage);
+            }
+            self.outdated.remove(index_name);
+            self.save_index(index_name, true).unwrap_or(());
+        }
+    }
+
+    /// Builds an in-memory lookup for `field` under `index_name`, which `find_field`,
+    /// `find_partial_matching`, `find_range` and `find_multi` then use instead of scanning
+    /// storage. It is kept current by `add_to_index`, `remove_from_index` and
+    /// `rebuild_index`, but not saved: build it again after loading. Returns false if the
+    /// index does not exist.
+    pub fn index_field(&mut self, index_name: &str, field: &str, storage: &HashMap<String, Value>) -> bool {
+        if !self.index_exists(index_name) {
+            return false;
         }
+        self.fields.entry(index_name.to_string()).or_default()
+            .insert(field.to_string(), FieldIndex::build(field, storage));
+        true
+    }
+
+    /// Fields of `index_name` that have a lookup from `index_field`, sorted.
+    pub fn indexed_fields(&self, index_name: &str) -> Vec<String> {
+        let mut fields: Vec<String> = self.fields.get(index_name)
+            .map(|fields| fields.keys().cloned().collect())
+            .unwrap_or_default();
+        fields.sort();
+        fields
+    }
+
+    fn field_index(&self, index_name: &str, field: &str) -> Option<&FieldIndex> {
+        self.fields.get(index_name)?.get(field)
     }
 
     pub fn clear_index(&mut self, index_name: &str) {
         if let Some(index) = self.indexes.get_mut(index_name) {
             index.clear();
-            self.save_index(index_name).unwrap_or(());
+            for field_index in self.fields.entry(index_name.to_string()).or_default().values_mut() {
+                *field_index = FieldIndex::default();
+            }
+            self.save_index(index_name, true).unwrap_or(());
         }
     }
 
@@ -123,14 +350,34 @@ impl HashIndex {
         }
     }
 
+    /// Every bucket of an index as `(hash, keys)`, sorted by hash and then key, for
+    /// inspecting what a lookup will see. Reads the index file when the index is not in
+    /// memory; `None` if it exists in neither place or its file does not parse or is in an
+    /// older format.
+    pub fn dump_index(&self, index_name: &str) -> Option<Vec<(u64, Vec<String>)>> {
+        let loaded;
+        let index = match self.indexes.get(index_name) {
+            Some(index) => index,
+            None => {
+                let index_file = self.index_dir.join(format!("{}.json", index_name));
+                loaded = read_index_file(&index_file).ok()??;
+                &loaded
+            }
+        };
+        Some(canonical_index(index)
+            .into_iter()
+            .map(|(hash, keys)| (hash, keys.into_iter().cloned().collect()))
+            .collect())
+    }
+
     pub fn list_indexes(&mut self) -> Vec<String> {
         let mut indexes = self.indexes.keys().cloned().collect::<Vec<_>>();
         
         if let Ok(entries) = fs::read_dir(&self.index_dir) {
             for entry in entries.flatten() {
                 if let Some(name) = entry.file_name().to_str() {
-                    if name.ends_with(".json") {
-                        let index_name = name.trim_end_matches(".json").to_string();
+                    if let Some(index_name) = name.strip_suffix(".json") {
+                        let index_name = index_name.to_string();
                         if !indexes.contains(&index_name) {
                             if self.load_index(&index_name).is_ok() {
                                 indexes.push(index_name);
@@ -144,7 +391,12 @@ impl HashIndex {
         indexes
     }
 
+    /// Whether `index_name` loaded cleanly and matches its `.hash` file. Indexes saved in an
+    /// older format (see `outdated_indexes`) always fail.
     pub fn verify_index_integrity(&self, index_name: &str) -> bool {
+        if self.outdated.contains(index_name) {
+            return false;
+        }
         if let Some(index) = self.indexes.get(index_name) {
             let index_file = self.index_dir.join(format!("{}.json", index_name));
             let hash_file = self.hash_dir.join(format!("{}.hash", index_name));
@@ -161,22 +413,46 @@ impl HashIndex {
         false
     }
 
-    pub fn create_data_hash(&self, data: &HashMap<String, Value>) -> String {
-        let mut hasher = Sha256::new();
-        let mut keys: Vec<_> = data.keys().collect();
-        keys.sort();
-
-        for key in keys {
-            hasher.update(key.as_bytes());
-            if let Ok(value_bytes) = serde_json::to_vec(&data[key]) {
-                hasher.update(&value_bytes);
-            }
+    /// Names of loaded indexes whose `.hash` file no longer matches their contents.
+    /// Outdated indexes are listed by `outdated_indexes` instead.
+    pub fn corrupted_indexes(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.indexes.keys()
+            .filter(|name| !self.outdated.contains(*name) && !self.verify_index_integrity(name))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Names of indexes whose file has no format version or an older one than
+    /// `INDEX_FORMAT_VERSION`. They load empty; `repair_index` rebuilds them.
+    pub fn outdated_indexes(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.outdated.iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Rebuilds `index_name` from `storage` if it fails the integrity check and rewrites
+    /// both its `.json` and `.hash` files. Returns whether a repair was needed.
+    pub fn repair_index(&mut self, index_name: &str, storage: &HashMap<String, Value>) -> Result<bool> {
+        if self.verify_index_integrity(index_name) {
+            return Ok(false);
         }
+        let index = self.indexes.entry(index_name.to_string()).or_default();
+        index.clear();
+        for (key, value) in storage {
+            index.entry(hash_value(value)).or_default().push(key.clone());
+        }
+        self.outdated.remove(index_name);
+        self.save_index(index_name, true)?;
+        Ok(true)
+    }
 
-        format!("{:x}", hasher.finalize())
+    pub fn create_data_hash(&self, data: &HashMap<String, Value>) -> String {
+        calculate_data_hash(data)
     }
 
-    pub fn save_data_hash(&self, filename: &str, hash: &str) -> io::Result<()> {
+    pub fn save_data_hash(&self, filename: &str, hash: &str) -> Result<()> {
         let hash_file = self.hash_dir.join(format!("{}.hash", filename));
         fs::write(hash_file, hash)?;
         Ok(())
@@ -194,16 +470,19 @@ impl HashIndex {
     }
 
     fn calculate_index_hash(&self, index: &HashMap<u64, Vec<String>>) -> String {
-        let json_data = serde_json::to_string(index).unwrap_or_default();
+        let json_data = serde_json::to_string(&canonical_index(index)).unwrap_or_default();
         calculate_sha256(&json_data)
     }
 
-    fn save_index(&self, index_name: &str) -> io::Result<()> {
+    /// Writes an index and its hash. With `keep_previous` the file being replaced is kept as
+    /// a backup first; whole-index rewrites ask for that, while adding or removing one key
+    /// does not, so single changes do not copy the whole file.
+    fn save_index(&self, index_name: &str, keep_previous: bool) -> Result<()> {
         if let Some(index) = self.indexes.get(index_name) {
             let index_file = self.index_dir.join(format!("{}.json", index_name));
             let hash_file = self.hash_dir.join(format!("{}.hash", index_name));
-            let json_data = serde_json::to_string_pretty(index)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let file = IndexFile { format: INDEX_FORMAT_VERSION, buckets: canonical_index(index) };
+            let json_data = serde_json::to_string_pretty(&file)?;
             
             let temp_file = index_file.with_extension("tmp");
             
@@ -213,6 +492,10 @@ impl HashIndex {
                 writer.write_all(json_data.as_bytes())?;
                 writer.flush()?;
             }
+
+            if keep_previous && self.backups_kept > 0 && index_file.exists() {
+                self.backup_index(index_name, &index_file, &hash_file)?;
+            }
             
             fs::rename(&temp_file, &index_file).map_err(|e| {
                 let _ = fs::remove_file(&temp_file);
@@ -225,103 +508,243 @@ impl HashIndex {
         Ok(())
     }
 
-    fn load_index(&mut self, index_name: &str) -> io::Result<()> {
-        let index_file = self.index_dir.join(format!("{}.json", index_name));
-        
-        if !index_file.exists() {
-            return Ok(());
+    /// Keeps the current index file as `<name>.backup.<n>` (with its hash as
+    /// `<name>.backup.<n>.hash`) before it is replaced, then drops versions past the limit.
+    /// Sequence numbers rather than timestamps, so several saves within a second each
+    /// keep their own version.
+    fn backup_index(&self, index_name: &str, index_file: &Path, hash_file: &Path) -> Result<()> {
+        let next = self.index_backup_files(index_name)?.first().map_or(1, |(n, _)| n + 1);
+        let backup_name = format!("{}.backup.{}", index_name, next);
+        let backup_file = self.index_dir.join(&backup_name);
+        // The index file is renamed over, never written in place, so a hard link keeps the
+        // old version without copying it.
+        if fs::hard_link(index_file, &backup_file).is_err() {
+            fs::copy(index_file, &backup_file)?;
+        }
+        if hash_file.exists() {
+            let _ = fs::copy(hash_file, self.hash_dir.join(format!("{}.hash", backup_name)));
         }
+        self.prune_index_backups(index_name, self.backups_kept)?;
+        Ok(())
+    }
 
-        let file = File::open(&index_file)?;
-        let mut reader = BufReader::new(file);
-        let mut content = String::new();
-        
-        for line_result in reader.lines() {
-            let line = line_result?;
-            content.push_str(&line);
-            content.push('\n');
+    /// Backups of an index as `(sequence number, path)`, newest first.
+    fn index_backup_files(&self, index_name: &str) -> Result<Vec<(u64, PathBuf)>> {
+        let prefix = format!("{}.backup.", index_name);
+        let mut backups: Vec<(u64, PathBuf)> = match fs::read_dir(&self.index_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    let n = name.strip_prefix(&prefix)?.parse().ok()?;
+                    Some((n, entry.path()))
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        backups.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+        Ok(backups)
+    }
+
+    /// Deletes all but the newest `keep` backups of an index. Returns how many went.
+    fn prune_index_backups(&self, index_name: &str, keep: usize) -> Result<usize> {
+        let stale: Vec<(u64, PathBuf)> = self.index_backup_files(index_name)?.into_iter().skip(keep).collect();
+        for (n, path) in &stale {
+            fs::remove_file(path)?;
+            let _ = fs::remove_file(self.hash_dir.join(format!("{}.backup.{}.hash", index_name, n)));
         }
+        Ok(stale.len())
+    }
 
-        if content.trim().is_empty() {
-            self.indexes.insert(index_name.to_string(), HashMap::new());
-            return Ok(());
+    /// Saved versions of an index, newest first.
+    pub fn list_index_backups(&self, index_name: &str) -> Vec<PathBuf> {
+        self.index_backup_files(index_name)
+            .map(|backups| backups.into_iter().map(|(_, path)| path).collect())
+            .unwrap_or_default()
+    }
+
+    /// Replaces an index with its newest backup that parses, is in the current format and
+    /// matches the hash saved with it, and saves it (which keeps the version being replaced
+    /// as a backup too). Returns the backup used.
+    pub fn restore_index_backup(&mut self, index_name: &str) -> Result<PathBuf> {
+        let mut rejected = Vec::new();
+        for (n, path) in self.index_backup_files(index_name)? {
+            let index = match read_index_file(&path) {
+                Ok(Some(index)) => index,
+                Ok(None) => {
+                    rejected.push(format!("{}: saved in an older index format", path.display()));
+                    continue;
+                }
+                Err(e) => {
+                    rejected.push(format!("{}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            let hash_file = self.hash_dir.join(format!("{}.backup.{}.hash", index_name, n));
+            match fs::read_to_string(&hash_file) {
+                Ok(hash) if hash.trim() == self.calculate_index_hash(&index) => {
+                    self.indexes.insert(index_name.to_string(), index);
+                    self.outdated.remove(index_name);
+                    self.save_index(index_name, true)?;
+                    return Ok(path);
+                }
+                Ok(_) => rejected.push(format!("{}: integrity hash mismatch", path.display())),
+                Err(_) => rejected.push(format!("{}: no integrity hash", path.display())),
+            }
         }
+        let reasons = if rejected.is_empty() { String::new() } else { format!(" ({})", rejected.join("; ")) };
+        Err(RedruError::NotFound(format!("No valid backup of index '{}'{}", index_name, reasons)))
+    }
 
-        let index_data: HashMap<u64, Vec<String>> = serde_json::from_str(&content)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fn load_index(&mut self, index_name: &str) -> Result<()> {
+        let index_file = self.index_dir.join(format!("{}.json", index_name));
+        
+        if !index_file.exists() {
+            return Ok(());
+        }
 
-        self.indexes.insert(index_name.to_string(), index_data);
+        match read_index_file(&index_file)? {
+            Some(index_data) => {
+                self.indexes.insert(index_name.to_string(), index_data);
+            }
+            None => {
+                self.indexes.insert(index_name.to_string(), HashMap::new());
+                self.outdated.insert(index_name.to_string());
+            }
+        }
         Ok(())
     }
 
-    pub fn load_all_indexes(&mut self) -> io::Result<()> {
+    /// Loads every index file. Returns the indexes that could not be parsed, with why; they
+    /// are kept as empty placeholders so the integrity check flags them for repair.
+    pub fn load_all_indexes(&mut self) -> Result<Vec<(String, RedruError)>> {
+        let mut unreadable = Vec::new();
         if !self.index_dir.exists() {
-            return Ok(());
+            return Ok(unreadable);
         }
 
         let entries = fs::read_dir(&self.index_dir)?;
         for entry in entries {
             let entry = entry?;
             if let Some(name) = entry.file_name().to_str() {
-                if name.ends_with(".json") {
-                    let index_name = name.trim_end_matches(".json");
-                    self.load_index(index_name)?;
+                if let Some(index_name) = name.strip_suffix(".json") {
+                    match self.load_index(index_name) {
+                        Ok(()) => {}
+                        Err(e @ RedruError::Serialization(_)) => {
+                            self.indexes.insert(index_name.to_string(), HashMap::new());
+                            unreadable.push((index_name.to_string(), e));
+                        }
+                        Err(e) => return Err(e),
+                    }
                 }
             }
         }
-        Ok(())
+        Ok(unreadable)
     }
 
     /// Find keys where a field contains a substring (case-insensitive, for String fields)
     pub fn find_partial(&self, index_name: &str, field: &str, substring: &str, storage: &HashMap<String, Value>) -> Vec<String> {
-        let mut results = Vec::new();
-        let substring = substring.to_lowercase();
-        for (key, value) in storage {
-            if let Some(field_value) = crate::hash_index::extract_field_value(value, field) {
-                if let Some(s) = field_value.as_str() {
-                    if s.to_lowercase().contains(&substring) {
-                        results.push(key.clone());
-                    }
-                }
-            }
+        self.find_partial_matching(index_name, field, substring, MatchMode::Contains, storage)
+    }
+
+    /// Like `find_partial`, but compares String fields using `mode`.
+    pub fn find_partial_matching(&self, index_name: &str, field: &str, query: &str, mode: MatchMode, storage: &HashMap<String, Value>) -> Vec<String> {
+        let matches = |value: &Value| {
+            extract_field_value(value, field).and_then(Value::as_str).is_some_and(|s| mode.matches_str(s, query))
+        };
+        match self.field_index(index_name, field) {
+            Some(field_index) => verify_candidates(field_index.text_candidates(query, mode), storage, matches),
+            None => scan(storage, matches),
         }
+    }
+
+    /// Find keys whose `field` equals `raw`, typed to match the field's stored values:
+    /// an example value decides whether `raw` is read as a number, boolean, array or object.
+    /// Results are sorted.
+    pub fn find_field(&self, index_name: &str, field: &str, raw: &str, storage: &HashMap<String, Value>) -> Vec<String> {
+        if !self.index_exists(index_name) {
+            return Vec::new();
+        }
+        let field_index = self.field_index(index_name, field);
+        let example = match field_index {
+            Some(field_index) => field_index.by_text.values().flatten()
+                .filter_map(|key| storage.get(key).and_then(|value| extract_field_value(value, field)).map(|v| (key, v)))
+                .min_by(|a, b| a.0.cmp(b.0)),
+            None => storage.iter()
+                .filter_map(|(key, value)| extract_field_value(value, field).map(|v| (key, v)))
+                .min_by(|a, b| a.0.cmp(b.0)),
+        };
+        let expected = match example {
+            Some((_, example)) => coerce_to_example(raw, example),
+            None => return Vec::new(),
+        };
+        let matches = |value: &Value| extract_field_value(value, field).is_some_and(|v| values_match(v, &expected));
+        let mut results = match field_index {
+            Some(field_index) => verify_candidates(field_index.equal_candidates(&expected), storage, matches),
+            None => scan(storage, matches),
+        };
+        results.sort();
         results
     }
 
-    /// Find keys where a numeric field is within a range (inclusive)
+    /// Find keys whose `field` is an array holding `element`. The element is typed like each
+    /// item it is compared with, as `find_field` types against an example value, so `3`
+    /// finds `[3]`, `[3.0]` and `["3"]` while `"3"` finds only `["3"]`. Always scans
+    /// storage; results are sorted.
+    pub fn find_array_contains(&self, field: &str, element: &str, storage: &HashMap<String, Value>) -> Vec<String> {
+        let as_number = coerce_to_example(element, &Value::from(0));
+        let as_bool = coerce_to_example(element, &Value::Bool(false));
+        let as_array = coerce_to_example(element, &Value::Array(Vec::new()));
+        let as_object = coerce_to_example(element, &Value::Object(serde_json::Map::new()));
+        let as_other = coerce_to_example(element, &Value::Null);
+        let typed_like = |item: &Value| match item {
+            Value::Number(_) => &as_number,
+            Value::Bool(_) => &as_bool,
+            Value::Array(_) => &as_array,
+            Value::Object(_) => &as_object,
+            Value::String(_) | Value::Null => &as_other,
+        };
+        let mut results = scan(storage, |value| {
+            extract_field_value(value, field)
+                .and_then(Value::as_array)
+                .is_some_and(|items| items.iter().any(|item| values_match(item, typed_like(item))))
+        });
+        results.sort();
+        results
+    }
+
+    /// Find keys where a numeric field is within a range (inclusive).
+    /// Uses the field's sorted lookup when `index_field` built one, otherwise scans storage.
     pub fn find_range(&self, index_name: &str, field: &str, min: f64, max: f64, storage: &HashMap<String, Value>) -> Vec<String> {
-        let mut results = Vec::new();
-        for (key, value) in storage {
-            if let Some(field_value) = crate::hash_index::extract_field_value(value, field) {
-                if let Some(n) = field_value.as_f64() {
-                    if n >= min && n <= max {
-                        results.push(key.clone());
-                    }
-                }
-            }
+        let matches = |value: &Value| {
+            extract_field_value(value, field).and_then(Value::as_f64).is_some_and(|n| n >= min && n <= max)
+        };
+        match self.field_index(index_name, field) {
+            Some(field_index) => verify_candidates(field_index.range(min, max), storage, matches),
+            None => scan(storage, matches),
         }
-        results
     }
 
-    /// Find keys where multiple fields match specified values (all must match)
+    /// Find keys where multiple fields match specified values (all must match).
+    /// Values are compared with `values_match`, so numbers match regardless of representation.
+    /// Candidates come from the first field with an `index_field` lookup; with none, storage is scanned.
     pub fn find_multi(&self, index_name: &str, field_values: &[(String, Value)], storage: &HashMap<String, Value>) -> Vec<String> {
-        let mut results = Vec::new();
-        'outer: for (key, value) in storage {
-            for (field, expected) in field_values {
-                if let Some(field_value) = crate::hash_index::extract_field_value(value, field) {
-                    if field_value != expected {
-                        continue 'outer;
-                    }
-                } else {
-                    continue 'outer;
-                }
-            }
-            results.push(key.clone());
+        let matches = |value: &Value| {
+            field_values.iter().all(|(field, expected)| {
+                extract_field_value(value, field).is_some_and(|field_value| values_match(field_value, expected))
+            })
+        };
+        let indexed = field_values.iter()
+            .find_map(|(field, expected)| self.field_index(index_name, field).map(|index| (index, expected)));
+        match indexed {
+            Some((field_index, expected)) => verify_candidates(field_index.equal_candidates(expected), storage, matches),
+            None => scan(storage, matches),
         }
-        results
     }
 
-    /// List all unique values for a given field in an index
+    /// List all unique values for a given field in an index.
+    /// Always scans storage: field lookups keep keys, not the original values.
     pub fn list_field_values(&self, index_name: &str, field: &str, storage: &HashMap<String, Value>) -> Vec<Value> {
         let mut values = Vec::new();
         let mut seen = std::collections::HashSet::new();
@@ -334,8 +757,181 @@ impl HashIndex {
         }
         values
     }
+
+    /// Counts the JSON types a field holds across `storage`, with the numeric range and the
+    /// number of distinct values, to help choose between a range lookup and a text search.
+    /// Always scans storage.
+    pub fn field_profile(&self, field: &str, storage: &HashMap<String, Value>) -> FieldProfile {
+        let mut profile = FieldProfile::default();
+        let mut distinct = std::collections::HashSet::new();
+        let mut sum = 0.0;
+        for value in storage.values() {
+            let Some(field_value) = extract_field_value(value, field) else {
+                profile.missing += 1;
+                continue;
+            };
+            distinct.insert(field_value);
+            match field_value {
+                Value::Null => profile.nulls += 1,
+                Value::Bool(_) => profile.booleans += 1,
+                Value::Number(n) => {
+                    profile.numbers += 1;
+                    if let Some(n) = n.as_f64() {
+                        sum += n;
+                        profile.min = Some(profile.min.map_or(n, |min| min.min(n)));
+                        profile.max = Some(profile.max.map_or(n, |max| max.max(n)));
+                    }
+                }
+                Value::String(_) => profile.strings += 1,
+                Value::Array(_) => profile.arrays += 1,
+                Value::Object(_) => profile.objects += 1,
+            }
+        }
+        if profile.numbers > 0 {
+            profile.mean = Some(sum / profile.numbers as f64);
+        }
+        profile.distinct = distinct.len();
+        profile
+    }
+}
+
+/// What one field looks like across a dataset; see `HashIndex::field_profile`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FieldProfile {
+    /// Records without the field.
+    pub missing: usize,
+    pub nulls: usize,
+    pub booleans: usize,
+    pub numbers: usize,
+    pub strings: usize,
+    pub arrays: usize,
+    pub objects: usize,
+    /// Smallest, largest and mean numeric value; `None` without numbers.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// Distinct values compared as JSON, so `30` and `30.0` count twice, as in `values`.
+    pub distinct: usize,
 }
 
+impl FieldProfile {
+    /// Records that have the field.
+    pub fn present(&self) -> usize {
+        self.nulls + self.booleans + self.numbers + self.strings + self.arrays + self.objects
+    }
+
+    /// `(type name, count)` for every type that occurs, most common first.
+    pub fn type_counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> = [
+            ("number", self.numbers),
+            ("string", self.strings),
+            ("boolean", self.booleans),
+            ("null", self.nulls),
+            ("array", self.arrays),
+            ("object", self.objects),
+        ]
+        .into_iter()
+        .filter(|&(_, count)| count > 0)
+        .collect();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts
+    }
+}
+
+/// Turns a query typed in the shell into a JSON value:
+/// `true`/`false` become booleans, `null` becomes null, anything that parses as a
+/// number becomes a number, and a double-quoted word (`"30"`) becomes the string
+/// inside the quotes. Everything else is kept as a plain string.
+pub fn parse_query_value(text: &str) -> Value {
+    match text {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        "null" => return Value::Null,
+        _ => {}
+    }
+    if let Some(inner) = text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        return Value::String(inner.to_string());
+    }
+    if let Ok(Value::Number(n)) = serde_json::from_str::<Value>(text) {
+        return Value::Number(n);
+    }
+    Value::String(text.to_string())
+}
+
+/// Types `raw` after a stored `example` value: numbers, booleans, arrays and objects are
+/// parsed as such when `raw` allows it. A double-quoted `raw` always stays a string, and
+/// anything else falls back to `parse_query_value`.
+pub fn coerce_to_example(raw: &str, example: &Value) -> Value {
+    if let Some(inner) = raw.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        return Value::String(inner.to_string());
+    }
+    let parsed = match example {
+        Value::Number(_) => serde_json::from_str::<Value>(raw).ok().filter(Value::is_number)
+            .or_else(|| raw.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(Value::Number)),
+        Value::Bool(_) => match raw.to_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        Value::Array(_) | Value::Object(_) => serde_json::from_str::<Value>(raw).ok()
+            .filter(|v| v.is_array() == example.is_array() && v.is_object() == example.is_object()),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| parse_query_value(raw))
+}
+
+/// Compares a stored value against a query value. Numbers compare by value as
+/// `NumberKey` describes (`30` matches `30.0`), and a non-string query also matches a
+/// stored string with the same text, so `30` finds both `30` and `"30"`; such a string is
+/// read as a JSON number, so long integers in it stay exact. A string query only matches
+/// strings.
+pub fn values_match(stored: &Value, expected: &Value) -> bool {
+    match (stored, expected) {
+        (Value::Number(a), Value::Number(b)) => NumberKey::of(a) == NumberKey::of(b),
+        (Value::String(s), Value::Number(n)) => match s.parse::<Number>() {
+            Ok(parsed) => NumberKey::of(&parsed) == NumberKey::of(n),
+            Err(_) => s.parse::<f64>().ok() == n.as_f64(),
+        },
+        (Value::String(s), Value::Bool(b)) => s == if *b { "true" } else { "false" },
+        (Value::String(s), Value::Null) => s == "null",
+        _ => stored == expected,
+    }
+}
+
+/// A JSON number as `values_match` and `hash_value` see it. Integers stay exact, and a
+/// float holding a whole number in the `i64`/`u64` range counts as that integer, so `30`,
+/// `30.0` and `-0.0`/`0` are equal while `9007199254740993` and `9007199254740992`, which
+/// the same `f64` stands for, are not. Other floats compare by their bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberKey {
+    Int(i64),
+    /// Integers above `i64::MAX`.
+    UInt(u64),
+    Float(u64),
+}
+
+impl NumberKey {
+    fn of(n: &Number) -> Self {
+        if let Some(i) = n.as_i64() {
+            return NumberKey::Int(i);
+        }
+        if let Some(u) = n.as_u64() {
+            return NumberKey::UInt(u);
+        }
+        const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+        let f = n.as_f64().unwrap_or(f64::NAN);
+        if f.fract() != 0.0 || !(-TWO_POW_63..2.0 * TWO_POW_63).contains(&f) {
+            NumberKey::Float(f.to_bits())
+        } else if f < TWO_POW_63 {
+            NumberKey::Int(f as i64)
+        } else {
+            NumberKey::UInt(f as u64)
+        }
+    }
+}
+
+/// Hash of a JSON value for the whole-value index and change tracking. Numbers hash by
+/// their `NumberKey`, so `{"age": 30}` and `{"age": 30.0}` land in the same bucket.
 pub fn hash_value(value: &Value) -> u64 {
     let mut hasher = DefaultHasher::new();
     hash_json_value(value, &mut hasher);
@@ -351,12 +947,10 @@ fn hash_json_value(value: &Value, hasher: &mut DefaultHasher) {
         }
         Value::Number(n) => {
             2u8.hash(hasher);
-            if let Some(i) = n.as_i64() {
-                i.hash(hasher);
-            } else if let Some(u) = n.as_u64() {
-                u.hash(hasher);
-            } else if let Some(f) = n.as_f64() {
-                f.to_bits().hash(hasher);
+            match NumberKey::of(n) {
+                NumberKey::Int(i) => i.hash(hasher),
+                NumberKey::UInt(u) => u.hash(hasher),
+                NumberKey::Float(bits) => bits.hash(hasher),
             }
         }
         Value::String(s) => {
@@ -397,7 +991,8 @@ pub fn hash_field_value(value: &Value, field_path: &str) -> Option<u64> {
     }
 }
 
-fn extract_field_value<'a>(value: &'a Value, field_path: &str) -> Option<&'a Value> {
+/// Resolves a dotted path such as `address.city` or `items.0.id`; numeric segments index arrays.
+pub fn extract_field_value<'a>(value: &'a Value, field_path: &str) -> Option<&'a Value> {
     let parts: Vec<&str> = field_path.split('.').collect();
     let mut current = value;
     
@@ -420,15 +1015,126 @@ fn extract_field_value<'a>(value: &'a Value, field_path: &str) -> Option<&'a Val
     Some(current)
 }
 
+/// `extract_field_value` for changing the value in place.
+pub fn extract_field_value_mut<'a>(value: &'a mut Value, field_path: &str) -> Option<&'a mut Value> {
+    let mut current = value;
+    for part in field_path.split('.') {
+        current = match current {
+            Value::Object(obj) => obj.get_mut(part)?,
+            Value::Array(arr) => arr.get_mut(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Reads an index file saved by `save_index`. `None` means it was written in an older
+/// format and must be rebuilt; an empty file is an empty index.
+fn read_index_file(path: &Path) -> Result<Option<HashMap<u64, Vec<String>>>> {
+    match read_json_file::<StoredIndex>(path)? {
+        Some(stored) => Ok(stored.current()),
+        None => Ok(Some(HashMap::new())),
+    }
+}
+
+/// Parses a JSON file straight from a buffered reader instead of reading it into a string
+/// first. Empty or whitespace-only files yield `None`.
+pub fn read_json_file<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    loop {
+        let (blank, available) = {
+            let buf = reader.fill_buf()?;
+            (buf.iter().take_while(|b| b.is_ascii_whitespace()).count(), buf.len())
+        };
+        if available == 0 {
+            return Ok(None);
+        }
+        if blank < available {
+            break;
+        }
+        reader.consume(blank);
+    }
+    serde_json::from_reader(reader).map(Some).map_err(|e| {
+        if e.is_io() {
+            RedruError::from(e)
+        } else {
+            RedruError::Serialization(format!("JSON parsing error: {}", e))
+        }
+    })
+}
+
+/// Texts a field value is filed under in a `FieldIndex`. Numbers use their `f64` form so
+/// `30` and `30.0` share an entry, and strings that parse as numbers are filed under that
+/// form too, matching how `values_match` compares them. The first text is the value's own.
+fn match_texts(value: &Value) -> Vec<String> {
+    let number_text = |n: f64| if n == 0.0 { "0".to_string() } else { n.to_string() };
+    match value {
+        Value::Number(n) => vec![n.as_f64().map(number_text).unwrap_or_else(|| n.to_string())],
+        Value::String(s) => {
+            let mut texts = vec![s.to_lowercase()];
+            if let Ok(n) = s.parse::<f64>()
+                && number_text(n) != texts[0]
+            {
+                texts.push(number_text(n));
+            }
+            texts
+        }
+        other => vec![other.to_string().to_lowercase()],
+    }
+}
+
+/// Looks up each candidate key and keeps those whose stored value satisfies `matches`.
+/// Results are sorted and deduplicated.
+fn verify_candidates<'a>(candidates: impl Iterator<Item = &'a String>, storage: &HashMap<String, Value>, matches: impl Fn(&Value) -> bool) -> Vec<String> {
+    let mut results: Vec<String> = candidates
+        .filter(|key| storage.get(*key).is_some_and(&matches))
+        .cloned()
+        .collect();
+    results.sort();
+    results.dedup();
+    results
+}
+
+fn scan(storage: &HashMap<String, Value>, matches: impl Fn(&Value) -> bool) -> Vec<String> {
+    storage.iter()
+        .filter(|(_, value)| matches(value))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Sorts hashes and the keys in each bucket so the same index always serializes to the
+/// same bytes. serde_json writes `u64` map keys as decimal strings, which round-trip exactly.
+fn canonical_index(index: &HashMap<u64, Vec<String>>) -> BTreeMap<u64, Vec<&String>> {
+    index.iter()
+        .map(|(hash, keys)| {
+            let mut keys: Vec<&String> = keys.iter().collect();
+            keys.sort();
+            (*hash, keys)
+        })
+        .collect()
+}
+
 pub fn calculate_sha256(data: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(data.as_bytes());
     format!("{:x}", hasher.finalize())
 }
 
+/// Hash of a database's records, taken in key order so the same data always hashes the
+/// same; serializing the map directly would follow its per-process iteration order.
 pub fn calculate_data_hash(data: &HashMap<String, Value>) -> String {
-    let json_data = serde_json::to_string(data).unwrap_or_default();
-    calculate_sha256(&json_data)
+    let mut hasher = Sha256::new();
+    let mut keys: Vec<_> = data.keys().collect();
+    keys.sort();
+
+    for key in keys {
+        hasher.update(key.as_bytes());
+        if let Ok(value_bytes) = serde_json::to_vec(&data[key]) {
+            hasher.update(&value_bytes);
+        }
+    }
+
+    format!("{:x}", hasher.finalize())
 }
 
 pub fn verify_data_hash(data: &HashMap<String, Value>, expected_hash: &str) -> bool {
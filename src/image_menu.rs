@@ -0,0 +1,667 @@
+//! Image mode: the menus and prompts in front of `redru::image_processor`.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use image::{DynamicImage, ImageFormat, Rgba};
+use image::codecs::png::FilterType as PngFilter;
+use redru::disk;
+use redru::display::icon;
+use redru::image_processor::{adjust_saturation, crop_image, describe_savings, lossless_extension, parse_hex_color,
+    parse_quality_map, rotate_image, BatchSummary, CompressOptions, CompressionMethod, CompressionResult, ImageFilter,
+    ImageProcessor, WatermarkPosition, DEFAULT_JPEG_QUALITY};
+use crate::prompt::confirm;
+
+/// Returns the images in the input directory, waiting for some to be added when there
+/// are none. Shows the absolute path, since the directory may have just been created.
+/// `None` when the user goes back to the menu instead.
+fn wait_for_images(processor: &ImageProcessor) -> io::Result<Option<Vec<fs::DirEntry>>> {
+    let dir = fs::canonicalize(processor.dir())?;
+    loop {
+        let files = processor.get_image_files()?;
+        if !files.is_empty() {
+            return Ok(Some(files));
+        }
+        let others = processor.other_files()?;
+        println!("No image files found in {}", dir.display());
+        if others > 0 {
+            println!("  ({} file(s) there are not images this tool can read.)", others);
+        }
+        print!("Add images and press Enter, type 'open' to show the folder, or 'q' to go back: ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(None);
+        }
+        match input.trim().to_lowercase().as_str() {
+            "q" | "quit" | "back" => return Ok(None),
+            "open" => {
+                if let Err(e) = open_in_file_manager(&dir) {
+                    println!("{} Could not open {}: {}", icon("❌"), dir.display(), e);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Shows `dir` in the platform's file manager without waiting for it to close.
+fn open_in_file_manager(dir: &Path) -> io::Result<()> {
+    let opener = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    std::process::Command::new(opener)
+        .arg(dir)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("'{}' is not installed", opener)),
+            _ => e,
+        })?;
+    Ok(())
+}
+
+pub fn run_image_processing() -> io::Result<()> {
+    let mut processor = ImageProcessor::new()?;
+    let Some(files) = wait_for_images(&processor)? else {
+        return Ok(());
+    };
+    
+    println!("Found {} image files:", files.len());
+    for (i, file) in files.iter().enumerate() {
+        let file_name = file.file_name();
+        let filename = file_name.to_string_lossy();
+        println!("  {}. {}", i + 1, filename);
+    }
+    
+    println!("\nImage Processing Options:");
+    println!("  1. Compress all images");
+    println!("  2. Resize all images");
+    println!("  3. Convert format");
+    println!("  4. Extract metadata");
+    println!("  5. Batch process");
+    println!("  6. Watermark images");
+    println!("  7. Rotate images");
+    println!("  8. Flip images");
+    println!("  9. Crop images");
+    println!("  10. Color filters");
+    println!("  11. Estimate compression (dry run)");
+    print!("Select option (1-11): ");
+    std::io::stdout().flush()?;
+    let mut opt = String::new();
+    std::io::stdin().read_line(&mut opt)?;
+    match opt.trim() {
+        "1" => {
+            compress_images(&processor, &files)?;
+        }
+        "2" => resize_images(&processor, &files)?,
+        "3" => convert_format(&processor, &files)?,
+        "4" => processor.extract_metadata(&files)?,
+        "5" => batch_process(&processor, &files)?,
+        "6" => watermark_images(&processor, &files)?,
+        "7" => rotate_images(&processor, &files)?,
+        "8" => flip_images(&processor, &files)?,
+        "9" => crop_images(&processor, &files)?,
+        "10" => filter_images(&processor, &files)?,
+        "11" => {
+            processor.set_dry_run(true);
+            compress_images(&processor, &files)?;
+            processor.set_dry_run(false);
+        }
+        _ => println!("Invalid option."),
+    }
+    Ok(())
+}
+
+fn compress_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Compression methods:");
+    println!("  1. JPEG Quality-based compression");
+    println!("  2. PNG Optimization");
+    println!("  3. WebP Conversion (lossless)");
+    println!("  4. Resize-based compression");
+    println!("  5. Auto-compress (best method per image)");
+    println!("  6. Progressive JPEG compression");
+    println!("  7. Lossless compression");
+    println!("  8. Adaptive compression");
+    println!("  9. Advanced filtering compression");
+    println!("  10. Multi-pass optimization");
+    print!("Select method (1-10): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    
+    let summary = match input.trim() {
+        "1" => compress_jpeg_quality(processor, files)?,
+        "2" => compress_png_optimization(processor, files)?,
+        "3" => compress_webp_conversion(processor, files)?,
+        "4" => compress_resize_based(processor, files)?,
+        "5" => compress_auto(processor, files)?,
+        "6" => compress_progressive_jpeg(processor, files)?,
+        "7" => compress_lossless(processor, files)?,
+        "8" => compress_adaptive(processor, files)?,
+        "9" => compress_advanced_filtering(processor, files)?,
+        "10" => compress_multi_pass(processor, files)?,
+        _ => {
+            println!("Invalid option. Using auto-compress.");
+            compress_auto(processor, files)?
+        }
+    };
+
+    if processor.is_dry_run() {
+        print_estimate_table(&summary.compressed);
+    }
+    Ok(summary)
+}
+
+/// Warns when the outputs may not fit (see `ImageProcessor::check_output_space`) and asks
+/// whether to go on; returns `false` to cancel. Without a terminal the answer is no, so
+/// piped runs stop instead of filling the disk.
+fn confirm_output_space(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<bool> {
+    if let Err(e) = processor.check_output_space(files) {
+        println!("{}  {}", icon("⚠️"), e);
+        if !confirm("Outputs may not fit. Continue anyway?")? {
+            println!("Cancelled.");
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// `ImageProcessor::compress_files` once the outputs are known to fit or the user said to
+/// go on. Dry runs write nothing, so they are not asked.
+fn compress_with(processor: &ImageProcessor, files: &[fs::DirEntry], options: &CompressOptions) -> io::Result<BatchSummary> {
+    if !processor.is_dry_run() && !confirm_output_space(processor, files)? {
+        return Ok(BatchSummary::cancelled());
+    }
+    processor.compress_files(files, options)
+}
+
+fn compress_jpeg_quality(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    let quality = prompt_quality("Enter JPEG quality (1-100, lower = smaller file): ", 85)?;
+    println!("Quality per image:");
+    println!("  Enter   use {} for every image", quality);
+    println!("  ask     choose for each image");
+    println!("  <file>  read a JSON file mapping file names to quality, e.g. {{\"logo.png\": 95}}");
+    print!("Choice: ");
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let quality_by_file = match input.trim() {
+        "" => HashMap::new(),
+        "ask" => ask_quality_per_file(files, quality)?,
+        path => match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse_quality_map(&text)) {
+            Ok(mut map) => {
+                let names: Vec<OsString> = files.iter().map(|f| f.file_name()).collect();
+                map.retain(|name, _| {
+                    let known = names.contains(name);
+                    if !known {
+                        println!("{}  '{}' in {} is not in this batch", icon("⚠️"), name.to_string_lossy(), path);
+                    }
+                    known
+                });
+                map
+            }
+            Err(e) => {
+                println!("{} Could not use '{}': {}", icon("❌"), path, e);
+                return Ok(BatchSummary::cancelled());
+            }
+        },
+    };
+    if quality_by_file.is_empty() {
+        println!("Compressing images with JPEG quality {}...", quality);
+    } else {
+        println!("Compressing images with JPEG quality {} ({} set per image)...", quality, quality_by_file.len());
+    }
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Jpeg, quality, quality_by_file, ..Default::default() })
+}
+
+fn compress_png_optimization(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    print!("Enter PNG compression level (1-9, higher = smaller but slower) [9]: ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let png_level: u8 = input.trim().parse().unwrap_or(9).clamp(1, 9);
+
+    print!("Enter filter (adaptive, none, sub, up, avg, paeth) [adaptive]: ");
+    std::io::stdout().flush()?;
+    input.clear();
+    std::io::stdin().read_line(&mut input)?;
+    let png_filter = match input.trim().to_lowercase().as_str() {
+        "none" => PngFilter::NoFilter,
+        "sub" => PngFilter::Sub,
+        "up" => PngFilter::Up,
+        "avg" => PngFilter::Avg,
+        "paeth" => PngFilter::Paeth,
+        _ => PngFilter::Adaptive,
+    };
+
+    println!("Optimizing PNG images (level {}, {:?} filter)...", png_level, png_filter);
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Png, png_level, png_filter, ..Default::default() })
+}
+
+fn compress_webp_conversion(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Converting to lossless WebP...");
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::WebP, ..Default::default() })
+}
+
+fn compress_resize_based(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    print!("Enter max width (0 to keep original): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let max_width: u32 = input.trim().parse().unwrap_or(0);
+    
+    print!("Enter max height (0 to keep original): ");
+    std::io::stdout().flush()?;
+    input.clear();
+    std::io::stdin().read_line(&mut input)?;
+    let max_height: u32 = input.trim().parse().unwrap_or(0);
+    
+    println!("Resize-based compression...");
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Resize, max_width, max_height, ..Default::default() })
+}
+
+fn compress_auto(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Auto-compressing images (best method per image)...");
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Auto, ..Default::default() })
+}
+
+fn compress_progressive_jpeg(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    let quality = prompt_quality("Enter JPEG quality (1-100): ", 85)?;
+    println!("Compressing images with Progressive JPEG quality {}...", quality);
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::ProgressiveJpeg, quality, ..Default::default() })
+}
+
+fn compress_lossless(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Lossless compression options:");
+    println!("  1. PNG lossless");
+    println!("  2. TIFF lossless");
+    println!("  3. WebP lossless");
+    print!("Select format (1-3): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    
+    let lossless_format = match input.trim() {
+        "2" => ImageFormat::Tiff,
+        "3" => ImageFormat::WebP,
+        _ => ImageFormat::Png,
+    };
+    
+    println!("Compressing images with lossless {}...", lossless_extension(lossless_format).to_uppercase());
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Lossless, lossless_format, ..Default::default() })
+}
+
+fn compress_adaptive(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Adaptive compression analyzing image characteristics...");
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Adaptive, ..Default::default() })
+}
+
+fn compress_advanced_filtering(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Advanced filtering options:");
+    println!("  1. Gaussian blur + compression");
+    println!("  2. Sharpen + compression");
+    println!("  3. Noise reduction + compression");
+    println!("  4. Edge enhancement + compression");
+    print!("Select filter (1-4): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    
+    let filter = match input.trim() {
+        "2" => ImageFilter::Sharpen,
+        "3" => ImageFilter::NoiseReduction,
+        "4" => ImageFilter::EdgeEnhancement,
+        _ => ImageFilter::Gaussian,
+    };
+    
+    println!("Applying {:?} filter and compressing...", filter);
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::Filtered, filter, ..Default::default() })
+}
+
+fn compress_multi_pass(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<BatchSummary> {
+    println!("Multi-pass optimization (resize + filter + compress)...");
+    compress_with(processor, files, &CompressOptions { method: CompressionMethod::MultiPass, ..Default::default() })
+}
+
+fn resize_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    print!("Enter new width: ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let width: u32 = input.trim().parse().unwrap_or(800);
+    print!("Enter new height: ");
+    std::io::stdout().flush()?;
+    input.clear();
+    std::io::stdin().read_line(&mut input)?;
+    let height: u32 = input.trim().parse().unwrap_or(600);
+    print!("Output format (jpg, png, webp; Enter keeps each file's format): ");
+    std::io::stdout().flush()?;
+    input.clear();
+    std::io::stdin().read_line(&mut input)?;
+    let target = match input.trim() {
+        "" => None,
+        extension => match ImageFormat::from_extension(extension) {
+            Some(format) => Some(format),
+            None => {
+                println!("Unknown format '{}'; keeping each file's format.", extension);
+                None
+            }
+        },
+    };
+    let upscale = confirm(&format!("Also enlarge images smaller than {}x{}?", width, height))?;
+    if !confirm_output_space(processor, files)? {
+        return Ok(());
+    }
+    println!("Resizing images to {}x{}...", width, height);
+    processor.resize_files(files, width, height, target, upscale)?;
+    Ok(())
+}
+
+fn convert_format(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    println!("Available formats: jpg, png, webp");
+    print!("Enter target format: ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let format = input.trim().to_lowercase();
+    if !["jpg", "png", "webp"].contains(&format.as_str()) {
+        println!("Unsupported format.");
+        return Ok(());
+    }
+    let quality = match format.as_str() {
+        "jpg" => prompt_quality(&format!("Enter JPEG quality (1-100, default {}): ", DEFAULT_JPEG_QUALITY), DEFAULT_JPEG_QUALITY)?,
+        _ => {
+            println!("{} keeps every pixel and any transparency, so there is no quality to choose.", format.to_uppercase());
+            DEFAULT_JPEG_QUALITY
+        }
+    };
+    if !confirm_output_space(processor, files)? {
+        return Ok(());
+    }
+    println!("Converting to {}...", format);
+    processor.convert_files(files, &format, quality)?;
+    Ok(())
+}
+
+fn batch_process(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    println!("Batch processing options:");
+    println!("  1. Compress + Resize");
+    println!("  2. Convert + Compress");
+    println!("  3. All operations");
+    print!("Select option (1-3): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    match input.trim() {
+        "1" => {
+            compress_auto(processor, files)?;
+            resize_images(processor, files)?;
+        }
+        "2" => {
+            convert_format(processor, files)?;
+            compress_auto(processor, files)?;
+        }
+        "3" => {
+            compress_auto(processor, files)?;
+            resize_images(processor, files)?;
+            convert_format(processor, files)?;
+            processor.extract_metadata(files)?;
+        }
+        _ => println!("Invalid option."),
+    }
+    Ok(())
+}
+
+fn watermark_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    println!("Watermark type:");
+    println!("  1. Image (PNG with transparency)");
+    println!("  2. Text");
+    print!("Select type (1-2): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let kind = input.trim().to_string();
+
+    println!("Position: 1. Top-left  2. Top-right  3. Bottom-left  4. Bottom-right  5. Center");
+    print!("Select position (1-5): ");
+    std::io::stdout().flush()?;
+    input.clear();
+    std::io::stdin().read_line(&mut input)?;
+    let position = match input.trim() {
+        "1" => WatermarkPosition::TopLeft,
+        "2" => WatermarkPosition::TopRight,
+        "3" => WatermarkPosition::BottomLeft,
+        "5" => WatermarkPosition::Center,
+        _ => WatermarkPosition::BottomRight,
+    };
+
+    print!("Enter opacity (0-100): ");
+    std::io::stdout().flush()?;
+    input.clear();
+    std::io::stdin().read_line(&mut input)?;
+    let opacity = input.trim().parse::<f32>().unwrap_or(50.0).clamp(0.0, 100.0) / 100.0;
+
+    match kind.as_str() {
+        "2" => {
+            print!("Enter watermark text: ");
+            std::io::stdout().flush()?;
+            input.clear();
+            std::io::stdin().read_line(&mut input)?;
+            let text = input.trim().to_string();
+
+            print!("Enter path to a .ttf/.otf font: ");
+            std::io::stdout().flush()?;
+            input.clear();
+            std::io::stdin().read_line(&mut input)?;
+            let font_path = input.trim().to_string();
+
+            processor.apply_text_watermark(files, &text, Path::new(&font_path), position, opacity)
+        }
+        _ => {
+            print!("Enter watermark image path: ");
+            std::io::stdout().flush()?;
+            input.clear();
+            std::io::stdin().read_line(&mut input)?;
+            let watermark_path = input.trim().to_string();
+
+            processor.apply_watermark(files, Path::new(&watermark_path), position, opacity)
+        }
+    }
+}
+
+fn rotate_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    print!("Enter rotation in degrees (clockwise, e.g. 90, 180, 270, 45): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let Ok(degrees) = input.trim().parse::<f32>() else {
+        println!("Invalid angle.");
+        return Ok(());
+    };
+
+    let mut background = Rgba([255, 255, 255, 255]);
+    if degrees.rem_euclid(90.0) != 0.0 {
+        print!("Background color for exposed corners (hex RRGGBB or RRGGBBAA, default ffffff): ");
+        std::io::stdout().flush()?;
+        input.clear();
+        std::io::stdin().read_line(&mut input)?;
+        if !input.trim().is_empty() {
+            match parse_hex_color(input.trim()) {
+                Some(color) => background = color,
+                None => println!("Invalid color, using white."),
+            }
+        }
+    }
+
+    println!("Rotating images by {} degrees...", degrees);
+    transform(processor, files, "rotated", |img| Ok(rotate_image(&img, degrees, background)))
+}
+
+fn flip_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    print!("Flip direction (h = horizontal, v = vertical): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let horizontal = match input.trim().to_lowercase().as_str() {
+        "h" | "horizontal" => true,
+        "v" | "vertical" => false,
+        _ => {
+            println!("Invalid direction.");
+            return Ok(());
+        }
+    };
+
+    println!("Flipping images...");
+    transform(processor, files, "flipped", |img| Ok(if horizontal { img.fliph() } else { img.flipv() }))
+}
+
+fn crop_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    let mut values = Vec::new();
+    for label in ["x", "y", "width", "height"] {
+        print!("Enter crop {}: ", label);
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        match input.trim().parse::<u32>() {
+            Ok(v) => values.push(v),
+            Err(_) => {
+                println!("Invalid number.");
+                return Ok(());
+            }
+        }
+    }
+
+    println!("Cropping images...");
+    transform(processor, files, "cropped", |img| crop_image(&img, values[0], values[1], values[2], values[3]))
+}
+
+/// Color adjustments written back in the source format, independent of compression.
+fn filter_images(processor: &ImageProcessor, files: &[fs::DirEntry]) -> io::Result<()> {
+    println!("Color filters:");
+    println!("  1. Grayscale");
+    println!("  2. Brightness");
+    println!("  3. Contrast");
+    println!("  4. Saturation");
+    print!("Select filter (1-4): ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let choice = input.trim().to_string();
+
+    match choice.as_str() {
+        "1" => {
+            println!("Converting images to grayscale...");
+            transform(processor, files, "grayscale", |img| Ok(img.grayscale()))
+        }
+        "2" => {
+            print!("Enter brightness change (-255 to 255): ");
+            std::io::stdout().flush()?;
+            input.clear();
+            std::io::stdin().read_line(&mut input)?;
+            let value: i32 = input.trim().parse().unwrap_or(0).clamp(-255, 255);
+            println!("Adjusting brightness by {}...", value);
+            transform(processor, files, "brightened", |img| Ok(img.brighten(value)))
+        }
+        "3" => {
+            print!("Enter contrast change (-100 to 100): ");
+            std::io::stdout().flush()?;
+            input.clear();
+            std::io::stdin().read_line(&mut input)?;
+            let value: f32 = input.trim().parse().unwrap_or(0.0_f32).clamp(-100.0, 100.0);
+            println!("Adjusting contrast by {}...", value);
+            transform(processor, files, "contrast", |img| Ok(img.adjust_contrast(value)))
+        }
+        "4" => {
+            print!("Enter saturation factor (0 = gray, 1 = unchanged, 2 = double): ");
+            std::io::stdout().flush()?;
+            input.clear();
+            std::io::stdin().read_line(&mut input)?;
+            let factor: f32 = input.trim().parse().unwrap_or(1.0_f32).clamp(0.0, 10.0);
+            println!("Adjusting saturation by {}x...", factor);
+            transform(processor, files, "saturated", |img| Ok(adjust_saturation(&img, factor)))
+        }
+        _ => {
+            println!("Invalid option.");
+            Ok(())
+        }
+    }
+}
+
+/// `ImageProcessor::transform_files` once the outputs are known to fit or the user said to go on.
+fn transform<F>(processor: &ImageProcessor, files: &[fs::DirEntry], suffix: &str, op: F) -> io::Result<()>
+where
+    F: Fn(DynamicImage) -> io::Result<DynamicImage>,
+{
+    if confirm_output_space(processor, files)? {
+        processor.transform_files(files, suffix, op)?;
+    }
+    Ok(())
+}
+
+fn print_estimate_table(results: &[CompressionResult]) {
+    if results.is_empty() {
+        println!("No files could be estimated.");
+        return;
+    }
+
+    let name_width = results.iter().map(|r| r.file.len()).max().unwrap_or(4).max(4);
+    println!("\nDry run - no files were written:");
+    println!("{:<width$}  {:>12}  {:>12}  {:>13}", "File", "Original", "Projected", "Change", width = name_width);
+    let (mut total_original, mut total_projected) = (0u64, 0u64);
+    for result in results {
+        total_original += result.original_size;
+        total_projected += result.compressed_size;
+        println!("{:<width$}  {:>12}  {:>12}  {:>13}",
+                 result.file, result.original_size, result.compressed_size,
+                 describe_savings(result.original_size, result.compressed_size), width = name_width);
+    }
+    println!("{:<width$}  {:>12}  {:>12}  {:>13}",
+             "Total", total_original, total_projected,
+             describe_savings(total_original, total_projected), width = name_width);
+}
+
+/// Shows each image's size and dimensions and asks for its quality. Enter keeps
+/// `default`; `all` keeps it for every remaining image too.
+fn ask_quality_per_file(files: &[fs::DirEntry], default: u8) -> io::Result<HashMap<OsString, u8>> {
+    let mut qualities = HashMap::new();
+    for file in files {
+        let name = file.file_name();
+        let size = file.metadata().map(|m| disk::format_bytes(m.len())).unwrap_or_else(|_| "?".to_string());
+        let dimensions = image::image_dimensions(file.path())
+            .map(|(w, h)| format!("{}x{}", w, h))
+            .unwrap_or_else(|_| "unknown size".to_string());
+        print!("{} ({}, {}) quality [{}, 'all' = {} for the rest]: ", name.to_string_lossy(), dimensions, size, default, default);
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            break;
+        }
+        match input.trim() {
+            "" => {}
+            "all" => break,
+            text => match text.parse::<u8>() {
+                Ok(quality) if (1..=100).contains(&quality) => {
+                    qualities.insert(name, quality);
+                }
+                _ => println!("  Not a quality from 1 to 100; using {}.", default),
+            },
+        }
+    }
+    Ok(qualities)
+}
+
+fn prompt_quality(prompt: &str, default: u8) -> io::Result<u8> {
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().parse().unwrap_or(default).clamp(1, 100))
+}
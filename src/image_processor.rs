@@ -1,454 +1,470 @@
 use std::fs;
-use std::path::Path;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
 use std::io::{self, Write};
-use image::{self, ImageFormat, GenericImageView, DynamicImage};
+use image::{self, ImageFormat, GenericImageView, DynamicImage, Rgba, RgbaImage};
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use crate::disk;
+use crate::display::icon;
+use crate::progress::Progress;
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+
+/// Lists what this tool wrote into its directory; see `ImageProcessor::record_output`.
+const OUTPUT_MANIFEST: &str = ".outputs.json";
 
 pub struct ImageProcessor {
     imgwo_dir: String,
+    dry_run: bool,
 }
 
-impl ImageProcessor {
-    pub fn new() -> io::Result<Self> {
-        let imgwo_dir = "imgwo".to_string();
-        if !Path::new(&imgwo_dir).exists() {
-            fs::create_dir_all(&imgwo_dir)?;
-            println!("Created 'imgwo' directory.");
-        }
-        Ok(ImageProcessor { imgwo_dir })
+#[derive(Debug, Clone)]
+pub struct CompressionResult {
+    pub file: String,
+    pub original_size: u64,
+    pub compressed_size: u64,
+}
+
+/// Per-file outcome of a batch operation; a failed file never stops the rest.
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub processed: usize,
+    /// File name and error for every file that could not be processed.
+    pub failed: Vec<(String, String)>,
+    /// Sizes of each compressed file; empty for non-compression batches.
+    pub compressed: Vec<CompressionResult>,
+    /// Set when the batch was called off before any file was processed.
+    pub cancelled: bool,
+    progress: Option<Progress>,
+}
+
+impl BatchSummary {
+    /// A batch called off before any file was processed.
+    pub fn cancelled() -> Self {
+        BatchSummary { cancelled: true, ..Default::default() }
     }
 
-    pub fn get_image_files(&self) -> io::Result<Vec<std::fs::DirEntry>> {
-        let files: Vec<_> = fs::read_dir(&self.imgwo_dir)?
-            .filter_map(|e| e.ok())
-            .filter(|e| {
-                let name = e.file_name().to_string_lossy().to_lowercase();
-                name.ends_with(".jpg") || name.ends_with(".jpeg") || name.ends_with(".png") || 
-                name.ends_with(".bmp") || name.ends_with(".gif") || name.ends_with(".webp") ||
-                name.ends_with(".tiff") || name.ends_with(".tga")
-            })
-            .collect();
-        Ok(files)
+    fn with_progress(label: &str, total: usize) -> Self {
+        BatchSummary { progress: Some(Progress::new(label, total)), ..Default::default() }
     }
 
-    pub fn compress_images(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Compression methods:");
-        println!("  1. JPEG Quality-based compression");
-        println!("  2. PNG Optimization");
-        println!("  3. WebP Conversion");
-        println!("  4. Resize-based compression");
-        println!("  5. Auto-compress (best method per image)");
-        println!("  6. Progressive JPEG compression");
-        println!("  7. Lossless compression");
-        println!("  8. Adaptive compression");
-        println!("  9. Advanced filtering compression");
-        println!("  10. Multi-pass optimization");
-        print!("Select method (1-10): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        
-        match input.trim() {
-            "1" => self.compress_jpeg_quality(files)?,
-            "2" => self.compress_png_optimization(files)?,
-            "3" => self.compress_webp_conversion(files)?,
-            "4" => self.compress_resize_based(files)?,
-            "5" => self.compress_auto(files)?,
-            "6" => self.compress_progressive_jpeg(files)?,
-            "7" => self.compress_lossless(files)?,
-            "8" => self.compress_adaptive(files)?,
-            "9" => self.compress_advanced_filtering(files)?,
-            "10" => self.compress_multi_pass(files)?,
-            _ => {
-                println!("Invalid option. Using auto-compress.");
-                self.compress_auto(files)?;
+    /// Runs one file's work, printing its output above the progress bar, and records the
+    /// outcome. Returns the work's value if it succeeded.
+    fn item<T>(&mut self, filename: &str, work: impl FnOnce() -> io::Result<T>) -> Option<T> {
+        let run = || work().inspect_err(|e| println!("  {} Failed: {}", icon("❌"), e));
+        let result = match &self.progress {
+            Some(progress) => progress.suspend(run),
+            None => run(),
+        };
+        if let Some(progress) = &mut self.progress {
+            progress.inc();
+        }
+        match result {
+            Ok(value) => {
+                self.processed += 1;
+                Some(value)
+            }
+            Err(e) => {
+                self.failed.push((filename.to_string(), e.to_string()));
+                None
             }
         }
-        Ok(())
     }
 
-    fn compress_jpeg_quality(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        print!("Enter JPEG quality (1-100, lower = smaller file): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let quality: u8 = input.trim().parse().unwrap_or(85).clamp(1, 100);
-        
-        println!("Compressing images with JPEG quality {}...", quality);
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_compressed.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_jpeg(&input_path, &output_path, quality) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Compressed ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Compressed");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
-        }
-        Ok(())
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
     }
 
-    fn compress_png_optimization(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Optimizing PNG images...");
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_optimized.png", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_png(&input_path, &output_path) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Optimized ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Optimized");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+    pub fn print(&self) {
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+        println!("{} processed, {} failed", self.processed, self.failed.len());
+        for (file, error) in &self.failed {
+            println!("  {}: {}", file, error);
         }
-        Ok(())
     }
+}
 
-    fn compress_webp_conversion(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        print!("Enter WebP quality (1-100): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let quality: u8 = input.trim().parse().unwrap_or(80).clamp(1, 100);
-        
-        println!("Converting to WebP with quality {}...", quality);
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}.webp", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_webp(&input_path, &output_path, quality) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Converted ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Converted");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+/// Encoding strategy used by `compress_file`; parameters come from `CompressOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionMethod {
+    /// JPEG at `quality`.
+    Jpeg,
+    /// Re-encoded as PNG.
+    Png,
+    /// Lossless WebP, alpha kept; `quality` does not apply.
+    WebP,
+    /// Shrunk to fit `max_width` x `max_height`, then JPEG.
+    Resize,
+    /// Resizing and quality picked from the image's dimensions and file size.
+    Auto,
+    /// JPEG at `quality`, intended for progressive output.
+    ProgressiveJpeg,
+    /// Lossless re-encode as `lossless_format`.
+    Lossless,
+    /// Quality picked from file size, resolution and aspect ratio.
+    Adaptive,
+    /// `filter` applied before JPEG encoding.
+    Filtered,
+    /// Downscale to 1920x1080, light denoise, then JPEG.
+    MultiPass,
+}
+
+/// Pre-processing applied by `CompressionMethod::Filtered`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFilter {
+    Gaussian,
+    Sharpen,
+    NoiseReduction,
+    EdgeEnhancement,
+}
+
+/// Parameters for `compress_file` and `compress_files`; fields the chosen method does not
+/// use are ignored.
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    pub method: CompressionMethod,
+    /// Encoder quality (1-100) for `Jpeg` and `ProgressiveJpeg`.
+    pub quality: u8,
+    /// Bounding box for `Resize`; 0 leaves that side unconstrained.
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Output format for `Lossless`: PNG, TIFF or WebP.
+    pub lossless_format: ImageFormat,
+    pub filter: ImageFilter,
+    /// zlib level (1-9) for PNG output from `Png` and PNG `Lossless`.
+    pub png_level: u8,
+    /// Scanline filter for PNG output; `Adaptive` picks one per row.
+    pub png_filter: PngFilter,
+    /// Quality for particular input file names, overriding `quality` for those files.
+    pub quality_by_file: HashMap<OsString, u8>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            method: CompressionMethod::Auto,
+            quality: 85,
+            max_width: 0,
+            max_height: 0,
+            lossless_format: ImageFormat::Png,
+            filter: ImageFilter::Gaussian,
+            png_level: 9,
+            png_filter: PngFilter::Adaptive,
+            quality_by_file: HashMap::new(),
         }
-        Ok(())
     }
+}
 
-    fn compress_resize_based(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        print!("Enter max width (0 to keep original): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let max_width: u32 = input.trim().parse().unwrap_or(0);
-        
-        print!("Enter max height (0 to keep original): ");
-        std::io::stdout().flush()?;
-        input.clear();
-        std::io::stdin().read_line(&mut input)?;
-        let max_height: u32 = input.trim().parse().unwrap_or(0);
-        
-        println!("Resize-based compression...");
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_resized.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_resize(&input_path, &output_path, max_width, max_height) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Resized ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Resized");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+impl CompressOptions {
+    /// `quality`, or the per-file override for `input_path`'s file name.
+    pub fn quality_for(&self, input_path: &Path) -> u8 {
+        input_path.file_name()
+            .and_then(|name| self.quality_by_file.get(name))
+            .copied()
+            .unwrap_or(self.quality)
+    }
+
+    /// Appended to the input's stem to name the output file.
+    fn suffix(&self) -> String {
+        match self.method {
+            CompressionMethod::Jpeg => "_compressed.jpg".to_string(),
+            CompressionMethod::Png => "_optimized.png".to_string(),
+            CompressionMethod::WebP => ".webp".to_string(),
+            CompressionMethod::Resize => "_resized.jpg".to_string(),
+            CompressionMethod::Auto => "_auto_compressed.jpg".to_string(),
+            CompressionMethod::ProgressiveJpeg => "_progressive.jpg".to_string(),
+            CompressionMethod::Lossless => format!(".{}", lossless_extension(self.lossless_format)),
+            CompressionMethod::Adaptive => "_adaptive.jpg".to_string(),
+            CompressionMethod::Filtered => "_filtered.jpg".to_string(),
+            CompressionMethod::MultiPass => "_multipass.jpg".to_string(),
         }
-        Ok(())
     }
 
-    fn compress_auto(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Auto-compressing images (best method per image)...");
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_auto_compressed.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_auto(&input_path, &output_path) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Auto-compressed ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Auto-compressed");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
-        }
-        Ok(())
+    fn png_encoder<W: Write>(&self, writer: W) -> PngEncoder<W> {
+        PngEncoder::new_with_quality(writer, CompressionType::Level(self.png_level.clamp(1, 9)), self.png_filter)
     }
 
-    fn compress_progressive_jpeg(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        print!("Enter JPEG quality (1-100): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let quality: u8 = input.trim().parse().unwrap_or(85).clamp(1, 100);
-        
-        println!("Compressing images with Progressive JPEG quality {}...", quality);
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_progressive.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_progressive_jpeg(&input_path, &output_path, quality) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Progressive JPEG ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Progressive JPEG");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+    fn label(&self) -> String {
+        match self.method {
+            CompressionMethod::Jpeg => "Compressed".to_string(),
+            CompressionMethod::Png => "Optimized".to_string(),
+            CompressionMethod::WebP => "Converted".to_string(),
+            CompressionMethod::Resize => "Resized".to_string(),
+            CompressionMethod::Auto => "Auto-compressed".to_string(),
+            CompressionMethod::ProgressiveJpeg => "Progressive JPEG".to_string(),
+            CompressionMethod::Lossless => format!("Lossless {}", lossless_extension(self.lossless_format).to_uppercase()),
+            CompressionMethod::Adaptive => "Adaptive".to_string(),
+            CompressionMethod::Filtered => "Filtered".to_string(),
+            CompressionMethod::MultiPass => "Multi-pass".to_string(),
         }
-        Ok(())
     }
+}
 
-    fn compress_lossless(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Lossless compression options:");
-        println!("  1. PNG lossless");
-        println!("  2. TIFF lossless");
-        println!("  3. WebP lossless");
-        print!("Select format (1-3): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        
-        let format = match input.trim() {
-            "1" => "png",
-            "2" => "tiff",
-            "3" => "webp",
-            _ => "png"
-        };
-        
-        println!("Compressing images with lossless {}...", format.to_uppercase());
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}.{}", self.imgwo_dir, stem, format);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_lossless(&input_path, &output_path, format) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Lossless {} ({} -> {} bytes, {:.1}% smaller)", 
-                               format.to_uppercase(), original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Lossless {}", format.to_uppercase());
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl ImageProcessor {
+    pub fn new() -> io::Result<Self> {
+        Self::with_dir("imgwo")
+    }
+
+    /// Uses `dir` for input listing and batch output instead of `imgwo`, creating it if needed.
+    pub fn with_dir(dir: &str) -> io::Result<Self> {
+        let imgwo_dir = dir.to_string();
+        if !Path::new(&imgwo_dir).exists() {
+            fs::create_dir_all(&imgwo_dir)?;
+            println!("Created '{}' directory.", imgwo_dir);
         }
-        Ok(())
+        Ok(ImageProcessor { imgwo_dir, dry_run: false })
     }
 
-    fn compress_adaptive(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Adaptive compression analyzing image characteristics...");
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_adaptive.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_adaptive(&input_path, &output_path) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Adaptive ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Adaptive");
-                    }
+    /// In dry-run mode compression encodes into memory only and reports projected sizes.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The directory images are read from and outputs are written to.
+    pub fn dir(&self) -> &Path {
+        Path::new(&self.imgwo_dir)
+    }
+
+    /// Lists files whose content is a recognised image, regardless of extension. Outputs
+    /// of earlier runs are left out (see `record_output`), so running an operation again
+    /// does not process its own results.
+    pub fn get_image_files(&self) -> io::Result<Vec<std::fs::DirEntry>> {
+        let outputs = self.read_output_manifest();
+        let mut skipped = 0;
+        let files: Vec<_> = fs::read_dir(&self.imgwo_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|ft| ft.is_file()).unwrap_or(false))
+            .filter(|e| {
+                let is_output = e.file_name().to_str().and_then(|name| outputs.get(name))
+                    .is_some_and(|&size| e.metadata().is_ok_and(|m| m.len() == size));
+                skipped += usize::from(is_output);
+                !is_output
+            })
+            .filter(|e| {
+                let path = e.path();
+                let Some(detected) = detect_format(&path) else {
+                    return false;
+                };
+                match ImageFormat::from_path(&path) {
+                    Ok(by_extension) if by_extension == detected => {}
+                    Ok(by_extension) => println!(
+                        "{}  {}: extension suggests {:?} but content is {:?}; using {:?}", icon("⚠️"),
+                        e.file_name().to_string_lossy(), by_extension, detected, detected
+                    ),
+                    Err(_) => println!(
+                        "{}  {}: no recognised extension, detected {:?} from content", icon("⚠️"),
+                        e.file_name().to_string_lossy(), detected
+                    ),
                 }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+                true
+            })
+            .collect();
+        if skipped > 0 {
+            println!("Skipping {} file(s) written by earlier runs.", skipped);
         }
-        Ok(())
+        Ok(files)
     }
 
-    fn compress_advanced_filtering(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Advanced filtering options:");
-        println!("  1. Gaussian blur + compression");
-        println!("  2. Sharpen + compression");
-        println!("  3. Noise reduction + compression");
-        println!("  4. Edge enhancement + compression");
-        print!("Select filter (1-4): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        
-        let filter_type = match input.trim() {
-            "1" => "gaussian",
-            "2" => "sharpen",
-            "3" => "noise_reduction",
-            "4" => "edge_enhancement",
-            _ => "gaussian"
+    fn output_manifest_path(&self) -> PathBuf {
+        Path::new(&self.imgwo_dir).join(OUTPUT_MANIFEST)
+    }
+
+    /// Output file name -> size written. Unreadable manifests count as empty, which at
+    /// worst lets old outputs be processed again. Names that are not valid UTF-8 cannot be
+    /// stored in it, so such outputs are treated like any other file.
+    fn read_output_manifest(&self) -> HashMap<String, u64> {
+        fs::read(self.output_manifest_path()).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Notes that `output_path` was written by this tool. The size is kept too, so a file
+    /// the user later replaces under the same name is treated as an input again.
+    fn record_output(&self, output_path: &Path, size: u64) -> io::Result<()> {
+        let Some(name) = output_path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
         };
-        
-        println!("Applying {} filter and compressing...", filter_type);
-        for file in files {
-            let input_path = file.path();
-            let file_name = file.file_name();
-            let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_filtered.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_with_filter(&input_path, &output_path, filter_type) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Filtered ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Filtered");
-                    }
-                }
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
-        }
-        Ok(())
+        let mut outputs = self.read_output_manifest();
+        outputs.insert(name.to_string(), size);
+        outputs.retain(|name, _| Path::new(&self.imgwo_dir).join(name).exists());
+        fs::write(self.output_manifest_path(), serde_json::to_vec_pretty(&outputs)?)
     }
 
-    fn compress_multi_pass(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Multi-pass optimization (resize + filter + compress)...");
+    /// Whether writing to `path` loses nothing: it does not exist, or it is an output of an
+    /// earlier run that still has the size that run wrote.
+    fn is_replaceable(&self, path: &Path) -> bool {
+        let Ok(metadata) = fs::metadata(path) else {
+            return true;
+        };
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+        parent == Path::new(&self.imgwo_dir)
+            && name.to_str().and_then(|name| self.read_output_manifest().get(name).copied()) == Some(metadata.len())
+    }
+
+    /// `<output dir>/<input stem><suffix>`, built from the raw file name so a name that is
+    /// not valid UTF-8 keeps its bytes rather than gaining replacement characters.
+    fn output_path(&self, input_path: &Path, suffix: &str) -> PathBuf {
+        let mut name = input_path.file_stem().unwrap_or(input_path.as_os_str()).to_os_string();
+        name.push(suffix);
+        Path::new(&self.imgwo_dir).join(name)
+    }
+
+    /// Fails when the output directory's free space is below the inputs' total size, a
+    /// rough bound on the outputs. The batch functions do not check this themselves.
+    pub fn check_output_space(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
+        let needed: u64 = files.iter().filter_map(|f| f.metadata().ok()).map(|m| m.len()).sum();
+        disk::ensure_space(Path::new(&self.imgwo_dir), needed)
+    }
+
+    /// Counts the files in the directory that are not images this tool can read.
+    pub fn other_files(&self) -> io::Result<usize> {
+        Ok(fs::read_dir(&self.imgwo_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file() && e.file_name() != OUTPUT_MANIFEST && detect_format(&e.path()).is_none())
+            .count())
+    }
+
+    /// Compresses every file into the output directory as `<stem><suffix>`, printing a line
+    /// per file and a final count. Files that fail are listed in `failed` and skipped.
+    pub fn compress_files(&self, files: &[std::fs::DirEntry], options: &CompressOptions) -> io::Result<BatchSummary> {
+        let suffix = options.suffix();
+        let label = options.label();
+        let mut summary = BatchSummary::with_progress(if self.dry_run { "Estimating" } else { "Compressing" }, files.len());
         for file in files {
             let input_path = file.path();
             let file_name = file.file_name();
             let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_multipass.jpg", self.imgwo_dir, stem);
-            
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.compress_image_multi_pass(&input_path, &output_path) {
-                Ok(original_size) => {
-                    if let Ok(compressed_size) = fs::metadata(&output_path).map(|m| m.len()) {
-                        let savings = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
-                        println!("  ✅ Multi-pass ({} -> {} bytes, {:.1}% smaller)", 
-                               original_size, compressed_size, savings);
-                    } else {
-                        println!("  ✅ Multi-pass");
-                    }
+            let output_path = self.output_path(&input_path, &suffix);
+
+            let result = summary.item(&filename, || {
+                if self.dry_run {
+                    println!("Estimating: {} -> {}", filename, output_path.display());
+                } else {
+                    println!("Processing: {} -> {}", filename, output_path.display());
                 }
-                Err(e) => println!("  ❌ Failed: {}", e),
+                self.compress_file(&input_path, &output_path, options).inspect(|result| {
+                    println!("  {} {} ({} -> {} bytes, {})", icon("✅"),
+                           label, result.original_size, result.compressed_size,
+                           describe_savings(result.original_size, result.compressed_size));
+                })
+            });
+            summary.compressed.extend(result);
+        }
+        summary.print();
+        Ok(summary)
+    }
+
+    /// Compresses one image to `output_path` (only sized, not written, in dry-run mode).
+    pub fn compress_file(&self, input_path: &Path, output_path: impl AsRef<Path>, options: &CompressOptions) -> io::Result<CompressionResult> {
+        let output_path = output_path.as_ref();
+        let quality = options.quality_for(input_path).clamp(1, 100);
+        let (original_size, compressed_size) = match options.method {
+            CompressionMethod::Jpeg => self.compress_image_jpeg(input_path, output_path, quality)?,
+            CompressionMethod::Png => self.compress_image_png(input_path, output_path, options)?,
+            CompressionMethod::WebP => self.compress_image_webp(input_path, output_path)?,
+            CompressionMethod::Resize => self.compress_image_resize(input_path, output_path, options.max_width, options.max_height)?,
+            CompressionMethod::Auto => self.compress_image_auto(input_path, output_path)?,
+            CompressionMethod::ProgressiveJpeg => self.compress_image_progressive_jpeg(input_path, output_path, quality)?,
+            CompressionMethod::Lossless => self.compress_image_lossless(input_path, output_path, options)?,
+            CompressionMethod::Adaptive => self.compress_image_adaptive(input_path, output_path)?,
+            CompressionMethod::Filtered => self.compress_image_with_filter(input_path, output_path, options.filter)?,
+            CompressionMethod::MultiPass => self.compress_image_multi_pass(input_path, output_path)?,
+        };
+        let file = input_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        Ok(CompressionResult { file, original_size, compressed_size })
+    }
+
+    /// Writes an encoded buffer to `output_path` unless in dry-run mode; returns its size.
+    /// An existing file is only replaced if an earlier run wrote it (see `record_output`):
+    /// anything else may be a source image, so the write fails with `AlreadyExists`.
+    fn write_output(&self, output_path: &Path, buffer: Vec<u8>) -> io::Result<u64> {
+        if !self.dry_run {
+            if !self.is_replaceable(output_path) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!(
+                    "{} already exists and was not written by this tool; not overwriting it", output_path.display())));
             }
+            fs::write(output_path, &buffer)?;
+            self.record_output(output_path, buffer.len() as u64)?;
         }
-        Ok(())
+        Ok(buffer.len() as u64)
     }
 
-    fn compress_image_jpeg(&self, input_path: &Path, output_path: &str, quality: u8) -> io::Result<u64> {
+    fn compress_image_jpeg(&self, input_path: &Path, output_path: &Path, quality: u8) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
+        let img = open_image(input_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, quality))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
-    }
-
-    fn compress_image_png(&self, input_path: &Path, output_path: &str) -> io::Result<u64> {
-        let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
-        img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
+    }
+
+    /// Re-encodes with the configured level and filter. A PNG input that does not get
+    /// smaller is copied unchanged, so optimization never grows a file.
+    fn compress_image_png(&self, input_path: &Path, output_path: &Path, options: &CompressOptions) -> io::Result<(u64, u64)> {
+        let original = fs::read(input_path)?;
+        let original_size = original.len() as u64;
+        let img = open_image(input_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
+        img.write_with_encoder(options.png_encoder(&mut output_file))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
+        let mut encoded = output_file.into_inner();
+        if encoded.len() as u64 >= original_size && detect_format(input_path) == Some(ImageFormat::Png) {
+            println!("  {}  Re-encoding did not shrink {}; keeping the original bytes", icon("ℹ️"), input_path.display());
+            encoded = original;
+        }
+        let compressed_size = self.write_output(output_path, encoded)?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_webp(&self, input_path: &Path, output_path: &str, quality: u8) -> io::Result<u64> {
+    /// The `image` crate only encodes lossless WebP, so there is no quality to pass.
+    fn compress_image_webp(&self, input_path: &Path, output_path: &Path) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
-        // Note: WebP support might require additional crates, using PNG as fallback
-        img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
+        let encoded = encode_with_format(&open_image(input_path)?, ImageFormat::WebP)?;
+        let compressed_size = self.write_output(output_path, encoded)?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_resize(&self, input_path: &Path, output_path: &str, max_width: u32, max_height: u32) -> io::Result<u64> {
+    fn compress_image_resize(&self, input_path: &Path, output_path: &Path, max_width: u32, max_height: u32) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let mut img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        
-        if max_width > 0 || max_height > 0 {
-            let (width, height) = img.dimensions();
-            let new_width = if max_width > 0 && width > max_width { max_width } else { width };
-            let new_height = if max_height > 0 && height > max_height { max_height } else { height };
-            
-            if new_width != width || new_height != height {
-                img = img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
-            }
-        }
+        let img = shrink_to_fit(open_image(input_path)?, max_width, max_height);
         
-        let mut output_file = fs::File::create(output_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, 85))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_auto(&self, input_path: &Path, output_path: &str) -> io::Result<u64> {
+    fn compress_image_auto(&self, input_path: &Path, output_path: &Path) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let img = open_image(input_path)?;
         let (width, height) = img.dimensions();
         
         // Auto-compression strategy based on image characteristics
-        let mut output_file = fs::File::create(output_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         
         if width > 1920 || height > 1080 {
             // Large image: resize + compress
-            let resized = img.resize(1920, 1080, image::imageops::FilterType::Lanczos3);
+            let resized = shrink_to_fit(img, 1920, 1080);
             resized.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, 80))
                 .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         } else if original_size > 1024 * 1024 {
@@ -461,53 +477,50 @@ impl ImageProcessor {
                 .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         }
         
-        Ok(original_size)
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_progressive_jpeg(&self, input_path: &Path, output_path: &str, quality: u8) -> io::Result<u64> {
+    fn compress_image_progressive_jpeg(&self, input_path: &Path, output_path: &Path, quality: u8) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
+        let img = open_image(input_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         
         // Progressive JPEG encoding (simulated - actual implementation would use a library that supports it)
         img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, quality))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_lossless(&self, input_path: &Path, output_path: &str, format: &str) -> io::Result<u64> {
+    fn compress_image_lossless(&self, input_path: &Path, output_path: &Path, options: &CompressOptions) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
+        let img = open_image(input_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         
-        match format {
-            "png" => {
-                img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            }
-            "tiff" => {
+        match options.lossless_format {
+            ImageFormat::Tiff => {
                 // TIFF lossless compression
                 img.write_with_encoder(image::codecs::tiff::TiffEncoder::new(&mut output_file))
                     .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
             }
-            "webp" => {
-                // WebP lossless (fallback to PNG)
-                img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            ImageFormat::WebP => {
+                output_file = io::Cursor::new(encode_with_format(&img, ImageFormat::WebP)?);
             }
             _ => {
-                img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
+                img.write_with_encoder(options.png_encoder(&mut output_file))
                     .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
             }
         }
-        Ok(original_size)
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_adaptive(&self, input_path: &Path, output_path: &str) -> io::Result<u64> {
+    fn compress_image_adaptive(&self, input_path: &Path, output_path: &Path) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let img = open_image(input_path)?;
         let (width, height) = img.dimensions();
-        let mut output_file = fs::File::create(output_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         
         // Adaptive compression based on image analysis
         let aspect_ratio = width as f64 / height as f64;
@@ -526,53 +539,47 @@ impl ImageProcessor {
         
         img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, quality))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_with_filter(&self, input_path: &Path, output_path: &str, filter_type: &str) -> io::Result<u64> {
+    fn compress_image_with_filter(&self, input_path: &Path, output_path: &Path, filter: ImageFilter) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let mut img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
+        let mut img = open_image(input_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         
         // Apply different filters based on type
-        match filter_type {
-            "gaussian" => {
+        match filter {
+            ImageFilter::Gaussian => {
                 // Simulate Gaussian blur by slightly blurring the image
                 img = img.blur(0.5);
             }
-            "sharpen" => {
+            ImageFilter::Sharpen => {
                 // Simulate sharpening
                 img = img.unsharpen(1.0, 1);
             }
-            "noise_reduction" => {
+            ImageFilter::NoiseReduction => {
                 // Simulate noise reduction by slight blur
                 img = img.blur(0.3);
             }
-            "edge_enhancement" => {
+            ImageFilter::EdgeEnhancement => {
                 // Simulate edge enhancement
                 img = img.unsharpen(2.0, 2);
             }
-            _ => {}
         }
         
         img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, 85))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
     }
 
-    fn compress_image_multi_pass(&self, input_path: &Path, output_path: &str) -> io::Result<u64> {
+    fn compress_image_multi_pass(&self, input_path: &Path, output_path: &Path) -> io::Result<(u64, u64)> {
         let original_size = fs::metadata(input_path)?.len();
-        let mut img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let (width, height) = img.dimensions();
-        let mut output_file = fs::File::create(output_path)?;
+        let mut output_file = io::Cursor::new(Vec::new());
         
         // Multi-pass optimization: resize + filter + compress
-        let target_width = if width > 1920 { 1920 } else { width };
-        let target_height = if height > 1080 { 1080 } else { height };
-        
-        if target_width != width || target_height != height {
-            img = img.resize(target_width, target_height, image::imageops::FilterType::Lanczos3);
-        }
+        let mut img = shrink_to_fit(open_image(input_path)?, 1920, 1080);
         
         // Apply noise reduction
         img = img.blur(0.2);
@@ -580,92 +587,90 @@ impl ImageProcessor {
         // Final compression
         img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, 75))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(original_size)
-    }
-
-    pub fn resize_images(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        print!("Enter new width: ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let width: u32 = input.trim().parse().unwrap_or(800);
-        print!("Enter new height: ");
-        std::io::stdout().flush()?;
-        input.clear();
-        std::io::stdin().read_line(&mut input)?;
-        let height: u32 = input.trim().parse().unwrap_or(600);
-        println!("Resizing images to {}x{}...", width, height);
+        let compressed_size = self.write_output(output_path, output_file.into_inner())?;
+        Ok((original_size, compressed_size))
+    }
+
+    /// Scales an image to fit within `width` x `height` (keeping its aspect ratio) and
+    /// writes it as `format`; returns the output size in bytes. Smaller images are only
+    /// enlarged with `upscale`; otherwise they are written at their own size. Alpha is
+    /// kept unless `format` is JPEG, in which case a warning is printed for transparent inputs.
+    pub fn resize_file(&self, input_path: &Path, output_path: impl AsRef<Path>, width: u32, height: u32, format: ImageFormat, upscale: bool) -> io::Result<u64> {
+        let img = open_image(input_path)?;
+        if format == ImageFormat::Jpeg && has_transparency(&img) {
+            println!("  {}  {} has transparency; JPEG output flattens it onto white", icon("⚠️"), input_path.display());
+        }
+        let (original_width, original_height) = img.dimensions();
+        let resized = if upscale {
+            img.resize(width, height, image::imageops::FilterType::Lanczos3)
+        } else {
+            let resized = shrink_to_fit(img, width, height);
+            if resized.dimensions() == (original_width, original_height) {
+                println!("  Kept at {}x{}; it already fits", original_width, original_height);
+            }
+            resized
+        };
+        self.write_output(output_path.as_ref(), encode_with_format(&resized, format)?)
+    }
+
+    /// `resize_file` for every file, as `<stem>_resized.<ext>`. Each file keeps its own
+    /// format unless `target` is given.
+    pub fn resize_files(&self, files: &[std::fs::DirEntry], width: u32, height: u32, target: Option<ImageFormat>, upscale: bool) -> io::Result<BatchSummary> {
+        let mut summary = BatchSummary::with_progress("Resizing", files.len());
         for file in files {
             let input_path = file.path();
             let file_name = file.file_name();
             let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}_resized.jpg", self.imgwo_dir, stem);
-            println!("Processing: {} -> {}", filename, output_path);
-            match self.resize_single_image(&input_path, &output_path, width, height) {
-                Ok(_) => println!("  ✅ Resized"),
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
+            let source = detect_format(&input_path).unwrap_or(ImageFormat::Png);
+            let format = target.unwrap_or(source);
+            let extension = format.extensions_str().first().copied().unwrap_or("png");
+            let output_path = self.output_path(&input_path, &format!("_resized.{}", extension));
+            summary.item(&filename, || {
+                println!("Processing: {} -> {}", filename, output_path.display());
+                self.resize_file(&input_path, &output_path, width, height, format, upscale).map(|_| println!("  {} Resized", icon("✅")))
+            });
         }
-        Ok(())
-    }
-
-    fn resize_single_image(&self, input_path: &Path, output_path: &str, width: u32, height: u32) -> io::Result<()> {
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let resized = img.resize(width, height, image::imageops::FilterType::Lanczos3);
-        let mut output_file = fs::File::create(output_path)?;
-        resized.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, 85))
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(())
+        summary.print();
+        Ok(summary)
+    }
+
+    /// Re-encodes an image as `jpg`, `png` or `webp`; returns the output size in bytes.
+    /// `quality` (1-100) applies to JPEG. PNG and WebP are lossless and keep alpha; JPEG
+    /// puts transparent areas on white and prints a warning when the input had any.
+    pub fn convert_file(&self, input_path: &Path, output_path: impl AsRef<Path>, format: &str, quality: u8) -> io::Result<u64> {
+        let format = match format {
+            "jpg" | "jpeg" => ImageFormat::Jpeg,
+            "png" => ImageFormat::Png,
+            "webp" => ImageFormat::WebP,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unsupported format")),
+        };
+        let img = open_image(input_path)?;
+        if format == ImageFormat::Jpeg && has_transparency(&img) {
+            println!("  {}  {} has transparency; JPEG cannot store it, so those areas become white", icon("⚠️"), input_path.display());
+        }
+        self.write_output(output_path.as_ref(), encode_with_quality(&img, format, quality)?)
     }
 
-    pub fn convert_format(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Available formats: jpg, png, webp");
-        print!("Enter target format: ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let format = input.trim().to_lowercase();
-        if !["jpg", "png", "webp"].contains(&format.as_str()) {
-            println!("Unsupported format.");
-            return Ok(());
-        }
-        println!("Converting to {}...", format);
+    /// `convert_file` for every file, as `<stem>.<format>`, or `<stem>_converted.<format>`
+    /// when that would be the input itself.
+    pub fn convert_files(&self, files: &[std::fs::DirEntry], format: &str, quality: u8) -> io::Result<BatchSummary> {
+        let mut summary = BatchSummary::with_progress("Converting", files.len());
         for file in files {
             let input_path = file.path();
             let file_name = file.file_name();
             let filename = file_name.to_string_lossy();
-            let stem = self.get_file_stem(&filename);
-            let output_path = format!("{}/{}.{}", self.imgwo_dir, stem, format);
-            println!("Converting: {} -> {}", filename, output_path);
-            match self.convert_single_image(&input_path, &output_path, &format) {
-                Ok(_) => println!("  ✅ Converted"),
-                Err(e) => println!("  ❌ Failed: {}", e),
-            }
-        }
-        Ok(())
-    }
-
-    fn convert_single_image(&self, input_path: &Path, output_path: &str, format: &str) -> io::Result<()> {
-        let img = image::open(input_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let mut output_file = fs::File::create(output_path)?;
-        match format {
-            "jpg" | "jpeg" => {
-                img.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output_file, 85))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            }
-            "png" => {
-                img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let mut output_path = self.output_path(&input_path, &format!(".{}", format));
+            // Converting photo.jpg to jpg would otherwise write over photo.jpg itself.
+            if output_path.file_name().is_some_and(|name| name.eq_ignore_ascii_case(&file_name)) {
+                output_path = self.output_path(&input_path, &format!("_converted.{}", format));
             }
-            "webp" => {
-                // Fallback to PNG for now
-                img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut output_file))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            }
-            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unsupported format")),
+            summary.item(&filename, || {
+                println!("Converting: {} -> {}", filename, output_path.display());
+                self.convert_file(&input_path, &output_path, format, quality).map(|_| println!("  {} Converted", icon("✅")))
+            });
         }
-        Ok(())
+        summary.print();
+        Ok(summary)
     }
 
     pub fn extract_metadata(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
@@ -682,7 +687,7 @@ impl ImageProcessor {
                 println!("  Permissions: {:?}", metadata.permissions());
                 
                 // Extract image-specific metadata
-                if let Ok(img) = image::open(&input_path) {
+                if let Ok(img) = open_image(&input_path) {
                     let (width, height) = img.dimensions();
                     println!("  Dimensions: {}x{}", width, height);
                     println!("  Format: {:?}", img.color());
@@ -693,76 +698,326 @@ impl ImageProcessor {
         Ok(())
     }
 
-    pub fn batch_process(&self, files: &[std::fs::DirEntry]) -> io::Result<()> {
-        println!("Batch processing options:");
-        println!("  1. Compress + Resize");
-        println!("  2. Convert + Compress");
-        println!("  3. All operations");
-        print!("Select option (1-3): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        match input.trim() {
-            "1" => {
-                self.compress_auto(files)?;
-                self.resize_images(files)?;
-            }
-            "2" => {
-                self.convert_format(files)?;
-                self.compress_auto(files)?;
-            }
-            "3" => {
-                self.compress_auto(files)?;
-                self.resize_images(files)?;
-                self.convert_format(files)?;
-                self.extract_metadata(files)?;
-            }
-            _ => println!("Invalid option."),
+    /// Composites `watermark_path` onto every file at `position`, scaled down when it
+    /// would not fit, with its own alpha multiplied by `opacity` (0.0-1.0).
+    pub fn apply_watermark(&self, files: &[std::fs::DirEntry], watermark_path: &Path, position: WatermarkPosition, opacity: f32) -> io::Result<()> {
+        let watermark = open_image(watermark_path)?.to_rgba8();
+        self.stamp_files(files, &watermark, position, opacity)
+    }
+
+    /// Renders `text` with the font at `font_path` and stamps it like an image watermark.
+    pub fn apply_text_watermark(&self, files: &[std::fs::DirEntry], text: &str, font_path: &Path, position: WatermarkPosition, opacity: f32) -> io::Result<()> {
+        if text.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Watermark text is empty"));
         }
+        let font_data = fs::read(font_path)?;
+        let font = FontVec::try_from_vec(font_data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let watermark = render_text(&font, text, 48.0);
+        self.stamp_files(files, &watermark, position, opacity)
+    }
+
+    fn stamp_files(&self, files: &[std::fs::DirEntry], watermark: &RgbaImage, position: WatermarkPosition, opacity: f32) -> io::Result<()> {
+        println!("Applying watermark...");
+        self.transform_files(files, "watermarked", |img| Ok(stamp_watermark(&img, watermark, position, opacity)))?;
         Ok(())
     }
 
-    fn get_file_stem(&self, filename: &str) -> String {
-        filename.trim_end_matches(".jpg").trim_end_matches(".jpeg")
-            .trim_end_matches(".png").trim_end_matches(".bmp")
-            .trim_end_matches(".gif").trim_end_matches(".webp")
-            .to_string()
+    /// Runs `op` over every file and writes `<stem>_<suffix>` in the source format.
+    pub fn transform_files<F>(&self, files: &[std::fs::DirEntry], suffix: &str, op: F) -> io::Result<BatchSummary>
+    where
+        F: Fn(DynamicImage) -> io::Result<DynamicImage>,
+    {
+        let mut summary = BatchSummary::with_progress("Processing", files.len());
+        for file in files {
+            let input_path = file.path();
+            let file_name = file.file_name();
+            let filename = file_name.to_string_lossy();
+            let format = detect_format(&input_path).unwrap_or(ImageFormat::Png);
+            let extension = format.extensions_str().first().copied().unwrap_or("png");
+            let output_path = self.output_path(&input_path, &format!("_{}.{}", suffix, extension));
+
+            summary.item(&filename, || {
+                println!("Processing: {} -> {}", filename, output_path.display());
+                open_image(&input_path)
+                    .and_then(&op)
+                    .and_then(|img| self.write_output(&output_path, encode_with_format(&img, format)?))
+                    .map(|_| println!("  {} Done", icon("✅")))
+            });
+        }
+        summary.print();
+        Ok(summary)
+    }
+}
+
+/// Reads a JSON object mapping input file names to a quality from 1 to 100.
+pub fn parse_quality_map(text: &str) -> Result<HashMap<OsString, u8>, String> {
+    let raw: HashMap<String, Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    raw.into_iter()
+        .map(|(name, value)| match value.as_u64() {
+            Some(quality @ 1..=100) => Ok((name.into(), quality as u8)),
+            _ => Err(format!("quality for '{}' must be a whole number from 1 to 100, not {}", name, value)),
+        })
+        .collect()
+}
+
+/// File extension for `CompressOptions::lossless_format`.
+pub fn lossless_extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Tiff => "tiff",
+        ImageFormat::WebP => "webp",
+        _ => "png",
+    }
+}
+
+/// Signed savings: positive when the output is smaller, negative when it grew.
+fn percent_saved(original: u64, compressed: u64) -> f64 {
+    if original == 0 {
+        0.0
+    } else {
+        (original as f64 - compressed as f64) / original as f64 * 100.0
+    }
+}
+
+/// Describes a size change as "12.5% smaller", "12.5% larger" or "same size".
+pub fn describe_savings(original: u64, compressed: u64) -> String {
+    let percent = percent_saved(original, compressed);
+    match compressed.cmp(&original) {
+        std::cmp::Ordering::Less => format!("{:.1}% smaller", percent),
+        std::cmp::Ordering::Greater => format!("{:.1}% larger", percent.abs()),
+        std::cmp::Ordering::Equal => "same size".to_string(),
+    }
+}
+
+/// Sniffs the image format from the file's magic bytes rather than its extension.
+fn detect_format(path: &Path) -> Option<ImageFormat> {
+    image::ImageReader::open(path).ok()?.with_guessed_format().ok()?.format()
+}
+
+fn stamp_watermark(base: &DynamicImage, watermark: &RgbaImage, position: WatermarkPosition, opacity: f32) -> DynamicImage {
+    let mut canvas = base.to_rgba8();
+    let (base_w, base_h) = canvas.dimensions();
+
+    let mut mark = watermark.clone();
+    if mark.width() > base_w || mark.height() > base_h {
+        mark = DynamicImage::ImageRgba8(mark)
+            .resize(base_w, base_h, image::imageops::FilterType::Lanczos3)
+            .to_rgba8();
+    }
+    for pixel in mark.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+    }
+
+    let margin = (base_w.min(base_h) / 50) as i64;
+    let (free_w, free_h) = ((base_w - mark.width()) as i64, (base_h - mark.height()) as i64);
+    let (x, y) = match position {
+        WatermarkPosition::TopLeft => (margin.min(free_w), margin.min(free_h)),
+        WatermarkPosition::TopRight => ((free_w - margin).max(0), margin.min(free_h)),
+        WatermarkPosition::BottomLeft => (margin.min(free_w), (free_h - margin).max(0)),
+        WatermarkPosition::BottomRight => ((free_w - margin).max(0), (free_h - margin).max(0)),
+        WatermarkPosition::Center => (free_w / 2, free_h / 2),
+    };
+
+    // overlay() alpha-blends each pixel, so the base image's own transparency is respected.
+    image::imageops::overlay(&mut canvas, &mark, x, y);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Rotates clockwise; right angles are lossless, other angles expand the canvas and
+/// fill the uncovered corners with `background`.
+pub fn rotate_image(img: &DynamicImage, degrees: f32, background: Rgba<u8>) -> DynamicImage {
+    let normalized = degrees.rem_euclid(360.0);
+    if normalized == 0.0 {
+        return img.clone();
+    } else if normalized == 90.0 {
+        return img.rotate90();
+    } else if normalized == 180.0 {
+        return img.rotate180();
+    } else if normalized == 270.0 {
+        return img.rotate270();
+    }
+
+    let source = img.to_rgba8();
+    let (w, h) = (source.width() as f32, source.height() as f32);
+    let (sin, cos) = normalized.to_radians().sin_cos();
+    let new_w = (w * cos.abs() + h * sin.abs()).ceil() as u32;
+    let new_h = (w * sin.abs() + h * cos.abs()).ceil() as u32;
+    let (cx, cy) = (w / 2.0, h / 2.0);
+    let (ncx, ncy) = (new_w as f32 / 2.0, new_h as f32 / 2.0);
+
+    // Inverse-map every destination pixel back into the source image.
+    let rotated = RgbaImage::from_fn(new_w, new_h, |x, y| {
+        let dx = x as f32 + 0.5 - ncx;
+        let dy = y as f32 + 0.5 - ncy;
+        let sx = dx * cos + dy * sin + cx;
+        let sy = -dx * sin + dy * cos + cy;
+        if sx >= 0.0 && sy >= 0.0 && sx < w && sy < h {
+            *source.get_pixel(sx as u32, sy as u32)
+        } else {
+            background
+        }
+    });
+    DynamicImage::ImageRgba8(rotated)
+}
+
+/// Crops to the given rectangle, clamping width/height to the image and erroring when the
+/// origin lies outside it.
+pub fn crop_image(img: &DynamicImage, x: u32, y: u32, width: u32, height: u32) -> io::Result<DynamicImage> {
+    let (img_w, img_h) = img.dimensions();
+    if x >= img_w || y >= img_h {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Crop origin ({}, {}) is outside the {}x{} image", x, y, img_w, img_h),
+        ));
+    }
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Crop width and height must be positive"));
+    }
+    let width = width.min(img_w - x);
+    let height = height.min(img_h - y);
+    Ok(img.crop_imm(x, y, width, height))
+}
+
+/// Scales each pixel's distance from its luma; `factor` 0.0 is grayscale, 1.0 is unchanged.
+pub fn adjust_saturation(img: &DynamicImage, factor: f32) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, _] = pixel.0.map(|c| c as f32);
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        for channel in 0..3 {
+            let value = luma + (pixel[channel] as f32 - luma) * factor;
+            pixel[channel] = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Parses `RRGGBB` or `RRGGBBAA`, with or without a leading `#`.
+pub fn parse_hex_color(hex: &str) -> Option<Rgba<u8>> {
+    let hex = hex.trim_start_matches('#');
+    if hex.len() != 6 && hex.len() != 8 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+fn render_text(font: &FontVec, text: &str, size: f32) -> RgbaImage {
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let width = text.chars()
+        .map(|c| scaled.h_advance(font.glyph_id(c)))
+        .sum::<f32>()
+        .ceil() as u32;
+    let height = (scaled.ascent() - scaled.descent()).ceil() as u32;
+    let mut canvas = RgbaImage::from_pixel(width.max(1), height.max(1), Rgba([255, 255, 255, 0]));
+
+    let mut caret = 0.0;
+    for c in text.chars() {
+        let glyph = font.glyph_id(c).with_scale_and_position(scale, ab_glyph::point(caret, scaled.ascent()));
+        caret += scaled.h_advance(glyph.id);
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let x = bounds.min.x as i64 + gx as i64;
+                let y = bounds.min.y as i64 + gy as i64;
+                if x >= 0 && y >= 0 && (x as u32) < canvas.width() && (y as u32) < canvas.height() {
+                    let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+                    pixel[3] = pixel[3].max((coverage * 255.0) as u8);
+                }
+            });
+        }
     }
+    canvas
+}
+
+/// JPEG quality used when the user is not asked for one.
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// Encodes `img` in `format` into memory; JPEG is flattened onto white at the default quality.
+fn encode_with_format(img: &DynamicImage, format: ImageFormat) -> io::Result<Vec<u8>> {
+    encode_with_quality(img, format, DEFAULT_JPEG_QUALITY)
 }
 
-pub fn run_image_processing() -> io::Result<()> {
-    let processor = ImageProcessor::new()?;
+/// Like `encode_with_format`, with `quality` (1-100) for JPEG. Other formats, WebP
+/// included, are written lossless with their alpha channel.
+fn encode_with_quality(img: &DynamicImage, format: ImageFormat, quality: u8) -> io::Result<Vec<u8>> {
+    let mut output = io::Cursor::new(Vec::new());
+    let result = if format == ImageFormat::Jpeg {
+        flatten_onto_white(img)
+            .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, quality.clamp(1, 100)))
+    } else if format == ImageFormat::WebP && img.color().bytes_per_pixel() > img.color().channel_count() {
+        // The WebP encoder only takes 8-bit channels.
+        let eight_bit = if img.color().has_alpha() {
+            DynamicImage::ImageRgba8(img.to_rgba8())
+        } else {
+            DynamicImage::ImageRgb8(img.to_rgb8())
+        };
+        eight_bit.write_to(&mut output, format)
+    } else {
+        img.write_to(&mut output, format)
+    };
+    result.map_err(io::Error::other)?;
+    Ok(output.into_inner())
+}
+
+/// Scales `img` down to fit within `max_width` x `max_height`, keeping its aspect ratio;
+/// 0 leaves that side unconstrained. An image that already fits is returned as is, so
+/// this never enlarges anything.
+fn shrink_to_fit(img: DynamicImage, max_width: u32, max_height: u32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let max_width = if max_width == 0 { width } else { max_width.min(width) };
+    let max_height = if max_height == 0 { height } else { max_height.min(height) };
+    if (max_width, max_height) == (width, height) {
+        return img;
+    }
+    img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3)
+}
+
+/// Whether any pixel is less than fully opaque; an unused alpha channel does not count.
+pub fn has_transparency(img: &DynamicImage) -> bool {
+    img.color().has_alpha() && img.to_rgba8().pixels().any(|p| p[3] < u8::MAX)
+}
+
+/// Drops the alpha channel by blending each pixel over white, so transparent areas do not
+/// turn black in formats without alpha.
+fn flatten_onto_white(img: &DynamicImage) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let rgba = img.to_rgba8();
+    let flat = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    });
+    DynamicImage::ImageRgb8(flat)
+}
+
+/// Decodes an image using its detected content format, so mislabeled files still load.
+fn open_image(path: &Path) -> io::Result<DynamicImage> {
+    image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(io::Error::other)
+}
+
+/// Non-interactive entry point: compresses every image in `dir` with `options` and
+/// returns the summary so callers can turn failures into an exit status. Nothing is
+/// written when the outputs may not fit (see `check_output_space`); the summary is then
+/// marked cancelled.
+pub fn run_batch_compression(dir: &str, options: &CompressOptions) -> io::Result<BatchSummary> {
+    let processor = ImageProcessor::with_dir(dir)?;
     let files = processor.get_image_files()?;
-    
     if files.is_empty() {
-        println!("No image files found in 'imgwo'. Please add some images and run again.");
-        return Ok(());
-    }
-    
-    println!("Found {} image files:", files.len());
-    for (i, file) in files.iter().enumerate() {
-        let file_name = file.file_name();
-        let filename = file_name.to_string_lossy();
-        println!("  {}. {}", i + 1, filename);
-    }
-    
-    println!("\nImage Processing Options:");
-    println!("  1. Compress all images");
-    println!("  2. Resize all images");
-    println!("  3. Convert format");
-    println!("  4. Extract metadata");
-    println!("  5. Batch process");
-    print!("Select option (1-5): ");
-    std::io::stdout().flush()?;
-    let mut opt = String::new();
-    std::io::stdin().read_line(&mut opt)?;
-    match opt.trim() {
-        "1" => processor.compress_images(&files)?,
-        "2" => processor.resize_images(&files)?,
-        "3" => processor.convert_format(&files)?,
-        "4" => processor.extract_metadata(&files)?,
-        "5" => processor.batch_process(&files)?,
-        _ => println!("Invalid option."),
-    }
-    Ok(())
-} 
\ No newline at end of file
+        println!("No image files found in '{}'.", dir);
+    }
+    if let Err(e) = processor.check_output_space(&files) {
+        println!("{}  {}", icon("⚠️"), e);
+        println!("Cancelled.");
+        return Ok(BatchSummary::cancelled());
+    }
+    processor.compress_files(&files, options)
+}
+
@@ -0,0 +1,23 @@
+//! The database, index, vector store, image processor and password vault behind the `geng`
+//! shell, usable without it. Nothing here reads from the terminal: the session shell,
+//! Simse and the image and password menus live in the `geng` binary, and everything here
+//! is plain calls that return `Result`s.
+
+pub mod crypto;
+pub mod db;
+pub mod disk;
+pub mod display;
+pub mod error;
+pub mod hash_index;
+pub mod image_processor;
+pub mod password_manager;
+pub mod progress;
+pub mod server;
+pub mod vector_db;
+pub mod wal;
+
+pub use db::InMemoryDB;
+pub use error::{RedruError, Result};
+pub use hash_index::HashIndex;
+pub use password_manager::PasswordManager;
+pub use vector_db::VectorDB;
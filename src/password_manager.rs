@@ -1,167 +1,354 @@
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
 use rand::Rng;
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Write};
 use std::path::Path;
+use std::time::SystemTime;
+use crate::crypto::DataKey;
+use crate::display::icon;
+use crate::error::{RedruError, Result};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The lockout scope of the master password; see `session_scope` for sessions.
+pub const MASTER_SCOPE: &str = "master";
+/// Supplies the master password without a prompt, for scripts and CI. See the README for
+/// what keeping a password in the environment exposes.
+pub const MASTER_PASSWORD_ENV: &str = "REDRU_MASTER_PASSWORD";
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const BASE_LOCKOUT_SECS: u64 = 30;
+const MAX_LOCKOUT_SECS: u64 = 60 * 60;
+
+pub const DEFAULT_GENERATED_LENGTH: usize = 20;
+const MIN_GENERATED_LENGTH: usize = 8;
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b"!@#$%^&*()-_=+[]{};:,.<>?";
+
+const VAULT_MAGIC: &[u8; 8] = b"REDRUVLT";
+const VAULT_VERSION: u8 = 1;
+const VAULT_SALT_LEN: usize = 16;
+const VAULT_NONCE_LEN: usize = 12;
+const VAULT_HEADER_LEN: usize = VAULT_MAGIC.len() + 1;
+
+const MIN_STRONG_LENGTH: usize = 12;
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "123456", "12345678", "123456789", "1234", "qwerty", "abc123",
+    "letmein", "admin", "welcome", "iloveyou", "monkey", "dragon", "111111",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PasswordData {
     pub hashed_password: String,
     pub salt: String,
     pub session_passwords: HashMap<String, String>, // session_name -> hashed_password
+    #[serde(default)]
+    pub failed_attempts: HashMap<String, FailedAttempts>, // scope -> consecutive failures
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// session_name -> the session's data key wrapped by its password (see `DataKey::wrap`).
+    /// Sessions protected before their data was encrypted get one when next unlocked.
+    #[serde(default)]
+    pub session_keys: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FailedAttempts {
+    pub count: u32,
+    pub locked_until: u64, // unix seconds, 0 when not locked
 }
 
 pub struct PasswordManager {
     password_file: String,
     password_data: Option<PasswordData>,
+    /// Data keys of sessions whose password was verified during this run.
+    unlocked: HashMap<String, DataKey>,
 }
 
 impl PasswordManager {
-    pub fn new() -> io::Result<Self> {
-        let password_file = "passwords.json".to_string();
+    /// Opens the vault of `profile`, or the default one (`passwords.json`) for `None`.
+    /// Each profile has its own master password, session passwords and lockouts.
+    pub fn new(profile: Option<&str>) -> Result<Self> {
+        let password_file = password_file(profile);
         let password_data = if Path::new(&password_file).exists() {
-            let content = fs::read_to_string(&password_file)?;
-            serde_json::from_str(&content).ok()
+            Some(Self::load_password_data(&password_file)?)
         } else {
             None
         };
-        
+
+
         Ok(PasswordManager {
             password_file,
             password_data,
+            unlocked: HashMap::new(),
         })
     }
 
-    pub fn is_master_password_set(&self) -> bool {
-        self.password_data.is_some()
-    }
+    /// A present-but-unreadable vault must never be treated as "no password set", so parse
+    /// failures fall back to the last good backup and otherwise surface as an error.
+    fn load_password_data(password_file: &str) -> Result<PasswordData> {
+        let content = fs::read_to_string(password_file)?;
+        let parse_error = match serde_json::from_str(&content) {
+            Ok(data) => return Ok(data),
+            Err(e) => e,
+        };
 
-    pub fn set_master_password(&mut self) -> io::Result<()> {
-        print!("Enter master password: ");
-        std::io::stdout().flush()?;
-        let mut password = String::new();
-        std::io::stdin().read_line(&mut password)?;
-        let password = password.trim();
+        let backup_file = backup_path(password_file);
+        if let Ok(backup) = fs::read_to_string(&backup_file)
+            && let Ok(data) = serde_json::from_str::<PasswordData>(&backup)
+        {
+            println!("{}  '{}' is corrupted ({}); restored from '{}'.", icon("⚠️"), password_file, parse_error, backup_file);
+            fs::copy(&backup_file, password_file)?;
+            return Ok(data);
+        }
 
-        print!("Confirm master password: ");
-        std::io::stdout().flush()?;
-        let mut confirm = String::new();
-        std::io::stdin().read_line(&mut confirm)?;
-        let confirm = confirm.trim();
+        Err(RedruError::Integrity(format!(
+            "'{}' exists but cannot be read ({}). Refusing to continue without password protection; \
+             restore the file or delete it to reset all passwords.",
+            password_file, parse_error
+        )))
+    }
 
-        if password != confirm {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passwords don't match"));
+    /// Number of consecutive wrong passwords allowed before a scope gets locked out.
+    pub fn set_max_attempts(&mut self, max_attempts: u32) -> Result<()> {
+        if let Some(ref mut data) = self.password_data {
+            data.max_attempts = max_attempts.max(1);
+            self.save_password_data()?;
+            println!("{} Lockout threshold set to {} attempts!", icon("✅"), max_attempts.max(1));
+        } else {
+            println!("No master password set.");
         }
+        Ok(())
+    }
 
+    pub fn is_master_password_set(&self) -> bool {
+        self.password_data.is_some()
+    }
+
+    /// Stores `password` as the master password, as given and with no strength check (see
+    /// `password_weaknesses`). Existing session passwords and lockouts are kept.
+    pub fn set_master_password_to(&mut self, password: &str) -> Result<()> {
         let salt = argon2::password_hash::SaltString::generate(&mut rand::thread_rng());
         let argon2 = Argon2::default();
         let password_hash = argon2.hash_password(password.as_bytes(), &salt)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Password hash error: {}", e)))?;
+            .map_err(|e| RedruError::Serialization(format!("Password hash error: {}", e)))?;
 
-        self.password_data = Some(PasswordData {
-            hashed_password: password_hash.to_string(),
-            salt: salt.to_string(),
-            session_passwords: HashMap::new(),
-        });
+        // Changing the master password keeps existing session passwords and lockout state.
+        match self.password_data {
+            Some(ref mut data) => {
+                data.hashed_password = password_hash.to_string();
+                data.salt = salt.to_string();
+            }
+            None => {
+                self.password_data = Some(PasswordData {
+                    hashed_password: password_hash.to_string(),
+                    salt: salt.to_string(),
+                    session_passwords: HashMap::new(),
+                    failed_attempts: HashMap::new(),
+                    max_attempts: DEFAULT_MAX_ATTEMPTS,
+                    session_keys: HashMap::new(),
+                });
+            }
+        }
 
-        self.save_password_data()?;
-        println!("✅ Master password set successfully!");
-        Ok(())
+        self.save_password_data()
     }
 
-    pub fn verify_master_password(&self) -> io::Result<bool> {
-        if let Some(ref data) = self.password_data {
-            print!("Enter master password: ");
-            std::io::stdout().flush()?;
-            let mut password = String::new();
-            std::io::stdin().read_line(&mut password)?;
-            let password = password.trim();
-
-            let parsed_hash = PasswordHash::new(&data.hashed_password)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Hash parse error: {}", e)))?;
-
-            match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
-                Ok(_) => {
-                    println!("✅ Master password verified!");
-                    Ok(true)
-                }
-                Err(_) => {
-                    println!("❌ Incorrect master password!");
-                    Ok(false)
-                }
-            }
-        } else {
-            Ok(true) // No password set, allow access
+    /// Checks the master password. A wrong one counts towards the lockout, and nothing is
+    /// accepted while locked out. `true` when no master password is set.
+    pub fn check_master_password(&mut self, password: &str) -> Result<bool> {
+        if self.password_data.is_none() {
+            return Ok(true);
         }
+        if self.lockout_remaining(MASTER_SCOPE).is_some() {
+            return Ok(false);
+        }
+        let verified = self.matches_master_password(password);
+        self.record_attempt(MASTER_SCOPE, verified)?;
+        Ok(verified)
     }
 
-    pub fn set_session_password(&mut self, session_name: &str) -> io::Result<()> {
+    fn matches_master_password(&self, password: &str) -> bool {
+        let Some(ref data) = self.password_data else {
+            return true;
+        };
+        if PasswordHash::new(&data.hashed_password).is_err() {
+            println!("{}  The stored master password hash is unreadable; no password will match it.", icon("⚠️"));
+        }
+        verify_password_hash(password, &data.hashed_password)
+    }
+
+    /// Protects a session with `password`, with no strength check. A session whose data is
+    /// already encrypted must be unlocked first (see `check_session_password`), since the
+    /// new password has to wrap the same data key.
+    pub fn set_session_password_to(&mut self, session_name: &str, password: &str) -> Result<()> {
+        if self.password_data.is_none() {
+            return Err(RedruError::Auth("Set a master password before protecting sessions".to_string()));
+        }
+        let key = self.unlocked_or_new_key(session_name).ok_or_else(|| RedruError::Auth(format!(
+            "Session '{}' is encrypted; check its current password first", session_name)))?;
+        self.store_session_password(session_name, password, key)
+    }
+
+    /// The key a new password for `session_name` should wrap, when no password is needed
+    /// to get it: the key unlocked this run, or a fresh one for unencrypted data.
+    fn unlocked_or_new_key(&self, session_name: &str) -> Option<DataKey> {
+        if let Some(key) = self.unlocked.get(session_name) {
+            return Some(key.clone());
+        }
+        (!self.is_session_encrypted(session_name)).then(DataKey::generate)
+    }
+
+    fn store_session_password(&mut self, session_name: &str, password: &str, key: DataKey) -> Result<()> {
+        let password_hash = hash_password(password)?;
+        let wrapped = key.wrap(password)?;
         if let Some(ref mut data) = self.password_data {
-            print!("Enter password for session '{}': ", session_name);
-            std::io::stdout().flush()?;
-            let mut password = String::new();
-            std::io::stdin().read_line(&mut password)?;
-            let password = password.trim();
-
-            print!("Confirm password: ");
-            std::io::stdout().flush()?;
-            let mut confirm = String::new();
-            std::io::stdin().read_line(&mut confirm)?;
-            let confirm = confirm.trim();
-
-            if password != confirm {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Passwords don't match"));
+            data.session_passwords.insert(session_name.to_string(), password_hash);
+            data.session_keys.insert(session_name.to_string(), wrapped);
+        }
+        self.unlocked.insert(session_name.to_string(), key);
+        self.save_password_data()
+    }
+
+    /// Whether the session has a data key, so its files are (or are about to be) encrypted.
+    pub fn is_session_encrypted(&self, session_name: &str) -> bool {
+        self.password_data.as_ref().is_some_and(|data| data.session_keys.contains_key(session_name))
+    }
+
+    /// The session's data key, once its password has been verified in this run.
+    pub fn session_key(&self, session_name: &str) -> Option<DataKey> {
+        self.unlocked.get(session_name).cloned()
+    }
+
+    /// Unwraps the session's data key with its verified password, or creates one for a
+    /// session protected before data keys existed.
+    fn unlock_session(&mut self, session_name: &str, password: &str) -> Result<()> {
+        let wrapped = match self.password_data {
+            Some(ref data) => data.session_keys.get(session_name).cloned(),
+            None => return Ok(()),
+        };
+        let key = match wrapped {
+            Some(wrapped) => DataKey::unwrap(&wrapped, password)?,
+            None => {
+                let key = DataKey::generate();
+                let wrapped = key.wrap(password)?;
+                if let Some(ref mut data) = self.password_data {
+                    data.session_keys.insert(session_name.to_string(), wrapped);
+                }
+                self.save_password_data()?;
+                key
             }
+        };
+        self.unlocked.insert(session_name.to_string(), key);
+        Ok(())
+    }
 
-            let salt = argon2::password_hash::SaltString::generate(&mut rand::thread_rng());
-            let argon2 = Argon2::default();
-            let password_hash = argon2.hash_password(password.as_bytes(), &salt)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Password hash error: {}", e)))?;
+    /// Builds a random password from the OS CSPRNG containing every enabled character class.
+    pub fn generate_password(length: usize, include_symbols: bool) -> String {
+        let mut classes = vec![LOWERCASE, UPPERCASE, DIGITS];
+        if include_symbols {
+            classes.push(SYMBOLS);
+        }
+        let charset: Vec<u8> = classes.concat();
+        let length = length.max(MIN_GENERATED_LENGTH);
+        let mut rng = OsRng;
 
-            data.session_passwords.insert(session_name.to_string(), password_hash.to_string());
-            self.save_password_data()?;
-            println!("✅ Session password set successfully!");
+        loop {
+            let password: String = (0..length)
+                .map(|_| charset[rng.gen_range(0..charset.len())] as char)
+                .collect();
+            if classes.iter().all(|class| password.bytes().any(|b| class.contains(&b))) {
+                return password;
+            }
         }
-        Ok(())
     }
 
-    pub fn verify_session_password(&self, session_name: &str) -> io::Result<bool> {
-        if let Some(ref data) = self.password_data {
-            if let Some(ref hashed_password) = data.session_passwords.get(session_name) {
-                print!("Enter password for session '{}': ", session_name);
-                std::io::stdout().flush()?;
-                let mut password = String::new();
-                std::io::stdin().read_line(&mut password)?;
-                let password = password.trim();
-
-                let parsed_hash = PasswordHash::new(hashed_password)
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Hash parse error: {}", e)))?;
-
-                match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
-                    Ok(_) => {
-                        println!("✅ Session password verified!");
-                        Ok(true)
-                    }
-                    Err(_) => {
-                        println!("❌ Incorrect session password!");
-                        Ok(false)
-                    }
-                }
-            } else {
-                Ok(true) // No password set for this session
+    /// Checks a session's password; on success the session's data key is unlocked (see
+    /// `session_key`). A wrong one counts towards the session's lockout. `true` for
+    /// sessions without a password, as there is nothing to check.
+    pub fn check_session_password(&mut self, session_name: &str, password: &str) -> Result<bool> {
+        let Some(hashed_password) = self.password_data.as_ref()
+            .and_then(|data| data.session_passwords.get(session_name).cloned()) else {
+            return Ok(true);
+        };
+        let scope = session_scope(session_name);
+        if self.lockout_remaining(&scope).is_some() {
+            return Ok(false);
+        }
+        if PasswordHash::new(&hashed_password).is_err() {
+            println!("{}  The stored hash for session '{}' is unreadable; no password will match it.", icon("⚠️"), session_name);
+        }
+        let verified = verify_password_hash(password, &hashed_password);
+        self.record_attempt(&scope, verified)?;
+        if verified {
+            self.unlock_session(session_name, password)?;
+        }
+        Ok(verified)
+    }
+
+    /// Seconds left before `scope` may be tried again, if it is currently locked out.
+    pub fn lockout_remaining(&self, scope: &str) -> Option<u64> {
+        let data = self.password_data.as_ref()?;
+        let record = data.failed_attempts.get(scope)?;
+        let now = unix_now();
+        if record.locked_until > now {
+            Some(record.locked_until - now)
+        } else {
+            None
+        }
+    }
+
+    /// Wrong passwords `scope` may still take before it is locked out.
+    pub fn attempts_remaining(&self, scope: &str) -> Option<u32> {
+        let data = self.password_data.as_ref()?;
+        let failures = data.failed_attempts.get(scope).map_or(0, |record| record.count);
+        Some(data.max_attempts.saturating_sub(failures))
+    }
+
+    fn record_failure(&mut self, scope: &str) -> Result<()> {
+        if let Some(ref mut data) = self.password_data {
+            let max_attempts = data.max_attempts;
+            let record = data.failed_attempts.entry(scope.to_string()).or_default();
+            record.count += 1;
+
+            if record.count >= max_attempts {
+                // Each failure past the limit doubles the wait, up to an hour.
+                let exponent = (record.count - max_attempts).min(16);
+                let lockout = (BASE_LOCKOUT_SECS << exponent).min(MAX_LOCKOUT_SECS);
+                record.locked_until = unix_now() + lockout;
             }
+        }
+        self.save_password_data()
+    }
+
+    fn record_attempt(&mut self, scope: &str, succeeded: bool) -> Result<()> {
+        if succeeded {
+            self.record_success(scope)
         } else {
-            Ok(true) // No master password set
+            self.record_failure(scope)
         }
     }
 
-    pub fn remove_session_password(&mut self, session_name: &str) -> io::Result<()> {
+    fn record_success(&mut self, scope: &str) -> Result<()> {
+        if let Some(ref mut data) = self.password_data
+            && data.failed_attempts.remove(scope).is_some()
+        {
+            return self.save_password_data();
+        }
+        Ok(())
+    }
+
+    pub fn remove_session_password(&mut self, session_name: &str) -> Result<()> {
         if let Some(ref mut data) = self.password_data {
             if data.session_passwords.remove(session_name).is_some() {
+                data.failed_attempts.remove(&session_scope(session_name));
+                data.session_keys.remove(session_name);
+                self.unlocked.remove(session_name);
                 self.save_password_data()?;
-                println!("✅ Session password removed!");
+                println!("{} Session password removed!", icon("✅"));
             } else {
                 println!("No password found for session '{}'", session_name);
             }
@@ -169,6 +356,10 @@ impl PasswordManager {
         Ok(())
     }
 
+    pub fn has_session_password(&self, session_name: &str) -> bool {
+        self.password_data.as_ref().is_some_and(|data| data.session_passwords.contains_key(session_name))
+    }
+
     pub fn list_protected_sessions(&self) -> Vec<String> {
         if let Some(ref data) = self.password_data {
             data.session_passwords.keys().cloned().collect()
@@ -177,37 +368,239 @@ impl PasswordManager {
         }
     }
 
-    fn save_password_data(&self) -> io::Result<()> {
+    fn save_password_data(&self) -> Result<()> {
         if let Some(ref data) = self.password_data {
-            let json = serde_json::to_string_pretty(data)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let json = serde_json::to_string_pretty(data)?;
+
+            // Keep the last readable vault around so a corrupted write can be recovered.
+            let is_readable = fs::read_to_string(&self.password_file)
+                .map(|content| serde_json::from_str::<PasswordData>(&content).is_ok())
+                .unwrap_or(false);
+            if is_readable {
+                fs::copy(&self.password_file, backup_path(&self.password_file))?;
+            }
+
             fs::write(&self.password_file, json)?;
         }
         Ok(())
     }
 
-    pub fn change_master_password(&mut self) -> io::Result<()> {
-        if self.verify_master_password()? {
-            self.set_master_password()?;
+    /// Replaces the master password, rolling back to the previous vault if any step fails
+    /// or a session password hash no longer reads afterwards.
+    pub fn change_master_password_to(&mut self, password: &str) -> Result<()> {
+        let previous = self.password_data.clone();
+        let result = self.set_master_password_to(password).and_then(|_| {
+            let invalid = self.revalidate_session_passwords();
+            if invalid.is_empty() {
+                Ok(())
+            } else {
+                Err(RedruError::Integrity(format!(
+                    "Unreadable password hash for session(s): {}",
+                    invalid.join(", ")
+                )))
+            }
+        });
+
+        if result.is_err() {
+            self.password_data = previous;
+            self.save_password_data()?;
         }
+        result
+    }
+
+    /// Names of protected sessions whose stored hash can no longer be parsed.
+    pub fn revalidate_session_passwords(&self) -> Vec<String> {
+        let mut invalid: Vec<String> = self.password_data.as_ref()
+            .map(|data| {
+                data.session_passwords.iter()
+                    .filter(|(_, hash)| PasswordHash::new(hash).is_err())
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        invalid.sort();
+        invalid
+    }
+
+    /// Writes the vault as `magic | version | salt | nonce | ciphertext`, encrypted with a key
+    /// derived from `passphrase`. The header is authenticated along with the payload.
+    pub fn export_vault<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<()> {
+        let data = self.password_data.as_ref()
+            .ok_or_else(|| RedruError::NotFound("No vault to export".to_string()))?;
+        let plaintext = serde_json::to_vec(data)?;
+
+        let mut salt = [0u8; VAULT_SALT_LEN];
+        let mut nonce = [0u8; VAULT_NONCE_LEN];
+        OsRng.fill(&mut salt);
+        OsRng.fill(&mut nonce);
+
+        let mut header = VAULT_MAGIC.to_vec();
+        header.push(VAULT_VERSION);
+
+        let cipher = vault_cipher(passphrase, &salt)?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &header })
+            .map_err(|_| RedruError::Serialization("Vault encryption failed".to_string()))?;
+
+        let mut blob = header;
+        blob.extend_from_slice(&salt);
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&ciphertext);
+        fs::write(path, blob)?;
+        println!("{} Vault exported!", icon("✅"));
         Ok(())
     }
 
-    pub fn reset_all_passwords(&mut self) -> io::Result<()> {
-        print!("Are you sure you want to reset all passwords? (yes/no): ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        
-        if input.trim().to_lowercase() == "yes" {
-            if Path::new(&self.password_file).exists() {
-                fs::remove_file(&self.password_file)?;
-            }
-            self.password_data = None;
-            println!("✅ All passwords reset!");
-        } else {
-            println!("Password reset cancelled.");
+    /// Restores a vault written by `export_vault`, rejecting unknown versions, wrong
+    /// passphrases, and tampered or truncated files without touching the current vault.
+    pub fn import_vault<P: AsRef<Path>>(&mut self, path: P, passphrase: &str) -> Result<()> {
+        let blob = fs::read(path)?;
+        let min_len = VAULT_HEADER_LEN + VAULT_SALT_LEN + VAULT_NONCE_LEN;
+        if blob.len() < min_len || &blob[..VAULT_MAGIC.len()] != VAULT_MAGIC {
+            return Err(RedruError::Integrity("Not a redru vault export".to_string()));
+        }
+
+        let version = blob[VAULT_MAGIC.len()];
+        if version != VAULT_VERSION {
+            return Err(RedruError::Integrity(format!(
+                "Unsupported vault version {} (expected {})",
+                version, VAULT_VERSION
+            )));
         }
+
+        let (header, rest) = blob.split_at(VAULT_HEADER_LEN);
+        let (salt, rest) = rest.split_at(VAULT_SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(VAULT_NONCE_LEN);
+
+        let cipher = vault_cipher(passphrase, salt)?;
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| RedruError::Auth("Wrong passphrase or corrupted vault".to_string()))?;
+        let data: PasswordData = serde_json::from_slice(&plaintext)
+            .map_err(|e| RedruError::Serialization(format!("Vault contents invalid: {}", e)))?;
+
+        self.password_data = Some(data);
+        self.save_password_data()?;
+        println!("{} Vault imported!", icon("✅"));
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Sessions whose data is encrypted with a key only their password unwraps, sorted.
+    /// Their data cannot be opened once the vault is reset.
+    pub fn encrypted_sessions(&self) -> Vec<String> {
+        let mut encrypted: Vec<String> = self.password_data.as_ref()
+            .map(|data| data.session_keys.keys().cloned().collect())
+            .unwrap_or_default();
+        encrypted.sort();
+        encrypted
+    }
+
+    /// Deletes the vault and its backup; see `encrypted_sessions` for what that loses.
+    pub fn reset_all_passwords(&mut self) -> Result<()> {
+        if Path::new(&self.password_file).exists() {
+            fs::remove_file(&self.password_file)?;
+        }
+        let backup_file = backup_path(&self.password_file);
+        if Path::new(&backup_file).exists() {
+            fs::remove_file(&backup_file)?;
+        }
+        self.password_data = None;
+        self.unlocked.clear();
+        println!("{} All passwords reset!", icon("✅"));
+        Ok(())
+    }
+}
+
+/// Returns the strength criteria `password` fails; an empty list means it looks strong.
+pub fn password_weaknesses(password: &str) -> Vec<&'static str> {
+    let mut weaknesses = Vec::new();
+    if password.chars().count() < MIN_STRONG_LENGTH {
+        weaknesses.push("shorter than 12 characters");
+    }
+    if !password.chars().any(|c| c.is_lowercase()) {
+        weaknesses.push("no lowercase letters");
+    }
+    if !password.chars().any(|c| c.is_uppercase()) {
+        weaknesses.push("no uppercase letters");
+    }
+    if !password.chars().any(|c| c.is_ascii_digit()) {
+        weaknesses.push("no digits");
+    }
+    if password.chars().all(|c| c.is_alphanumeric()) {
+        weaknesses.push("no symbols");
+    }
+    if COMMON_PASSWORDS.contains(&password.to_lowercase().as_str()) {
+        weaknesses.push("is a commonly used password");
+    }
+    weaknesses
+}
+
+/// The value of `MASTER_PASSWORD_ENV`, if it is set. A value that is not valid UTF-8 is an
+/// error rather than "unset", so a mistyped variable cannot quietly bring the prompt back.
+pub fn master_password_from_env() -> Result<Option<String>> {
+    match std::env::var(MASTER_PASSWORD_ENV) {
+        Ok(password) => Ok(Some(password)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => Err(RedruError::Auth(format!(
+            "{} is set but is not valid UTF-8", MASTER_PASSWORD_ENV))),
+    }
+}
+
+/// `passwords.json`, or `passwords.<profile>.json`.
+pub fn password_file(profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("passwords.{}.json", profile),
+        None => "passwords.json".to_string(),
+    }
+}
+
+fn backup_path(password_file: &str) -> String {
+    format!("{}.bak", password_file)
+}
+
+fn vault_cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| RedruError::Serialization(format!("Key derivation error: {}", e)))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Argon2 hash (default parameters) that stands in for a stored hash that does not parse.
+/// The password behind it does not matter: a match against it is never accepted.
+const STAND_IN_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$PBk4ahFFUhH11DypVlNg3Q$Kf0gUAXF0FvFO8kL7ZC6R9Tedx/G7Zzx8dur0Y7ooXo";
+
+/// Whether `password` matches `stored`. Every call costs one full Argon2 run, which
+/// compares in constant time: a wrong password, and a stored hash that does not parse
+/// (checked against `STAND_IN_HASH`, then rejected), take as long as a right one.
+pub fn verify_password_hash(password: &str, stored: &str) -> bool {
+    let (hash, usable) = match PasswordHash::new(stored) {
+        Ok(hash) => (hash, true),
+        Err(_) => (PasswordHash::new(STAND_IN_HASH).expect("stand-in hash parses"), false),
+    };
+    Argon2::default().verify_password(password.as_bytes(), &hash).is_ok() && usable
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = argon2::password_hash::SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| RedruError::Serialization(format!("Password hash error: {}", e)))
+}
+
+fn default_max_attempts() -> u32 {
+    DEFAULT_MAX_ATTEMPTS
+}
+
+/// The lockout scope of a session's password, for `lockout_remaining` and `attempts_remaining`.
+pub fn session_scope(session_name: &str) -> String {
+    format!("session:{}", session_name)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
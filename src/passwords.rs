@@ -0,0 +1,344 @@
+//! The password prompts and the password menu. `PasswordManager` itself never asks for
+//! anything; these read what it needs from the terminal and report how checks went.
+
+use std::io::{self, Write};
+use redru::display::icon;
+use redru::error::{RedruError, Result};
+use redru::password_manager::{master_password_from_env, password_weaknesses, session_scope, PasswordManager,
+    DEFAULT_GENERATED_LENGTH, MASTER_PASSWORD_ENV, MASTER_SCOPE};
+use crate::prompt::{confirm, confirm_default_yes};
+use crate::sessions::{convert_session_files, ensure_session_encrypted, select_session};
+
+pub fn set_master_password(password_manager: &mut PasswordManager) -> Result<()> {
+    let Some(password) = read_new_password("Enter master password: ", "Confirm master password: ")? else {
+        println!("Master password not changed.");
+        return Ok(());
+    };
+    password_manager.set_master_password_to(&password)?;
+    println!("{} Master password set successfully!", icon("✅"));
+    Ok(())
+}
+
+/// Checks the master password, taken from `MASTER_PASSWORD_ENV` when that is set and
+/// asked for otherwise. A wrong password from the environment fails like a wrong one
+/// typed (and counts towards the lockout); it never falls back to prompting, which
+/// would wait forever in an unattended run.
+pub fn verify_master_password(password_manager: &mut PasswordManager) -> Result<bool> {
+    if !password_manager.is_master_password_set() {
+        return Ok(true); // No password set, allow access
+    }
+    if report_lockout(password_manager, MASTER_SCOPE) {
+        return Ok(false);
+    }
+
+    let from_env = master_password_from_env()?;
+    let password = match &from_env {
+        Some(password) => password.trim().to_string(),
+        None => read_password("Enter master password: ")?,
+    };
+
+    let verified = password_manager.check_master_password(&password)?;
+    match (verified, from_env.is_some()) {
+        (true, true) => println!("{} Master password verified from {}!", icon("✅"), MASTER_PASSWORD_ENV),
+        (true, false) => println!("{} Master password verified!", icon("✅")),
+        (false, true) => println!("{} {} does not hold the master password. Fix or unset it; no prompt is shown while it is set.",
+            icon("❌"), MASTER_PASSWORD_ENV),
+        (false, false) => println!("{} Incorrect master password!", icon("❌")),
+    }
+    if !verified {
+        report_failure(password_manager, MASTER_SCOPE);
+    }
+    Ok(verified)
+}
+
+/// Checks the master password again, then replaces it. A failed change is rolled back.
+pub fn change_master_password(password_manager: &mut PasswordManager) -> Result<()> {
+    if !verify_master_password(password_manager)? {
+        return Ok(());
+    }
+
+    let result = match read_new_password("Enter master password: ", "Confirm master password: ") {
+        Ok(Some(password)) => password_manager.change_master_password_to(&password),
+        Ok(None) => {
+            println!("Master password not changed.");
+            return Ok(());
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => println!("{} Master password set successfully!", icon("✅")),
+        Err(e) => println!("{} Master password change rolled back: {}", icon("❌"), e),
+    }
+    Ok(())
+}
+
+pub fn set_session_password(password_manager: &mut PasswordManager, session_name: &str) -> Result<()> {
+    if !password_manager.is_master_password_set() || !unlock_for_new_password(password_manager, session_name)? {
+        return Ok(());
+    }
+    let prompt = format!("Enter password for session '{}': ", session_name);
+    let Some(password) = read_new_password(&prompt, "Confirm password: ")? else {
+        println!("Session password not changed.");
+        return Ok(());
+    };
+    password_manager.set_session_password_to(session_name, &password)?;
+    println!("{} Session password set successfully!", icon("✅"));
+    Ok(())
+}
+
+/// Protects a session with a freshly generated password, shown once and stored only as a hash.
+pub fn set_generated_session_password(password_manager: &mut PasswordManager, session_name: &str, length: usize, include_symbols: bool) -> Result<()> {
+    if !password_manager.is_master_password_set() || !unlock_for_new_password(password_manager, session_name)? {
+        return Ok(());
+    }
+    let password = PasswordManager::generate_password(length, include_symbols);
+    password_manager.set_session_password_to(session_name, &password)?;
+    println!("Generated password for session '{}': {}", session_name, password);
+    println!("{}  Store it somewhere safe now, it will not be shown again.", icon("⚠️"));
+    println!("{} Session password set successfully!", icon("✅"));
+    Ok(())
+}
+
+/// A new password has to wrap the session's current data key, so an encrypted session not
+/// unlocked in this run needs its current password first. `false` when it was not given.
+fn unlock_for_new_password(password_manager: &mut PasswordManager, session_name: &str) -> Result<bool> {
+    if password_manager.session_key(session_name).is_some() || !password_manager.is_session_encrypted(session_name) {
+        return Ok(true);
+    }
+    println!("The session's data is encrypted with its current password; enter that first.");
+    if verify_session_password(password_manager, session_name)? {
+        Ok(true)
+    } else {
+        println!("Session password not changed.");
+        Ok(false)
+    }
+}
+
+pub fn verify_session_password(password_manager: &mut PasswordManager, session_name: &str) -> Result<bool> {
+    if !password_manager.has_session_password(session_name) {
+        // Nothing to check, so no prompt either. The pickers already mark which
+        // sessions are protected, and only to someone past the master password.
+        return Ok(true);
+    }
+    let scope = session_scope(session_name);
+    if report_lockout(password_manager, &scope) {
+        return Ok(false);
+    }
+
+    let password = read_password(&format!("Enter password for session '{}': ", session_name))?;
+    match password_manager.check_session_password(session_name, &password) {
+        Ok(true) => {
+            println!("{} Session password verified!", icon("✅"));
+            Ok(true)
+        }
+        Ok(false) => {
+            println!("{} Incorrect session password!", icon("❌"));
+            report_failure(password_manager, &scope);
+            Ok(false)
+        }
+        Err(e) => {
+            println!("{} Could not unlock the data of session '{}': {}", icon("❌"), session_name, e);
+            Ok(false)
+        }
+    }
+}
+
+pub fn reset_all_passwords(password_manager: &mut PasswordManager) -> Result<()> {
+    let encrypted = password_manager.encrypted_sessions();
+    if !encrypted.is_empty() {
+        println!("{}  The data of these sessions is encrypted and cannot be opened after a reset: {}",
+            icon("⚠️"), encrypted.join(", "));
+    }
+    if confirm("Are you sure you want to reset all passwords?")? {
+        password_manager.reset_all_passwords()?;
+    } else {
+        println!("Password reset cancelled.");
+    }
+    Ok(())
+}
+
+fn report_lockout(password_manager: &PasswordManager, scope: &str) -> bool {
+    if let Some(remaining) = password_manager.lockout_remaining(scope) {
+        println!("{} Too many failed attempts. Try again in {} seconds.", icon("🔒"), remaining);
+        true
+    } else {
+        false
+    }
+}
+
+/// After a wrong password: the lockout it started, or how many tries are left.
+fn report_failure(password_manager: &PasswordManager, scope: &str) {
+    if let Some(lockout) = password_manager.lockout_remaining(scope) {
+        println!("{} Too many failed attempts. Locked for {} seconds.", icon("🔒"), lockout);
+    } else if let Some(attempts) = password_manager.attempts_remaining(scope) {
+        println!("{} attempt(s) remaining before lockout.", attempts);
+    }
+}
+
+fn read_password(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    Ok(password.trim().to_string())
+}
+
+/// Asks for a new password twice. `None` when it is weak and the user does not keep it.
+fn read_new_password(prompt: &str, confirm_prompt: &str) -> Result<Option<String>> {
+    let password = read_password(prompt)?;
+    if password != read_password(confirm_prompt)? {
+        return Err(RedruError::Auth("Passwords don't match".to_string()));
+    }
+    Ok(accept_password_strength(&password)?.then_some(password))
+}
+
+/// Warns about weak passwords and asks whether to keep them anyway; never blocks outright.
+fn accept_password_strength(password: &str) -> Result<bool> {
+    let weaknesses = password_weaknesses(password);
+    if weaknesses.is_empty() {
+        return Ok(true);
+    }
+
+    println!("{}  This password is weak:", icon("⚠️"));
+    for weakness in &weaknesses {
+        println!("  - {}", weakness);
+    }
+    Ok(confirm("Use it anyway?")?)
+}
+
+pub fn password_management_menu(password_manager: &mut PasswordManager) -> io::Result<()> {
+    loop {
+        println!("\n{} Password Management:", icon("🔐"));
+        println!("  1. Set/Change master password");
+        println!("  2. Set session password");
+        println!("  3. Remove session password");
+        println!("  4. List protected sessions");
+        println!("  5. Reset all passwords");
+        println!("  6. Set lockout threshold");
+        println!("  7. Generate session password");
+        println!("  8. Export vault");
+        println!("  9. Import vault");
+        println!("  10. Back to main menu");
+        print!("Select option (1-10): ");
+        std::io::stdout().flush()?;
+        
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        
+        match input.trim() {
+            "1" => {
+                if password_manager.is_master_password_set() {
+                    change_master_password(password_manager)?;
+                } else {
+                    set_master_password(password_manager)?;
+                }
+            }
+            "2" => {
+                if let Some(session_name) = select_session(password_manager)? {
+                    set_session_password(password_manager, &session_name)?;
+                    ensure_session_encrypted(&session_name, password_manager.session_key(&session_name).as_ref())?;
+                }
+            }
+            "3" => {
+                let protected_sessions = password_manager.list_protected_sessions();
+                if protected_sessions.is_empty() {
+                    println!("No protected sessions found.");
+                    continue;
+                }
+                
+                println!("Protected sessions:");
+                for (i, session) in protected_sessions.iter().enumerate() {
+                    println!("  {}. {}", i + 1, session);
+                }
+                
+                print!("Select session (1-{}): ", protected_sessions.len());
+                std::io::stdout().flush()?;
+                let mut session_input = String::new();
+                std::io::stdin().read_line(&mut session_input)?;
+                
+                if let Ok(index) = session_input.trim().parse::<usize>()
+                    && index > 0 && index <= protected_sessions.len()
+                {
+                    let session_name = &protected_sessions[index - 1];
+                    if password_manager.is_session_encrypted(session_name) {
+                        // The data stays readable only if it is decrypted before the key goes.
+                        println!("The session's data is encrypted with its password; enter it to decrypt.");
+                        if !verify_session_password(password_manager, session_name)? {
+                            println!("Session password not removed.");
+                            continue;
+                        }
+                        let key = password_manager.session_key(session_name);
+                        convert_session_files(session_name, key.as_ref(), None)?;
+                    }
+                    password_manager.remove_session_password(session_name)?;
+                }
+            }
+            "4" => {
+                let protected_sessions = password_manager.list_protected_sessions();
+                if protected_sessions.is_empty() {
+                    println!("No protected sessions found.");
+                } else {
+                    println!("Protected sessions:");
+                    for session in protected_sessions {
+                        println!("  {} {}", icon("🔒"), session);
+                    }
+                }
+            }
+            "5" => {
+                reset_all_passwords(password_manager)?;
+            }
+            "6" => {
+                print!("Failed attempts allowed before lockout: ");
+                std::io::stdout().flush()?;
+                let mut attempts_input = String::new();
+                std::io::stdin().read_line(&mut attempts_input)?;
+                match attempts_input.trim().parse::<u32>() {
+                    Ok(attempts) => password_manager.set_max_attempts(attempts)?,
+                    Err(_) => println!("Invalid number."),
+                }
+            }
+            "7" => {
+                if let Some(session_name) = select_session(password_manager)? {
+                    let (length, include_symbols) = prompt_generator_options()?;
+                    set_generated_session_password(password_manager, &session_name, length, include_symbols)?;
+                    ensure_session_encrypted(&session_name, password_manager.session_key(&session_name).as_ref())?;
+                }
+            }
+            "8" | "9" => {
+                print!("Vault file path: ");
+                std::io::stdout().flush()?;
+                let mut path = String::new();
+                std::io::stdin().read_line(&mut path)?;
+
+                print!("Vault passphrase: ");
+                std::io::stdout().flush()?;
+                let mut passphrase = String::new();
+                std::io::stdin().read_line(&mut passphrase)?;
+
+                let result = if input.trim() == "8" {
+                    password_manager.export_vault(path.trim(), passphrase.trim())
+                } else {
+                    password_manager.import_vault(path.trim(), passphrase.trim())
+                };
+                if let Err(e) = result {
+                    println!("{} {}", icon("❌"), e);
+                }
+            }
+            "10" => break,
+            _ => println!("Invalid option."),
+        }
+    }
+    Ok(())
+}
+
+pub fn prompt_generator_options() -> io::Result<(usize, bool)> {
+    print!("Password length (default {}): ", DEFAULT_GENERATED_LENGTH);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let length = input.trim().parse().unwrap_or(DEFAULT_GENERATED_LENGTH);
+
+    let include_symbols = confirm_default_yes("Include symbols?")?;
+
+    Ok((length, include_symbols))
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Share of the total between textual progress lines when stdout is not a terminal.
+const TEXT_REPORT_PERCENT: usize = 10;
+/// Smaller jobs get no textual progress; their per-item output is enough.
+const TEXT_REPORT_MIN: usize = 20;
+
+/// Items completed out of a known total. On a terminal this is a bar with an ETA; when
+/// output is piped or scripted, jobs of 20 items or more print a plain line every 10%.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    label: String,
+    total: usize,
+    done: usize,
+    started: Instant,
+    /// Percentage at which the next textual line is printed.
+    next_percent: usize,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: usize) -> Self {
+        let bar = std::io::stdout().is_terminal().then(|| {
+            let bar = ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stdout());
+            bar.set_style(
+                ProgressStyle::with_template("{msg} [{bar:30}] {pos}/{len} ETA {eta}")
+                    .expect("static template is valid")
+                    .progress_chars("=> "),
+            );
+            bar.set_message(label.to_string());
+            bar
+        });
+        Progress {
+            bar,
+            label: label.to_string(),
+            total,
+            done: 0,
+            started: Instant::now(),
+            next_percent: if total < TEXT_REPORT_MIN { usize::MAX } else { TEXT_REPORT_PERCENT },
+        }
+    }
+
+    /// Runs `f` with the bar hidden, so anything it prints is not drawn over.
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.bar {
+            Some(bar) => bar.suspend(f),
+            None => f(),
+        }
+    }
+
+    pub fn inc(&mut self) {
+        self.done += 1;
+        match &self.bar {
+            Some(bar) => bar.inc(1),
+            None if self.done < self.total && self.done * 100 / self.total >= self.next_percent => {
+                let percent = self.done * 100 / self.total;
+                println!("{}: {}/{} ({}%), ETA {}", self.label, self.done, self.total, percent, format_eta(self.eta()));
+                self.next_percent = (percent / TEXT_REPORT_PERCENT + 1) * TEXT_REPORT_PERCENT;
+            }
+            None => {}
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn eta(&self) -> Duration {
+        let per_item = self.started.elapsed() / self.done.max(1) as u32;
+        per_item * self.total.saturating_sub(self.done) as u32
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("label", &self.label)
+            .field("done", &self.done)
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+fn format_eta(eta: Duration) -> String {
+    match eta.as_secs() {
+        secs @ 0..60 => format!("{}s", secs),
+        secs @ 60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        secs => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
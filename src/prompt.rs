@@ -0,0 +1,30 @@
+use std::io::{self, Write};
+
+/// `y`/`yes` and `n`/`no` in any case, ignoring surrounding whitespace; anything else is
+/// not an answer.
+pub fn parse_yes_no(answer: &str) -> Option<bool> {
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(true),
+        "n" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Asks a yes/no question. Only an explicit yes counts, so a typo or end of input never
+/// confirms a destructive action.
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    Ok(ask(prompt, "(y/n)")?.unwrap_or(false))
+}
+
+/// Like `confirm`, but anything other than an explicit no (including just Enter) is a yes.
+pub fn confirm_default_yes(prompt: &str) -> io::Result<bool> {
+    Ok(ask(prompt, "(Y/n)")?.unwrap_or(true))
+}
+
+fn ask(prompt: &str, choices: &str) -> io::Result<Option<bool>> {
+    print!("{} {}: ", prompt, choices);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(parse_yes_no(&input))
+}
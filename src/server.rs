@@ -0,0 +1,191 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::crypto::DataKey;
+use crate::db::ShardedDB;
+use crate::display::icon;
+use crate::error::RedruError;
+use crate::hash_index::MatchMode;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+const WORKERS: usize = 4;
+
+/// Starts serving the database at `db_file` over HTTP on background threads, which keep
+/// answering until `ServerHandle::stop` is called.
+///
+/// Records live in a `ShardedDB`, so requests for keys in different shards do not wait for
+/// each other. A write is only acknowledged once a snapshot containing it has been saved;
+/// concurrent writes share one save when they land while another save is in progress.
+/// Saves are encrypted with `key` when the session is password-protected. With `read_only`,
+/// PUT and DELETE are refused with 405 and nothing is ever written.
+pub fn serve(db_file: &str, addr: &str, key: Option<DataKey>, read_only: bool) -> io::Result<ServerHandle> {
+    let db = ShardedDB::open_with_key(db_file, key)?;
+    let server = Server::http(addr)
+        .map_err(|e| io::Error::other(format!("Could not bind {}: {}", addr, e)))?;
+    let server = Arc::new(server);
+
+    println!("{} Serving '{}' on http://{}", icon("🌐"), db_file, addr);
+    println!("  GET    /keys          - List keys");
+    println!("  GET    /keys/<key>    - Get a value");
+    if read_only {
+        println!("  (read-only: PUT and DELETE are refused)");
+    } else {
+        println!("  PUT    /keys/<key>    - Insert or replace a value (JSON body)");
+        println!("  DELETE /keys/<key>    - Delete a value");
+    }
+    println!("  POST   /search        - Body {{\"field\": ..., \"value\": ..., \"mode\": \"exact|icase|contains|prefix\"}}");
+
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let db = db.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(request, &db, read_only);
+                }
+            })
+        })
+        .collect();
+
+    Ok(ServerHandle { server, workers })
+}
+
+/// A running `serve`. Dropping it leaves the server running until the process exits.
+pub struct ServerHandle {
+    server: Arc<Server>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address actually bound, which differs from the requested one for port 0.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Stops accepting requests and waits for the ones in flight to be answered.
+    pub fn stop(self) {
+        for _ in 0..self.workers.len() {
+            self.server.unblock();
+        }
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn handle_request(mut request: Request, db: &ShardedDB, read_only: bool) {
+    let mut body = String::new();
+    let (status, payload) = match request.as_reader().read_to_string(&mut body) {
+        Ok(_) if read_only && matches!(request.method(), Method::Put | Method::Delete) => method_not_allowed(),
+        Ok(_) => route(request.method(), request.url(), &body, db),
+        Err(e) => (400, json!({"error": format!("Could not read body: {}", e)})),
+    };
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("static header is valid");
+    let response = Response::from_string(payload.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    let _ = request.respond(response);
+}
+
+fn route(method: &Method, url: &str, body: &str, db: &ShardedDB) -> (u16, Value) {
+    let path = url.split('?').next().unwrap_or("");
+
+    if path == "/keys" || path == "/keys/" {
+        return match method {
+            Method::Get => {
+                let mut keys = db.list_keys();
+                keys.sort();
+                (200, json!(keys))
+            }
+            _ => method_not_allowed(),
+        };
+    }
+
+    if path == "/search" {
+        return match method {
+            Method::Post => {
+                let query: Value = match serde_json::from_str(body) {
+                    Ok(query) => query,
+                    Err(e) => return (400, json!({"error": format!("Invalid JSON: {}", e)})),
+                };
+                let (Some(field), Some(value)) = (query.get("field").and_then(Value::as_str), query.get("value")) else {
+                    return (400, json!({"error": "Body must contain \"field\" and \"value\""}));
+                };
+                let mode = match query.get("mode").and_then(Value::as_str) {
+                    None => MatchMode::Exact,
+                    Some(name) => match MatchMode::parse(name) {
+                        Some(mode) => mode,
+                        None => return (400, json!({"error": format!("Unknown match mode '{}'", name)})),
+                    },
+                };
+                // Strings are passed as raw text so the text-based modes see them unquoted.
+                let value = value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string());
+                let mut keys = db.search_by_field_matching(field, &value, mode);
+                keys.sort();
+                (200, json!(keys))
+            }
+            _ => method_not_allowed(),
+        };
+    }
+
+    let Some(key) = path.strip_prefix("/keys/").map(percent_decode) else {
+        return (404, json!({"error": "Not found"}));
+    };
+
+    match method {
+        Method::Get => match db.get(&key) {
+            Some(value) => (200, value),
+            None => (404, json!({"error": format!("Key '{}' not found", key)})),
+        },
+        Method::Put => {
+            let value: Value = match serde_json::from_str(body) {
+                Ok(value) => value,
+                Err(e) => return (400, json!({"error": format!("Invalid JSON: {}", e)})),
+            };
+            let saved = db.insert(&key, value).and_then(|existed| db.save_if_dirty().map(|_| existed));
+            match saved {
+                Ok(existed) => (if existed { 200 } else { 201 }, json!({"key": key})),
+                Err(e @ RedruError::Limit(_)) => (413, json!({"error": e.to_string()})),
+                Err(e @ RedruError::Conflict(_)) => (409, json!({"error": format!("Not saved: {}", e)})),
+                Err(e) => (500, json!({"error": format!("Save failed: {}", e)})),
+            }
+        }
+        Method::Delete => {
+            let deleted = if db.delete(&key) { db.save_if_dirty().map(|_| true) } else { Ok(false) };
+            match deleted {
+                Ok(true) => (200, json!({"deleted": key})),
+                Ok(false) => (404, json!({"error": format!("Key '{}' not found", key)})),
+                Err(e @ RedruError::Conflict(_)) => (409, json!({"error": format!("Not saved: {}", e)})),
+                Err(e) => (500, json!({"error": format!("Save failed: {}", e)})),
+            }
+        }
+        _ => method_not_allowed(),
+    }
+}
+
+fn method_not_allowed() -> (u16, Value) {
+    (405, json!({"error": "Method not allowed"}))
+}
+
+/// Decodes `%XX` escapes in a URL path segment; malformed escapes are kept literally.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = segment.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,325 @@
+//! Picking, creating, deleting and serving sessions, and where their files live.
+
+use std::io::{self, Write};
+use std::fs;
+use std::path::Path;
+use redru::{db, server};
+use redru::crypto::DataKey;
+use redru::db::InMemoryDB;
+use redru::display::icon;
+use redru::password_manager::PasswordManager;
+use crate::passwords::{prompt_generator_options, set_generated_session_password, set_session_password, verify_session_password};
+use crate::prompt::confirm;
+use crate::shell::run_session;
+use crate::{read_only, PROFILE};
+
+pub fn use_existing_session(password_manager: &mut PasswordManager) -> io::Result<()> {
+    let sessions = get_available_sessions()?;
+    if sessions.is_empty() {
+        println!("No sessions found.");
+        return Ok(());
+    }
+    
+    println!("Available sessions:");
+    for (i, session) in sessions.iter().enumerate() {
+        let protected = password_manager.list_protected_sessions().contains(session);
+        let status = if protected { icon("🔒") } else { icon("🔓") };
+        println!("  {}. {} {}", i + 1, status, session);
+    }
+    for (i, session) in sessions.iter().enumerate() {
+        if let Some(other) = case_collision(session, &sessions[i + 1..]) {
+            println!("{}  '{}' and '{}' differ only in case and will clash on case-insensitive filesystems.", icon("⚠️"), session, other);
+        }
+    }
+    
+    print!("Select session (1-{}): ", sessions.len());
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    
+    if let Ok(index) = input.trim().parse::<usize>() {
+        if index > 0 && index <= sessions.len() {
+            let session_name = &sessions[index - 1];
+            
+            // Check if session is password protected
+            if password_manager.list_protected_sessions().contains(session_name) {
+                if !verify_session_password(password_manager, session_name)? {
+                    println!("{} Access denied to session '{}'", icon("❌"), session_name);
+                    return Ok(());
+                }
+            }
+            
+            run_session(session_name, password_manager.session_key(session_name))?;
+        } else {
+            println!("Invalid session number.");
+        }
+    } else {
+        println!("Invalid input.");
+    }
+    Ok(())
+}
+
+pub fn serve_session(password_manager: &mut PasswordManager) -> io::Result<()> {
+    let Some(session_name) = select_session(password_manager)? else {
+        return Ok(());
+    };
+    if password_manager.list_protected_sessions().contains(&session_name)
+        && !verify_session_password(password_manager, &session_name)?
+    {
+        println!("{} Access denied to session '{}'", icon("❌"), session_name);
+        return Ok(());
+    }
+
+    print!("Bind address (default {}): ", server::DEFAULT_ADDR);
+    std::io::stdout().flush()?;
+    let mut addr = String::new();
+    std::io::stdin().read_line(&mut addr)?;
+    let addr = match addr.trim() {
+        "" => server::DEFAULT_ADDR,
+        addr => addr,
+    };
+
+    let key = password_manager.session_key(&session_name);
+    if !read_only() {
+        ensure_session_encrypted(&session_name, key.as_ref())?;
+    }
+    let db_file = format!("{}/database.json", session_dir(&session_name)?);
+    let server = match server::serve(&db_file, addr, key, read_only()) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("{} Server error: {}", icon("❌"), e);
+            return Ok(());
+        }
+    };
+    println!("Press Enter to stop the server.");
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    server.stop();
+    println!("Server stopped.");
+    Ok(())
+}
+
+pub fn create_new_session(password_manager: &mut PasswordManager) -> io::Result<()> {
+    print!("Enter session name: ");
+    std::io::stdout().flush()?;
+    let mut session_name = String::new();
+    std::io::stdin().read_line(&mut session_name)?;
+    let session_name = session_name.trim();
+    
+    if let Err(problem) = check_session_name(session_name) {
+        println!("{} Invalid session name: {}.", icon("❌"), problem);
+        return Ok(());
+    }
+    
+    // Check if session already exists
+    let sessions = get_available_sessions()?;
+    if sessions.contains(&session_name.to_string()) {
+        println!("Session '{}' already exists.", session_name);
+        return Ok(());
+    }
+    if let Some(existing) = case_collision(session_name, &sessions) {
+        println!("{} Session '{}' differs from existing session '{}' only in case.", icon("❌"), session_name, existing);
+        println!("   On case-insensitive filesystems they would share one directory. Choose another name.");
+        return Ok(());
+    }
+    
+    // Ask if user wants to password protect this session
+    if confirm("Do you want to password protect this session?")? {
+        if confirm("Generate a strong password?")? {
+            let (length, include_symbols) = prompt_generator_options()?;
+            set_generated_session_password(password_manager, session_name, length, include_symbols)?;
+        } else {
+            set_session_password(password_manager, session_name)?;
+        }
+    }
+    
+    // Create session directory
+    let session_dir = session_dir(session_name)?;
+    fs::create_dir_all(&session_dir)?;
+    
+    // Create initial database file
+    let db_file = format!("{}/database.json", session_dir);
+    let mut db = InMemoryDB::new();
+    db.set_encryption_key(password_manager.session_key(session_name));
+    db.save_to_file_with_path(&db_file)?;
+    
+    println!("{} Session '{}' created successfully!", icon("✅"), session_name);
+    Ok(())
+}
+
+pub fn delete_session(password_manager: &mut PasswordManager) -> io::Result<()> {
+    let sessions = get_available_sessions()?;
+    if sessions.is_empty() {
+        println!("No sessions found.");
+        return Ok(());
+    }
+    
+    println!("Available sessions:");
+    for (i, session) in sessions.iter().enumerate() {
+        let protected = password_manager.list_protected_sessions().contains(session);
+        let status = if protected { icon("🔒") } else { icon("🔓") };
+        println!("  {}. {} {}", i + 1, status, session);
+    }
+    
+    print!("Select session to delete (1-{}): ", sessions.len());
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    
+    if let Ok(index) = input.trim().parse::<usize>() {
+        if index > 0 && index <= sessions.len() {
+            let session_name = &sessions[index - 1];
+            
+            // Check if session is password protected
+            if password_manager.list_protected_sessions().contains(session_name) {
+                if !verify_session_password(password_manager, session_name)? {
+                    println!("{} Access denied to session '{}'", icon("❌"), session_name);
+                    return Ok(());
+                }
+            }
+            
+            if confirm(&format!("Are you sure you want to delete session '{}'?", session_name))? {
+                let session_dir = session_dir(session_name)?;
+                if Path::new(&session_dir).exists() {
+                    fs::remove_dir_all(&session_dir)?;
+                }
+                password_manager.remove_session_password(session_name)?;
+                println!("{} Session '{}' deleted successfully!", icon("✅"), session_name);
+            } else {
+                println!("Session deletion cancelled.");
+            }
+        } else {
+            println!("Invalid session number.");
+        }
+    } else {
+        println!("Invalid input.");
+    }
+    Ok(())
+}
+
+pub fn select_session(password_manager: &PasswordManager) -> io::Result<Option<String>> {
+    let sessions = get_available_sessions()?;
+    if sessions.is_empty() {
+        println!("No sessions found.");
+        return Ok(None);
+    }
+
+    println!("Available sessions:");
+    for (i, session) in sessions.iter().enumerate() {
+        let protected = password_manager.list_protected_sessions().contains(session);
+        let status = if protected { icon("🔒") } else { icon("🔓") };
+        println!("  {}. {} {}", i + 1, status, session);
+    }
+
+    print!("Select session (1-{}): ", sessions.len());
+    std::io::stdout().flush()?;
+    let mut session_input = String::new();
+    std::io::stdin().read_line(&mut session_input)?;
+
+    match session_input.trim().parse::<usize>() {
+        Ok(index) if index > 0 && index <= sessions.len() => Ok(Some(sessions[index - 1].clone())),
+        _ => Ok(None),
+    }
+}
+
+/// `sessions`, or `sessions.<profile>` when a profile was chosen.
+fn sessions_root() -> String {
+    match PROFILE.get() {
+        Some(profile) => format!("sessions.{}", profile),
+        None => "sessions".to_string(),
+    }
+}
+
+/// Session directories under `sessions_root()`. Directories whose names `check_session_name`
+/// rejects are left out; the shell never creates them, so they were made by hand. So are
+/// names that are not valid UTF-8, with a warning: shown lossily they would name a
+/// different directory, and opening that would start a new, empty session.
+pub fn get_available_sessions() -> io::Result<Vec<String>> {
+    let sessions_dir = sessions_root();
+    if !Path::new(&sessions_dir).exists() {
+        return Ok(Vec::new());
+    }
+    
+    let sessions: Vec<String> = fs::read_dir(&sessions_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+        .filter_map(|e| match e.file_name().into_string() {
+            Ok(name) => Some(name),
+            Err(name) => {
+                println!("{}  Skipping session directory {}: its name is not valid UTF-8", icon("⚠️"),
+                    Path::new(&sessions_dir).join(name).display());
+                None
+            }
+        })
+        .filter(|name| check_session_name(name).is_ok())
+        .collect();
+    
+    Ok(sessions)
+}
+
+/// Why `name` cannot be used as a session directory, if it can't. Names must be a single
+/// plain path component, so none can reach outside the sessions directory.
+pub fn check_session_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        Err("it cannot be empty")
+    } else if name.contains(['/', '\\', ':']) {
+        Err("it cannot contain '/', '\\' or ':'")
+    } else if name.contains("..") {
+        Err("it cannot contain '..'")
+    } else if name == "." {
+        Err("it cannot be '.'")
+    } else if name.chars().any(char::is_control) {
+        Err("it cannot contain control characters")
+    } else if name.len() > 255 {
+        Err("it must be at most 255 bytes")
+    } else {
+        Ok(())
+    }
+}
+
+/// The directory holding session `name`, refusing names that `check_session_name` rejects.
+pub fn session_dir(name: &str) -> io::Result<String> {
+    check_session_name(name).map_err(|problem| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid session name '{}': {}", name.escape_debug(), problem))
+    })?;
+    Ok(format!("{}/{}", sessions_root(), name))
+}
+
+/// An existing session whose name equals `name` apart from letter case. Such sessions
+/// share one directory on case-insensitive filesystems (the macOS and Windows defaults).
+pub fn case_collision<'a>(name: &str, sessions: &'a [String]) -> Option<&'a String> {
+    let lower = name.to_lowercase();
+    sessions.iter().find(|session| session.as_str() != name && session.to_lowercase() == lower)
+}
+
+/// Rewrites a session's database, unsaved log records and backups from `from` encryption
+/// (`None` for plain) to `to`.
+pub fn convert_session_files(session_name: &str, from: Option<&DataKey>, to: Option<&DataKey>) -> io::Result<()> {
+    let dir = session_dir(session_name)?;
+    let db_file = format!("{}/database.json", dir);
+    if !Path::new(&db_file).exists() {
+        return Ok(());
+    }
+    let mut db = InMemoryDB::load_from_file_path_with_key(&db_file, from.cloned())?;
+    for warning in db.take_warnings() {
+        println!("{}  {}", icon("⚠️"), warning);
+    }
+    db.enable_wal(format!("{}/database.wal", dir))?;
+    db.set_encryption_key(to.cloned());
+    db.save_to_file_with_path(&db_file)?;
+    let backups = db::convert_backups(&db_file, from, to)?;
+    let done = if to.is_some() { "encrypted" } else { "decrypted" };
+    println!("{} Session '{}' {} ({} backup(s) rewritten).", icon("✅"), session_name, done, backups);
+    Ok(())
+}
+
+/// Encrypts a protected session that is still stored in plain text: one protected before
+/// data keys existed, or one that just got its first password.
+pub fn ensure_session_encrypted(session_name: &str, key: Option<&DataKey>) -> io::Result<()> {
+    let db_file = format!("{}/database.json", session_dir(session_name)?);
+    if key.is_none() || !Path::new(&db_file).exists() || db::is_encrypted_file(Path::new(&db_file)) {
+        return Ok(());
+    }
+    println!("{} Encrypting session '{}' with its password...", icon("🔒"), session_name);
+    convert_session_files(session_name, key, key)
+}
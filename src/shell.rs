@@ -0,0 +1,1293 @@
+//! The session shell: the command loop run on an open session and its helpers.
+
+use std::io::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use redru::{db, disk, display};
+use redru::crypto::DataKey;
+use redru::db::{backup_label, ChangeCounts, ConflictPolicy, FileVerification, InMemoryDB, RecordLimits, RepairReport, StorageFormat};
+use redru::display::icon;
+use redru::error::RedruError;
+use redru::hash_index::{coerce_to_example, extract_field_value, parse_query_value, read_json_file, FieldProfile, HashIndex, MatchMode};
+use crate::prompt::confirm;
+use crate::sessions::{ensure_session_encrypted, session_dir};
+use crate::{read_only, tests};
+
+fn describe_age(time: std::time::SystemTime) -> String {
+    let secs = match time.elapsed() {
+        Ok(elapsed) => elapsed.as_secs(),
+        Err(_) => return "just now".to_string(),
+    };
+    match secs {
+        0..60 => format!("{}s ago", secs),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn check_indexes(hash_index: &mut HashIndex, db: &InMemoryDB) -> io::Result<()> {
+    for (name, e) in hash_index.load_all_indexes()? {
+        println!("{}  Index '{}' is unreadable: {}", icon("⚠️"), name, e);
+    }
+    for name in hash_index.outdated_indexes() {
+        hash_index.repair_index(&name, db.get_all_data())?;
+        println!("{} Index '{}' was saved in an older format; rebuilt from session data.", icon("✅"), name);
+    }
+    for name in hash_index.corrupted_indexes() {
+        if confirm(&format!("{}  Index '{}' failed its integrity check. Rebuild it from session data?", icon("⚠️"), name))? {
+            hash_index.repair_index(&name, db.get_all_data())?;
+            println!("{} Index '{}' rebuilt.", icon("✅"), name);
+        } else {
+            println!("Index '{}' left as is; lookups may be wrong.", name);
+        }
+    }
+    // Shell indexes are named after their field; field lookups are not saved, so rebuild them.
+    for name in hash_index.list_indexes() {
+        hash_index.index_field(&name, &name, db.get_all_data());
+    }
+    Ok(())
+}
+
+/// Keeps the shell's indexes in step with a change to `key`: `old` is the value it had
+/// before, `new` the value it has now.
+fn reindex_key(hash_index: &mut HashIndex, key: &str, old: Option<&serde_json::Value>, new: Option<&serde_json::Value>) {
+    for name in hash_index.list_indexes() {
+        if let Some(old) = old {
+            hash_index.remove_from_index(&name, key, old);
+        }
+        if let Some(new) = new {
+            hash_index.add_to_index(&name, key, new);
+        }
+    }
+}
+
+/// `add` and `add-file` only create records: replacing an existing key takes `--force` or
+/// a yes here. `set` and `update` replace without asking.
+fn confirm_overwrite(db: &InMemoryDB, key: &str, force: bool) -> io::Result<bool> {
+    if force || !db.exists(key) {
+        return Ok(true);
+    }
+    if confirm(&format!("'{}' already exists. Overwrite it?", key))? {
+        return Ok(true);
+    }
+    println!("{} '{}' was left unchanged. Use 'set' or 'add --force' to overwrite it.", icon("❌"), key);
+    Ok(false)
+}
+
+/// The recap shown when a session is left. Deletions get a warning icon, so a command
+/// that removed more than intended stands out before the save.
+fn print_session_summary(commands_run: usize, changes: ChangeCounts, records: usize) {
+    println!("Session summary:");
+    println!("  Commands run: {}", commands_run);
+    println!("  Added:        {}", changes.added);
+    println!("  Updated:      {}", changes.updated);
+    if changes.deleted > 0 {
+        println!("  Deleted:      {} {}", changes.deleted, icon("⚠️"));
+    } else {
+        println!("  Deleted:      0");
+    }
+    println!("  Records now:  {}", records);
+}
+
+/// Records listed by `rank`; the rest are only counted.
+const RANK_SHOWN: usize = 20;
+
+/// Parses a `rank` argument such as `rating:0.7` or `stats.views:-1`. The weight follows
+/// the last `:`, so the field itself may contain one.
+pub fn parse_weight(spec: &str) -> Result<(String, f64), String> {
+    let (field, weight) = spec.rsplit_once(':')
+        .filter(|(field, _)| !field.is_empty())
+        .ok_or_else(|| format!("'{}' is not <field>:<weight>", spec))?;
+    match weight.parse::<f64>() {
+        Ok(weight) if weight.is_finite() => Ok((field.to_string(), weight)),
+        _ => Err(format!("'{}' in '{}' is not a number", weight, spec)),
+    }
+}
+
+/// Parses the day count given to `prune-backups` and `backup-retention`. Counts whose
+/// seconds do not fit in a `u64` are refused rather than wrapped to a shorter age.
+pub fn parse_days(text: &str) -> Result<Duration, String> {
+    let days = text.parse::<u64>().map_err(|_| format!("'{}' is not a number of days", text))?;
+    days.checked_mul(86400)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{} days is too long; the most is {}", days, u64::MAX / 86400))
+}
+
+/// Splits a `batch-add` line into its key and JSON value at the first tab or space.
+pub fn parse_batch_line(line: &str) -> Result<(String, serde_json::Value), String> {
+    let line = line.trim();
+    let Some((key, json)) = line.split_once(char::is_whitespace) else {
+        return Err("expected '<key> <json>'".to_string());
+    };
+    serde_json::from_str(json.trim())
+        .map(|value| (key.to_string(), value))
+        .map_err(|e| format!("invalid JSON for '{}': {}", key, e))
+}
+
+/// Saves a session, printing why not on failure. Unless `force` is set, a database file
+/// another process wrote since this session loaded it is left alone.
+fn save_session(db: &InMemoryDB, db_file: &str, force: bool) -> bool {
+    let saved = if force { db.overwrite_file_with_path(db_file) } else { db.save_to_file_with_path(db_file) };
+    match saved {
+        Ok(()) => true,
+        Err(e @ RedruError::Conflict(_)) => {
+            println!("{} Not saved: {}", icon("❌"), e);
+            println!("   Is the session open somewhere else? Changes stay in memory; add --force to overwrite.");
+            false
+        }
+        Err(e) => {
+            println!("{} Failed to save: {}", icon("❌"), e);
+            false
+        }
+    }
+}
+
+/// Rebuilds every shell index after a change that may touch many keys.
+fn reindex_all(hash_index: &mut HashIndex, db: &InMemoryDB) {
+    for name in hash_index.list_indexes() {
+        hash_index.rebuild_index(&name, db.get_all_data());
+    }
+}
+
+/// Prints the "Found N matches" block shared by the query commands.
+fn print_matches(results: &[String]) {
+    if results.is_empty() {
+        println!("No matches found.");
+    } else {
+        println!("Found {} matches:", results.len());
+        display::print_keys(results);
+    }
+}
+
+/// Splits an optional leading match flag (`--exact`, `--icase`, `--contains`, `--prefix`)
+/// off a command's arguments.
+fn take_match_flag<'a>(args: &'a [&'a str], default: MatchMode) -> (MatchMode, &'a [&'a str]) {
+    match args.first().and_then(|flag| MatchMode::from_flag(flag)) {
+        Some(mode) => (mode, &args[1..]),
+        None => (default, args),
+    }
+}
+
+/// Before a restore replaces a database file that no longer parses, offers to keep its raw
+/// bytes as `<name>.corrupt` for manual recovery.
+fn offer_corrupt_copy(db_file: &str, key: Option<&DataKey>) -> io::Result<()> {
+    let path = Path::new(db_file);
+    if !db::file_is_unreadable(path, key) {
+        return Ok(());
+    }
+    if confirm(&format!("{} cannot be parsed. Save a copy as {} before restoring?",
+        db_file, path.with_extension("corrupt").display()))? {
+        let copy = db::save_corrupt_copy(path)?;
+        println!("{} Saved the unreadable file to {}", icon("✅"), copy.display());
+    }
+    Ok(())
+}
+
+fn print_field_profile(field: &str, profile: &FieldProfile, total: usize) {
+    if profile.present() == 0 {
+        println!("No record has '{}'.", field);
+        return;
+    }
+    println!("Field '{}' appears in {} of {} record(s):", field, profile.present(), total);
+    let types: Vec<String> = profile.type_counts().iter()
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+    println!("  types:    {}", types.join(", "));
+    if let (Some(min), Some(max), Some(mean)) = (profile.min, profile.max, profile.mean) {
+        println!("  numbers:  min {}, max {}, mean {:.2}", min, max, mean);
+    }
+    println!("  distinct: {}", profile.distinct);
+}
+
+fn print_repair_report(report: &RepairReport) {
+    println!("Tried {} backup(s):", report.tried());
+    for (path, reason) in &report.rejected {
+        println!("  {} {}: {}", icon("❌"), path.display(), reason);
+    }
+    if let Some(path) = &report.restored_from {
+        println!("  {} {}", icon("✅"), path.display());
+    }
+    if let Some(path) = &report.corrupt_copy {
+        println!("The unreadable file was saved to {}", path.display());
+    }
+}
+
+/// Prints one line per `verify` check and the totals; returns how many checks failed.
+/// A missing saved hash is a warning, not a failure: sessions saved before hashes were
+/// recorded have none until their next save.
+fn print_verify_report(db_file: &str, file: &FileVerification, indexes: &[String], corrupted: &[String]) -> usize {
+    let (mut passed, mut failed) = (0, 0);
+    let mut check = |ok: bool, text: String| {
+        if ok { passed += 1 } else { failed += 1 }
+        println!("  {} {}", icon(if ok { "✅" } else { "❌" }), text);
+    };
+    match &file.unreadable {
+        None => check(true, format!("{} reads", db_file)),
+        Some(e) => check(false, format!("{} does not read: {}", db_file, e)),
+    }
+    match file.hash_matches {
+        Some(ok) => check(ok, format!("saved records {} the hash recorded with them",
+            if ok { "match" } else { "do not match" })),
+        None if file.unreadable.is_none() => println!("  {}  No saved hash to check the records against; the next save records one",
+            icon("⚠️")),
+        None => {}
+    }
+    for name in indexes {
+        check(!corrupted.contains(name), format!("index '{}'", name));
+    }
+    println!("{} check(s) passed, {} failed.", passed, failed);
+    failed
+}
+
+/// Loads a session database. When the file does not parse, offers to restore the newest
+/// valid backup instead of leaving the session unusable.
+fn load_session_db(db_file: &str, key: Option<DataKey>) -> io::Result<InMemoryDB> {
+    let error = match InMemoryDB::load_from_file_path_with_key(db_file, key.clone()) {
+        Ok(db) => return Ok(db),
+        Err(e) => e,
+    };
+    println!("{} Could not load {}: {}", icon("❌"), db_file, error);
+    if !confirm("Restore the newest valid backup?")? {
+        return Err(error.into());
+    }
+    offer_corrupt_copy(db_file, key.as_ref())?;
+    let mut db = InMemoryDB::new();
+    db.set_encryption_key(key);
+    let report = db.restore_from_backup_path(db_file)?;
+    print_repair_report(&report);
+    Ok(db)
+}
+
+/// Shell commands that change the database, its files or its indexes; refused in
+/// read-only sessions.
+const WRITE_COMMANDS: &[&str] = &[
+    "add", "set", "update", "add-file", "batch-add", "delete", "replace", "index", "drop-index", "restore-index",
+    "save", "backup", "prune-backups", "restore", "repair", "merge-file", "import", "import-jsonl",
+    "restore-deleted", "empty-trash", "vacuum",
+];
+
+pub fn run_session(session_name: &str, key: Option<DataKey>) -> io::Result<()> {
+    if !read_only() {
+        ensure_session_encrypted(session_name, key.as_ref())?;
+    }
+    let db_file = format!("{}/database.json", session_dir(session_name)?);
+    let mut db = load_session_db(&db_file, key.clone())?;
+    for warning in db.take_warnings() {
+        println!("{}  {}", icon("⚠️"), warning);
+    }
+    // `delete` in the shell goes to the trash unless `soft-delete off` says otherwise.
+    db.set_soft_delete(true);
+    let wal_file = format!("{}/database.wal", session_dir(session_name)?);
+    if read_only() {
+        db.set_read_only(true);
+        if fs::metadata(&wal_file).is_ok_and(|m| m.len() > 0) {
+            println!("{}  Changes from a run that did not save are in {} and are not shown.", icon("⚠️"), wal_file);
+        }
+    } else {
+        let recovered = db.enable_wal(&wal_file)?;
+        if recovered > 0 {
+            println!("{} Recovered {} unsaved change(s) from the write-ahead log", icon("✅"), recovered);
+        }
+    }
+    let prompt = if db.is_read_only() { format!("{} [ro]", session_name) } else { session_name.to_string() };
+    let mut hash_index = HashIndex::new();
+    check_indexes(&mut hash_index, &db)?;
+    
+    println!("{} Session '{}' loaded. Type 'help' for commands.", icon("🔓"), session_name);
+    
+    let mut command_history: Vec<String> = Vec::new();
+    let mut history_index = 0;
+    // For the summary printed on exit. Changes recovered from the log above were made by
+    // an earlier run, so they are not counted.
+    let at_start = db.fingerprint();
+    let mut commands_run = 0;
+    let mut end_of_input = false;
+    
+    loop {
+        print!("{}> ", prompt);
+        std::io::stdout().flush()?;
+        
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            // End of input (a piped script ran out): leave as `exit` would.
+            input = "exit".to_string();
+            end_of_input = true;
+        }
+        let input = input.trim();
+        
+        if input.is_empty() {
+            continue;
+        }
+        
+        // Add to command history
+        command_history.push(input.to_string());
+        history_index = command_history.len();
+        
+        let parts: Vec<&str> = input.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+        if db.is_read_only() && WRITE_COMMANDS.contains(&parts[0]) {
+            println!("{} '{}' is not available in a read-only session.", icon("🔒"), parts[0]);
+            continue;
+        }
+        if parts[0] != "exit" {
+            commands_run += 1;
+        }
+        
+        match parts[0] {
+            "help" => {
+                println!("Available commands:");
+                println!("  add [--force] <key> <json_data> - Add a new record; asks before overwriting unless --force");
+                println!("  set <key> <json_data>     - Add or overwrite a record without asking");
+                println!("  update <key> <json_data>  - Overwrite an existing record");
+                println!("  add-file [--force] <key> <path> - Add the JSON in a file as the value for key");
+                println!("  batch-add                 - Add '<key> <json>' lines until a blank line, then save once");
+                println!("  get <key>                 - Get data by key");
+                println!("  delete <key>              - Delete data by key (to the trash while soft-delete is on)");
+                println!("  trash                     - List deleted records waiting in the trash");
+                println!("  restore-deleted <key>     - Bring a record back from the trash");
+                println!("  empty-trash               - Permanently drop everything in the trash");
+                println!("  soft-delete [on|off]      - Show or set whether delete keeps records in the trash (default on)");
+                println!("  list                      - List all keys");
+                println!("  sample <n>                - Show n records picked at random");
+                println!("  rank <field:weight>... [asc|desc] - Order records by a weighted sum of numeric fields (missing = 0)");
+                println!("  search [--preview] [mode] <field> <value> - Search by field value (field may be a path like a.b or items.0.id); --preview shows each match's value");
+                println!("  index <field>             - Create index on field (speeds up find/partial/range/multi)");
+                println!("  find <index> <field> <value> - Find using index");
+                println!("  partial [mode] <index> <field> <substring> - Partial match search");
+                println!("    modes: --exact, --icase, --contains, --prefix (search defaults to --exact, partial to --contains)");
+                println!("  range <index> <field> <min> <max> - Range search");
+                println!("  multi <index> <field1> <value1> [field2 value2...] - Multi-field search");
+                println!("    find/multi values: 30, true and null are typed; quote a value (\"30\") to match only strings");
+                println!("  values <index> <field>    - List all values for field");
+                println!("  contains <field> <value>  - Find records whose array field holds value");
+                println!("  has <field>               - Find records where field exists (even as null)");
+                println!("  missing <field>           - Find records without field (a null field is not missing)");
+                println!("  replace <field> <from> <to> - Set field to <to> wherever it equals <from> (typed as for find)");
+                println!("  profile <field>           - Show the types, range and distinct values of a field");
+                println!("  indexes                   - List all indexes");
+                println!("  index-stats <name>        - Show unique hashes and entries for an index");
+                println!("  dump-index <name>         - Print every bucket of an index and its keys");
+                println!("  drop-index <name>         - Delete an index and its files");
+                println!("  index-backups [<count>]   - Show or set how many previous versions of each index are kept");
+                println!("  restore-index <name>      - Replace an index with its newest valid backup");
+                println!("  save [--force]            - Save database (--force: even if another process changed the file)");
+                println!("  backup [label]            - Create backup, optionally labeled (e.g. before-migration)");
+                println!("  backups                   - List backups with timestamps");
+                println!("  prune-backups <days>      - Delete backups older than the given age");
+                println!("  vacuum [<keep>]           - Rewrite the database compactly, keep the newest backups, empty the log");
+                println!("  backup-retention [<days>|off] - Delete backups older than this after each backup");
+                println!("  max-backups [<count>|off] - Keep only the newest backups after each backup");
+                println!("  restore [n|label]         - Restore newest valid backup, backup #n, or the newest with a label");
+                println!("  repair                    - Repair corrupted database");
+                println!("  verify                    - Check the saved database, its hash and every index; offer repairs");
+                println!("  stats [--json]            - Show database statistics (--json: as a JSON object)");
+                println!("  auto-save <on|off>        - Toggle auto-save");
+                println!("  format [json|msgpack]     - Show or change the on-disk encoding");
+                println!("  pretty <on|off>           - Save indented (default) or compact JSON");
+                println!("  limits [<key> <value>]    - Show or set the max key length and value size in bytes");
+                println!("  merge-file <path> <overwrite|skip|error> - Merge another database file into this one");
+                println!("  export <file>             - Write every record to one JSON file");
+                println!("  import <file> [--replace] - Add records from an exported file, or replace everything");
+                println!("  export-jsonl <file> [<key-field>] - Write one record per line, with its key in key-field if given");
+                println!("  import-jsonl <file> [<key-field>] - Add one record per line, keyed by key-field or numbered");
+                println!("  history                   - Show command history");
+                println!("  clear                     - Clear screen");
+                println!("  test                      - Run database tests");
+                println!("  exit [--force]            - Save and exit session (--force as for save)");
+            }
+            "add" | "set" | "update" => {
+                let command = parts[0];
+                let force = command == "add" && parts.get(1) == Some(&"--force");
+                let args = &parts[1 + usize::from(force)..];
+                if args.len() < 2 {
+                    let usage = if command == "add" { "add [--force]" } else { command };
+                    println!("Usage: {} <key> <json_data>", usage);
+                    continue;
+                }
+                let key = args[0];
+                let json_data = args[1..].join(" ");
+                match serde_json::from_str(&json_data) {
+                    Ok(data) => {
+                        if let Err(e) = db.check_limits(key, &data) {
+                            println!("{} {}", icon("❌"), e);
+                            continue;
+                        }
+                        match command {
+                            "update" if !db.exists(key) => {
+                                println!("{} Key '{}' not found; use 'add' to create it.", icon("❌"), key);
+                                continue;
+                            }
+                            "add" if !confirm_overwrite(&db, key, force)? => continue,
+                            _ => {}
+                        }
+                        let old = db.get(key).cloned();
+                        if let Err(e) = db.add(key, data.clone()) {
+                            println!("{} Data not changed: {}", icon("❌"), e);
+                            continue;
+                        }
+                        reindex_key(&mut hash_index, key, old.as_ref(), Some(&data));
+                        if old.is_some() {
+                            println!("{} Data updated successfully!", icon("✅"));
+                        } else {
+                            println!("{} Data added successfully!", icon("✅"));
+                        }
+                    }
+                    Err(e) => println!("{} Invalid JSON: {}", icon("❌"), e),
+                }
+            }
+            "batch-add" => {
+                println!("Enter '<key> <json>' lines; finish with a blank line or end of input.");
+                let mut line_numbers = Vec::new();
+                let mut records = Vec::new();
+                let mut unparsed = Vec::new();
+                let mut line = String::new();
+                for line_number in 1.. {
+                    line.clear();
+                    if std::io::stdin().read_line(&mut line)? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                    match parse_batch_line(&line) {
+                        Ok(record) => {
+                            line_numbers.push(line_number);
+                            records.push(record);
+                        }
+                        Err(reason) => unparsed.push((line_number, reason)),
+                    }
+                }
+                let report = match db.add_batch(records) {
+                    Ok(report) => report,
+                    Err(e) => {
+                        println!("{} Batch not added: {}", icon("❌"), e);
+                        continue;
+                    }
+                };
+                let mut failures = unparsed;
+                failures.extend(report.rejected.into_iter().map(|(row, reason)| (line_numbers[row], reason)));
+                failures.sort();
+                if report.added > 0 {
+                    reindex_all(&mut hash_index, &db);
+                    save_session(&db, &db_file, false);
+                }
+                println!("{} Added {} record(s); {} line(s) failed.", icon(if failures.is_empty() { "✅" } else { "⚠️" }),
+                    report.added, failures.len());
+                for (line_number, reason) in &failures {
+                    println!("  Line {}: {}", line_number, reason);
+                }
+            }
+            "add-file" => {
+                let force = parts.get(1) == Some(&"--force");
+                let args = &parts[1 + usize::from(force)..];
+                if args.len() < 2 {
+                    println!("Usage: add-file [--force] <key> <path.json>");
+                    continue;
+                }
+                let key = args[0];
+                let path = args[1..].join(" ");
+                let data = match read_json_file::<serde_json::Value>(Path::new(&path)) {
+                    Ok(Some(data)) => data,
+                    Ok(None) => {
+                        println!("{} '{}' is empty", icon("❌"), path);
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("{} Could not read '{}': {}", icon("❌"), path, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = db.check_limits(key, &data) {
+                    println!("{} {}", icon("❌"), e);
+                    continue;
+                }
+                if !confirm_overwrite(&db, key, force)? {
+                    continue;
+                }
+                let old = db.get(key).cloned();
+                if let Err(e) = db.add(key, data.clone()) {
+                    println!("{} Data not added: {}", icon("❌"), e);
+                    continue;
+                }
+                reindex_key(&mut hash_index, key, old.as_ref(), Some(&data));
+                println!("{} Data added from '{}'!", icon("✅"), path);
+            }
+            "get" => {
+                if parts.len() != 2 {
+                    println!("Usage: get <key>");
+                    continue;
+                }
+                match db.get(parts[1]) {
+                    Some(data) => println!("{}", serde_json::to_string_pretty(&data).unwrap()),
+                    None => println!("{} Key not found", icon("❌")),
+                }
+            }
+            "delete" => {
+                if parts.len() != 2 {
+                    println!("Usage: delete <key>");
+                    continue;
+                }
+                let old = db.get(parts[1]).cloned();
+                match db.delete_key(parts[1]) {
+                    Ok(true) => {
+                        reindex_key(&mut hash_index, parts[1], old.as_ref(), None);
+                        if db.is_soft_delete() {
+                            println!("{} Moved '{}' to the trash; 'restore-deleted {}' brings it back.", icon("✅"), parts[1], parts[1]);
+                        } else {
+                            println!("{} Data deleted successfully!", icon("✅"));
+                        }
+                    }
+                    Ok(false) => println!("{} Key not found", icon("❌")),
+                    Err(e) => println!("{} Not deleted: {}", icon("❌"), e),
+                }
+            }
+            "trash" => {
+                let keys = db.trashed_keys();
+                if keys.is_empty() {
+                    println!("The trash is empty.");
+                } else {
+                    println!("Deleted records ({}):", keys.len());
+                    display::print_keys(&keys);
+                }
+            }
+            "restore-deleted" => {
+                if parts.len() != 2 {
+                    println!("Usage: restore-deleted <key>");
+                    continue;
+                }
+                match db.restore_deleted(parts[1]) {
+                    Ok(true) => {
+                        reindex_key(&mut hash_index, parts[1], None, db.get(parts[1]));
+                        println!("{} Restored '{}' from the trash.", icon("✅"), parts[1]);
+                    }
+                    Ok(false) => println!("{} '{}' is not in the trash. Use 'trash' to list it.", icon("❌"), parts[1]),
+                    Err(e) => println!("{} Failed to restore '{}': {}", icon("❌"), parts[1], e),
+                }
+            }
+            "empty-trash" => {
+                match db.empty_trash() {
+                    Ok(0) => println!("The trash is already empty."),
+                    Ok(purged) => println!("{} Permanently deleted {} record(s) from the trash.", icon("✅"), purged),
+                    Err(e) => println!("{} Failed to empty the trash: {}", icon("❌"), e),
+                }
+            }
+            "soft-delete" => {
+                match parts.get(1).copied() {
+                    None => println!("Soft delete is {}.", if db.is_soft_delete() { "on" } else { "off" }),
+                    Some("on") if parts.len() == 2 => {
+                        db.set_soft_delete(true);
+                        println!("{} Deleted records will go to the trash.", icon("✅"));
+                    }
+                    Some("off") if parts.len() == 2 => {
+                        db.set_soft_delete(false);
+                        println!("{} Deleted records will be dropped immediately.", icon("✅"));
+                    }
+                    _ => println!("Usage: soft-delete [on|off]"),
+                }
+            }
+            "list" => {
+                let keys = db.list_keys();
+                if keys.is_empty() {
+                    println!("No data found.");
+                } else {
+                    println!("Keys:");
+                    display::print_keys(&keys);
+                }
+            }
+            "sample" => {
+                let Some(n) = parts.get(1).and_then(|n| n.parse::<usize>().ok()).filter(|_| parts.len() == 2) else {
+                    println!("Usage: sample <n>");
+                    continue;
+                };
+                let picked = db.sample(n);
+                if picked.is_empty() {
+                    println!("No data found.");
+                    continue;
+                }
+                println!("{} of {} records, picked at random:", picked.len(), db.len());
+                let keys: Vec<String> = picked.into_iter().map(|(key, _)| key).collect();
+                display::print_previews(&keys, |key| db.get(key));
+            }
+            "rank" => {
+                let (descending, specs) = match parts.last().copied() {
+                    Some("asc") => (false, &parts[1..parts.len() - 1]),
+                    Some("desc") => (true, &parts[1..parts.len() - 1]),
+                    _ => (true, &parts[1..]),
+                };
+                let weights: Result<Vec<(String, f64)>, String> = specs.iter().map(|spec| parse_weight(spec)).collect();
+                let weights = match weights {
+                    Ok(weights) if !weights.is_empty() => weights,
+                    Ok(_) => {
+                        println!("Usage: rank <field:weight> [<field:weight>...] [asc|desc]");
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("{} {}", icon("❌"), e);
+                        continue;
+                    }
+                };
+                let ranked = db.rank_by(&weights, descending);
+                if ranked.is_empty() {
+                    println!("No data found.");
+                    continue;
+                }
+                for (i, (key, score)) in ranked.iter().take(RANK_SHOWN).enumerate() {
+                    println!("  {:>3}. {:<24} {:.4}", i + 1, key, score);
+                }
+                if ranked.len() > RANK_SHOWN {
+                    println!("  ... {} more", ranked.len() - RANK_SHOWN);
+                }
+            }
+            "search" => {
+                let preview = parts.get(1) == Some(&"--preview");
+                let (mode, args) = take_match_flag(&parts[1 + usize::from(preview)..], MatchMode::Exact);
+                if args.len() < 2 {
+                    println!("Usage: search [--preview] [--exact|--icase|--contains|--prefix] <field> <value>");
+                    continue;
+                }
+                let field = args[0];
+                let value = args[1..].join(" ");
+                let results = db.search_by_field_matching(field, &value, mode);
+                if preview && !results.is_empty() {
+                    println!("Found {} matches:", results.len());
+                    display::print_previews(&results, |key| db.get(key));
+                } else {
+                    print_matches(&results);
+                }
+            }
+            "index" => {
+                if parts.len() != 2 {
+                    println!("Usage: index <field>");
+                    continue;
+                }
+                // Rebuilding an existing index keeps its previous version; creating it afresh would not.
+                if !hash_index.index_exists(parts[1]) {
+                    hash_index.create_index(parts[1]);
+                }
+                hash_index.rebuild_index(parts[1], db.get_all_data());
+                hash_index.index_field(parts[1], parts[1], db.get_all_data());
+                println!("{} Index created successfully!", icon("✅"));
+            }
+            "indexes" => {
+                let mut names = hash_index.list_indexes();
+                names.sort();
+                if names.is_empty() {
+                    println!("No indexes.");
+                } else {
+                    println!("Indexes:");
+                    for name in names {
+                        println!("  {}", name);
+                    }
+                }
+            }
+            "dump-index" => {
+                if parts.len() != 2 {
+                    println!("Usage: dump-index <name>");
+                    continue;
+                }
+                let Some(buckets) = hash_index.dump_index(parts[1]) else {
+                    println!("Index '{}' not found.", parts[1]);
+                    continue;
+                };
+                let entries: usize = buckets.iter().map(|(_, keys)| keys.len()).sum();
+                println!("Index '{}': {} bucket(s), {} entries", parts[1], buckets.len(), entries);
+                for (hash, keys) in &buckets {
+                    println!("  {:016x}  {}", hash, keys.join(", "));
+                }
+            }
+            "index-stats" => {
+                if parts.len() != 2 {
+                    println!("Usage: index-stats <name>");
+                    continue;
+                }
+                match hash_index.get_index_stats(parts[1]) {
+                    Some((unique_hashes, total_entries)) => {
+                        println!("Index '{}':", parts[1]);
+                        println!("  Unique hashes: {}", unique_hashes);
+                        println!("  Total entries: {}", total_entries);
+                        let fields = hash_index.indexed_fields(parts[1]);
+                        if !fields.is_empty() {
+                            println!("  Field lookups: {}", fields.join(", "));
+                        }
+                    }
+                    None => println!("Index '{}' not found.", parts[1]),
+                }
+            }
+            "drop-index" => {
+                if parts.len() != 2 {
+                    println!("Usage: drop-index <name>");
+                    continue;
+                }
+                if hash_index.index_exists(parts[1]) {
+                    hash_index.drop_index(parts[1]);
+                    println!("{} Index '{}' dropped.", icon("✅"), parts[1]);
+                } else {
+                    println!("Index '{}' not found.", parts[1]);
+                }
+            }
+            "index-backups" => match parts.get(1) {
+                None => println!("The previous {} version(s) of each index are kept.", hash_index.index_backups()),
+                Some(count) => match count.parse::<usize>() {
+                    Ok(count) if parts.len() == 2 => {
+                        hash_index.set_index_backups(count);
+                        println!("{} The previous {} version(s) of each index will be kept.", icon("✅"), count);
+                    }
+                    _ => println!("Usage: index-backups [<count>]"),
+                },
+            },
+            "restore-index" => {
+                if parts.len() != 2 {
+                    println!("Usage: restore-index <name>");
+                    continue;
+                }
+                match hash_index.restore_index_backup(parts[1]) {
+                    Ok(path) => println!("{} Index '{}' restored from {}", icon("✅"), parts[1], path.display()),
+                    Err(e) => println!("{} Failed to restore index: {}", icon("❌"), e),
+                }
+            }
+            "find" => {
+                if parts.len() < 4 {
+                    println!("Usage: find <index> <field> <value>");
+                    continue;
+                }
+                let index_name = parts[1];
+                let field = parts[2];
+                let value = parts[3..].join(" ");
+                let results = hash_index.find_field(index_name, field, &value, db.get_all_data());
+                print_matches(&results);
+            }
+            "replace" => {
+                if parts.len() < 4 {
+                    println!("Usage: replace <field> <from> <to>");
+                    continue;
+                }
+                let field = parts[1];
+                // Both values are typed after the field's stored values, as `find` types its value.
+                let example = db.get_all_data().iter()
+                    .filter_map(|(key, value)| extract_field_value(value, field).map(|v| (key, v)))
+                    .min_by(|a, b| a.0.cmp(b.0))
+                    .map(|(_, v)| v.clone());
+                let Some(example) = example else {
+                    println!("No record has a '{}' field.", field);
+                    continue;
+                };
+                let from = coerce_to_example(parts[2], &example);
+                let to = coerce_to_example(&parts[3..].join(" "), &example);
+                match db.replace_field_value(field, &from, to.clone()) {
+                    Ok(0) => println!("No records have {} = {}.", field, from),
+                    Ok(count) => {
+                        reindex_all(&mut hash_index, &db);
+                        save_session(&db, &db_file, false);
+                        println!("{} Set {} from {} to {} in {} record(s).", icon("✅"), field, from, to, count);
+                    }
+                    Err(e) => println!("{} Replace failed: {}", icon("❌"), e),
+                }
+            }
+            "has" | "missing" => {
+                if parts.len() != 2 {
+                    println!("Usage: {} <field>", parts[0]);
+                    continue;
+                }
+                let results = if parts[0] == "has" { db.keys_with_field(parts[1]) } else { db.keys_missing_field(parts[1]) };
+                print_matches(&results);
+            }
+            "contains" => {
+                if parts.len() < 3 {
+                    println!("Usage: contains <field> <value>");
+                    continue;
+                }
+                let element = parts[2..].join(" ");
+                let results = hash_index.find_array_contains(parts[1], &element, db.get_all_data());
+                print_matches(&results);
+            }
+            "partial" => {
+                let (mode, args) = take_match_flag(&parts[1..], MatchMode::Contains);
+                if args.len() < 3 {
+                    println!("Usage: partial [--exact|--icase|--contains|--prefix] <index> <field> <substring>");
+                    continue;
+                }
+                let index_name = args[0];
+                let field = args[1];
+                let substring = args[2..].join(" ");
+                let results = hash_index.find_partial_matching(index_name, field, &substring, mode, db.get_all_data());
+                print_matches(&results);
+            }
+            "range" => {
+                if parts.len() != 5 {
+                    println!("Usage: range <index> <field> <min> <max>");
+                    continue;
+                }
+                let index_name = parts[1];
+                let field = parts[2];
+                if let (Ok(min), Ok(max)) = (parts[3].parse::<f64>(), parts[4].parse::<f64>()) {
+                    let results = hash_index.find_range(index_name, field, min, max, &db.get_all_data());
+                    print_matches(&results);
+                } else {
+                    println!("{} Invalid min/max values", icon("❌"));
+                }
+            }
+            "multi" => {
+                if parts.len() < 4 || !parts.len().is_multiple_of(2) {
+                    println!("Usage: multi <index> <field1> <value1> [field2 value2...]");
+                    continue;
+                }
+                let index_name = parts[1];
+                let mut field_values = Vec::new();
+                for i in (2..parts.len()).step_by(2) {
+                    if i + 1 < parts.len() {
+                        field_values.push((parts[i].to_string(), parse_query_value(parts[i + 1])));
+                    }
+                }
+                let results = hash_index.find_multi(index_name, &field_values, &db.get_all_data());
+                print_matches(&results);
+            }
+            "values" => {
+                if parts.len() != 3 {
+                    println!("Usage: values <index> <field>");
+                    continue;
+                }
+                let index_name = parts[1];
+                let field = parts[2];
+                let values = hash_index.list_field_values(index_name, field, &db.get_all_data());
+                if values.is_empty() {
+                    println!("No values found.");
+                } else {
+                    println!("Field values:");
+                    for value in values {
+                        println!("  {}", value);
+                    }
+                }
+            }
+            "profile" => {
+                if parts.len() != 2 {
+                    println!("Usage: profile <field>");
+                    continue;
+                }
+                let profile = hash_index.field_profile(parts[1], db.get_all_data());
+                print_field_profile(parts[1], &profile, db.len());
+            }
+            "save" => {
+                if save_session(&db, &db_file, parts.get(1) == Some(&"--force")) {
+                    println!("{} Database saved successfully!", icon("✅"));
+                }
+            }
+            "backup" => {
+                if parts.len() > 2 {
+                    println!("Usage: backup [label]");
+                    continue;
+                }
+                match db.create_backup_with_path(&db_file, parts.get(1).copied()) {
+                    Ok(Some(path)) => println!("{} Backup created: {}", icon("✅"), path.display()),
+                    Ok(None) => println!("{} Nothing to back up yet; save the database first.", icon("⚠️")),
+                    Err(e) => println!("{} Failed to create backup: {}", icon("❌"), e),
+                }
+            }
+            "prune-backups" => {
+                let (Some(days), 2) = (parts.get(1), parts.len()) else {
+                    println!("Usage: prune-backups <days>");
+                    continue;
+                };
+                let age = match parse_days(days) {
+                    Ok(age) => age,
+                    Err(e) => {
+                        println!("{} {}", icon("❌"), e);
+                        continue;
+                    }
+                };
+                match db.prune_backups_with_path(&db_file, age) {
+                    Ok(removed) => println!("{} Deleted {} backup(s) older than {} day(s).", icon("✅"), removed, days),
+                    Err(e) => println!("{} Failed to prune backups: {}", icon("❌"), e),
+                }
+            }
+            "vacuum" => {
+                let keep = match parts.get(1) {
+                    None => db.max_backups().unwrap_or(db::DEFAULT_VACUUM_BACKUPS),
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) if parts.len() == 2 => count,
+                        _ => {
+                            println!("Usage: vacuum [<keep>]");
+                            continue;
+                        }
+                    },
+                };
+                let was_pretty = db.is_pretty();
+                match db.vacuum_with_path(&db_file, keep) {
+                    Ok(report) => {
+                        println!("{} Vacuum done: {} reclaimed.", icon("✅"), disk::format_bytes(report.reclaimed()));
+                        println!("  Database file: {} -> {}", disk::format_bytes(report.data_bytes_before), disk::format_bytes(report.data_bytes_after));
+                        println!("  Backups deleted: {} ({}), newest {} unlabelled kept", report.backups_removed, disk::format_bytes(report.backup_bytes), keep);
+                        println!("  Write-ahead log emptied: {}", disk::format_bytes(report.wal_bytes));
+                        if was_pretty {
+                            println!("  Later saves stay compact; 'pretty on' brings indentation back.");
+                        }
+                    }
+                    Err(e @ RedruError::Conflict(_)) => {
+                        println!("{} Not vacuumed: {}", icon("❌"), e);
+                        println!("   Is the session open somewhere else? Nothing was changed.");
+                    }
+                    Err(e) => println!("{} Vacuum failed: {}", icon("❌"), e),
+                }
+            }
+            "backup-retention" => {
+                match parts.get(1).copied() {
+                    None => match db.backup_retention() {
+                        Some(age) => println!("Backups older than {} day(s) are deleted after each backup.", age.as_secs() / 86400),
+                        None => println!("Backups are kept regardless of age."),
+                    },
+                    Some("off") if parts.len() == 2 => {
+                        db.set_backup_retention(None);
+                        println!("{} Backups will be kept regardless of age.", icon("✅"));
+                    }
+                    Some(_) if parts.len() != 2 => println!("Usage: backup-retention [<days>|off]"),
+                    Some(days) => match parse_days(days) {
+                        Ok(age) => {
+                            db.set_backup_retention(Some(age));
+                            println!("{} Backups older than {} day(s) will be deleted after each backup.", icon("✅"), days);
+                        }
+                        Err(e) => println!("{} {}", icon("❌"), e),
+                    },
+                }
+            }
+            "max-backups" => {
+                match parts.get(1).copied() {
+                    None => match db.max_backups() {
+                        Some(count) => println!("The newest {} backup(s) are kept.", count),
+                        None => println!("Backups are kept regardless of count."),
+                    },
+                    Some("off") if parts.len() == 2 => {
+                        db.set_max_backups(None);
+                        println!("{} Backups will be kept regardless of count.", icon("✅"));
+                    }
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) if parts.len() == 2 => {
+                            db.set_max_backups(Some(count));
+                            println!("{} Only the newest {} backup(s) will be kept.", icon("✅"), count);
+                        }
+                        _ => println!("Usage: max-backups [<count>|off]"),
+                    },
+                }
+            }
+            "backups" => {
+                let backups = db.list_backups_with_path(&db_file)?;
+                if backups.is_empty() {
+                    println!("No backups found.");
+                } else {
+                    println!("Backups (newest first):");
+                    for (i, (path, modified)) in backups.iter().enumerate() {
+                        match backup_label(path) {
+                            Some(label) => println!("  {}. [{}] {} ({})", i + 1, label, path.display(), describe_age(*modified)),
+                            None => println!("  {}. {} ({})", i + 1, path.display(), describe_age(*modified)),
+                        }
+                    }
+                }
+            }
+            "restore" => {
+                if parts.len() == 1 {
+                    offer_corrupt_copy(&db_file, key.as_ref())?;
+                    match db.restore_from_backup_path(&db_file) {
+                        Ok(report) => {
+                            reindex_all(&mut hash_index, &db);
+                            print_repair_report(&report);
+                            println!("{} Database restored successfully!", icon("✅"));
+                        }
+                        Err(e) => println!("{} Failed to restore: {}", icon("❌"), e),
+                    }
+                    continue;
+                }
+                let backups = db.list_backups_with_path(&db_file)?;
+                let chosen = match parts[1].parse::<usize>() {
+                    Ok(n) => n.checked_sub(1).and_then(|i| backups.get(i)),
+                    Err(_) => backups.iter().find(|(path, _)| backup_label(path) == Some(parts[1])),
+                };
+                match chosen {
+                    Some((path, _)) => {
+                        offer_corrupt_copy(&db_file, key.as_ref())?;
+                        match db.restore_backup(path) {
+                            Ok(_) => {
+                                reindex_all(&mut hash_index, &db);
+                                println!("{} Database restored from {}", icon("✅"), path.display());
+                            }
+                            Err(e) => println!("{} Failed to restore: {}", icon("❌"), e),
+                        }
+                    }
+                    None => println!("No backup with that number or label. Use 'backups' to list them."),
+                }
+            }
+            "repair" => {
+                offer_corrupt_copy(&db_file, key.as_ref())?;
+                match db.repair_corrupted_database(&db_file) {
+                    Ok(report) => {
+                        reindex_all(&mut hash_index, &db);
+                        print_repair_report(&report);
+                        println!("{} Database repaired successfully!", icon("✅"));
+                    }
+                    Err(e) => println!("{} Failed to repair: {}", icon("❌"), e),
+                }
+            }
+            "verify" => {
+                let file = match db.verify_file_with_path(&db_file) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("{} Could not verify {}: {}", icon("❌"), db_file, e);
+                        continue;
+                    }
+                };
+                let mut indexes = hash_index.list_indexes();
+                indexes.sort();
+                let corrupted = hash_index.corrupted_indexes();
+                println!("Verifying session '{}':", session_name);
+                if print_verify_report(&db_file, &file, &indexes, &corrupted) == 0 {
+                    continue;
+                }
+                if db.is_read_only() {
+                    println!("{} Repairs are not available in a read-only session.", icon("🔒"));
+                    continue;
+                }
+                if !file.passed() && confirm("Restore the database from its newest valid backup and save it?")? {
+                    offer_corrupt_copy(&db_file, key.as_ref())?;
+                    match db.repair_corrupted_database(&db_file) {
+                        Ok(report) => {
+                            reindex_all(&mut hash_index, &db);
+                            print_repair_report(&report);
+                            // The file on disk is the one that failed, so replace it whoever wrote it.
+                            if save_session(&db, &db_file, true) {
+                                println!("{} Database restored and saved.", icon("✅"));
+                            }
+                        }
+                        Err(e) => println!("{} Failed to repair: {}", icon("❌"), e),
+                    }
+                }
+                for name in &corrupted {
+                    if confirm(&format!("Rebuild index '{}' from session data?", name))? {
+                        match hash_index.repair_index(name, db.get_all_data()) {
+                            Ok(_) => println!("{} Index '{}' rebuilt.", icon("✅"), name),
+                            Err(e) => println!("{} Could not rebuild index '{}': {}", icon("❌"), name, e),
+                        }
+                    }
+                }
+            }
+            "stats" => {
+                let stats = db.get_statistics();
+                match parts.get(1) {
+                    None => {}
+                    Some(&"--json") => {
+                        match serde_json::to_string_pretty(&stats) {
+                            Ok(json) => println!("{}", json),
+                            Err(e) => println!("{} Could not serialize stats: {}", icon("❌"), e),
+                        }
+                        continue;
+                    }
+                    Some(_) => {
+                        println!("Usage: stats [--json]");
+                        continue;
+                    }
+                }
+                println!("Database Statistics:");
+                println!("  Total records: {}", stats.total_records);
+                println!("  Total size: {} bytes", stats.total_size);
+                if stats.pretty_size > 0 {
+                    let saved = stats.pretty_size.saturating_sub(stats.total_size);
+                    println!("  Pretty JSON size: {} bytes (compact saves {} bytes, {:.1}%)",
+                        stats.pretty_size, saved, saved as f64 * 100.0 / stats.pretty_size as f64);
+                }
+                println!("  Save style: {}", if stats.pretty_saves { "pretty" } else { "compact" });
+                println!("  Average record size: {:.2} bytes", stats.average_record_size);
+                if let Some(largest) = &stats.largest_record {
+                    println!("  Largest record: '{}' ({} bytes)", largest.key, largest.size);
+                }
+                if !stats.value_types.is_empty() {
+                    let types: Vec<String> = stats.value_types.iter().map(|(name, count)| format!("{} {}", name, count)).collect();
+                    println!("  Records by type: {}", types.join(", "));
+                }
+                println!("  Last modified: {}", stats.last_modified);
+            }
+            "format" => {
+                if parts.len() == 1 {
+                    println!("Storage format: {:?}", db.format());
+                    continue;
+                }
+                match StorageFormat::parse(parts[1]) {
+                    Some(format) => {
+                        db.set_format(format);
+                        println!("{} Storage format set to {:?}; it applies on the next save.", icon("✅"), format);
+                    }
+                    None => println!("Usage: format [json|msgpack]"),
+                }
+            }
+            "merge-file" => {
+                let policy = parts.get(2).and_then(|name| ConflictPolicy::parse(name));
+                let (3, Some(policy)) = (parts.len(), policy) else {
+                    println!("Usage: merge-file <path> <overwrite|skip|error>");
+                    continue;
+                };
+                match db.merge_from_file(parts[1], policy) {
+                    Ok(report) => {
+                        println!("{} Merged '{}': {} added, {} overwritten, {} skipped.", icon("✅"),
+                            parts[1], report.added, report.overwritten, report.skipped);
+                        if report.added + report.overwritten > 0 {
+                            reindex_all(&mut hash_index, &db);
+                            save_session(&db, &db_file, false);
+                        }
+                    }
+                    Err(e) => println!("{} Merge failed: {}", icon("❌"), e),
+                }
+            }
+            "limits" => {
+                if parts.len() == 1 {
+                    let limits = db.limits();
+                    println!("Max key length: {} bytes", limits.max_key_len);
+                    println!("Max value size: {} bytes", limits.max_value_size);
+                    continue;
+                }
+                match (parts.len(), parts.get(1).and_then(|n| n.parse().ok()), parts.get(2).and_then(|n| n.parse().ok())) {
+                    (3, Some(max_key_len), Some(max_value_size)) => {
+                        db.set_limits(RecordLimits { max_key_len, max_value_size });
+                        println!("{} Keys up to {} bytes and values up to {} bytes will be accepted.", icon("✅"), max_key_len, max_value_size);
+                    }
+                    _ => println!("Usage: limits [<max_key_len> <max_value_bytes>]"),
+                }
+            }
+            "export" => {
+                if parts.len() != 2 {
+                    println!("Usage: export <file>");
+                    continue;
+                }
+                match db.export_all(parts[1]) {
+                    Ok(count) => println!("{} Exported {} records to '{}'.", icon("✅"), count, parts[1]),
+                    Err(e) => println!("{} Export failed: {}", icon("❌"), e),
+                }
+            }
+            "import" => {
+                let replace = match parts.len() {
+                    2 => false,
+                    3 if parts[2] == "--replace" => true,
+                    _ => {
+                        println!("Usage: import <file> [--replace]");
+                        continue;
+                    }
+                };
+                match db.import_all(parts[1], replace) {
+                    Ok(count) => {
+                        reindex_all(&mut hash_index, &db);
+                        save_session(&db, &db_file, false);
+                        let verb = if replace { "Replaced the database with" } else { "Imported" };
+                        println!("{} {} {} records from '{}'.", icon("✅"), verb, count, parts[1]);
+                    }
+                    Err(e) => println!("{} Import failed: {}", icon("❌"), e),
+                }
+            }
+            "export-jsonl" => {
+                if !(2..=3).contains(&parts.len()) {
+                    println!("Usage: export-jsonl <file> [<key-field>]");
+                    continue;
+                }
+                match db.export_jsonl(parts[1], parts.get(2).copied()) {
+                    Ok(count) => println!("{} Exported {} records to '{}'.", icon("✅"), count, parts[1]),
+                    Err(e) => println!("{} Export failed: {}", icon("❌"), e),
+                }
+            }
+            "import-jsonl" => {
+                if !(2..=3).contains(&parts.len()) {
+                    println!("Usage: import-jsonl <file> [<key-field>]");
+                    continue;
+                }
+                let report = match db.import_jsonl(parts[1], parts.get(2).copied()) {
+                    Ok(report) => report,
+                    Err(e) => {
+                        println!("{} Import failed: {}", icon("❌"), e);
+                        continue;
+                    }
+                };
+                if report.imported > 0 {
+                    reindex_all(&mut hash_index, &db);
+                    save_session(&db, &db_file, false);
+                }
+                println!("{} Imported {} records from '{}'; {} line(s) skipped.",
+                    icon(if report.skipped.is_empty() { "✅" } else { "⚠️" }), report.imported, parts[1], report.skipped.len());
+                const SHOWN: usize = 10;
+                for (line_number, reason) in report.skipped.iter().take(SHOWN) {
+                    println!("  Line {}: {}", line_number, reason);
+                }
+                if report.skipped.len() > SHOWN {
+                    println!("  ... and {} more", report.skipped.len() - SHOWN);
+                }
+            }
+            "pretty" => {
+                match parts.get(1).copied() {
+                    Some("on") if parts.len() == 2 => {
+                        db.set_pretty(true);
+                        println!("{} JSON will be saved indented.", icon("✅"));
+                    }
+                    Some("off") if parts.len() == 2 => {
+                        db.set_pretty(false);
+                        println!("{} JSON will be saved compact.", icon("✅"));
+                    }
+                    _ => println!("Usage: pretty <on|off>"),
+                }
+            }
+            "auto-save" => {
+                if parts.len() != 2 {
+                    println!("Usage: auto-save <on|off>");
+                    continue;
+                }
+                match parts[1] {
+                    "on" => {
+                        db.enable_auto_save();
+                        println!("{} Auto-save enabled!", icon("✅"));
+                    }
+                    "off" => {
+                        db.disable_auto_save();
+                        println!("{} Auto-save disabled!", icon("✅"));
+                    }
+                    _ => println!("Usage: auto-save <on|off>"),
+                }
+            }
+            "history" => {
+                if command_history.is_empty() {
+                    println!("No command history.");
+                } else {
+                    println!("Command History:");
+                    for (i, cmd) in command_history.iter().enumerate() {
+                        println!("  {}. {}", i + 1, cmd);
+                    }
+                }
+            }
+            "clear" => {
+                print!("\x1B[2J\x1B[1;1H"); // Clear screen
+            }
+            "test" => {
+                println!("Running database tests...");
+                match tests::run_tests() {
+                    Ok(_) => println!("{} All tests passed!", icon("✅")),
+                    Err(e) => println!("{} Tests failed: {}", icon("❌"), e),
+                }
+            }
+            "exit" if db.is_read_only() => {
+                print_session_summary(commands_run, db.changes_since(&at_start), db.len());
+                println!("Read-only session; nothing to save. Goodbye!");
+                break;
+            }
+            "exit" => {
+                print_session_summary(commands_run, db.changes_since(&at_start), db.len());
+                println!("Saving database before exit...");
+                // Staying in the session keeps unsaved changes in memory until the save can succeed.
+                if !save_session(&db, &db_file, parts.get(1) == Some(&"--force")) {
+                    if end_of_input {
+                        // Nobody is left to run `exit` again; the write-ahead log keeps the changes.
+                        println!("   No more input; leaving without saving. The next start recovers the changes from {}.", wal_file);
+                        break;
+                    }
+                    println!("   Fix the problem and run 'exit' again.");
+                    continue;
+                }
+                println!("Goodbye!");
+                break;
+            }
+            _ => {
+                println!("Unknown command. Type 'help' for available commands.");
+            }
+        }
+    }
+    Ok(())
+}
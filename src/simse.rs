@@ -0,0 +1,576 @@
+//! Simse (file-to-vector mode) and the vector store menu. The store itself is
+//! `redru::vector_db`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use redru::display::icon;
+use redru::vector_db::{parse_vector_file, parse_vector_line, settings_path, write_query_results, ComponentStats,
+    MappedVectors, Metric, QueryMatch, SavedMatch, VectorDB, VectorFileSummary, VectorFormat, VectorSettings,
+    VectorStats};
+use crate::prompt::confirm;
+
+fn run_simse() -> io::Result<()> {
+    use std::io::Read;
+    let sils_dir = "sils";
+    if !std::path::Path::new(sils_dir).exists() {
+        fs::create_dir_all(sils_dir)?;
+    }
+    println!("Drop one or more files into the 'sils' directory and press Enter when ready...");
+    let mut _dummy = String::new();
+    std::io::stdin().read_line(&mut _dummy)?;
+    let vectors_path = format!("{}/vectors.bin", sils_dir);
+    // Skip the stores this mode writes itself, and their settings, so they are never
+    // re-read as input.
+    let settings_file = settings_path(&vectors_path);
+    let mut files: Vec<_> = fs::read_dir(sils_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() != "vectors.json" && e.file_name() != "vectors.bin")
+        .map(|e| e.path())
+        .filter(|path| path.is_file() && *path != settings_file)
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        println!("No file found in 'sils'. Exiting simse mode.");
+        return Ok(());
+    }
+    let Some(selected) = select_sils_files(files)? else {
+        println!("Invalid selection. Exiting simse mode.");
+        return Ok(());
+    };
+    let require_uniform = confirm("Require every row to have the same dimension?")?;
+
+    let display = VectorSettings::load(&settings_file).unwrap_or_default();
+    let mut vectors = Vec::new();
+    let mut dimension: Option<(usize, PathBuf)> = None;
+    for file_path in &selected {
+        println!("Reading file: {}", file_path.display());
+        let mut content = String::new();
+        fs::File::open(file_path)?.read_to_string(&mut content)?;
+        let (file_vectors, summary) = match parse_vector_file(&content, require_uniform) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                println!("{} {}: {}. Nothing was saved.", icon("❌"), file_path.display(), e);
+                return Ok(());
+            }
+        };
+        if file_vectors.is_empty() {
+            println!("{}  No numeric rows found in {}.", icon("⚠️"), file_path.display());
+            continue;
+        }
+        if let Some(file_dimension) = summary.dimension {
+            match &dimension {
+                None => dimension = Some((file_dimension, file_path.clone())),
+                Some((expected, first)) if *expected != file_dimension => {
+                    if require_uniform {
+                        println!("{} {} has dimension {}, but {} has {}. Nothing was saved.", icon("❌"),
+                            file_path.display(), file_dimension, first.display(), expected);
+                        return Ok(());
+                    }
+                    println!("{}  {} has dimension {}, but {} has {}.", icon("⚠️"),
+                        file_path.display(), file_dimension, first.display(), expected);
+                }
+                Some(_) => {}
+            }
+        }
+        println!("File converted to vectors:");
+        for vec in &file_vectors {
+            println!("{}", display.format_vector(vec));
+        }
+        print_vector_file_warnings(&summary);
+        vectors.extend(file_vectors);
+    }
+    if vectors.is_empty() {
+        println!("No numeric rows found. Nothing was saved.");
+        return Ok(());
+    }
+    // Save vectors to sils/vectors.bin
+    let count = vectors.len();
+    VectorDB::new(&vectors_path, VectorFormat::Binary)?.set_vectors(vectors)?;
+    println!("{} vectors from {} file(s) saved to {}", count, selected.len(), vectors_path);
+    // Vector DB CLI
+    vector_db_cli(&vectors_path)?;
+    Ok(())
+}
+
+/// Lets the user pick one input file, or all of them, when `sils` holds more than one.
+fn select_sils_files(files: Vec<PathBuf>) -> io::Result<Option<Vec<PathBuf>>> {
+    if files.len() == 1 {
+        return Ok(Some(files));
+    }
+    println!("Files in 'sils':");
+    for (i, path) in files.iter().enumerate() {
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        println!("  {}. {}", i + 1, name);
+    }
+    print!("Select file (1-{}, or 'a' for all): ", files.len());
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    if input.eq_ignore_ascii_case("a") {
+        return Ok(Some(files));
+    }
+    match input.parse::<usize>() {
+        Ok(index) if index > 0 && index <= files.len() => Ok(Some(vec![files[index - 1].clone()])),
+        _ => Ok(None),
+    }
+}
+
+fn print_vector_file_warnings(summary: &VectorFileSummary) {
+    if !summary.skipped_lines.is_empty() {
+        println!("{}  Skipped {} line(s) with no numeric values: {:?}", icon("⚠️"), summary.skipped_lines.len(), summary.skipped_lines);
+    }
+    if summary.skipped_values > 0 {
+        println!("{}  Dropped {} non-numeric value(s) from kept rows.", icon("⚠️"), summary.skipped_values);
+    }
+    if !summary.ragged_lines.is_empty() {
+        println!(
+            "{}  {} row(s) differ from dimension {} and will not match queries of that size:", icon("⚠️"),
+            summary.ragged_lines.len(),
+            summary.dimension.unwrap_or(0)
+        );
+        for (line, len) in &summary.ragged_lines {
+            println!("  line {}: {} values", line, len);
+        }
+    }
+}
+
+fn vector_db_cli(vectors_path: &str) -> io::Result<()> {
+    let mut db = VectorDB::new(vectors_path, VectorFormat::from_path(vectors_path))?;
+    for warning in db.take_warnings() {
+        println!("{}  {}", icon("⚠️"), warning);
+    }
+    let mut last_results: Option<Vec<SavedMatch>> = None;
+    loop {
+        let metric = db.settings().default_metric;
+        let top_k = db.settings().top_k;
+        println!("\nVector DB Options:");
+        println!("  1. Add new vector");
+        println!("  2. Query similar vectors ({}, default)", metric.label());
+        println!("  3. Query similar vectors ({})", metric.other().label());
+        println!("  4. Batch query ({})", metric.label());
+        println!("  5. List all vectors");
+        println!("  6. Delete a vector");
+        println!("  7. Save/load as binary");
+        println!("  8. Update a vector");
+        println!("  9. Normalize all vectors");
+        println!("  10. Import vectors from file");
+        println!("  11. Stats");
+        println!("  12. Save last query results");
+        println!("  13. Query and display settings (metric, matches, threshold, precision)");
+        println!("  14. Exit");
+        print!("Select option (1-14): ");
+        std::io::stdout().flush()?;
+        let mut opt = String::new();
+        std::io::stdin().read_line(&mut opt)?;
+        let opt = opt.trim();
+        match opt {
+            "1" => {
+                print!("Enter vector as comma-separated numbers: ");
+                std::io::stdout().flush()?;
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input)?;
+                let vec: Vec<f64> = input.trim().split(',').filter_map(|s| s.trim().parse().ok()).collect();
+                if db.add_vector(vec).is_ok() {
+                    println!("Vector added.");
+                } else {
+                    println!("Invalid vector.");
+                }
+            }
+            "2" => {
+                if let Some(results) = query_vector(&db, metric.is_cosine())? {
+                    last_results = Some(results);
+                }
+            }
+            "3" => {
+                if let Some(results) = query_vector(&db, metric.other().is_cosine())? {
+                    last_results = Some(results);
+                }
+            }
+            "4" => {
+                print!("Enter batch of query vectors (one per line, end with empty line):\n");
+                std::io::stdout().flush()?;
+                let mut batch = Vec::new();
+                loop {
+                    let mut input = String::new();
+                    std::io::stdin().read_line(&mut input)?;
+                    let line = input.trim();
+                    if line.is_empty() { break; }
+                    let vec: Vec<f64> = line.split(',').filter_map(|s| s.trim().parse().ok()).collect();
+                    if !vec.is_empty() { batch.push(vec); }
+                }
+                let results = db.batch_query(&batch, metric.is_cosine());
+                for (i, result) in results.iter().enumerate() {
+                    println!("\nQuery {}:", i+1);
+                    print_top_matches(result, db.settings());
+                }
+                if !results.is_empty() {
+                    last_results = Some(results.iter().enumerate()
+                        .flat_map(|(i, result)| SavedMatch::from_matches(i + 1, result, top_k))
+                        .collect());
+                }
+            }
+            "5" => {
+                for (i, v) in db.list_vectors().iter().enumerate() {
+                    println!("  {}: {}", i, db.settings().format_vector(v));
+                }
+            }
+            "6" => {
+                print!("Enter index of vector to delete (a range like 2..5 / 2..=5, or 'zero' to drop all-zero vectors): ");
+                std::io::stdout().flush()?;
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input)?;
+                if input.trim().eq_ignore_ascii_case("zero") {
+                    let removed = db.retain(|v| v.iter().any(|&x| x != 0.0))?;
+                    println!("{} zero vectors deleted.", removed);
+                } else if let Some((start, end)) = parse_index_range(input.trim()) {
+                    match db.delete_range(start, end) {
+                        Ok(removed) => println!("{} vectors deleted.", removed),
+                        Err(e) => println!("Delete failed: {}", e),
+                    }
+                } else if let Ok(idx) = input.trim().parse::<usize>() {
+                    if db.delete_vector(idx).is_ok() {
+                        println!("Vector deleted.");
+                    } else {
+                        println!("Invalid index.");
+                    }
+                } else {
+                    println!("Invalid input.");
+                }
+            }
+            "7" => {
+                println!("  a. Save as binary");
+                println!("  b. Load from binary");
+                println!("  c. Query binary file via memory map (read-only)");
+                print!("Select (a/b/c): ");
+                std::io::stdout().flush()?;
+                let mut sub = String::new();
+                std::io::stdin().read_line(&mut sub)?;
+                match sub.trim() {
+                    "a" => {
+                        let bin_path = Path::new(vectors_path).with_extension("bin").to_string_lossy().to_string();
+                        if db.save_as_binary(&bin_path).is_ok() {
+                            println!("Saved to {}", bin_path);
+                        }
+                    }
+                    "b" => {
+                        let bin_path = Path::new(vectors_path).with_extension("bin").to_string_lossy().to_string();
+                        if db.load_from_binary(&bin_path).is_ok() {
+                            println!("Loaded from {}", bin_path);
+                        }
+                    }
+                    "c" => {
+                        let bin_path = Path::new(vectors_path).with_extension("bin").to_string_lossy().to_string();
+                        if let Some(results) = query_mapped(&bin_path, db.settings())? {
+                            last_results = Some(results);
+                        }
+                    }
+                    _ => println!("Invalid option."),
+                }
+            }
+            "8" => {
+                print!("Enter index of vector to update: ");
+                std::io::stdout().flush()?;
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input)?;
+                let Ok(idx) = input.trim().parse::<usize>() else {
+                    println!("Invalid input.");
+                    continue;
+                };
+                print!("Enter new vector as comma-separated numbers: ");
+                std::io::stdout().flush()?;
+                input.clear();
+                std::io::stdin().read_line(&mut input)?;
+                let vec: Vec<f64> = input.trim().split(',').filter_map(|s| s.trim().parse().ok()).collect();
+                match db.update_vector(idx, vec) {
+                    Ok(_) => println!("Vector updated."),
+                    Err(e) => println!("Update failed: {}", e),
+                }
+            }
+            "9" => {
+                match db.normalize_all() {
+                    Ok(0) => println!("All vectors normalized. New vectors will be normalized on insert."),
+                    Ok(skipped) => println!("Vectors normalized ({} zero vectors left unchanged).", skipped),
+                    Err(e) => println!("Normalization failed: {}", e),
+                }
+            }
+            "10" => {
+                print!("Enter path of a CSV or whitespace-separated vector file: ");
+                std::io::stdout().flush()?;
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input)?;
+                let content = match fs::read_to_string(input.trim()) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        println!("Could not read file: {}", e);
+                        continue;
+                    }
+                };
+                // Keep file line numbers so rejected rows can be reported meaningfully.
+                let (line_numbers, rows): (Vec<usize>, Vec<Vec<f64>>) = content
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.trim().is_empty())
+                    .map(|(i, line)| (i + 1, parse_vector_line(line)))
+                    .unzip();
+                let report = db.add_batch(rows)?;
+                println!("Imported {} vectors.", report.added);
+                for (row, reason) in &report.rejected {
+                    println!("  Rejected line {}: {}", line_numbers[*row], reason);
+                }
+            }
+            "11" => print_vector_stats(&db.stats()),
+            "12" => match &last_results {
+                Some(results) => save_query_results(results)?,
+                None => println!("Run a query first."),
+            },
+            "13" => {
+                let settings = read_settings(db.settings())?;
+                match db.set_settings(settings) {
+                    Ok(()) => println!("{} Query settings saved to {}.", icon("✅"), settings_path(vectors_path).display()),
+                    Err(e) => println!("{} Could not save query settings: {}", icon("❌"), e),
+                }
+            }
+            "14" => break,
+            _ => println!("Invalid option."),
+        }
+    }
+    Ok(())
+}
+
+/// Runs one query and returns the matches it printed, or `None` when no query ran.
+fn query_vector(db: &VectorDB, cosine: bool) -> io::Result<Option<Vec<SavedMatch>>> {
+    print!("Enter query vector as comma-separated numbers: ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let query: Vec<f64> = input.trim().split(',').filter_map(|s| s.trim().parse().ok()).collect();
+    if query.is_empty() {
+        println!("Invalid query vector.");
+        return Ok(None);
+    }
+    let settings = db.settings();
+    let threshold = read_threshold(cosine, default_threshold(settings, cosine))?;
+    let results = db.query_similar_within(&query, cosine, threshold);
+    if results.is_empty() && threshold.is_some() {
+        println!("No vectors within the threshold.");
+        return Ok(Some(Vec::new()));
+    }
+    print_top_matches(&results, settings);
+    Ok(Some(SavedMatch::from_matches(1, &results, settings.top_k)))
+}
+
+/// The store's threshold applies only to queries with its default metric: a distance
+/// cutoff means nothing as a cosine similarity, and the other way round.
+fn default_threshold(settings: &VectorSettings, cosine: bool) -> Option<f64> {
+    settings.threshold.filter(|_| settings.default_metric.is_cosine() == cosine)
+}
+
+/// Asks for each query setting in turn; blank answers keep the current value.
+fn read_settings(current: &VectorSettings) -> io::Result<VectorSettings> {
+    let mut settings = current.clone();
+    let answer = |prompt: String| -> io::Result<String> {
+        print!("{}", prompt);
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Ok(input.trim().to_lowercase())
+    };
+
+    match answer(format!("Default metric, euclidean or cosine [{}]: ", current.default_metric.label()))?.as_str() {
+        "" => {}
+        "e" | "euclidean" => settings.default_metric = Metric::Euclidean,
+        "c" | "cosine" => settings.default_metric = Metric::Cosine,
+        other => println!("Unknown metric '{}', keeping {}.", other, current.default_metric.label()),
+    }
+    match answer(format!("Matches shown per query [{}]: ", current.top_k))?.as_str() {
+        "" => {}
+        text => match text.parse::<usize>() {
+            Ok(k) if k > 0 => settings.top_k = k,
+            _ => println!("Invalid count '{}', keeping {}.", text, current.top_k),
+        },
+    }
+    if settings.default_metric != current.default_metric {
+        // The old cutoff was for the other metric.
+        settings.threshold = None;
+    }
+    let shown = settings.threshold.map_or("none".to_string(), |t| t.to_string());
+    let kind = if settings.default_metric.is_cosine() { "minimum cosine similarity" } else { "maximum distance" };
+    match answer(format!("Default threshold, {} ('none' to clear) [{}]: ", kind, shown))?.as_str() {
+        "" => {}
+        "none" => settings.threshold = None,
+        text => match text.parse::<f64>() {
+            Ok(threshold) if threshold.is_finite() => settings.threshold = Some(threshold),
+            _ => println!("Invalid threshold '{}', keeping {}.", text, shown),
+        },
+    }
+    match answer(format!("Decimal places shown [{}]: ", current.precision))?.as_str() {
+        "" => {}
+        text => match text.parse::<usize>() {
+            Ok(precision) if precision <= MAX_PRECISION => settings.precision = precision,
+            _ => println!("Invalid precision '{}' (0 to {}), keeping {}.", text, MAX_PRECISION, current.precision),
+        },
+    }
+    match answer(format!("Components shown at each end of long vectors, 0 for all [{}]: ", current.edge_components))?.as_str() {
+        "" => {}
+        text => match text.parse::<usize>() {
+            Ok(edge) => settings.edge_components = edge,
+            _ => println!("Invalid count '{}', keeping {}.", text, current.edge_components),
+        },
+    }
+    Ok(settings)
+}
+
+/// More decimals than an f64 holds only prints noise.
+const MAX_PRECISION: usize = 17;
+
+const DEFAULT_RESULTS_PATH: &str = "query_results.json";
+
+fn save_query_results(results: &[SavedMatch]) -> io::Result<()> {
+    print!("Save to (.json or .csv) [{}]: ", DEFAULT_RESULTS_PATH);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    let path = Path::new(if input.is_empty() { DEFAULT_RESULTS_PATH } else { input });
+    if path.exists() && !confirm(&format!("{} exists. Overwrite?", path.display()))? {
+        println!("Not saved.");
+        return Ok(());
+    }
+    match write_query_results(path, results) {
+        Ok(()) => println!("{} Saved {} result(s) to {}", icon("✅"), results.len(), path.display()),
+        Err(e) => println!("{} Could not save results: {}", icon("❌"), e),
+    }
+    Ok(())
+}
+
+/// Asks for an optional cutoff. Blank input means `default`, `none` or unparsable input
+/// means no cutoff.
+fn read_threshold(cosine: bool, default: Option<f64>) -> io::Result<Option<f64>> {
+    let shown = default.map_or("none".to_string(), |t| t.to_string());
+    if cosine {
+        print!("Minimum cosine similarity, -1 to 1 (blank for {}): ", shown);
+    } else {
+        print!("Maximum distance (blank for {}): ", shown);
+    }
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(default);
+    }
+    if input.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    match input.parse::<f64>() {
+        Ok(threshold) if threshold.is_finite() => Ok(Some(threshold)),
+        _ => {
+            println!("Invalid threshold '{}', showing all matches.", input);
+            Ok(None)
+        }
+    }
+}
+
+fn query_mapped(bin_path: &str, settings: &VectorSettings) -> io::Result<Option<Vec<SavedMatch>>> {
+    let cosine = settings.default_metric.is_cosine();
+    let mapped = match MappedVectors::open(bin_path) {
+        Ok(mapped) => mapped,
+        Err(e) => {
+            println!("Could not map {}: {}", bin_path, e);
+            return Ok(None);
+        }
+    };
+    if mapped.is_empty() {
+        println!("No vectors in {}.", bin_path);
+        return Ok(None);
+    }
+    println!("Mapped {} vectors from {}", mapped.len(), bin_path);
+    print!("Enter query vector as comma-separated numbers: ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let query: Vec<f64> = input.trim().split(',').filter_map(|s| s.trim().parse().ok()).collect();
+    if query.is_empty() {
+        println!("Invalid query vector.");
+        return Ok(None);
+    }
+    let threshold = read_threshold(cosine, settings.threshold)?;
+    println!("Top {} closest vectors:", settings.top_k);
+    let mut saved = Vec::new();
+    for &(index, distance) in mapped.query_similar_within(&query, cosine, threshold).iter().take(settings.top_k) {
+        if let Some(vector) = mapped.get(index) {
+            println!("  idx {}: {} (distance: {})", index, settings.format_vector(&vector), settings.format_distance(distance));
+            saved.push(SavedMatch { query: 1, index, distance, vector });
+        }
+    }
+    Ok(Some(saved))
+}
+
+fn print_top_matches(results: &[QueryMatch<'_>], settings: &VectorSettings) {
+    println!("Top {} closest vectors:", settings.top_k);
+    for m in results.iter().take(settings.top_k) {
+        println!("  idx {}: {} (distance: {})", m.index, settings.format_vector(&m.vector.to_vec()), settings.format_distance(m.distance));
+    }
+}
+
+pub fn run_vector_processing() -> io::Result<()> {
+    run_simse()
+}
+
+/// Opens an existing vector store straight in the query shell, skipping the `sils`
+/// ingestion that `run_vector_processing` starts with.
+pub fn open_vector_store(path: &str) -> io::Result<()> {
+    if !Path::new(path).is_file() {
+        println!("{} No vector store found at '{}'.", icon("❌"), path);
+        return Ok(());
+    }
+    println!("{} Opening vector store '{}'", icon("📂"), path);
+    vector_db_cli(path)
+}
+
+/// Parses `a..b` (exclusive) or `a..=b` (inclusive) into a half-open range.
+fn parse_index_range(input: &str) -> Option<(usize, usize)> {
+    if let Some((start, end)) = input.split_once("..=") {
+        Some((start.trim().parse().ok()?, end.trim().parse::<usize>().ok()? + 1))
+    } else if let Some((start, end)) = input.split_once("..") {
+        Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+    } else {
+        None
+    }
+}
+
+/// Components listed one per line before the rest are summarised.
+const SHOWN_COMPONENTS: usize = 16;
+
+fn print_vector_stats(stats: &VectorStats) {
+    println!("Vectors: {}", stats.count);
+    match stats.dimensions.as_slice() {
+        [] => return,
+        [(dimension, _)] => println!("Dimension: {}", dimension),
+        dimensions => {
+            let counts: Vec<String> = dimensions.iter()
+                .map(|(dimension, count)| format!("{} with {}", count, dimension))
+                .collect();
+            println!("{}  Ragged dimensions: {}", icon("⚠️"), counts.join(", "));
+        }
+    }
+    if stats.non_finite > 0 {
+        println!("{}  {} NaN or infinite value(s), not counted below", icon("⚠️"), stats.non_finite);
+    }
+    let finite: Vec<&ComponentStats> = stats.components.iter().filter(|c| !c.min.is_nan()).collect();
+    if let (Some(min), Some(max)) = (
+        finite.iter().map(|c| c.min).min_by(f64::total_cmp),
+        finite.iter().map(|c| c.max).max_by(f64::total_cmp),
+    ) {
+        println!("All components: min {:.4}, max {:.4}", min, max);
+    }
+    println!("{:>9}  {:>12}  {:>12}  {:>12}", "component", "min", "max", "mean");
+    for (i, c) in stats.components.iter().take(SHOWN_COMPONENTS).enumerate() {
+        println!("{:>9}  {:>12.4}  {:>12.4}  {:>12.4}", i, c.min, c.max, c.mean);
+    }
+    if stats.components.len() > SHOWN_COMPONENTS {
+        println!("  ... {} more components", stats.components.len() - SHOWN_COMPONENTS);
+    }
+}
@@ -1,6 +1,17 @@
-use crate::db::InMemoryDB;
+use redru::crypto::{self, DataKey};
+use redru::db::{self, ConflictPolicy, InMemoryDB, MergeReport, RecordLimits, ShardedDB, SharedDB, StorageFormat};
+use redru::disk;
+use redru::display::{self, format_columns, icon, truncate_preview};
+use redru::error::RedruError;
+use redru::image_processor::{describe_savings, has_transparency, parse_quality_map, run_batch_compression, CompressOptions, CompressionMethod, ImageProcessor};
+use redru::hash_index::{self, hash_value, parse_query_value, values_match, HashIndex, MatchMode};
+use crate::prompt::parse_yes_no;
+use redru::wal::{self, Wal, WalRecord};
+use redru::vector_db::{self, parse_vector_file, settings_path, vector_stats, write_query_results, ComponentStats, MappedVectors, Metric, QuantizedVector, SavedMatch, VectorDB, VectorFormat, VectorSettings, VectorStorage, VectorView};
 use serde_json::{json, Value};
 use std::io;
+use std::path::Path;
+use std::time::Duration;
 
 pub fn run_tests() -> io::Result<()> {
     test_basic_operations()?;
@@ -9,7 +20,73 @@ pub fn run_tests() -> io::Result<()> {
     test_search()?;
     test_integrity()?;
     test_backup_repair()?;
+    test_repair_file_report()?;
+    test_repair_hash_per_session()?;
+    test_repair_report()?;
     test_import_export()?;
+    test_jsonl()?;
+    test_parallel_batch_query()?;
+    test_mapped_vectors()?;
+    test_index_repair()?;
+    test_index_serialization()?;
+    test_dump_index()?;
+    test_index_backups()?;
+    test_backup_picker()?;
+    test_labeled_backup()?;
+    test_vacuum()?;
+    test_backup_pruning()?;
+    test_shared_db()?;
+    test_sharded_db()?;
+    test_periodic_save()?;
+    test_wal_recovery()?;
+    test_session_encryption()?;
+    test_concurrent_modification()?;
+    test_server_shutdown()?;
+    test_verify_file()?;
+    test_read_only()?;
+    test_error_variants()?;
+    test_storage_formats()?;
+    test_nested_search()?;
+    test_match_modes()?;
+    test_query_coercion()?;
+    test_find_field()?;
+    test_replace_field_value()?;
+    test_sample()?;
+    test_rank_by()?;
+    test_change_counts()?;
+    test_soft_delete()?;
+    test_field_presence()?;
+    test_batch_add()?;
+    test_field_index()?;
+    test_numeric_values()?;
+    test_field_profile()?;
+    test_array_contains()?;
+    test_record_limits()?;
+    test_disk_space()?;
+    test_export_import()?;
+    test_session_case_collision()?;
+    test_session_names()?;
+    test_profiles()?;
+    test_password_hash_verification()?;
+    test_password_api()?;
+    test_yes_no_answers()?;
+    test_columnar_output()?;
+    test_merge_from_file()?;
+    test_nan_distances()?;
+    test_query_threshold()?;
+    test_vector_file_parsing()?;
+    test_vector_stats()?;
+    test_save_query_results()?;
+    test_vector_settings()?;
+    test_vector_query_defaults()?;
+    test_vector_display()?;
+    test_quantized_vectors()?;
+    test_compact_save()?;
+    test_stats_json()?;
+    test_truncated_binary()?;
+    test_image_api()?;
+    test_batch_summary()?;
+    test_non_utf8_names()?;
     Ok(())
 }
 
@@ -150,6 +227,187 @@ fn test_backup_repair() -> io::Result<()> {
     Ok(())
 }
 
+fn test_repair_file_report() -> io::Result<()> {
+    let file_path = "repair_file_report.json";
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    db.set_backup_enabled(true);
+    // One backup only: backups made within the same second share a name.
+    db.set_auto_save(false);
+    db.insert("kept", json!(1))?;
+    db.save()?;
+    db.insert("lost", json!(2))?;
+    db.save()?;
+    std::fs::write(file_path, "garbage \x00 bytes")?;
+
+    let report = db.repair_file()?;
+    assert!(report.restored_from.is_some());
+    assert!(report.rejected.is_empty());
+    assert!(db.exists("kept"));
+    assert!(!db.exists("lost"));
+
+    drop(db);
+    for (backup, _) in InMemoryDB::new().list_backups_with_path(file_path)? {
+        std::fs::remove_file(db::data_hash_path(&backup))?;
+        std::fs::remove_file(backup)?;
+    }
+    if let Some(copy) = report.corrupt_copy {
+        std::fs::remove_file(copy)?;
+    }
+    std::fs::remove_file(file_path)?;
+    std::fs::remove_file(db::data_hash_path(Path::new(file_path)))?;
+    Ok(())
+}
+
+fn test_repair_hash_per_session() -> io::Result<()> {
+    // Every session's file is `database.json`; a repair in one must not be checked
+    // against the hash another one saved.
+    let dirs = ["repair_session_a", "repair_session_b"];
+    let mut sessions = Vec::new();
+    for (i, dir) in dirs.iter().enumerate() {
+        std::fs::create_dir_all(dir)?;
+        let mut db = InMemoryDB::new_with_persistence(format!("{}/database.json", dir))?;
+        db.set_auto_save(false);
+        db.insert("session", json!(i))?;
+        db.save()?;
+        db.insert("later", json!(i))?;
+        db.save()?;
+        sessions.push(db);
+    }
+    assert!(!Path::new("hashes/database.hash").exists());
+    std::fs::write("repair_session_a/database.json", "{ torn")?;
+
+    let report = sessions[0].repair_file()?;
+    assert!(report.rejected.is_empty(), "{:?}", report.rejected);
+    assert_eq!(sessions[0].get("session"), Some(&json!(0)));
+    assert!(sessions[0].verify_data_integrity());
+
+    drop(sessions);
+    for dir in dirs {
+        std::fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+fn test_repair_report() -> io::Result<()> {
+    let file_path = "repair_report.json";
+    let good = "repair_report.backup.1.json";
+    let broken = "repair_report.backup.2.json";
+    std::fs::write(good, r#"{"kept": 1}"#)?;
+    std::fs::write(broken, "{ truncated")?;
+    let now = std::time::SystemTime::now();
+    std::fs::File::options().write(true).open(good)?.set_modified(now - Duration::from_secs(60))?;
+    std::fs::File::options().write(true).open(broken)?.set_modified(now)?;
+    std::fs::write(file_path, "garbage \x00 bytes")?;
+
+    let mut db = InMemoryDB::new();
+    let report = db.restore_from_backup_path(file_path)?;
+    assert_eq!(report.tried(), 2);
+    assert_eq!(report.rejected.len(), 1);
+    assert_eq!(report.rejected[0].0, Path::new(".").join(broken));
+    assert_eq!(report.restored_from.as_deref(), Some(Path::new(".").join(good).as_path()));
+    assert!(db.exists("kept"));
+
+    assert!(db::file_is_unreadable(Path::new(file_path), None));
+    let copy = db::save_corrupt_copy(Path::new(file_path))?;
+    assert_eq!(copy, Path::new("repair_report.corrupt"));
+    assert_eq!(std::fs::read(&copy)?, std::fs::read(file_path)?);
+    let second = db::save_corrupt_copy(Path::new(file_path))?;
+    assert_ne!(second, copy);
+
+    std::fs::remove_file(good)?;
+    let error = InMemoryDB::new().restore_from_backup_path(file_path).unwrap_err();
+    assert!(error.to_string().contains("tried 1"), "{}", error);
+
+    for path in [file_path, broken] {
+        std::fs::remove_file(path)?;
+    }
+    std::fs::remove_file(copy)?;
+    std::fs::remove_file(second)?;
+    Ok(())
+}
+
+fn test_jsonl() -> io::Result<()> {
+    let input = "test_import.jsonl";
+    let output = "test_export.jsonl";
+    std::fs::write(input, concat!(
+        "{\"id\": \"u1\", \"name\": \"Alice\"}\n",
+        "not json\n",
+        "\n",
+        "[1, 2]\n",
+        "{\"name\": \"no id\"}\n",
+        "{\"id\": 7, \"name\": \"Bob\"}\r\n",
+        "{\"id\": null}\n",
+        "{\"id\": \"u1\", \"name\": \"Alice again\"}",
+    ))?;
+
+    let mut db = InMemoryDB::new();
+    let report = db.import_jsonl(input, Some("id"))?;
+    assert_eq!(report.imported, 3);
+    assert_eq!(report.skipped.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![2, 4, 5, 7]);
+    assert!(report.skipped[2].1.contains("no 'id' field"));
+    assert_eq!(db.get("u1"), Some(&json!({"id": "u1", "name": "Alice again"})));
+    assert_eq!(db.get("7"), Some(&json!({"id": 7, "name": "Bob"})));
+
+    // Without a key field, records are numbered after the largest numeric key.
+    let report = db.import_jsonl(input, None)?;
+    assert_eq!(report.imported, 5);
+    assert_eq!(db.get("8"), Some(&json!({"id": "u1", "name": "Alice"})));
+    assert_eq!(db.get("12"), Some(&json!({"id": "u1", "name": "Alice again"})));
+
+    // Export writes the key into the field, so importing with it restores every record.
+    assert_eq!(db.export_jsonl(output, Some("key"))?, 7);
+    let lines = std::fs::read_to_string(output)?;
+    assert_eq!(lines.lines().count(), 7);
+    assert!(lines.lines().next().is_some_and(|line| line.contains("\"key\":\"10\"")));
+    let mut copy = InMemoryDB::new();
+    assert_eq!(copy.import_jsonl(output, Some("key"))?.imported, 7);
+    assert_eq!(copy.get("u1").and_then(|v| v.get("name")), Some(&json!("Alice again")));
+    assert_eq!(copy.len(), 7);
+
+    db.add("scalar", json!(3))?;
+    assert!(db.export_jsonl(output, Some("key")).is_err());
+    assert_eq!(db.export_jsonl(output, None)?, 8);
+    assert!(std::fs::read_to_string(output)?.lines().any(|line| line == "3"));
+
+    let mut limited = InMemoryDB::new();
+    limited.set_limits(RecordLimits { max_key_len: 1, max_value_size: 1024 });
+    let report = limited.import_jsonl(input, Some("id"))?;
+    assert_eq!((report.imported, report.skipped.len()), (1, 6));
+
+    // A dotted key field is written as nested objects, which import reads back.
+    let mut nested = InMemoryDB::new();
+    nested.add("a", json!({"meta": {"tag": "x"}}))?;
+    nested.add("b", json!({"name": "no meta"}))?;
+    assert_eq!(nested.export_jsonl(output, Some("meta.id"))?, 2);
+    assert!(std::fs::read_to_string(output)?.lines().any(|line| line == r#"{"meta":{"id":"a","tag":"x"}}"#));
+    let mut copy = InMemoryDB::new();
+    assert_eq!(copy.import_jsonl(output, Some("meta.id"))?.imported, 2);
+    assert_eq!(copy.get("b"), Some(&json!({"name": "no meta", "meta": {"id": "b"}})));
+    nested.add("c", json!({"meta": 5}))?;
+    assert!(matches!(nested.export_jsonl(output, Some("meta.id")), Err(RedruError::Serialization(_))));
+
+    // Large imports are logged a batch at a time, never as one copy of the whole map.
+    let wal_path = "test_import.wal";
+    let _ = std::fs::remove_file(wal_path);
+    let lines: String = (0..db::JSONL_IMPORT_BATCH + 5).map(|i| format!("{{\"n\": {}}}\n", i)).collect();
+    std::fs::write(input, lines)?;
+    let mut logged = InMemoryDB::new();
+    logged.enable_wal(wal_path)?;
+    assert_eq!(logged.import_jsonl(input, None)?.imported, db::JSONL_IMPORT_BATCH + 5);
+    let (records, _) = wal::decode_records(&std::fs::read(wal_path)?, None);
+    let batch_sizes: Vec<usize> = records.iter().map(|record| match record {
+        WalRecord::InsertMany { records } => records.len(),
+        other => panic!("unexpected {:?}", other),
+    }).collect();
+    assert_eq!(batch_sizes, vec![db::JSONL_IMPORT_BATCH, 5]);
+    drop(logged);
+
+    std::fs::remove_file(wal_path)?;
+    std::fs::remove_file(input)?;
+    std::fs::remove_file(output)?;
+    Ok(())
+}
+
 fn test_import_export() -> io::Result<()> {
     let export_file = "export_test.json";
     let mut db = InMemoryDB::new();
@@ -161,10 +419,2128 @@ fn test_import_export() -> io::Result<()> {
     assert_eq!(keys.len(), 2);
     
     let _ = std::fs::remove_file(export_file);
+    assert_eq!(db.export_all(export_file)?, 2);
     
     let mut db2 = InMemoryDB::new();
     assert!(db2.is_empty());
+    assert_eq!(db2.import_all(export_file, false)?, 2);
+    assert_eq!(db2.get("export2"), Some(&json!({"key": "value"})));
     
     std::fs::remove_file(export_file)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+fn test_parallel_batch_query() -> io::Result<()> {
+    let file_path = "test_vectors.json";
+    let _ = std::fs::remove_file(file_path);
+
+    let mut db = VectorDB::new(file_path, VectorFormat::Json)?;
+    // Starts at 1: a zero vector has an undefined (NaN) cosine distance, which never compares equal.
+    let vectors: Vec<Vec<f64>> = (1..=200)
+        .map(|i| vec![(i % 17) as f64, (i * 7 % 23) as f64, i as f64 / 10.0])
+        .collect();
+    db.set_vectors(vectors)?;
+    let queries: Vec<Vec<f64>> = (0..64).map(|i| vec![i as f64, 1.0, (i % 5) as f64]).collect();
+
+    for threads in [0, 2] {
+        db.set_threads(threads);
+        for cosine in [false, true] {
+            let sequential: Vec<_> = queries.iter().map(|q| db.query_similar(q, cosine)).collect();
+            assert_eq!(db.batch_query(&queries, cosine), sequential);
+        }
+    }
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_mapped_vectors() -> io::Result<()> {
+    let file_path = "test_vectors.bin";
+    let _ = std::fs::remove_file(file_path);
+
+    let mut db = VectorDB::new(file_path, VectorFormat::Binary)?;
+    db.set_vectors(vec![vec![1.0, 2.0], vec![3.0, 4.0, 5.0], vec![0.5, 0.5], vec![9.0, 9.0]])?;
+    let mapped = MappedVectors::open(file_path)?;
+    assert_eq!(mapped.len(), 4);
+    assert_eq!(mapped.get(1), Some(vec![3.0, 4.0, 5.0]));
+    assert_eq!(mapped.get(4), None);
+
+    let query = vec![1.0, 1.0];
+    for cosine in [false, true] {
+        let expected: Vec<(usize, f64)> = db.query_similar(&query, cosine).iter().map(|m| (m.index, m.distance)).collect();
+        assert_eq!(mapped.query_similar(&query, cosine), expected);
+    }
+
+    drop(mapped);
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_index_repair() -> io::Result<()> {
+    let index_name = "test_repair_index";
+    let mut storage = std::collections::HashMap::new();
+    storage.insert("user1".to_string(), json!({"name": "Alice"}));
+    storage.insert("user2".to_string(), json!({"name": "Bob"}));
+
+    let mut index = HashIndex::new();
+    index.create_index(index_name);
+    index.rebuild_index(index_name, &storage);
+    assert!(index.verify_index_integrity(index_name));
+
+    std::fs::write(format!("Indefx/{}.json", index_name), "{ not json")?;
+    let mut reloaded = HashIndex::new();
+    let unreadable = reloaded.load_all_indexes()?;
+    assert!(unreadable.iter().any(|(name, e)| name == index_name && matches!(e, RedruError::Serialization(_))));
+    assert!(reloaded.corrupted_indexes().contains(&index_name.to_string()));
+    assert!(reloaded.find_by_value(index_name, &json!({"name": "Alice"})).is_empty());
+
+    assert!(reloaded.repair_index(index_name, &storage)?);
+    assert!(reloaded.verify_index_integrity(index_name));
+    assert_eq!(reloaded.find_by_value(index_name, &json!({"name": "Alice"})), vec!["user1".to_string()]);
+    assert!(!reloaded.repair_index(index_name, &storage)?);
+
+    // Files without a format version were bucketed by an older `hash_value`: they load
+    // empty and are rebuilt rather than trusted.
+    let stale: std::collections::BTreeMap<u64, Vec<&str>> = [(1, vec!["user1"])].into_iter().collect();
+    std::fs::write(format!("Indefx/{}.json", index_name), serde_json::to_string(&stale)?)?;
+    let mut reloaded = HashIndex::new();
+    reloaded.load_all_indexes()?;
+    assert_eq!(reloaded.outdated_indexes(), vec![index_name.to_string()]);
+    assert!(!reloaded.corrupted_indexes().contains(&index_name.to_string()));
+    assert!(!reloaded.verify_index_integrity(index_name));
+    assert!(reloaded.find_by_hash(index_name, 1).is_empty());
+    assert!(reloaded.repair_index(index_name, &storage)?);
+    assert!(reloaded.outdated_indexes().is_empty());
+    assert_eq!(reloaded.find_by_value(index_name, &json!({"name": "Bob"})), vec!["user2".to_string()]);
+    let mut again = HashIndex::new();
+    again.load_all_indexes()?;
+    assert!(again.outdated_indexes().is_empty());
+    assert!(again.verify_index_integrity(index_name));
+
+    reloaded.drop_index(index_name);
+    Ok(())
+}
+
+fn test_index_serialization() -> io::Result<()> {
+    let index_name = "test_stable_index";
+    let mut storage = std::collections::HashMap::new();
+    for i in 0..50 {
+        storage.insert(format!("user{}", i), json!({"group": i % 3}));
+    }
+
+    let mut index = HashIndex::new();
+    index.create_index(index_name);
+    index.rebuild_index(index_name, &storage);
+    let first = std::fs::read_to_string(format!("Indefx/{}.json", index_name))?;
+
+    // A fresh process iterates its maps in a different order but must agree on the hash.
+    let mut reloaded = HashIndex::new();
+    reloaded.load_all_indexes()?;
+    assert!(reloaded.verify_index_integrity(index_name));
+    reloaded.rebuild_index(index_name, &storage);
+    assert_eq!(std::fs::read_to_string(format!("Indefx/{}.json", index_name))?, first);
+
+    // Hashes near u64::MAX survive the trip through JSON string keys.
+    let saved: serde_json::Value = serde_json::from_str(&first)?;
+    assert_eq!(saved["format"], json!(hash_index::INDEX_FORMAT_VERSION));
+    let hashes: std::collections::HashMap<u64, Vec<String>> = serde_json::from_value(saved["buckets"].clone())?;
+    let mut expected = index.get_all_hashes(index_name);
+    let mut actual: Vec<u64> = hashes.keys().copied().collect();
+    expected.sort();
+    actual.sort();
+    assert_eq!(actual, expected);
+    let big: std::collections::BTreeMap<u64, Vec<String>> = [(u64::MAX, vec!["k".to_string()])].into_iter().collect();
+    let text = serde_json::to_string(&big)?;
+    assert_eq!(serde_json::from_str::<std::collections::BTreeMap<u64, Vec<String>>>(&text)?, big);
+
+    reloaded.drop_index(index_name);
+    Ok(())
+}
+
+fn test_dump_index() -> io::Result<()> {
+    let index_name = "test_dump_index";
+    let mut storage = std::collections::HashMap::new();
+    for i in 0..9 {
+        storage.insert(format!("user{}", i), json!({"group": i % 3}));
+    }
+
+    let mut index = HashIndex::new();
+    index.create_index(index_name);
+    index.rebuild_index(index_name, &storage);
+    let buckets = index.dump_index(index_name).unwrap();
+    assert_eq!(buckets.len(), 3);
+    assert!(buckets.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    let group_zero = buckets.iter().find(|(hash, _)| *hash == hash_value(&json!({"group": 0}))).unwrap();
+    assert_eq!(group_zero.1, vec!["user0", "user3", "user6"]);
+
+    // A handle that never loaded the index reads it from disk.
+    assert_eq!(HashIndex::new().dump_index(index_name), Some(buckets));
+    assert_eq!(index.dump_index("no_such_dump_index"), None);
+
+    index.drop_index(index_name);
+    Ok(())
+}
+
+fn test_index_backups() -> io::Result<()> {
+    let index_name = "test_index_backups";
+    let good: std::collections::HashMap<String, Value> = (0..4).map(|i| (format!("k{}", i), json!(i))).collect();
+    let mut index = HashIndex::new();
+    index.drop_index(index_name);
+    index.create_index(index_name);
+    index.rebuild_index(index_name, &good);
+    let good_buckets = index.dump_index(index_name);
+
+    // A bad rebuild is recoverable from the version it replaced.
+    index.rebuild_index(index_name, &std::collections::HashMap::new());
+    assert_eq!(index.list_index_backups(index_name).len(), 2);
+    let restored = index.restore_index_backup(index_name)?;
+    assert!(restored.ends_with(format!("{}.backup.2", index_name)));
+    assert_eq!(index.dump_index(index_name), good_buckets);
+    assert!(index.verify_index_integrity(index_name));
+    assert_eq!(index.list_index_backups(index_name).len(), 3);
+
+    // Single-key changes save the index without copying it.
+    index.add_to_index(index_name, "k9", &json!(9));
+    index.remove_from_index(index_name, "k9", &json!(9));
+    assert_eq!(index.list_index_backups(index_name).len(), 3);
+    assert!(index.verify_index_integrity(index_name));
+
+    // Only the newest versions are kept.
+    index.set_index_backups(2);
+    index.rebuild_index(index_name, &good);
+    let backups = index.list_index_backups(index_name);
+    assert_eq!(backups.len(), 2);
+    assert!(backups[0].ends_with(format!("{}.backup.4", index_name)));
+
+    // A backup that no longer matches its hash is passed over.
+    std::fs::write(&backups[0], "{}")?;
+    let restored = index.restore_index_backup(index_name)?;
+    assert_eq!(restored, backups[1]);
+
+    index.set_index_backups(0);
+    index.rebuild_index(index_name, &good);
+    assert!(index.list_index_backups(index_name).len() <= 2);
+    index.drop_index(index_name);
+    assert!(index.list_index_backups(index_name).is_empty());
+    assert!(index.restore_index_backup(index_name).is_err());
+    Ok(())
+}
+
+fn test_backup_picker() -> io::Result<()> {
+    let file_path = "picker_test.json";
+    let old_backup = "picker_test.backup.1";
+    let new_backup = "picker_test.backup.2";
+    std::fs::write(old_backup, r#"{"good": "data"}"#)?;
+    std::thread::sleep(Duration::from_millis(20));
+    std::fs::write(new_backup, r#"{"bad": "write"}"#)?;
+
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    let backups = db.list_backups();
+    assert_eq!(backups.len(), 2);
+    assert!(backups[0].0.ends_with(new_backup));
+
+    db.restore_backup(&backups[1].0)?;
+    assert!(db.exists("good"));
+    assert!(!db.exists("bad"));
+
+    for backup in db.list_backups() {
+        let _ = std::fs::remove_file(db::data_hash_path(&backup.0));
+        std::fs::remove_file(backup.0)?;
+    }
+    std::fs::remove_file(file_path)?;
+    let _ = std::fs::remove_file(db::data_hash_path(Path::new(file_path)));
+    Ok(())
+}
+
+fn test_labeled_backup() -> io::Result<()> {
+    let file_path = "label_test.json";
+    let db = InMemoryDB::new();
+    assert!(db.create_backup_with_path(file_path, Some("before-migration"))?.is_none());
+
+    std::fs::write(file_path, r#"{"a": 1}"#)?;
+    let plain = db.create_backup_with_path(file_path, None)?.expect("file exists");
+    let labeled = db.create_backup_with_path(file_path, Some("before-migration"))?.expect("file exists");
+    assert_eq!(db::backup_label(&plain), None);
+    assert_eq!(db::backup_label(&labeled), Some("before-migration"));
+    assert!(labeled.to_string_lossy().contains("label_test.backup.before-migration."));
+
+    for bad in ["", "has space", "dot.ted", "../up", &"x".repeat(65)] {
+        assert!(db::check_backup_label(bad).is_err(), "{:?} should be rejected", bad);
+        assert!(db.create_backup_with_path(file_path, Some(bad)).is_err());
+    }
+    assert!(db::check_backup_label("v2_pre-import").is_ok());
+
+    let backups = db.list_backups_with_path(file_path)?;
+    assert_eq!(backups.len(), 2);
+    assert!(backups.iter().any(|(path, _)| path.file_name() == labeled.file_name()));
+    for (path, _) in backups {
+        std::fs::remove_file(path)?;
+    }
+    std::fs::remove_file(file_path)?;
+
+    // A labeled backup carries the file's hash, so a repair can use it.
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    db.set_auto_save(false);
+    db.insert("kept", json!(1))?;
+    db.save()?;
+    let labeled = db.create_backup_with_path(file_path, Some("before"))?.expect("file exists");
+    assert!(db::data_hash_path(&labeled).exists());
+    std::fs::write(file_path, "{ torn")?;
+    let report = db.repair_file()?;
+    assert_eq!(report.restored_from.as_deref().and_then(Path::file_name), labeled.file_name());
+    assert!(db.exists("kept"));
+
+    drop(db);
+    for (path, _) in InMemoryDB::new().list_backups_with_path(file_path)? {
+        let _ = std::fs::remove_file(db::data_hash_path(&path));
+        std::fs::remove_file(path)?;
+    }
+    if let Some(copy) = report.corrupt_copy {
+        std::fs::remove_file(copy)?;
+    }
+    std::fs::remove_file(file_path)?;
+    std::fs::remove_file(db::data_hash_path(Path::new(file_path)))?;
+    Ok(())
+}
+
+fn test_vacuum() -> io::Result<()> {
+    let file_path = "vacuum_test.json";
+    let wal_path = "vacuum_test.wal";
+    let records: serde_json::Map<String, Value> = (0..20).map(|i| (format!("k{}", i), json!({"n": i, "tags": ["a", "b"]}))).collect();
+    std::fs::write(file_path, serde_json::to_string_pretty(&records)?)?;
+    let now = std::time::SystemTime::now();
+    let mut backups = Vec::new();
+    for (i, name) in ["vacuum_test.backup.100", "vacuum_test.backup.200", "vacuum_test.backup.keep.300", "vacuum_test.backup.400"].iter().enumerate() {
+        std::fs::write(name, "{}")?;
+        std::fs::File::options().write(true).open(name)?.set_modified(now - Duration::from_secs(100 * (4 - i as u64)))?;
+        backups.push(name.to_string());
+    }
+
+    let mut db = InMemoryDB::load_from_file_path(file_path)?;
+    db.enable_wal(wal_path)?;
+    db.insert("k0", json!({"n": 0}))?;
+    let wal_bytes = std::fs::metadata(wal_path)?.len();
+    assert!(wal_bytes > 0);
+    let before = std::fs::metadata(file_path)?.len();
+
+    let report = db.vacuum_with_path(file_path, 1)?;
+    assert_eq!(report.data_bytes_before, before);
+    assert_eq!(report.data_bytes_after, std::fs::metadata(file_path)?.len());
+    assert!(report.data_bytes_after < before);
+    assert_eq!(report.wal_bytes, wal_bytes);
+    assert_eq!(std::fs::metadata(wal_path)?.len(), 0);
+    // The newest unlabelled backup and the labelled one survive.
+    assert_eq!((report.backups_removed, report.backup_bytes), (2, 4));
+    let left: Vec<bool> = backups.iter().map(|b| Path::new(b).exists()).collect();
+    assert_eq!(left, vec![false, false, true, true]);
+    assert_eq!(report.reclaimed(), before - report.data_bytes_after + 4 + wal_bytes);
+    assert!(!db.is_pretty());
+    assert_eq!(InMemoryDB::load_from_file_path(file_path)?.get("k0"), Some(&json!({"n": 0})));
+
+    drop(db);
+    for file in [file_path, wal_path, &backups[2], &backups[3]] {
+        std::fs::remove_file(file)?;
+    }
+    let _ = std::fs::remove_file(db::data_hash_path(Path::new(file_path)));
+    Ok(())
+}
+
+fn test_backup_pruning() -> io::Result<()> {
+    let file_path = "prune_test.json";
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let old_backup = format!("prune_test.backup.{}", now - 10 * 86400);
+    let old_hash = db::data_hash_path(Path::new(&old_backup));
+    let recent_backup = format!("prune_test.backup.{}", now - 3600);
+    std::fs::write(&old_backup, "{}")?;
+    std::fs::write(&old_hash, "x")?;
+    std::fs::write(&recent_backup, "{}")?;
+    std::fs::write(file_path, "{}")?;
+
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    assert_eq!(db.prune_backups_older_than(std::time::Duration::from_secs(7 * 86400))?, 1);
+    assert!(!std::path::Path::new(&old_backup).exists());
+    assert!(!std::path::Path::new(&old_hash).exists());
+    assert!(std::path::Path::new(&recent_backup).exists());
+
+    // Day counts typed in the shell are refused when their seconds would overflow.
+    assert_eq!(crate::shell::parse_days("7"), Ok(Duration::from_secs(7 * 86400)));
+    assert!(crate::shell::parse_days(&(u64::MAX / 86400).to_string()).is_ok());
+    assert!(crate::shell::parse_days(&(u64::MAX / 86400 + 1).to_string()).is_err());
+    assert!(crate::shell::parse_days("-1").is_err());
+
+    // Retention runs after each backup and combines with the count limit.
+    db.set_backup_retention(Some(std::time::Duration::from_secs(60)));
+    db.insert("a", json!(1))?;
+    let backups = db.list_backups();
+    assert_eq!(backups.len(), 1);
+    assert!(!std::path::Path::new(&recent_backup).exists());
+
+    db.set_backup_retention(None);
+    db.set_max_backups(Some(1));
+    std::fs::write(&recent_backup, "{}")?;
+    std::thread::sleep(Duration::from_millis(1100));
+    db.insert("b", json!(2))?;
+    assert_eq!(db.list_backups().len(), 1);
+
+    for backup in db.list_backups() {
+        let _ = std::fs::remove_file(db::data_hash_path(&backup.0));
+        std::fs::remove_file(backup.0)?;
+    }
+    std::fs::remove_file(file_path)?;
+    let _ = std::fs::remove_file(db::data_hash_path(Path::new(file_path)));
+    Ok(())
+}
+
+fn test_shared_db() -> io::Result<()> {
+    let file_path = "shared_test.json";
+    let _ = std::fs::remove_file(file_path);
+
+    let mut inner = InMemoryDB::new_with_persistence(file_path)?;
+    inner.set_backup_enabled(false);
+    let db = SharedDB::new(inner);
+
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let db = db.clone();
+            std::thread::spawn(move || -> io::Result<()> {
+                for i in 0..25 {
+                    db.insert(&format!("t{}_{}", t, i), json!({"thread": t, "n": i}))?;
+                    assert!(db.exists(&format!("t{}_{}", t, i)));
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("writer thread panicked")?;
+    }
+
+    assert_eq!(db.len(), 100);
+    assert!(db.update("t0_0", json!({"thread": 0, "n": -1}))?);
+    assert_eq!(db.get("t0_0"), Some(json!({"thread": 0, "n": -1})));
+    assert_eq!(db.search_by_field("thread", "3").len(), 25);
+    db.delete("t0_0")?;
+    assert_eq!(db.list_keys().len(), 99);
+    assert!(!db.is_empty());
+
+    let reloaded = InMemoryDB::new_with_persistence(file_path)?;
+    assert_eq!(reloaded.len(), 99);
+    assert_eq!(db.read(|db| db.len()), 99);
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_sharded_db() -> io::Result<()> {
+    let file_path = "sharded_test.json";
+    let _ = std::fs::remove_file(file_path);
+
+    let db = ShardedDB::open(file_path)?;
+    assert!(db.is_empty());
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let db = db.clone();
+            std::thread::spawn(move || -> io::Result<()> {
+                for i in 0..25 {
+                    let key = format!("t{}_{}", t, i);
+                    assert!(!db.insert(&key, json!({"thread": t, "n": i}))?);
+                    assert!(db.exists(&key));
+                    db.save_if_dirty()?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("writer thread panicked")?;
+    }
+
+    assert_eq!(db.len(), 100);
+    assert_eq!(db.list_keys().len(), 100);
+    assert!(!db.save_if_dirty()?, "every write was already saved by its writer");
+    assert_eq!(InMemoryDB::load_from_file_path(file_path)?.len(), 100);
+
+    assert!(db.insert("t0_0", json!({"thread": 0, "n": -1}))?);
+    assert_eq!(db.get("t0_0"), Some(json!({"thread": 0, "n": -1})));
+    assert!(db.delete("t0_1"));
+    assert!(!db.delete("t0_1"));
+    assert!(db.save_if_dirty()?);
+
+    // Searches match the same records as the unsharded database.
+    let shared = SharedDB::new(InMemoryDB::new());
+    shared.write(|shared| db.snapshot().into_iter().try_for_each(|(k, v)| shared.add(&k, v)))?;
+    for (field, value, mode) in [("thread", "3", MatchMode::Exact), ("n", "2", MatchMode::Prefix)] {
+        let mut sharded = db.search_by_field_matching(field, value, mode);
+        let mut expected = shared.search_by_field_matching(field, value, mode);
+        sharded.sort();
+        expected.sort();
+        assert_eq!(sharded, expected);
+    }
+
+    let too_long = "k".repeat(db.limits().max_key_len + 1);
+    assert!(matches!(db.insert(&too_long, json!(1)), Err(RedruError::Limit(_))));
+
+    let reopened = ShardedDB::open(file_path)?;
+    assert_eq!(reopened.snapshot(), db.snapshot());
+    assert_eq!(reopened.len(), 99);
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_periodic_save() -> io::Result<()> {
+    let file_path = "periodic_test.json";
+    let _ = std::fs::remove_file(file_path);
+
+    let mut inner = InMemoryDB::new_with_persistence(file_path)?;
+    inner.set_auto_save(false);
+    inner.set_backup_enabled(false);
+    let db = SharedDB::new(inner);
+    let saver = db.enable_periodic_save(Duration::from_millis(50));
+
+    db.insert("tick", json!(1))?;
+    assert!(db.read(|db| db.is_dirty()));
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!db.read(|db| db.is_dirty()));
+    assert!(InMemoryDB::new_with_persistence(file_path)?.exists("tick"));
+
+    assert!(saver.take_failures().is_empty());
+    db.insert("on_drop", json!(2))?;
+    drop(saver);
+    assert!(InMemoryDB::new_with_persistence(file_path)?.exists("on_drop"));
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_verify_file() -> io::Result<()> {
+    let path = "test_verify.json";
+    let hash_path = db::data_hash_path(Path::new(path));
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(&hash_path);
+
+    let mut db = InMemoryDB::new();
+    assert!(db.verify_file_with_path(path)?.passed());
+    db.add("a", json!({"n": 1}))?;
+    db.add("b", json!([1, 2]))?;
+    db.save_to_file_with_path(path)?;
+    assert_eq!(hash_path, Path::new("test_verify.json.hash"));
+    let report = db.verify_file_with_path(path)?;
+    assert_eq!(report, db::FileVerification { unreadable: None, hash_matches: Some(true) });
+
+    // The hash does not depend on the order records were added in.
+    let mut reversed = InMemoryDB::new();
+    reversed.add("b", json!([1, 2]))?;
+    reversed.add("a", json!({"n": 1}))?;
+    assert_eq!(redru::hash_index::calculate_data_hash(reversed.get_all_data()),
+        redru::hash_index::calculate_data_hash(db.get_all_data()));
+
+    // Unsaved changes are not what is checked.
+    db.add("c", json!(null))?;
+    assert!(db.verify_file_with_path(path)?.passed());
+
+    std::fs::write(path, r#"{"a": {"n": 2}, "b": [1, 2]}"#)?;
+    let report = db.verify_file_with_path(path)?;
+    assert_eq!(report.hash_matches, Some(false));
+    assert!(!report.passed());
+
+    std::fs::write(path, "{ not json")?;
+    let report = db.verify_file_with_path(path)?;
+    assert!(report.unreadable.is_some());
+    assert!(!report.passed());
+
+    std::fs::remove_file(&hash_path)?;
+    std::fs::write(path, r#"{"a": 1}"#)?;
+    let report = db.verify_file_with_path(path)?;
+    assert_eq!(report.hash_matches, None);
+    assert!(report.passed());
+
+    // Saves by the server and by persistent databases record the same hash.
+    let served = ShardedDB::open(path)?;
+    served.insert("served", json!(2))?;
+    assert!(served.save_if_dirty()?);
+    assert_eq!(db.verify_file_with_path(path)?, db::FileVerification { unreadable: None, hash_matches: Some(true) });
+    let mut persistent = InMemoryDB::new_with_persistence(path)?;
+    persistent.set_backup_enabled(false);
+    persistent.insert("persistent", json!(3))?;
+    persistent.save()?;
+    assert_eq!(db.verify_file_with_path(path)?, db::FileVerification { unreadable: None, hash_matches: Some(true) });
+
+    drop(persistent);
+    std::fs::remove_file(path)?;
+    std::fs::remove_file(&hash_path)?;
+    Ok(())
+}
+
+fn test_concurrent_modification() -> io::Result<()> {
+    let file_path = "test_concurrent.json";
+    let _ = std::fs::remove_file(file_path);
+
+    // Two shells open the same (not yet existing) session; the second to save must not
+    // silently discard the first one's record.
+    let mut first = InMemoryDB::load_from_file_path(file_path)?;
+    let mut second = InMemoryDB::load_from_file_path(file_path)?;
+    first.add("from_first", json!(1))?;
+    first.save_to_file_with_path(file_path)?;
+    second.add("from_second_longer", json!(2))?;
+    assert!(matches!(second.save_to_file_with_path(file_path), Err(RedruError::Conflict(_))));
+    assert!(InMemoryDB::load_from_file_path(file_path)?.exists("from_first"));
+
+    // Forcing overwrites once; the writer's own saves then go through again.
+    second.overwrite_file_with_path(file_path)?;
+    second.add("again", json!(3))?;
+    second.save_to_file_with_path(file_path)?;
+    assert!(matches!(first.save_to_file_with_path(file_path), Err(RedruError::Conflict(_))));
+
+    // Persistent databases check on every save too.
+    let mut persistent = InMemoryDB::new_with_persistence(file_path)?;
+    persistent.set_backup_enabled(false);
+    let mut other = InMemoryDB::load_from_file_path(file_path)?;
+    other.add("meanwhile", json!([1, 2, 3]))?;
+    other.save_to_file_with_path(file_path)?;
+    assert!(matches!(persistent.insert("late", json!(4)), Err(RedruError::Conflict(_))));
+
+    // So do the sharded databases `serve` saves through.
+    let served = ShardedDB::open(file_path)?;
+    served.insert("served", json!(5))?;
+    assert!(served.save_if_dirty()?);
+    let mut shell = InMemoryDB::load_from_file_path(file_path)?;
+    shell.add("from_shell_meanwhile", json!(6))?;
+    shell.save_to_file_with_path(file_path)?;
+    served.insert("served_later", json!(7))?;
+    assert!(matches!(served.save_if_dirty(), Err(RedruError::Conflict(_))));
+    assert!(InMemoryDB::load_from_file_path(file_path)?.exists("from_shell_meanwhile"));
+    // The refused change is kept and still pending.
+    assert!(matches!(served.save_if_dirty(), Err(RedruError::Conflict(_))));
+    assert!(served.exists("served_later"));
+
+    std::fs::remove_file(file_path)?;
+    let _ = std::fs::remove_file(db::data_hash_path(Path::new(file_path)));
+    Ok(())
+}
+
+fn test_server_shutdown() -> io::Result<()> {
+    use std::io::{Read, Write};
+
+    let file_path = "test_server_shutdown.json";
+    let mut db = InMemoryDB::new();
+    db.add("served", json!(1))?;
+    db.save_to_file_with_path(file_path)?;
+
+    let server = redru::server::serve(file_path, "127.0.0.1:0", None, true)?;
+    let addr = server.addr().expect("bound to an IP address");
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(b"GET /keys HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("[\"served\"]"), "{}", response);
+    // Returns once the workers have finished, with nothing read from stdin.
+    server.stop();
+
+    std::fs::remove_file(file_path)?;
+    let _ = std::fs::remove_file(db::data_hash_path(Path::new(file_path)));
+    Ok(())
+}
+
+fn test_read_only() -> io::Result<()> {
+    let file_path = "test_read_only.json";
+    let mut db = InMemoryDB::new();
+    db.add("user1", json!({"status": "new"}))?;
+    db.save_to_file_with_path(file_path)?;
+    let on_disk = std::fs::read(file_path)?;
+
+    let mut db = InMemoryDB::load_from_file_path(file_path)?;
+    db.set_read_only(true);
+    let read_only = |result: Result<(), RedruError>| matches!(result, Err(RedruError::ReadOnly(_)));
+    assert!(read_only(db.insert("user2", json!(1))));
+    assert!(read_only(db.update("user1", json!(1)).map(|_| ())));
+    assert!(read_only(db.delete("user1")));
+    assert!(read_only(db.clear()));
+    assert!(read_only(db.replace_field_value("status", &json!("new"), json!("old")).map(|_| ())));
+    assert!(read_only(db.import_all(file_path, true).map(|_| ())));
+    assert!(read_only(db.save_to_file_with_path(file_path)));
+    assert!(read_only(db.overwrite_file_with_path(file_path)));
+    assert!(read_only(db.add("user3", json!(3))));
+    assert!(read_only(db.delete_key("user1").map(|_| ())));
+    assert_eq!(db.keys(), ["user1"]);
+    assert_eq!(db.get("user1"), Some(&json!({"status": "new"})));
+    assert_eq!(std::fs::read(file_path)?, on_disk);
+
+    let converted: io::Error = RedruError::ReadOnly("no".to_string()).into();
+    assert_eq!(converted.kind(), io::ErrorKind::PermissionDenied);
+
+    db.set_read_only(false);
+    db.insert("user2", json!(2)).map_err(io::Error::other)?;
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_session_encryption() -> io::Result<()> {
+    let key = DataKey::generate();
+    let sealed = key.seal(b"secret")?;
+    assert!(crypto::is_sealed(&sealed));
+    assert_eq!(key.open(&sealed)?, b"secret");
+    assert!(DataKey::generate().open(&sealed).is_err());
+    let wrapped = key.wrap("correct horse")?;
+    assert_eq!(DataKey::unwrap(&wrapped, "correct horse")?, key);
+    assert!(matches!(DataKey::unwrap(&wrapped, "wrong horse"), Err(RedruError::Auth(_))));
+
+    let file_path = "test_encrypted.json";
+    let wal_path = "test_encrypted.wal";
+    let backup_path = "test_encrypted.backup.1";
+    for path in [file_path, wal_path, backup_path] {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // Nothing readable reaches the file, and only the right key opens it.
+    let mut db = InMemoryDB::load_from_file_path_with_key(file_path, Some(key.clone()))?;
+    assert!(db.is_encrypted());
+    db.add("card", json!({"number": "4111-1111"}))?;
+    db.save_to_file_with_path(file_path)?;
+    let bytes = std::fs::read(file_path)?;
+    assert!(!String::from_utf8_lossy(&bytes).contains("4111"));
+    assert!(db::is_encrypted_file(Path::new(file_path)));
+    assert!(!db::file_is_unreadable(Path::new(file_path), None));
+    assert!(InMemoryDB::load_from_file_path(file_path).is_err());
+    assert!(InMemoryDB::load_from_file_path_with_key(file_path, Some(DataKey::generate())).is_err());
+    let loaded = InMemoryDB::load_from_file_path_with_key(file_path, Some(key.clone()))?;
+    assert_eq!(loaded.get_all_data(), db.get_all_data());
+
+    // Logged changes are encrypted too, and a missing key refuses the log without cutting it.
+    db.enable_wal(wal_path)?;
+    db.add("pin", json!("1234"))?;
+    drop(db);
+    let wal_bytes = std::fs::read(wal_path)?;
+    assert!(!String::from_utf8_lossy(&wal_bytes).contains("1234"));
+    assert!(Wal::open(wal_path, None).is_err());
+    assert_eq!(std::fs::read(wal_path)?, wal_bytes);
+    let mut reopened = InMemoryDB::load_from_file_path_with_key(file_path, Some(key.clone()))?;
+    assert_eq!(reopened.enable_wal(wal_path)?, 1);
+
+    // Backups follow the session's protection in both directions.
+    std::fs::write(backup_path, r#"{"old": "plain"}"#)?;
+    assert_eq!(db::convert_backups(file_path, None, Some(&key))?, 1);
+    assert!(db::is_encrypted_file(Path::new(backup_path)));
+    assert_eq!(db::convert_backups(file_path, None, Some(&key))?, 0);
+    assert_eq!(db::convert_backups(file_path, Some(&key), None)?, 1);
+    assert!(InMemoryDB::load_from_file_path(backup_path)?.exists("old"));
+
+    reopened.set_encryption_key(None);
+    reopened.save_to_file_with_path(file_path)?;
+    assert_eq!(InMemoryDB::load_from_file_path(file_path)?.get("pin"), Some(&json!("1234")));
+
+    for path in [file_path, wal_path, backup_path] {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn test_wal_recovery() -> io::Result<()> {
+    let file_path = "wal_test.json";
+    let wal_path = "wal_test.wal";
+    let _ = std::fs::remove_file(file_path);
+    let _ = std::fs::remove_file(wal_path);
+
+    let expected = {
+        let mut db = InMemoryDB::new_with_persistence(file_path)?;
+        db.set_auto_save(false);
+        db.set_backup_enabled(false);
+        assert_eq!(db.enable_wal(wal_path)?, 0);
+        db.insert("a", json!(1))?;
+        db.insert("b", json!({"nested": [1, 2]}))?;
+        db.update("a", json!("changed"))?;
+        db.delete("b")?;
+        db.clear()?;
+        db.insert("c", json!(3))?;
+        db.add("d", json!(null))?;
+        db.delete_key("c")?;
+        db.add("e", json!("ünïcode"))?;
+        db.get_all_data().clone()
+        // Dropped without saving, like a crash.
+    };
+
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    assert!(db.is_empty());
+    assert_eq!(db.enable_wal(wal_path)?, 9);
+    assert_eq!(db.get_all_data(), &expected);
+    assert!(db.is_dirty());
+
+    // Replaying records that already reached the persistence file changes nothing.
+    let bytes = std::fs::read(wal_path)?;
+    let (records, valid_len) = wal::decode_records(&bytes, None);
+    assert_eq!(valid_len, bytes.len());
+    let mut replayed_twice = expected.clone();
+    records.iter().cloned().for_each(|record| record.apply(&mut replayed_twice, &mut std::collections::HashMap::new()));
+    assert_eq!(replayed_twice, expected);
+
+    // Every truncation keeps exactly the records that are complete, never a partial one.
+    let mut boundaries = vec![0];
+    for record in &records {
+        boundaries.push(boundaries.last().unwrap() + wal::encode_record(record, None)?.len());
+    }
+    for offset in 0..=bytes.len() {
+        let (prefix, valid) = wal::decode_records(&bytes[..offset], None);
+        let complete = boundaries.iter().filter(|&&b| b > 0 && b <= offset).count();
+        assert_eq!(prefix.len(), complete, "offset {}", offset);
+        assert_eq!(prefix[..], records[..complete]);
+        assert_eq!(valid, boundaries[complete]);
+    }
+
+    // A torn tail is cut off on open, and later appends stay readable.
+    let torn = boundaries[3] + 5;
+    std::fs::write(wal_path, &bytes[..torn])?;
+    let (wal, recovered) = Wal::open(wal_path, None)?;
+    assert_eq!(recovered[..], records[..3]);
+    assert_eq!(std::fs::metadata(wal_path)?.len(), boundaries[3] as u64);
+    wal.append(&WalRecord::Insert { key: "late".to_string(), value: json!(true) })?;
+    drop(wal);
+    let mut partial = InMemoryDB::new();
+    assert_eq!(partial.enable_wal(wal_path)?, 4);
+    assert_eq!(partial.get("late"), Some(&json!(true)));
+    assert_eq!(partial.get("a"), Some(&json!("changed")));
+
+    // A flipped bit fails the checksum and drops that record and everything after it.
+    let mut flipped = bytes.clone();
+    flipped[boundaries[1] + 10] ^= 0x01;
+    assert_eq!(wal::decode_records(&flipped, None).0.len(), 1);
+
+    // Saving makes the log redundant.
+    std::fs::write(wal_path, &bytes)?;
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    db.set_backup_enabled(false);
+    db.enable_wal(wal_path)?;
+    db.save()?;
+    assert_eq!(std::fs::metadata(wal_path)?.len(), 0);
+    drop(db);
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    assert_eq!(db.enable_wal(wal_path)?, 0);
+    assert_eq!(db.get_all_data(), &expected);
+
+    std::fs::remove_file(file_path)?;
+    std::fs::remove_file(wal_path)?;
+    Ok(())
+}
+
+fn test_error_variants() -> io::Result<()> {
+    let file_path = "error_test.json";
+    std::fs::write(file_path, "{ not json")?;
+    assert!(matches!(InMemoryDB::load_from_file_path(file_path), Err(RedruError::Serialization(_))));
+
+    let mut db = InMemoryDB::new();
+    assert!(matches!(db.restore_backup("missing_backup.json"), Err(RedruError::Io(_))));
+    assert!(matches!(db.restore_from_backup_path(file_path), Err(RedruError::NotFound(_))));
+
+    let converted: io::Error = RedruError::Auth("denied".to_string()).into();
+    assert_eq!(converted.kind(), io::ErrorKind::PermissionDenied);
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_storage_formats() -> io::Result<()> {
+    let file_path = "format_test.db";
+    let _ = std::fs::remove_file(file_path);
+    let record = json!({"name": "Alice", "tags": ["a", "b"], "score": 1.5, "big": u64::MAX});
+
+    {
+        let mut db = InMemoryDB::new_with_format(file_path, StorageFormat::MessagePack)?;
+        db.set_backup_enabled(false);
+        db.insert("user1", record.clone())?;
+    }
+    assert!(std::fs::read(file_path)?.starts_with(b"REDRUMPK"));
+
+    let db = InMemoryDB::load_from_file_path(file_path)?;
+    assert_eq!(db.format(), StorageFormat::MessagePack);
+    assert_eq!(db.get("user1"), Some(&record));
+
+    let mut db = InMemoryDB::new_with_persistence(file_path)?;
+    assert_eq!(db.get("user1"), Some(&record));
+    db.set_backup_enabled(false);
+    db.set_format(StorageFormat::Json);
+    db.save()?;
+    assert!(std::fs::read_to_string(file_path)?.trim_start().starts_with('{'));
+    assert_eq!(InMemoryDB::load_from_file_path(file_path)?.get("user1"), Some(&record));
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_nested_search() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    db.add("u1", json!({"name": "Alice", "address": {"city": "Oslo"}, "items": [{"id": 7}]}))?;
+    db.add("u2", json!({"name": "Bob", "address": {"city": "Rome"}, "items": [{"id": 8}]}))?;
+    db.add("u3", json!({"a.b": "literal"}))?;
+
+    assert_eq!(db.search_by_field("address.city", "Oslo"), vec!["u1".to_string()]);
+    assert_eq!(db.search_by_field("items.0.id", "8"), vec!["u2".to_string()]);
+    assert_eq!(db.search_by_field("name", "\"Bob\""), vec!["u2".to_string()]);
+    assert_eq!(db.search_by_field("a.b", "literal"), vec!["u3".to_string()]);
+    assert!(db.search_by_field("items.1.id", "7").is_empty());
+    Ok(())
+}
+
+fn test_match_modes() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    db.add("a", json!({"code": "AB-12", "n": 123}))?;
+    db.add("b", json!({"code": "ab-12x", "n": 45}))?;
+
+    let sorted = |mut keys: Vec<String>| { keys.sort(); keys };
+    assert_eq!(db.search_by_field("code", "ab-12"), Vec::<String>::new());
+    assert_eq!(db.search_by_field_matching("code", "AB-12", MatchMode::Exact), vec!["a".to_string()]);
+    assert_eq!(db.search_by_field_matching("code", "ab-12", MatchMode::IgnoreCase), vec!["a".to_string()]);
+    assert_eq!(sorted(db.search_by_field_matching("code", "B-1", MatchMode::Contains)), vec!["a", "b"]);
+    assert_eq!(db.search_by_field_matching("code", "ab-12X", MatchMode::Prefix), vec!["b".to_string()]);
+    assert_eq!(db.search_by_field_matching("n", "12", MatchMode::Prefix), vec!["a".to_string()]);
+    assert_eq!(db.search_by_field_matching("n", "45", MatchMode::IgnoreCase), vec!["b".to_string()]);
+
+    assert_eq!(sorted(db.find_partial("idx", "code", "ab")), vec!["a", "b"]);
+    assert_eq!(db.find_partial_matching("idx", "code", "ab", MatchMode::Prefix).len(), 2);
+    assert!(db.find_partial_matching("idx", "code", "ab", MatchMode::Exact).is_empty());
+    assert_eq!(db.find_partial_matching("idx", "code", "AB-12", MatchMode::Exact), vec!["a".to_string()]);
+    assert_eq!(MatchMode::from_flag("--icase"), Some(MatchMode::IgnoreCase));
+    assert_eq!(MatchMode::from_flag("icase"), None);
+    Ok(())
+}
+
+fn test_query_coercion() -> io::Result<()> {
+    assert_eq!(parse_query_value("30"), json!(30));
+    assert_eq!(parse_query_value("-1.5"), json!(-1.5));
+    assert_eq!(parse_query_value("true"), json!(true));
+    assert_eq!(parse_query_value("null"), Value::Null);
+    assert_eq!(parse_query_value("\"30\""), json!("30"));
+    assert_eq!(parse_query_value("Alice"), json!("Alice"));
+
+    let mut db = InMemoryDB::new();
+    db.add("n", json!({"age": 30, "active": true}))?;
+    db.add("f", json!({"age": 30.0, "active": false}))?;
+    db.add("s", json!({"age": "30", "active": true}))?;
+
+    let find = |pairs: &[(&str, &str)]| {
+        let pairs: Vec<(String, Value)> = pairs.iter().map(|(f, v)| (f.to_string(), parse_query_value(v))).collect();
+        let mut keys = db.find_multi("idx", &pairs);
+        keys.sort();
+        keys
+    };
+    assert_eq!(find(&[("age", "30")]), vec!["f", "n", "s"]);
+    assert_eq!(find(&[("age", "\"30\"")]), vec!["s"]);
+    assert_eq!(find(&[("age", "30"), ("active", "true")]), vec!["n", "s"]);
+    assert!(find(&[("age", "31")]).is_empty());
+    Ok(())
+}
+
+fn test_find_field() -> io::Result<()> {
+    let mut storage = std::collections::HashMap::new();
+    storage.insert("a".to_string(), json!({"age": 30, "active": true, "tags": [1, 2], "zip": "01234"}));
+    storage.insert("b".to_string(), json!({"age": 1000, "active": false, "tags": [3], "zip": "98765"}));
+    storage.insert("c".to_string(), json!({"age": "30", "active": "true"}));
+
+    let mut index = HashIndex::new();
+    let index_name = "test_find_field_index";
+    assert!(index.find_field(index_name, "age", "30", &storage).is_empty());
+    index.create_index(index_name);
+
+    assert_eq!(index.find_field(index_name, "age", "30", &storage), vec!["a", "c"]);
+    assert_eq!(index.find_field(index_name, "age", "1e3", &storage), vec!["b"]);
+    assert_eq!(index.find_field(index_name, "age", "\"30\"", &storage), vec!["c"]);
+    assert_eq!(index.find_field(index_name, "active", "TRUE", &storage), vec!["a", "c"]);
+    assert_eq!(index.find_field(index_name, "tags", "[1,2]", &storage), vec!["a"]);
+    // Leading zeros keep a string field from being read as a number.
+    assert_eq!(index.find_field(index_name, "zip", "01234", &storage), vec!["a"]);
+    assert!(index.find_field(index_name, "missing", "1", &storage).is_empty());
+
+    index.drop_index(index_name);
+    Ok(())
+}
+
+fn test_replace_field_value() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    db.add("a", json!({"status": "pending", "meta": {"level": 1}}))?;
+    db.add("b", json!({"status": "pending", "meta": {"level": 1.0}}))?;
+    db.add("c", json!({"status": "done", "meta": {"level": "1"}}))?;
+    db.add("d", json!({"other": true}))?;
+
+    assert_eq!(db.replace_field_value("status", &json!("pending"), json!("active")).map_err(io::Error::other)?, 2);
+    assert_eq!(db.get("a"), Some(&json!({"status": "active", "meta": {"level": 1}})));
+    assert_eq!(db.get("c").unwrap()["status"], json!("done"));
+    assert_eq!(db.get("d"), Some(&json!({"other": true})));
+    let mut active = db.search_by_field("status", "active");
+    active.sort();
+    assert_eq!(active, ["a", "b"]);
+
+    // Dotted paths, compared as find compares: 1 matches 1.0 and "1".
+    assert_eq!(db.replace_field_value("meta.level", &json!(1), json!(2)).map_err(io::Error::other)?, 3);
+    assert_eq!(db.search_by_field("meta.level", "2").len(), 3);
+    assert_eq!(db.replace_field_value("status", &json!("missing"), json!("x")).map_err(io::Error::other)?, 0);
+
+    // All or nothing when a new value is too large.
+    db.set_limits(RecordLimits { max_key_len: 16, max_value_size: 40 });
+    let big = json!("x".repeat(30));
+    assert!(matches!(db.replace_field_value("status", &json!("active"), big), Err(RedruError::Limit(_))));
+    assert_eq!(db.search_by_field("status", "active").len(), 2);
+    Ok(())
+}
+
+fn test_rank_by() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    db.add("cheap", json!({"price": 10, "rating": 3.0}))?;
+    db.add("good", json!({"price": 40, "rating": 4.8}))?;
+    db.add("unrated", json!({"price": 5}))?;
+    db.add("nested", json!({"price": "n/a", "stats": {"rating": 5}}))?;
+    db.add("tie", json!({"price": 10, "rating": 3.0}))?;
+
+    let weights = vec![("price".to_string(), -0.1), ("rating".to_string(), 1.0)];
+    let ranked = db.rank_by(&weights, true);
+    let keys: Vec<&str> = ranked.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(keys, vec!["cheap", "tie", "good", "nested", "unrated"]);
+    assert_eq!(ranked[0].1, 2.0);
+    assert!((ranked[2].1 - 0.8).abs() < 1e-9);
+    assert_eq!(ranked[3].1, 0.0);
+    assert_eq!(ranked[4].1, -0.5);
+
+    let ascending = db.rank_by(&weights, false);
+    assert_eq!(ascending.first().map(|(key, _)| key.as_str()), Some("unrated"));
+    // Ties stay in key order either way.
+    let ties: Vec<&str> = ascending.iter().map(|(key, _)| key.as_str()).filter(|key| ["cheap", "tie"].contains(key)).collect();
+    assert_eq!(ties, vec!["cheap", "tie"]);
+
+    let nested = db.rank_by(&[("stats.rating".to_string(), 2.0)], true);
+    assert_eq!(nested[0], ("nested".to_string(), 10.0));
+    assert!(db.rank_by(&[], true).iter().all(|(_, score)| *score == 0.0));
+
+    assert_eq!(crate::shell::parse_weight("rating:0.7"), Ok(("rating".to_string(), 0.7)));
+    assert_eq!(crate::shell::parse_weight("a:b:-2"), Ok(("a:b".to_string(), -2.0)));
+    assert!(crate::shell::parse_weight("rating").is_err());
+    assert!(crate::shell::parse_weight(":1").is_err());
+    assert!(crate::shell::parse_weight("rating:high").is_err());
+    assert!(crate::shell::parse_weight("rating:inf").is_err());
+    Ok(())
+}
+
+fn test_change_counts() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    db.add("kept", json!({"n": 1}))?;
+    db.add("edited", json!({"n": 2}))?;
+    db.add("gone", json!({"n": 3}))?;
+    let start = db.fingerprint();
+    assert_eq!(db.changes_since(&start), db::ChangeCounts::default());
+
+    db.add("edited", json!({"n": 20}))?;
+    db.delete_key("gone")?;
+    db.add("new", json!(null))?;
+    db.add("brief", json!(1))?;
+    db.delete_key("brief")?;
+    // Writing back the same value is not a change.
+    db.add("kept", json!({"n": 1}))?;
+    assert_eq!(db.changes_since(&start), db::ChangeCounts { added: 1, updated: 1, deleted: 1 });
+
+    db.add("edited", json!({"n": 2}))?;
+    assert_eq!(db.changes_since(&start).updated, 0);
+    Ok(())
+}
+
+fn test_soft_delete() -> io::Result<()> {
+    let file_path = "soft_delete_test.json";
+    let mut db = InMemoryDB::new();
+    db.add("hard", json!(1))?;
+    assert!(db.delete_key("hard")?);
+    assert!(db.trashed_keys().is_empty());
+
+    db.set_soft_delete(true);
+    db.add("a", json!({"n": 1}))?;
+    db.add("b", json!({"n": 2}))?;
+    assert!(db.delete_key("a")?);
+    db.delete("b")?;
+    assert!(!db.soft_delete("missing")?);
+    assert!(!db.exists("a") && !db.exists("b"));
+    assert_eq!(db.trashed_keys(), vec!["a", "b"]);
+    assert_eq!(db.get_trashed("a"), Some(&json!({"n": 1})));
+
+    assert!(db.restore_deleted("a")?);
+    assert_eq!(db.get("a"), Some(&json!({"n": 1})));
+    assert!(!db.restore_deleted("a")?);
+
+    // A key reused since the delete is not overwritten by the deleted record.
+    db.add("b", json!("new"))?;
+    assert!(matches!(db.restore_deleted("b"), Err(RedruError::Conflict(_))));
+    assert_eq!(db.get_trashed("b"), Some(&json!({"n": 2})));
+
+    // The trash survives a save and reload, and its file goes once it is emptied.
+    db.save_to_file_with_path(file_path)?;
+    let trash_file = db::trash_path(Path::new(file_path));
+    assert!(trash_file.exists());
+    let mut reloaded = InMemoryDB::load_from_file_path(file_path)?;
+    assert_eq!(reloaded.trashed_keys(), vec!["b"]);
+    assert_eq!(reloaded.empty_trash()?, 1);
+    assert_eq!(reloaded.empty_trash()?, 0);
+    reloaded.save_to_file_with_path(file_path)?;
+    assert!(!trash_file.exists());
+
+    // An unreadable trash does not stop the load: a copy is kept and the caller is told once.
+    std::fs::write(&trash_file, "not json")?;
+    let mut reloaded = InMemoryDB::load_from_file_path(file_path)?;
+    assert!(reloaded.trashed_keys().is_empty());
+    let warnings = reloaded.take_warnings();
+    let copy = trash_file.with_extension("corrupt");
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains(&copy.display().to_string()), "{}", warnings[0]);
+    assert!(reloaded.take_warnings().is_empty());
+    std::fs::remove_file(&copy)?;
+    std::fs::remove_file(&trash_file)?;
+
+    std::fs::remove_file(file_path)?;
+    std::fs::remove_file(db::data_hash_path(Path::new(file_path)))?;
+
+    // Moves to and from the trash are logged, so a crash before the save loses neither.
+    let wal_path = "soft_delete_test.wal";
+    let _ = std::fs::remove_file(wal_path);
+    {
+        let mut db = InMemoryDB::new();
+        db.set_soft_delete(true);
+        db.enable_wal(wal_path)?;
+        db.add("gone", json!({"n": 1}))?;
+        db.add("back", json!({"n": 2}))?;
+        db.add("purged", json!({"n": 3}))?;
+        db.delete("purged")?;
+        db.empty_trash()?;
+        assert!(db.delete_key("gone")?);
+        db.delete("back")?;
+        assert!(db.restore_deleted("back")?);
+        // Dropped without saving, like a crash.
+    }
+    let mut db = InMemoryDB::new();
+    db.enable_wal(wal_path)?;
+    assert_eq!(db.keys(), ["back"]);
+    assert_eq!(db.trashed_keys(), vec!["gone"]);
+    assert!(db.restore_deleted("gone")?);
+    assert_eq!(db.get("gone"), Some(&json!({"n": 1})));
+    drop(db);
+    std::fs::remove_file(wal_path)?;
+    Ok(())
+}
+
+fn test_sample() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    assert!(db.sample(3).is_empty());
+    for i in 0..4 {
+        db.add(&format!("k{}", i), json!(i))?;
+    }
+
+    let picked = db.sample(3);
+    assert_eq!(picked.len(), 3);
+    let mut keys: Vec<&str> = picked.iter().map(|(key, _)| key.as_str()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(keys.len(), 3);
+    assert!(picked.iter().all(|(key, value)| db.get(key) == Some(*value)));
+
+    assert_eq!(db.sample(100).len(), 4);
+    assert!(db.sample(0).is_empty());
+
+    // Every record turns up about a quarter of the time.
+    let mut counts = std::collections::HashMap::new();
+    for _ in 0..4000 {
+        for (key, _) in db.sample(1) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+    assert_eq!(counts.len(), 4);
+    assert!(counts.values().all(|&count| (700..1300).contains(&count)), "{:?}", counts);
+    Ok(())
+}
+
+fn test_field_presence() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    db.add("set", json!({"email": "a@example.com", "address": {"city": "Oslo"}}))?;
+    db.add("null", json!({"email": null, "address": {"city": null}}))?;
+    db.add("absent", json!({"address": {}}))?;
+    db.add("scalar", json!(42))?;
+
+    let sorted = |mut keys: Vec<String>| { keys.sort(); keys };
+    assert_eq!(sorted(db.keys_with_field("email")), ["null", "set"]);
+    assert_eq!(sorted(db.keys_missing_field("email")), ["absent", "scalar"]);
+    assert_eq!(sorted(db.keys_with_field("address.city")), ["null", "set"]);
+    assert_eq!(sorted(db.keys_missing_field("address.city")), ["absent", "scalar"]);
+    // Searching for null finds explicit nulls only.
+    assert_eq!(db.search_by_field("email", "null"), ["null"]);
+    Ok(())
+}
+
+fn test_batch_add() -> io::Result<()> {
+    assert_eq!(crate::shell::parse_batch_line("user1\t{\"age\": 30}\n"), Ok(("user1".to_string(), json!({"age": 30}))));
+    assert_eq!(crate::shell::parse_batch_line("user2 [1, 2]"), Ok(("user2".to_string(), json!([1, 2]))));
+    assert!(crate::shell::parse_batch_line("lonely").is_err());
+    assert!(crate::shell::parse_batch_line("user3\t{broken").is_err());
+
+    let wal_path = "test_batch_add.wal";
+    let _ = std::fs::remove_file(wal_path);
+    let mut db = InMemoryDB::new();
+    db.enable_wal(wal_path).map_err(io::Error::other)?;
+    db.set_limits(RecordLimits { max_key_len: 8, max_value_size: 1024 });
+    let report = db.add_batch(vec![
+        ("a".to_string(), json!(1)),
+        ("much_too_long_key".to_string(), json!(2)),
+        ("b".to_string(), json!({"x": true})),
+    ]).map_err(io::Error::other)?;
+    assert_eq!(report.added, 2);
+    assert_eq!(report.rejected.len(), 1);
+    assert_eq!(report.rejected[0].0, 1);
+    assert_eq!(db.len(), 2);
+
+    // The whole batch is one log record, and replays as such.
+    let (_, records) = Wal::open(wal_path, None).map_err(io::Error::other)?;
+    assert_eq!(records, vec![WalRecord::InsertMany {
+        records: vec![("a".to_string(), json!(1)), ("b".to_string(), json!({"x": true}))],
+    }]);
+    let mut replayed = InMemoryDB::new();
+    assert_eq!(replayed.enable_wal(wal_path).map_err(io::Error::other)?, 1);
+    assert_eq!(replayed.get_all_data(), db.get_all_data());
+
+    std::fs::remove_file(wal_path)?;
+    Ok(())
+}
+
+fn test_field_index() -> io::Result<()> {
+    let mut storage = std::collections::HashMap::new();
+    for i in 0..40 {
+        storage.insert(format!("user{:02}", i), json!({"age": i, "name": format!("Name{}", i % 4), "code": (i % 5).to_string()}));
+    }
+    let index_name = "test_field_lookup_index";
+    let mut index = HashIndex::new();
+    index.create_index(index_name);
+    index.rebuild_index(index_name, &storage);
+
+    let scanned_range = { let mut keys = index.find_range(index_name, "age", 10.0, 12.5, &storage); keys.sort(); keys };
+    let scanned_prefix = { let mut keys = index.find_partial_matching(index_name, "name", "name1", MatchMode::Prefix, &storage); keys.sort(); keys };
+    let scanned_multi = { let mut keys = index.find_multi(index_name, &[("code".to_string(), json!(2)), ("name".to_string(), json!("Name2"))], &storage); keys.sort(); keys };
+
+    assert!(!index.index_field("no_such_index", "age", &storage));
+    for field in ["age", "name", "code"] {
+        assert!(index.index_field(index_name, field, &storage));
+    }
+    assert_eq!(index.indexed_fields(index_name), vec!["age", "code", "name"]);
+    assert_eq!(index.find_range(index_name, "age", 10.0, 12.5, &storage), scanned_range);
+    assert_eq!(scanned_range, vec!["user10", "user11", "user12"]);
+    assert_eq!(index.find_partial_matching(index_name, "name", "name1", MatchMode::Prefix, &storage), scanned_prefix);
+    assert_eq!(scanned_prefix.len(), 10);
+    assert!(index.find_partial_matching(index_name, "name", "name1", MatchMode::Exact, &storage).is_empty());
+    // `2` matches the string "2" and `Name2` only where both hold.
+    assert_eq!(index.find_multi(index_name, &[("code".to_string(), json!(2)), ("name".to_string(), json!("Name2"))], &storage), scanned_multi);
+    assert_eq!(index.find_field(index_name, "code", "2", &storage).len(), 8);
+
+    // Lookups follow changes made through the index.
+    let old = storage.insert("user10".to_string(), json!({"age": 99, "name": "Renamed"})).unwrap();
+    index.remove_from_index(index_name, "user10", &old);
+    index.add_to_index(index_name, "user10", &storage["user10"]);
+    storage.insert("late".to_string(), json!({"age": 11.0}));
+    index.add_to_index(index_name, "late", &storage["late"]);
+    assert_eq!(index.find_range(index_name, "age", 10.0, 12.5, &storage), vec!["late", "user11", "user12"]);
+    assert_eq!(index.find_range(index_name, "age", 99.0, 99.0, &storage), vec!["user10"]);
+
+    index.drop_index(index_name);
+    assert!(index.indexed_fields(index_name).is_empty());
+    Ok(())
+}
+
+fn test_numeric_values() -> io::Result<()> {
+    // Whole numbers are equal however they were written.
+    assert_eq!(hash_value(&json!(30)), hash_value(&json!(30.0)));
+    assert_eq!(hash_value(&json!(0)), hash_value(&json!(-0.0)));
+    assert_eq!(hash_value(&json!({"age": 30})), hash_value(&json!({"age": 30.0})));
+    assert_eq!(hash_value(&json!(u64::MAX - 2047)), hash_value(&json!(18446744073709549568.0)));
+    assert!(values_match(&json!(30.0), &json!(30)));
+    assert!(values_match(&json!(-5), &json!(-5.0)));
+    assert_ne!(hash_value(&json!(1)), hash_value(&json!(1.5)));
+    assert!(!values_match(&json!(1.5), &json!(1)));
+
+    // Integers past f64 precision stay distinct.
+    let big = 9_007_199_254_740_993u64;
+    assert!(!values_match(&json!(big), &json!(big - 1)));
+    assert!(!values_match(&json!(big), &json!(9007199254740992.0)));
+    assert!(values_match(&json!(big - 1), &json!(9007199254740992.0)));
+    assert_ne!(hash_value(&json!(big)), hash_value(&json!(big - 1)));
+    assert!(values_match(&json!(big.to_string()), &json!(big)));
+    assert!(!values_match(&json!(big.to_string()), &json!(big - 1)));
+    assert!(!values_match(&json!((big - 1).to_string()), &json!(big)));
+    assert!(values_match(&json!("30.0"), &json!(30)));
+    assert!(values_match(&json!("+5"), &json!(5)));
+
+    let mut storage = std::collections::HashMap::new();
+    storage.insert("float".to_string(), json!({"age": 30.0, "id": big - 1}));
+    storage.insert("int".to_string(), json!({"age": 30, "id": big}));
+    let index_name = "test_numeric_values_index";
+    let mut index = HashIndex::new();
+    index.create_index(index_name);
+    index.rebuild_index(index_name, &storage);
+    let mut found = index.find_by_value(index_name, &json!({"age": 30, "id": big - 1}));
+    found.sort();
+    assert_eq!(found, vec!["float"]);
+    for indexed in [false, true] {
+        if indexed {
+            assert!(index.index_field(index_name, "id", &storage));
+            assert!(index.index_field(index_name, "age", &storage));
+        }
+        assert_eq!(index.find_multi(index_name, &[("age".to_string(), json!(30))], &storage).len(), 2);
+        assert_eq!(index.find_multi(index_name, &[("id".to_string(), json!(big))], &storage), vec!["int"]);
+        assert_eq!(index.find_field(index_name, "id", &big.to_string(), &storage), vec!["int"]);
+    }
+
+    index.drop_index(index_name);
+    Ok(())
+}
+
+fn test_field_profile() -> io::Result<()> {
+    let mut storage = std::collections::HashMap::new();
+    storage.insert("a".to_string(), json!({"age": 30, "tags": ["x"]}));
+    storage.insert("b".to_string(), json!({"age": 30.0}));
+    storage.insert("c".to_string(), json!({"age": 12}));
+    storage.insert("d".to_string(), json!({"age": "unknown"}));
+    storage.insert("e".to_string(), json!({"age": null}));
+    storage.insert("f".to_string(), json!({"name": "no age"}));
+
+    let index = HashIndex::new();
+    let profile = index.field_profile("age", &storage);
+    assert_eq!(profile.present(), 5);
+    assert_eq!(profile.missing, 1);
+    assert_eq!((profile.numbers, profile.strings, profile.nulls), (3, 1, 1));
+    assert_eq!(profile.type_counts()[0], ("number", 3));
+    assert_eq!((profile.min, profile.max), (Some(12.0), Some(30.0)));
+    assert!((profile.mean.unwrap() - 24.0).abs() < 1e-9);
+    assert_eq!(profile.distinct, 5);
+
+    let tags = index.field_profile("tags", &storage);
+    assert_eq!((tags.arrays, tags.missing), (1, 5));
+    assert_eq!(tags.mean, None);
+    assert_eq!(index.field_profile("nowhere", &storage).present(), 0);
+    Ok(())
+}
+
+fn test_array_contains() -> io::Result<()> {
+    let mut storage = std::collections::HashMap::new();
+    storage.insert("a".to_string(), json!({"tags": ["red", "blue"], "meta": {"ids": [1, 2]}}));
+    storage.insert("b".to_string(), json!({"tags": ["3", "green"], "meta": {"ids": [3.0]}}));
+    storage.insert("c".to_string(), json!({"tags": "red", "flags": [true], "pairs": [[1, 2]]}));
+
+    let index = HashIndex::new();
+    assert_eq!(index.find_array_contains("tags", "red", &storage), vec!["a"]);
+    assert_eq!(index.find_array_contains("meta.ids", "3", &storage), vec!["b"]);
+    assert_eq!(index.find_array_contains("meta.ids", "1e0", &storage), vec!["a"]);
+    // A typed element also finds the same text stored as a string; quoting limits it to strings.
+    assert_eq!(index.find_array_contains("tags", "3", &storage), vec!["b"]);
+    assert!(index.find_array_contains("meta.ids", "\"3\"", &storage).is_empty());
+    assert_eq!(index.find_array_contains("flags", "TRUE", &storage), vec!["c"]);
+    assert_eq!(index.find_array_contains("pairs", "[1, 2]", &storage), vec!["c"]);
+    assert!(index.find_array_contains("tags", "purple", &storage).is_empty());
+    Ok(())
+}
+
+fn test_record_limits() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    assert_eq!(db.limits(), RecordLimits::default());
+    db.insert("normal", json!({"name": "Alice", "tags": ["a", "b"]}))?;
+
+    db.set_limits(RecordLimits { max_key_len: 8, max_value_size: 32 });
+    assert!(matches!(db.insert("much_too_long_key", json!(1)), Err(RedruError::Limit(_))));
+    assert!(matches!(db.insert("big", json!("x".repeat(40))), Err(RedruError::Limit(_))));
+    assert!(!db.exists("big"));
+    db.insert("small", json!("x".repeat(20)))?;
+    assert!(matches!(db.update("small", json!("y".repeat(40))), Err(RedruError::Limit(_))));
+    assert_eq!(db.get("small"), Some(&json!("x".repeat(20))));
+    Ok(())
+}
+
+fn test_disk_space() -> io::Result<()> {
+    let available = disk::available_space(std::path::Path::new("not/yet/created.json"));
+    assert!(available.is_some_and(|bytes| bytes > 0));
+    disk::ensure_space(std::path::Path::new("."), 0)?;
+    let err = disk::ensure_space(std::path::Path::new("."), u64::MAX / 2).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+
+    assert_eq!(disk::format_bytes(512), "512 B");
+    assert_eq!(disk::format_bytes(1536), "1.5 KiB");
+    assert_eq!(disk::format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    Ok(())
+}
+
+fn test_export_import() -> io::Result<()> {
+    let export_path = "export_import_test.json";
+    let mut db = InMemoryDB::new();
+    db.set_format(StorageFormat::MessagePack);
+    db.insert("a", json!({"n": 1}))?;
+    db.insert("b", json!([1, 2, 3]))?;
+    assert_eq!(db.export_all(export_path)?, 2);
+    let exported: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(export_path)?)?;
+    assert_eq!(exported, json!({"a": {"n": 1}, "b": [1, 2, 3]}));
+
+    let wal_path = "export_import_test.wal";
+    let _ = std::fs::remove_file(wal_path);
+    let mut other = InMemoryDB::new();
+    other.enable_wal(wal_path)?;
+    other.insert("a", json!("old"))?;
+    other.insert("c", json!(true))?;
+    assert_eq!(other.import_all(export_path, false)?, 2);
+    assert_eq!(other.get("a"), Some(&json!({"n": 1})));
+    assert!(other.exists("c"));
+    let mut recovered = InMemoryDB::new();
+    recovered.enable_wal(wal_path)?;
+    assert_eq!(recovered.get_all_data(), other.get_all_data());
+
+    assert_eq!(other.import_all(export_path, true)?, 2);
+    assert!(!other.exists("c"));
+    assert_eq!(other.len(), 2);
+    let mut recovered = InMemoryDB::new();
+    recovered.enable_wal(wal_path)?;
+    assert_eq!(recovered.get_all_data(), other.get_all_data());
+    std::fs::remove_file(wal_path)?;
+
+    other.set_limits(RecordLimits { max_key_len: 8, max_value_size: 4 });
+    other.insert("d", json!(1))?;
+    assert!(matches!(other.import_all(export_path, true), Err(RedruError::Limit(_))));
+    assert!(other.exists("d"));
+
+    std::fs::write(export_path, "[1, 2]")?;
+    assert!(matches!(other.import_all(export_path, false), Err(RedruError::Serialization(_))));
+    std::fs::remove_file(export_path)?;
+    Ok(())
+}
+
+fn test_session_case_collision() -> io::Result<()> {
+    let sessions = vec!["MyDB".to_string(), "notes".to_string()];
+    assert_eq!(crate::sessions::case_collision("mydb", &sessions), Some(&"MyDB".to_string()));
+    assert_eq!(crate::sessions::case_collision("NOTES", &sessions), Some(&"notes".to_string()));
+    assert_eq!(crate::sessions::case_collision("MyDB", &sessions), None);
+    assert_eq!(crate::sessions::case_collision("other", &sessions), None);
+    Ok(())
+}
+
+fn test_session_names() -> io::Result<()> {
+    for name in ["notes", "my db", "v1.2", "Ünïcode", ".hidden"] {
+        assert!(crate::sessions::check_session_name(name).is_ok(), "{} should be allowed", name);
+    }
+    for name in ["", ".", "..", "../../etc", "a/b", "/etc", "a\\b", "..\\up", "c:x", "a..b", "tab\there", "nul\0"] {
+        assert!(crate::sessions::check_session_name(name).is_err(), "{:?} should be rejected", name);
+    }
+    assert!(crate::sessions::check_session_name(&"x".repeat(256)).is_err());
+    assert_eq!(crate::sessions::session_dir("notes")?, "sessions/notes");
+    let err = crate::sessions::session_dir("../escape").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+fn test_profiles() -> io::Result<()> {
+    let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+    let mut plain = args(&["vectors", "v.bin"]);
+    assert_eq!(crate::take_profile(&mut plain), Ok(None));
+    assert_eq!(plain, args(&["vectors", "v.bin"]));
+
+    for given in [args(&["--profile", "alice", "vectors"]), args(&["vectors", "--profile=alice"])] {
+        let mut given = given;
+        assert_eq!(crate::take_profile(&mut given), Ok(Some("alice".to_string())));
+        assert_eq!(given, args(&["vectors"]));
+    }
+    assert!(crate::take_profile(&mut args(&["--profile"])).is_err());
+    assert!(crate::take_profile(&mut args(&["--profile=../bob"])).is_err());
+
+    assert_eq!(redru::password_manager::password_file(None), "passwords.json");
+    assert_eq!(redru::password_manager::password_file(Some("alice")), "passwords.alice.json");
+    Ok(())
+}
+
+fn test_password_hash_verification() -> io::Result<()> {
+    use redru::password_manager::{hash_password, verify_password_hash};
+
+    let stored = hash_password("Right#Pass123").map_err(io::Error::other)?;
+    assert!(verify_password_hash("Right#Pass123", &stored));
+    assert!(!verify_password_hash("Wrong#Pass123", &stored));
+    assert!(!verify_password_hash("", &stored));
+    // An unreadable stored hash still costs a full check, and nothing matches it.
+    for broken in ["", "not a hash", &stored[..stored.len() / 2]] {
+        assert!(!verify_password_hash("Right#Pass123", broken), "{:?}", broken);
+    }
+    Ok(())
+}
+
+fn test_password_api() -> io::Result<()> {
+    use redru::PasswordManager;
+    use redru::password_manager::{session_scope, MASTER_SCOPE};
+
+    let profile = "api_test";
+    let file = redru::password_manager::password_file(Some(profile));
+    let mut manager = PasswordManager::new(Some(profile))?;
+    assert!(manager.check_master_password("anything")?);
+    assert!(manager.set_session_password_to("s", "Session#Pass1").is_err());
+
+    manager.set_master_password_to("Master#Pass123")?;
+    assert!(manager.check_master_password("Master#Pass123")?);
+    assert!(!manager.check_master_password("wrong")?);
+    assert_eq!(manager.attempts_remaining(MASTER_SCOPE), Some(4));
+    manager.change_master_password_to("Master#Pass456")?;
+    assert!(!manager.check_master_password("Master#Pass123")?);
+    assert!(manager.check_master_password("Master#Pass456")?);
+    assert_eq!(manager.attempts_remaining(MASTER_SCOPE), Some(5));
+    assert!(!manager.has_session_password("s"));
+    manager.set_session_password_to("s", "Session#Pass1")?;
+    assert!(manager.has_session_password("s"));
+    let key = manager.session_key("s").expect("a new password unlocks the session");
+
+    // The data is encrypted now, so a new password needs the current one first.
+    let mut reopened = PasswordManager::new(Some(profile))?;
+    assert!(reopened.session_key("s").is_none());
+    assert!(reopened.set_session_password_to("s", "Session#Pass2").is_err());
+    assert!(!reopened.check_session_password("s", "wrong")?);
+    assert_eq!(reopened.attempts_remaining(&session_scope("s")), Some(4));
+    assert!(reopened.check_session_password("s", "Session#Pass1")?);
+    reopened.set_session_password_to("s", "Session#Pass2")?;
+
+    let mut again = PasswordManager::new(Some(profile))?;
+    assert!(again.check_session_password("s", "Session#Pass2")?);
+    assert_eq!(again.session_key("s"), Some(key));
+    assert!(again.check_session_password("unprotected", "anything")?);
+
+    // Resetting asks nothing; the caller warns about `encrypted_sessions` first.
+    assert_eq!(again.encrypted_sessions(), ["s"]);
+    again.reset_all_passwords()?;
+    assert!(!again.is_master_password_set());
+    assert!(!Path::new(&file).exists());
+    assert!(!Path::new(&format!("{}.bak", file)).exists());
+    Ok(())
+}
+
+fn test_yes_no_answers() -> io::Result<()> {
+    for answer in ["y", "Y", "yes", "YES", " Yes \n", "\ty"] {
+        assert_eq!(parse_yes_no(answer), Some(true), "{:?}", answer);
+    }
+    for answer in ["n", "N", "no", " NO\n"] {
+        assert_eq!(parse_yes_no(answer), Some(false), "{:?}", answer);
+    }
+    for answer in ["", "\n", "yep", "ye s", "1", "true"] {
+        assert_eq!(parse_yes_no(answer), None, "{:?}", answer);
+    }
+    Ok(())
+}
+
+fn test_columnar_output() -> io::Result<()> {
+    let keys: Vec<String> = ["alpha", "beta", "gamma", "delta", "epsilon"].iter().map(|k| k.to_string()).collect();
+    assert_eq!(format_columns(&keys, 30), vec!["  alpha  gamma  epsilon", "  beta   delta"]);
+    assert_eq!(format_columns(&keys, 80), vec!["  alpha  beta  gamma  delta  epsilon"]);
+    // Too narrow for anything: one key per row, never truncated.
+    assert_eq!(format_columns(&keys, 5).len(), 5);
+    assert!(format_columns(&[], 80).is_empty());
+
+    assert_eq!(truncate_preview(&json!({"a": 1}), 20), r#"{"a":1}"#);
+    assert_eq!(truncate_preview(&json!({"a": "long text"}), 8), r#"{"a":"l…"#);
+    assert_eq!(truncate_preview(&json!("ééééé"), 4).chars().count(), 4);
+
+    display::set_plain(true);
+    assert_eq!((icon("✅"), icon("❌"), icon("⚠️"), icon("ℹ️")), ("[ok]", "[error]", "[warn]", "[info]"));
+    assert!(!display::use_color());
+    display::set_plain(false);
+    assert_eq!(icon("✅"), "✅");
+    Ok(())
+}
+
+fn test_merge_from_file() -> io::Result<()> {
+    let other_path = "merge_test_other.json";
+    let mut other = InMemoryDB::new();
+    other.add("shared", json!("theirs"))?;
+    other.add("new", json!(1))?;
+    other.save_to_file_with_path(other_path)?;
+
+    let base = || -> io::Result<InMemoryDB> {
+        let mut db = InMemoryDB::new();
+        db.add("shared", json!("ours"))?;
+        db.add("own", json!(2))?;
+        Ok(db)
+    };
+
+    let mut db = base()?;
+    let report = db.merge_from_file(other_path, ConflictPolicy::Skip)?;
+    assert_eq!(report, MergeReport { added: 1, overwritten: 0, skipped: 1 });
+    assert_eq!(db.get("shared"), Some(&json!("ours")));
+    assert_eq!(db.get("new"), Some(&json!(1)));
+
+    let wal_path = "merge_test.wal";
+    let _ = std::fs::remove_file(wal_path);
+    let mut db = base()?;
+    db.enable_wal(wal_path)?;
+    let report = db.merge_from_file(other_path, ConflictPolicy::Overwrite)?;
+    assert_eq!(report, MergeReport { added: 1, overwritten: 1, skipped: 0 });
+    assert_eq!(db.get("shared"), Some(&json!("theirs")));
+    // Only the merged keys are logged; "own" predates the log.
+    let mut recovered = InMemoryDB::new();
+    assert_eq!(recovered.enable_wal(wal_path)?, 1);
+    assert_eq!(recovered.keys().len(), 2);
+    assert_eq!(recovered.get("shared"), Some(&json!("theirs")));
+    std::fs::remove_file(wal_path)?;
+
+    let mut db = base()?;
+    let result = db.merge_from_file(other_path, ConflictPolicy::Error);
+    assert!(matches!(result, Err(RedruError::Conflict(_))));
+    assert_eq!(db.len(), 2);
+    assert!(db.get("new").is_none());
+
+    std::fs::remove_file(other_path)?;
+    Ok(())
+}
+
+fn test_nan_distances() -> io::Result<()> {
+    let file_path = "test_vectors_nan.json";
+    let _ = std::fs::remove_file(file_path);
+
+    let mut db = VectorDB::new(file_path, VectorFormat::Json)?;
+    db.set_vectors(vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![f64::NAN, 1.0], vec![0.0, 1.0]])?;
+
+    let cosine: Vec<usize> = db.query_similar(&[1.0, 0.0], true).iter().map(|m| m.index).collect();
+    assert_eq!(&cosine[..2], &[1, 3]);
+    assert!(db.query_similar(&[1.0, 0.0], true)[2..].iter().all(|m| m.distance.is_nan()));
+
+    let euclidean = db.query_similar(&[1.0, 0.0], false);
+    assert_eq!(euclidean[0].index, 1);
+    assert_eq!(euclidean[0].vector.to_vec(), vec![1.0, 0.0]);
+    assert_eq!(euclidean[3].index, 2);
+    assert!(euclidean[3].distance.is_nan());
+
+    assert!(db.query_similar(&[0.0, 0.0], true).iter().all(|m| m.distance.is_nan()));
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_query_threshold() -> io::Result<()> {
+    let file_path = "test_vectors_threshold.bin";
+    let _ = std::fs::remove_file(file_path);
+
+    let mut db = VectorDB::new(file_path, VectorFormat::Binary)?;
+    db.set_vectors(vec![vec![1.0, 0.0], vec![1.0, 1.0], vec![0.0, 1.0], vec![-1.0, 0.0], vec![0.0, 0.0]])?;
+    let query = vec![1.0, 0.0];
+
+    // Euclidean: the threshold is a maximum distance.
+    let near: Vec<usize> = db.query_similar_within(&query, false, Some(1.0)).iter().map(|m| m.index).collect();
+    assert_eq!(near, vec![0, 1, 4]);
+    assert!(db.query_similar_within(&query, false, Some(-1.0)).is_empty());
+
+    // Cosine: the threshold is a minimum similarity; the zero vector never passes.
+    let similar: Vec<usize> = db.query_similar_within(&query, true, Some(0.5)).iter().map(|m| m.index).collect();
+    assert_eq!(similar, vec![0, 1]);
+    assert_eq!(db.query_similar_within(&query, true, Some(-1.0)).len(), 4);
+    assert_eq!(db.query_similar_within(&query, true, None).len(), 5);
+
+    let mapped = MappedVectors::open(file_path)?;
+    let expected: Vec<(usize, f64)> = db.query_similar_within(&query, true, Some(0.5)).iter().map(|m| (m.index, m.distance)).collect();
+    assert_eq!(mapped.query_similar_within(&query, true, Some(0.5)), expected);
+
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_vector_file_parsing() -> io::Result<()> {
+    let content = "x,y,z\n1,2,3\n\n4,oops,5,6\n7,8\n";
+    let (vectors, summary) = parse_vector_file(content, false)?;
+    assert_eq!(vectors, vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0], vec![7.0, 8.0]]);
+    assert_eq!(summary.dimension, Some(3));
+    assert_eq!(summary.skipped_lines, vec![1]);
+    assert_eq!(summary.skipped_values, 1);
+    assert_eq!(summary.ragged_lines, vec![(5, 2)]);
+
+    let err = parse_vector_file(content, true).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(parse_vector_file("1 2\n3 4\n", true).is_ok());
+    Ok(())
+}
+
+fn test_vector_stats() -> io::Result<()> {
+    let stats = vector_stats(&[]);
+    assert_eq!(stats.count, 0);
+    assert!(stats.dimensions.is_empty() && stats.components.is_empty());
+
+    let stats = vector_stats(&[vec![1.0, -2.0], vec![3.0, 4.0], vec![5.0, f64::NAN, 9.0]]);
+    assert_eq!(stats.count, 3);
+    assert!(stats.is_ragged());
+    assert_eq!(stats.dimensions, vec![(2, 2), (3, 1)]);
+    assert_eq!(stats.non_finite, 1);
+    assert_eq!(stats.components[0], ComponentStats { min: 1.0, max: 5.0, mean: 3.0 });
+    assert_eq!(stats.components[1], ComponentStats { min: -2.0, max: 4.0, mean: 1.0 });
+    assert_eq!(stats.components[2], ComponentStats { min: 9.0, max: 9.0, mean: 9.0 });
+
+    assert!(!vector_stats(&[vec![1.0], vec![2.0]]).is_ragged());
+    Ok(())
+}
+
+fn test_save_query_results() -> io::Result<()> {
+    let results = vec![
+        SavedMatch { query: 1, index: 3, distance: 0.5, vector: vec![1.0, 2.5] },
+        SavedMatch { query: 2, index: 0, distance: 0.0, vector: vec![-1.0] },
+    ];
+    let json_path = Path::new("test_query_results.json");
+    let csv_path = Path::new("test_query_results.CSV");
+
+    write_query_results(json_path, &results)?;
+    let loaded: Vec<SavedMatch> = serde_json::from_str(&std::fs::read_to_string(json_path)?)?;
+    assert_eq!(loaded, results);
+
+    write_query_results(csv_path, &results)?;
+    assert_eq!(
+        std::fs::read_to_string(csv_path)?,
+        "query,index,distance,vector\n1,3,0.5,1 2.5\n2,0,0,-1\n"
+    );
+
+    std::fs::remove_file(json_path)?;
+    std::fs::remove_file(csv_path)?;
+    Ok(())
+}
+
+fn test_vector_settings() -> io::Result<()> {
+    let path = settings_path("test_settings_vectors.bin");
+    assert_eq!(path, Path::new("test_settings_vectors.settings.json"));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(VectorSettings::load(&path)?.default_metric, Metric::Euclidean);
+
+    let settings = VectorSettings { default_metric: Metric::Cosine, top_k: 3, threshold: Some(0.5), ..Default::default() };
+    settings.save(&path)?;
+    assert!(std::fs::read_to_string(&path)?.contains("\"cosine\""));
+    assert_eq!(VectorSettings::load(&path)?, settings);
+
+    std::fs::write(&path, "{}")?;
+    assert_eq!(VectorSettings::load(&path)?.default_metric, Metric::Euclidean);
+    std::fs::write(&path, "not json")?;
+    assert!(VectorSettings::load(&path).is_err());
+    let mut db = VectorDB::new("test_settings_vectors.bin", VectorFormat::Binary)?;
+    assert_eq!(db.settings().default_metric, Metric::Euclidean);
+    assert_eq!(db.take_warnings().len(), 1);
+    assert!(db.take_warnings().is_empty());
+    std::fs::remove_file(&path)?;
+
+    // Normalized mode is a setting, so a reopened store keeps normalizing.
+    let store_path = "test_settings_vectors.bin";
+    let _ = std::fs::remove_file(store_path);
+    let mut db = VectorDB::new(store_path, VectorFormat::Binary)?;
+    db.add_vector(vec![3.0, 4.0])?;
+    db.normalize_all()?;
+    assert!(db.settings().normalized);
+    let mut reopened = VectorDB::new(store_path, VectorFormat::Binary)?;
+    reopened.add_vector(vec![6.0, 8.0])?;
+    reopened.update_vector(0, vec![0.0, 2.0])?;
+    assert_eq!(reopened.list_vectors().to_vec(), vec![vec![0.0, 1.0], vec![0.6, 0.8]]);
+
+    std::fs::remove_file(store_path)?;
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+fn test_vector_query_defaults() -> io::Result<()> {
+    let store_path = "test_defaults_vectors.json";
+    let bin_path = "test_defaults_vectors.bin";
+    let other_path = "test_defaults_other.json";
+    let cleanup = || {
+        for path in [store_path, bin_path, other_path] {
+            let _ = std::fs::remove_file(path);
+            let _ = std::fs::remove_file(settings_path(path));
+        }
+    };
+    cleanup();
+
+    let mut db = VectorDB::new(store_path, VectorFormat::Json)?;
+    assert_eq!(db.settings(), &VectorSettings::default());
+    assert_eq!(db.settings().top_k, 5);
+    db.set_vectors((1..=10).map(|i| vec![i as f64, 0.0]).collect())?;
+    assert_eq!(db.query_with_defaults(&[0.0, 0.0]).len(), 5);
+
+    let settings = VectorSettings { default_metric: Metric::Euclidean, top_k: 3, threshold: Some(2.5), ..Default::default() };
+    db.set_settings(settings.clone())?;
+    assert!(settings_path(store_path).exists());
+    let nearest: Vec<f64> = db.query_with_defaults(&[0.0, 0.0]).iter().map(|m| m.distance).collect();
+    assert_eq!(nearest, vec![1.0, 2.0]);
+    assert_eq!(db.query_with_defaults(&[5.0, 0.0]).len(), 3);
+
+    // The defaults travel with the binary export into another store.
+    db.save_as_binary(bin_path)?;
+    assert_eq!(VectorSettings::load(&settings_path(bin_path))?, settings);
+    let mut other = VectorDB::new(other_path, VectorFormat::Json)?;
+    other.load_from_binary(bin_path)?;
+    assert_eq!(other.settings(), &settings);
+    assert_eq!(VectorDB::new(other_path, VectorFormat::Json)?.settings(), &settings);
+
+    // A sidecar written before top-k and thresholds existed keeps the old behaviour.
+    std::fs::write(settings_path(store_path), r#"{"default_metric":"cosine"}"#)?;
+    let old = VectorDB::new(store_path, VectorFormat::Json)?;
+    assert_eq!(old.settings().default_metric, Metric::Cosine);
+    assert_eq!(old.settings().top_k, 5);
+    assert_eq!(old.settings().threshold, None);
+
+    cleanup();
+    Ok(())
+}
+
+fn test_vector_display() -> io::Result<()> {
+    let settings = VectorSettings::default();
+    assert_eq!(settings.format_vector(&[1.0, -0.5, 1.0 / 3.0]), "[1.0000, -0.5000, 0.3333]");
+    assert_eq!(settings.format_vector(&[]), "[]");
+    assert_eq!(settings.format_distance(2.0_f64.sqrt()), "1.4142");
+
+    let embedding: Vec<f64> = (0..768).map(|i| i as f64 / 10.0).collect();
+    assert_eq!(settings.format_vector(&embedding),
+        "[0.0000, 0.1000, 0.2000, …, 76.5000, 76.6000, 76.7000] (768 dims)");
+    let short = VectorSettings { precision: 1, edge_components: 1, ..Default::default() };
+    assert_eq!(short.format_vector(&embedding), "[0.0, …, 76.7] (768 dims)");
+    assert_eq!(short.format_vector(&[0.25, 0.75]), "[0.2, 0.8]");
+    let all = VectorSettings { precision: 0, edge_components: 0, ..Default::default() };
+    assert_eq!(all.format_vector(&embedding).matches(", ").count(), 767);
+
+    // Sidecars written before these settings existed get the defaults.
+    let old: VectorSettings = serde_json::from_str(r#"{"default_metric": "cosine", "top_k": 2}"#)?;
+    assert_eq!((old.precision, old.edge_components), (4, 3));
+    Ok(())
+}
+
+fn test_quantized_vectors() -> io::Result<()> {
+    let v = [0.3, -1.27, 0.01, 1.0];
+    let q = QuantizedVector::quantize(&v).expect("finite");
+    for (x, y) in v.iter().zip(q.dequantize()) {
+        assert!((x - y).abs() <= 1.27 / 254.0 + 1e-12, "{} vs {}", x, y);
+    }
+    assert_eq!(QuantizedVector::quantize(&[0.0, 0.0]).expect("finite").dequantize(), vec![0.0, 0.0]);
+    assert!(QuantizedVector::quantize(&[1.0, f64::NAN]).is_none());
+
+    let float_path = "quantized_float_test.json";
+    let int8_path = "quantized_int8_test.json";
+    let vectors = vec![vec![1.0, 0.0], vec![0.0, 2.0], vec![0.6, 0.5], vec![0.0, 0.0]];
+    let mut float = VectorDB::new(float_path, VectorFormat::Json)?;
+    let mut int8 = VectorDB::with_storage(int8_path, VectorFormat::Json, VectorStorage::Int8)?;
+    float.set_vectors(vectors.clone())?;
+    int8.set_vectors(vectors)?;
+    assert_eq!(int8.storage(), VectorStorage::Int8);
+    assert!(matches!(int8.get(1), Some(VectorView::Int8(_))));
+
+    // Both metrics rank the same way, and distances agree to within the rounding.
+    for cosine in [false, true] {
+        let expected = float.query_similar(&[0.9, 0.1], cosine);
+        let got = int8.query_similar(&[0.9, 0.1], cosine);
+        let order = |matches: &[vector_db::QueryMatch<'_>]| matches.iter().map(|m| m.index).collect::<Vec<_>>();
+        assert_eq!(order(&got), order(&expected));
+        for (g, e) in got.iter().zip(&expected) {
+            assert!(g.distance.is_nan() && e.distance.is_nan() || (g.distance - e.distance).abs() < 0.01);
+        }
+    }
+
+    assert!(int8.add_vector(vec![f64::INFINITY, 1.0]).is_err());
+    let report = int8.add_batch(vec![vec![1.0, f64::NAN], vec![0.5, 0.5]])?;
+    assert_eq!((report.added, report.rejected.len()), (1, 1));
+    int8.update_vector(0, vec![0.0, 1.0])?;
+    assert_eq!(int8.list_vectors()[0], vec![0.0, 1.0]);
+    assert_eq!(int8.list_vectors().len(), 5);
+
+    // The file holds the dequantized values, so reopening it quantizes to the same codes.
+    let reopened = VectorDB::with_storage(int8_path, VectorFormat::Json, VectorStorage::Int8)?;
+    assert_eq!(reopened.get(2), int8.get(2));
+
+    let nan_path = "quantized_nan_test.bin";
+    VectorDB::new(nan_path, VectorFormat::Binary)?.set_vectors(vec![vec![1.0, 2.0], vec![f64::NAN, 0.0]])?;
+    assert!(VectorDB::with_storage(nan_path, VectorFormat::Binary, VectorStorage::Int8).is_err());
+    for path in [float_path, int8_path, nan_path] {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn test_compact_save() -> io::Result<()> {
+    let pretty_path = "pretty_test.json";
+    let compact_path = "compact_test.json";
+    let mut db = InMemoryDB::new();
+    db.add("user1", json!({"name": "Alice", "tags": ["a", "b"]}))?;
+    db.add("user2", json!({"name": "Bob", "age": 30}))?;
+    assert!(db.is_pretty());
+
+    db.save_to_file_with_path(pretty_path)?;
+    db.set_pretty(false);
+    db.save_to_file_with_path(compact_path)?;
+    let pretty_len = std::fs::metadata(pretty_path)?.len() as usize;
+    let compact_len = std::fs::metadata(compact_path)?.len() as usize;
+    assert!(compact_len < pretty_len);
+    assert!(!std::fs::read_to_string(compact_path)?.contains('\n'));
+
+    for path in [pretty_path, compact_path] {
+        let loaded = InMemoryDB::load_from_file_path(path)?;
+        assert_eq!(loaded.get_all_data(), db.get_all_data());
+    }
+    let stats = db.get_statistics();
+    assert_eq!((stats.total_size, stats.pretty_size), (compact_len, pretty_len));
+
+    std::fs::remove_file(pretty_path)?;
+    std::fs::remove_file(compact_path)?;
+    Ok(())
+}
+
+fn test_stats_json() -> io::Result<()> {
+    let mut db = InMemoryDB::new();
+    let empty = serde_json::to_value(db.get_statistics())?;
+    assert_eq!(empty["largest_record"], Value::Null);
+    assert_eq!(empty["value_types"], json!({}));
+
+    db.add("b", json!({"name": "Bob"}))?;
+    db.add("a", json!({"name": "Ann"}))?;
+    db.add("list", json!([1, 2]))?;
+    db.add("flag", json!(true))?;
+    db.set_pretty(false);
+    let stats = serde_json::to_value(db.get_statistics())?;
+    let mut keys: Vec<&str> = stats.as_object().unwrap().keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, ["average_record_size", "largest_record", "last_modified", "pretty_saves",
+        "pretty_size", "schema_version", "total_records", "total_size", "value_types"]);
+    assert_eq!(stats["schema_version"], json!(db::STATS_SCHEMA_VERSION));
+    assert_eq!(stats["total_records"], json!(4));
+    assert_eq!(stats["pretty_saves"], json!(false));
+    // Two 14-byte objects tie; the smaller key wins.
+    assert_eq!(stats["largest_record"], json!({"key": "a", "size": 14}));
+    assert_eq!(stats["value_types"], json!({"array": 1, "boolean": 1, "object": 2}));
+    Ok(())
+}
+
+fn test_truncated_binary() -> io::Result<()> {
+    let file_path = "test_truncated.bin";
+    let _ = std::fs::remove_file(file_path);
+    VectorDB::new(file_path, VectorFormat::Binary)?.set_vectors(vec![vec![1.0, 2.0], vec![3.0, 4.0, 5.0]])?;
+    let bytes = std::fs::read(file_path)?;
+
+    // Cut mid-vector, and mid length prefix.
+    for cut in [4, 8 * 3 + 4] {
+        std::fs::write(file_path, &bytes[..bytes.len() - cut])?;
+        let err = VectorDB::new(file_path, VectorFormat::Binary).err().expect("truncated file must not load");
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(MappedVectors::open(file_path).is_err());
+    }
+
+    // A garbage length prefix is rejected before anything is allocated.
+    let mut corrupt = bytes.clone();
+    let second_prefix = bytes.len() - 8 * 4;
+    corrupt[second_prefix..second_prefix + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(file_path, &corrupt)?;
+    let err = VectorDB::new(file_path, VectorFormat::Binary).err().expect("corrupt file must not load");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    std::fs::write(file_path, &bytes)?;
+    assert_eq!(VectorDB::new(file_path, VectorFormat::Binary)?.list_vectors().len(), 2);
+    std::fs::remove_file(file_path)?;
+    Ok(())
+}
+
+fn test_image_api() -> io::Result<()> {
+    let dir = "test_image_api";
+    let _ = std::fs::remove_dir_all(dir);
+    let mut processor = ImageProcessor::with_dir(dir)?;
+    let input = std::path::Path::new(dir).join("gradient.png");
+    image::RgbImage::from_fn(64, 32, |x, y| image::Rgb([(x * 4) as u8, (y * 8) as u8, 128]))
+        .save(&input)
+        .map_err(io::Error::other)?;
+
+    let options = CompressOptions { method: CompressionMethod::Jpeg, quality: 50, ..Default::default() };
+    let output = format!("{}/out.jpg", dir);
+    let result = processor.compress_file(&input, &output, &options)?;
+    assert_eq!(result.file, "gradient.png");
+    assert_eq!(result.original_size, std::fs::metadata(&input)?.len());
+    assert_eq!(result.compressed_size, std::fs::metadata(&output)?.len());
+
+    // A per-file quality overrides the batch value for that file only.
+    let mut per_file = CompressOptions { method: CompressionMethod::Jpeg, quality: 95, ..Default::default() };
+    let high = processor.compress_file(&input, format!("{}/q95.jpg", dir), &per_file)?.compressed_size;
+    per_file.quality_by_file = parse_quality_map(r#"{"gradient.png": 10, "other.png": 50}"#).map_err(io::Error::other)?;
+    assert_eq!(per_file.quality_for(&input), 10);
+    assert_eq!(per_file.quality_for(std::path::Path::new("elsewhere.png")), 95);
+    assert!(processor.compress_file(&input, format!("{}/q10.jpg", dir), &per_file)?.compressed_size < high);
+    assert!(parse_quality_map(r#"{"a.png": 0}"#).is_err());
+    assert!(parse_quality_map(r#"{"a.png": 101}"#).is_err());
+    assert!(parse_quality_map(r#"{"a.png": "high"}"#).is_err());
+    assert!(parse_quality_map("[80]").is_err());
+
+    processor.set_dry_run(true);
+    let estimate = format!("{}/estimate.jpg", dir);
+    assert!(processor.compress_file(&input, &estimate, &options)?.compressed_size > 0);
+    assert!(!std::path::Path::new(&estimate).exists());
+    processor.set_dry_run(false);
+
+    let resized = format!("{}/small.jpg", dir);
+    assert_eq!(processor.resize_file(&input, &resized, 16, 16, image::ImageFormat::Jpeg, false)?, std::fs::metadata(&resized)?.len());
+    assert_eq!(image::image_dimensions(&resized).map_err(io::Error::other)?, (16, 8));
+
+    // A box larger than the image leaves it alone unless enlarging is asked for.
+    let kept = format!("{}/kept.png", dir);
+    processor.resize_file(&input, &kept, 1920, 1080, image::ImageFormat::Png, false)?;
+    assert_eq!(image::image_dimensions(&kept).map_err(io::Error::other)?, (64, 32));
+    processor.resize_file(&input, &kept, 256, 256, image::ImageFormat::Png, true)?;
+    assert_eq!(image::image_dimensions(&kept).map_err(io::Error::other)?, (256, 128));
+    let boxed = CompressOptions { method: CompressionMethod::Resize, max_width: 1920, max_height: 0, ..Default::default() };
+    processor.compress_file(&input, format!("{}/boxed.jpg", dir), &boxed)?;
+    assert_eq!(image::image_dimensions(format!("{}/boxed.jpg", dir)).map_err(io::Error::other)?, (64, 32));
+
+    // Transparent PNGs stay PNGs with their alpha channel.
+    let logo = std::path::Path::new(dir).join("logo.png");
+    image::RgbaImage::from_fn(20, 20, |x, _| image::Rgba([255, 0, 0, (x * 12) as u8]))
+        .save(&logo)
+        .map_err(io::Error::other)?;
+    let small_logo = format!("{}/logo_small.png", dir);
+    processor.resize_file(&logo, &small_logo, 10, 10, image::ImageFormat::Png, false)?;
+    let reloaded = image::open(&small_logo).map_err(io::Error::other)?;
+    assert!(reloaded.color().has_alpha());
+    assert!(reloaded.to_rgba8().pixels().any(|p| p[3] < 255));
+
+    // Conversion honours JPEG quality, writes real WebP and keeps alpha where it can.
+    let low = format!("{}/low.jpg", dir);
+    let high = format!("{}/high.jpg", dir);
+    assert!(processor.convert_file(&input, &low, "jpg", 20)? < processor.convert_file(&input, &high, "jpg", 95)?);
+    assert!(has_transparency(&image::open(&logo).map_err(io::Error::other)?));
+    assert!(!has_transparency(&image::open(&input).map_err(io::Error::other)?));
+    let flat = format!("{}/logo.jpg", dir);
+    processor.convert_file(&logo, &flat, "jpg", 90)?;
+    let flat = image::open(&flat).map_err(io::Error::other)?.to_rgb8();
+    // The leftmost column is fully transparent, so it turns white rather than black.
+    assert!(flat.get_pixel(0, 10).0.iter().all(|&c| c > 240));
+    let webp = format!("{}/logo.webp", dir);
+    processor.convert_file(&logo, &webp, "webp", 90)?;
+    assert_eq!(image::ImageReader::open(&webp)?.with_guessed_format()?.format(), Some(image::ImageFormat::WebP));
+    assert_eq!(image::open(&webp).map_err(io::Error::other)?.to_rgba8(), image::open(&logo).map_err(io::Error::other)?.to_rgba8());
+    let wide = std::path::Path::new(dir).join("wide.png");
+    image::ImageBuffer::<image::Rgba<u16>, _>::from_pixel(4, 4, image::Rgba([60000, 0, 0, 30000]))
+        .save(&wide)
+        .map_err(io::Error::other)?;
+    processor.convert_file(&wide, format!("{}/wide.webp", dir), "webp", 90)?;
+    assert!(processor.convert_file(&input, format!("{}/x.bmp", dir), "bmp", 90).is_err());
+
+    // Source images are never written over, even when the target name matches; outputs of
+    // earlier runs are.
+    let source = std::fs::read(&input)?;
+    let refused = processor.convert_file(&input, input.to_str().unwrap(), "png", 90).unwrap_err();
+    assert_eq!(refused.kind(), io::ErrorKind::AlreadyExists);
+    assert!(processor.resize_file(&logo, input.to_str().unwrap(), 8, 8, image::ImageFormat::Png, false).is_err());
+    assert_eq!(std::fs::read(&input)?, source);
+    processor.convert_file(&logo, &webp, "webp", 50)?;
+
+    assert_eq!(describe_savings(200, 150), "25.0% smaller");
+    assert_eq!(describe_savings(100, 112), "12.0% larger");
+    assert_eq!(describe_savings(100, 100), "same size");
+    assert_eq!(describe_savings(0, 10), "0.0% larger");
+
+    // PNG optimization never grows a file, even with the weakest settings.
+    for (png_level, png_filter) in [(9, image::codecs::png::FilterType::Adaptive), (1, image::codecs::png::FilterType::NoFilter)] {
+        let options = CompressOptions { method: CompressionMethod::Png, png_level, png_filter, ..Default::default() };
+        let optimized = format!("{}/optimized_{}.png", dir, png_level);
+        let result = processor.compress_file(&logo, &optimized, &options)?;
+        assert!(result.compressed_size <= result.original_size);
+        let original = image::open(&logo).map_err(io::Error::other)?;
+        assert_eq!(image::open(&optimized).map_err(io::Error::other)?.to_rgba8(), original.to_rgba8());
+    }
+
+    // WebP compression, plain or as the lossless format, writes real lossless WebP.
+    for options in [
+        CompressOptions { method: CompressionMethod::WebP, ..Default::default() },
+        CompressOptions { method: CompressionMethod::Lossless, lossless_format: image::ImageFormat::WebP, ..Default::default() },
+    ] {
+        let output = format!("{}/logo_{:?}.webp", dir, options.method);
+        processor.compress_file(&logo, &output, &options)?;
+        assert_eq!(image::ImageReader::open(&output)?.with_guessed_format()?.format(), Some(image::ImageFormat::WebP));
+        let original = image::open(&logo).map_err(io::Error::other)?;
+        assert_eq!(image::open(&output).map_err(io::Error::other)?.to_rgba8(), original.to_rgba8());
+    }
+
+    // The batch calls behind the image menus ask nothing; outputs are named after the input.
+    let batch_dir = "test_image_batch";
+    let _ = std::fs::remove_dir_all(batch_dir);
+    let batch = ImageProcessor::with_dir(batch_dir)?;
+    std::fs::copy(&input, format!("{}/photo.png", batch_dir))?;
+    let files = batch.get_image_files()?;
+    batch.check_output_space(&files)?;
+    assert_eq!(batch.resize_files(&files, 16, 16, None, false)?.processed, 1);
+    assert_eq!(image::image_dimensions(format!("{}/photo_resized.png", batch_dir)).map_err(io::Error::other)?, (16, 8));
+    assert_eq!(batch.convert_files(&files, "png", 90)?.processed, 1);
+    assert!(Path::new(&format!("{}/photo_converted.png", batch_dir)).exists());
+    assert_eq!(batch.transform_files(&files, "flipped", |img| Ok(img.fliph()))?.processed, 1);
+    assert!(Path::new(&format!("{}/photo_flipped.png", batch_dir)).exists());
+    std::fs::remove_dir_all(batch_dir)?;
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+fn test_non_utf8_names() -> io::Result<()> {
+    use std::ffi::OsString;
+    #[cfg(unix)]
+    use std::os::unix::ffi::OsStringExt;
+    let dir = "test_non_utf8_names";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir)?;
+    let mut names = vec![OsString::from("café.png")];
+    #[cfg(unix)]
+    names.push(OsString::from_vec(b"caf\xe9.png".to_vec()));
+    let image = image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([(x * 16) as u8, (y * 16) as u8, 0]));
+    for name in &names {
+        image.save_with_format(Path::new(dir).join(name), image::ImageFormat::Png).map_err(io::Error::other)?;
+    }
+
+    // Outputs are named from the exact bytes of each input, so no two collide.
+    let summary = run_batch_compression(dir, &CompressOptions::default())?;
+    assert_eq!(summary.processed, names.len());
+    assert!(!summary.has_failures());
+    for name in &names {
+        let mut output = Path::new(name).file_stem().unwrap_or_default().to_os_string();
+        output.push("_auto_compressed.jpg");
+        assert!(Path::new(dir).join(output).exists());
+    }
+    let last = names.last().cloned().unwrap_or_default();
+    let options = CompressOptions { quality: 85, quality_by_file: [(last.clone(), 10)].into(), ..Default::default() };
+    assert_eq!(options.quality_for(&Path::new(dir).join(&last)), 10);
+    assert_eq!(options.quality_for(Path::new("caf\u{FFFD}.png")), 85);
+    std::fs::remove_dir_all(dir)?;
+
+    // Session names that are not UTF-8 are skipped, not listed under a mangled name.
+    let session = Path::new("sessions").join("test_café_session");
+    std::fs::create_dir_all(&session)?;
+    #[cfg(unix)]
+    let unreadable = Path::new("sessions").join(OsString::from_vec(b"test_caf\xe9".to_vec()));
+    #[cfg(unix)]
+    std::fs::create_dir_all(&unreadable)?;
+    let sessions = crate::sessions::get_available_sessions()?;
+    assert!(sessions.contains(&"test_café_session".to_string()));
+    assert!(!sessions.iter().any(|name| name.contains('\u{FFFD}')));
+    std::fs::remove_dir(&session)?;
+    #[cfg(unix)]
+    std::fs::remove_dir(&unreadable)?;
+    Ok(())
+}
+
+fn test_batch_summary() -> io::Result<()> {
+    let dir = "test_batch_summary";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir)?;
+    // Noisy pixels so the JPEG output is smaller than the PNG input.
+    image::RgbImage::from_fn(64, 64, |x, y| {
+        let n = x.wrapping_mul(2654435761).wrapping_add(y.wrapping_mul(40503)) >> 8;
+        image::Rgb([n as u8, (n >> 8) as u8, (n >> 16) as u8])
+    })
+    .save(format!("{}/good.png", dir))
+    .map_err(io::Error::other)?;
+    std::fs::write(format!("{}/broken.png", dir), b"\x89PNG\r\n\x1a\nnot really a png")?;
+
+    let summary = run_batch_compression(dir, &CompressOptions::default())?;
+    assert_eq!(summary.processed, 1);
+    assert!(summary.has_failures());
+    assert_eq!(summary.failed[0].0, "broken.png");
+    assert_eq!(summary.compressed.len(), 1);
+
+    // A second run skips the first run's output instead of compressing it again.
+    let inputs: Vec<String> = summary.compressed.iter().map(|r| r.file.clone()).collect();
+    let again = run_batch_compression(dir, &CompressOptions::default())?;
+    assert_eq!(again.processed, 1);
+    assert_eq!(again.compressed.iter().map(|r| r.file.clone()).collect::<Vec<_>>(), inputs);
+    let listed = |processor: &ImageProcessor| -> io::Result<Vec<String>> {
+        let mut names: Vec<String> = processor.get_image_files()?.iter().map(|e| e.file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        Ok(names)
+    };
+    let processor = ImageProcessor::with_dir(dir)?;
+    assert_eq!(listed(&processor)?, vec!["broken.png", "good.png"]);
+    // An output replaced by the user counts as an input again.
+    std::fs::copy(format!("{}/good.png", dir), format!("{}/good_auto_compressed.jpg", dir))?;
+    assert_eq!(listed(&processor)?, vec!["broken.png", "good.png", "good_auto_compressed.jpg"]);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
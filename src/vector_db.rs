@@ -1,54 +1,650 @@
-use std::fs;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
 use std::io::{self, Write, Read};
-use serde_json::Value;
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use crate::progress::Progress;
+
+const BINARY_MAGIC: &[u8; 8] = b"REDRUVEC";
+const BINARY_VERSION: u16 = 1;
+const BINARY_HEADER_LEN: usize = BINARY_MAGIC.len() + 2;
+/// Upper bound on a record's declared length in binary files; anything larger is corruption.
+const MAX_VECTOR_DIM: u64 = 1 << 24;
+/// Batches smaller than this are answered on the calling thread; spawning work costs more.
+const PARALLEL_BATCH_MIN: usize = 8;
+/// `add_batch` shows progress only for batches at least this large.
+const BATCH_PROGRESS_MIN: usize = 10_000;
+
+/// On-disk layout used for the live vector store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorFormat {
+    Json,
+    /// `REDRUVEC` magic, u16 version, then `[u64 len][f64; len]` records, all little-endian.
+    Binary,
+}
+
+impl VectorFormat {
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".bin") { VectorFormat::Binary } else { VectorFormat::Json }
+    }
+}
+
+/// One ranked result from `VectorDB::query_similar`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMatch<'a> {
+    /// Position of the vector in the store when the query ran.
+    pub index: usize,
+    pub distance: f64,
+    pub vector: VectorView<'a>,
+}
+
+/// A query result copied out of the store, so it can still be saved after the store
+/// changes. `query` numbers the queries of a batch from 1.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedMatch {
+    pub query: usize,
+    pub index: usize,
+    pub distance: f64,
+    pub vector: Vec<f64>,
+}
+
+impl SavedMatch {
+    /// The first `top_k` of `matches`, numbered as the results of query `query`.
+    pub fn from_matches(query: usize, matches: &[QueryMatch<'_>], top_k: usize) -> Vec<SavedMatch> {
+        matches.iter().take(top_k)
+            .map(|m| SavedMatch { query, index: m.index, distance: m.distance, vector: m.vector.to_vec() })
+            .collect()
+    }
+}
+
+/// Writes query results as CSV when `path` ends in `.csv` and as a JSON array otherwise.
+/// CSV columns are `query,index,distance,vector`, with the vector's components separated
+/// by spaces so every row has the same four fields.
+pub fn write_query_results(path: &Path, results: &[SavedMatch]) -> io::Result<()> {
+    let is_csv = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let content = if is_csv {
+        let mut csv = String::from("query,index,distance,vector\n");
+        for m in results {
+            let vector: Vec<String> = m.vector.iter().map(f64::to_string).collect();
+            csv.push_str(&format!("{},{},{},{}\n", m.query, m.index, m.distance, vector.join(" ")));
+        }
+        csv
+    } else {
+        serde_json::to_string_pretty(results).map_err(io::Error::other)?
+    };
+    fs::write(path, content)
+}
+
+/// Distance used by the query shell's default queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    #[default]
+    Euclidean,
+    Cosine,
+}
+
+impl Metric {
+    pub fn is_cosine(self) -> bool {
+        self == Metric::Cosine
+    }
+
+    pub fn other(self) -> Metric {
+        match self {
+            Metric::Euclidean => Metric::Cosine,
+            Metric::Cosine => Metric::Euclidean,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Metric::Euclidean => "Euclidean",
+            Metric::Cosine => "Cosine",
+        }
+    }
+}
+
+/// How a store is meant to be queried and kept, in a sidecar next to it (see
+/// `settings_path`) so a reopened or reloaded store behaves the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorSettings {
+    #[serde(default)]
+    pub default_metric: Metric,
+    /// Matches shown, and saved, per query.
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// Cutoff for queries that do not give one: the largest distance kept, or for cosine
+    /// the smallest similarity kept (see `VectorDB::query_similar_within`).
+    #[serde(default)]
+    pub threshold: Option<f64>,
+    /// Decimal places shown for components and distances. Display only: stored values
+    /// keep full precision.
+    #[serde(default = "default_precision")]
+    pub precision: usize,
+    /// Components shown at each end of a longer vector, with `…` in between; 0 shows
+    /// every component.
+    #[serde(default = "default_edge_components")]
+    pub edge_components: usize,
+    /// Set by `VectorDB::normalize_all`: vectors added or updated later are scaled to unit
+    /// length as well.
+    #[serde(default)]
+    pub normalized: bool,
+}
+
+fn default_top_k() -> usize {
+    5
+}
+
+fn default_precision() -> usize {
+    4
+}
+
+fn default_edge_components() -> usize {
+    3
+}
+
+impl Default for VectorSettings {
+    fn default() -> Self {
+        VectorSettings {
+            default_metric: Metric::default(),
+            top_k: default_top_k(),
+            threshold: None,
+            precision: default_precision(),
+            edge_components: default_edge_components(),
+            normalized: false,
+        }
+    }
+}
+
+impl VectorSettings {
+    /// Defaults when `path` does not exist; an unreadable sidecar is an error, so it is
+    /// not silently replaced by defaults on the next save.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self).map_err(io::Error::other)?)
+    }
+
+    /// `[0.1000, 0.2000, 0.3000, …, 0.7000, 0.8000, 0.9000] (768 dims)` for a vector longer
+    /// than twice `edge_components`; shorter ones are shown whole.
+    pub fn format_vector(&self, vector: &[f64]) -> String {
+        let component = |x: &f64| format!("{:.*}", self.precision, x);
+        let edge = self.edge_components;
+        if edge == 0 || vector.len() <= edge * 2 {
+            let all: Vec<String> = vector.iter().map(component).collect();
+            return format!("[{}]", all.join(", "));
+        }
+        let head: Vec<String> = vector[..edge].iter().map(component).collect();
+        let tail: Vec<String> = vector[vector.len() - edge..].iter().map(component).collect();
+        format!("[{}, …, {}] ({} dims)", head.join(", "), tail.join(", "), vector.len())
+    }
+
+    pub fn format_distance(&self, distance: f64) -> String {
+        format!("{:.*}", self.precision, distance)
+    }
+}
+
+/// `sils/vectors.bin` keeps its settings in `sils/vectors.settings.json`.
+pub fn settings_path(vectors_path: &str) -> PathBuf {
+    Path::new(vectors_path).with_extension("settings.json")
+}
+
+/// How `VectorDB` holds vectors in memory, chosen when it is opened. Files on disk are the
+/// same either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VectorStorage {
+    /// Every component as an `f64`.
+    #[default]
+    Float64,
+    /// Every vector as `i8` codes and one scale (see `QuantizedVector`): an eighth of the
+    /// memory, for a rounding error of at most 1/254 of the vector's largest component.
+    Int8,
+}
+
+/// A vector stored as `scale * codes[i]`, with the scale chosen so the component of
+/// largest magnitude becomes ±127.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedVector {
+    scale: f64,
+    codes: Vec<i8>,
+}
+
+impl QuantizedVector {
+    /// `None` if a component is NaN or infinite, since those have no code.
+    pub fn quantize(vector: &[f64]) -> Option<Self> {
+        if !vector.iter().all(|x| x.is_finite()) {
+            return None;
+        }
+        let max = vector.iter().fold(0.0f64, |max, x| max.max(x.abs()));
+        let scale = max / i8::MAX as f64;
+        let codes = vector.iter()
+            .map(|x| if scale == 0.0 { 0 } else { (x / scale).round() as i8 })
+            .collect();
+        Some(QuantizedVector { scale, codes })
+    }
+
+    pub fn dequantize(&self) -> Vec<f64> {
+        self.codes.iter().map(|&c| c as f64 * self.scale).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    /// Euclidean distance to `query`, dequantizing one component at a time.
+    fn euclidean_distance(&self, query: &[f64]) -> f64 {
+        self.codes.iter().zip(query).map(|(&c, y)| (c as f64 * self.scale - y).powi(2)).sum::<f64>().sqrt()
+    }
+
+    /// Cosine similarity to `query`, computed on the codes: the scale cancels out. NaN for
+    /// a zero vector, as for `VectorDB::cosine_similarity`.
+    fn cosine_similarity(&self, query: &[f64]) -> f64 {
+        let dot: f64 = self.codes.iter().zip(query).map(|(&c, y)| c as f64 * y).sum();
+        let norm_codes = self.codes.iter().map(|&c| (c as i64 * c as i64) as f64).sum::<f64>().sqrt();
+        let norm_query = query.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm_codes == 0.0 || norm_query == 0.0 { f64::NAN } else { dot / (norm_codes * norm_query) }
+    }
+}
+
+/// A stored vector, borrowed in whichever form the store keeps it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorView<'a> {
+    Float64(&'a [f64]),
+    Int8(&'a QuantizedVector),
+}
+
+impl VectorView<'_> {
+    fn len(self) -> usize {
+        match self {
+            VectorView::Float64(v) => v.len(),
+            VectorView::Int8(q) => q.len(),
+        }
+    }
+
+    /// The components as `f64`s; dequantized for `Int8` stores.
+    pub fn to_vec(self) -> Vec<f64> {
+        match self {
+            VectorView::Float64(v) => v.to_vec(),
+            VectorView::Int8(q) => q.dequantize(),
+        }
+    }
+
+    /// `1 - cosine similarity` or Euclidean distance to `query`, as `query_similar` ranks.
+    fn distance(self, query: &[f64], cosine: bool) -> f64 {
+        match (self, cosine) {
+            (VectorView::Float64(v), true) => 1.0 - VectorDB::cosine_similarity(v, query),
+            (VectorView::Float64(v), false) => VectorDB::euclidean_distance(v, query),
+            (VectorView::Int8(q), true) => 1.0 - q.cosine_similarity(query),
+            (VectorView::Int8(q), false) => q.euclidean_distance(query),
+        }
+    }
+}
+
+/// The vectors of a `VectorDB`, in the form its `VectorStorage` asks for.
+enum StoredVectors {
+    Float64(Vec<Vec<f64>>),
+    Int8(Vec<QuantizedVector>),
+}
+
+impl StoredVectors {
+    /// Fails with `InvalidData` naming the first vector an `Int8` store cannot hold.
+    fn from_vectors(storage: VectorStorage, vectors: Vec<Vec<f64>>) -> io::Result<Self> {
+        match storage {
+            VectorStorage::Float64 => Ok(StoredVectors::Float64(vectors)),
+            VectorStorage::Int8 => vectors.iter().enumerate()
+                .map(|(i, v)| QuantizedVector::quantize(v).ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Vector {} has NaN or infinite components and cannot be stored as Int8", i),
+                )))
+                .collect::<io::Result<_>>()
+                .map(StoredVectors::Int8),
+        }
+    }
+
+    fn storage(&self) -> VectorStorage {
+        match self {
+            StoredVectors::Float64(_) => VectorStorage::Float64,
+            StoredVectors::Int8(_) => VectorStorage::Int8,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            StoredVectors::Float64(v) => v.len(),
+            StoredVectors::Int8(v) => v.len(),
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<VectorView<'_>> {
+        match self {
+            StoredVectors::Float64(v) => v.get(index).map(|v| VectorView::Float64(v)),
+            StoredVectors::Int8(v) => v.get(index).map(VectorView::Int8),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = VectorView<'_>> + '_> {
+        match self {
+            StoredVectors::Float64(v) => Box::new(v.iter().map(|v| VectorView::Float64(v))),
+            StoredVectors::Int8(v) => Box::new(v.iter().map(VectorView::Int8)),
+        }
+    }
+
+    /// Converts `vector` to the stored form without adding it.
+    fn encode(&self, vector: Vec<f64>) -> io::Result<StoredVector> {
+        match self {
+            StoredVectors::Float64(_) => Ok(StoredVector::Float64(vector)),
+            StoredVectors::Int8(_) => QuantizedVector::quantize(&vector).map(StoredVector::Int8).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "NaN or infinite components cannot be stored as Int8")
+            }),
+        }
+    }
+
+    fn push(&mut self, vector: StoredVector) {
+        match (self, vector) {
+            (StoredVectors::Float64(v), StoredVector::Float64(x)) => v.push(x),
+            (StoredVectors::Int8(v), StoredVector::Int8(x)) => v.push(x),
+            _ => unreachable!("vectors are encoded by the store they are added to"),
+        }
+    }
+
+    fn set(&mut self, index: usize, vector: StoredVector) {
+        match (self, vector) {
+            (StoredVectors::Float64(v), StoredVector::Float64(x)) => v[index] = x,
+            (StoredVectors::Int8(v), StoredVector::Int8(x)) => v[index] = x,
+            _ => unreachable!("vectors are encoded by the store they are added to"),
+        }
+    }
+
+    fn remove(&mut self, index: usize) {
+        match self {
+            StoredVectors::Float64(v) => drop(v.remove(index)),
+            StoredVectors::Int8(v) => drop(v.remove(index)),
+        }
+    }
+
+    fn drain(&mut self, range: std::ops::Range<usize>) {
+        match self {
+            StoredVectors::Float64(v) => drop(v.drain(range)),
+            StoredVectors::Int8(v) => drop(v.drain(range)),
+        }
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(VectorView<'_>) -> bool) {
+        match self {
+            StoredVectors::Float64(v) => v.retain(|x| keep(VectorView::Float64(x))),
+            StoredVectors::Int8(v) => v.retain(|x| keep(VectorView::Int8(x))),
+        }
+    }
+
+    /// Every vector as `f64`s: borrowed from a `Float64` store, dequantized from an `Int8` one.
+    fn as_f64(&self) -> Cow<'_, [Vec<f64>]> {
+        match self {
+            StoredVectors::Float64(v) => Cow::Borrowed(v),
+            StoredVectors::Int8(v) => Cow::Owned(v.iter().map(QuantizedVector::dequantize).collect()),
+        }
+    }
+}
+
+/// One vector converted by `StoredVectors::encode`, ready to add to that store.
+enum StoredVector {
+    Float64(Vec<f64>),
+    Int8(QuantizedVector),
+}
+
+impl StoredVector {
+    fn to_vec(&self) -> Cow<'_, [f64]> {
+        match self {
+            StoredVector::Float64(v) => Cow::Borrowed(v),
+            StoredVector::Int8(q) => Cow::Owned(q.dequantize()),
+        }
+    }
+}
+
+/// Outcome of `add_batch`: rows are identified by their position in the input batch.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub added: usize,
+    pub rejected: Vec<(usize, String)>,
+}
 
 pub struct VectorDB {
-    vectors: Vec<Vec<f64>>,
+    vectors: StoredVectors,
     file_path: String,
+    format: VectorFormat,
+    threads: Option<usize>,
+    settings: VectorSettings,
+    /// Problems met while opening that did not stop it; see `take_warnings`.
+    warnings: Vec<String>,
 }
 
 impl VectorDB {
-    pub fn new(file_path: &str) -> io::Result<Self> {
-        let vectors: Vec<Vec<f64>> = if let Ok(data) = fs::read_to_string(file_path) {
-            serde_json::from_str(&data).unwrap_or_default()
-        } else {
-            Vec::new()
+    pub fn new(file_path: &str, format: VectorFormat) -> io::Result<Self> {
+        Self::with_storage(file_path, format, VectorStorage::Float64)
+    }
+
+    /// Like `new`, holding the vectors in memory as `storage` says. Saves write what is in
+    /// memory, so an `Int8` store rewrites its file with the quantized values the first
+    /// time it changes.
+    pub fn with_storage(file_path: &str, format: VectorFormat, storage: VectorStorage) -> io::Result<Self> {
+        let vectors: Vec<Vec<f64>> = match format {
+            VectorFormat::Json => {
+                if let Ok(data) = fs::read_to_string(file_path) {
+                    serde_json::from_str(&data).unwrap_or_default()
+                } else {
+                    Vec::new()
+                }
+            }
+            VectorFormat::Binary => {
+                if Path::new(file_path).exists() {
+                    decode_binary(&fs::read(file_path)?)?
+                } else {
+                    Vec::new()
+                }
+            }
         };
+        let settings_file = settings_path(file_path);
+        let mut warnings = Vec::new();
+        let settings = VectorSettings::load(&settings_file).unwrap_or_else(|e| {
+            warnings.push(format!("Ignoring unreadable settings in {}: {}", settings_file.display(), e));
+            VectorSettings::default()
+        });
         Ok(VectorDB {
-            vectors,
+            vectors: StoredVectors::from_vectors(storage, vectors)?,
             file_path: file_path.to_string(),
+            format,
+            threads: None,
+            settings,
+            warnings,
         })
     }
 
     pub fn add_vector(&mut self, vector: Vec<f64>) -> io::Result<()> {
+        if self.settings.normalized {
+            return self.add_normalized(vector);
+        }
+        self.push_vector(vector)
+    }
+
+    fn push_vector(&mut self, vector: Vec<f64>) -> io::Result<()> {
         if !vector.is_empty() {
-            self.vectors.push(vector);
-            self.save()?;
+            let vector = self.vectors.encode(vector)?;
+            if self.format == VectorFormat::Binary && Path::new(&self.file_path).exists() {
+                // Binary records are self-delimiting, so a new vector is just appended.
+                let mut record = Vec::new();
+                encode_record(&vector.to_vec(), &mut record);
+                OpenOptions::new().append(true).open(&self.file_path)?.write_all(&record)?;
+                self.vectors.push(vector);
+            } else {
+                self.vectors.push(vector);
+                self.save()?;
+            }
         }
         Ok(())
     }
 
-    pub fn query_similar(&self, query: &Vec<f64>, cosine: bool) -> Vec<(usize, f64)> {
-        let mut results: Vec<(usize, f64)> = self.vectors.iter().enumerate()
+    /// Adds `vector` scaled to unit length. Zero vectors have no direction and are rejected.
+    pub fn add_normalized(&mut self, vector: Vec<f64>) -> io::Result<()> {
+        let vector = normalize(&vector)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Cannot normalize a zero vector"))?;
+        self.push_vector(vector)
+    }
+
+    /// L2-normalizes every stored vector and switches the DB into normalized mode, so later
+    /// updates are normalized too; the mode is kept in the settings sidecar, so it lasts
+    /// after the store is reopened. Returns how many zero vectors were left untouched.
+    ///
+    /// On unit vectors cosine similarity is just the dot product and Euclidean distance is
+    /// `sqrt(2 - 2 * cos)`, so both metrics rank results identically; magnitude is discarded,
+    /// so Euclidean queries no longer distinguish vectors that differ only in length.
+    pub fn normalize_all(&mut self) -> io::Result<usize> {
+        let mut skipped = 0;
+        for i in 0..self.vectors.len() {
+            let Some(unit) = self.vectors.get(i).and_then(|v| normalize(&v.to_vec())) else {
+                skipped += 1;
+                continue;
+            };
+            let unit = self.vectors.encode(unit)?;
+            self.vectors.set(i, unit);
+        }
+        self.save()?;
+        if !self.settings.normalized {
+            let settings = VectorSettings { normalized: true, ..self.settings.clone() };
+            self.set_settings(settings)?;
+        }
+        Ok(skipped)
+    }
+
+    /// Replaces the vector at `index` in place, keeping every other index stable.
+    pub fn update_vector(&mut self, index: usize, new_vector: Vec<f64>) -> io::Result<()> {
+        let Some(existing) = self.vectors.get(index) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No vector at index {}", index)));
+        };
+        if existing.len() != new_vector.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Dimension mismatch: expected {}, got {}", existing.len(), new_vector.len()),
+            ));
+        }
+
+        let new_vector = if self.settings.normalized {
+            normalize(&new_vector)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Cannot normalize a zero vector"))?
+        } else {
+            new_vector
+        };
+        let new_vector = self.vectors.encode(new_vector)?;
+        self.vectors.set(index, new_vector);
+        self.save()
+    }
+
+    /// Appends every valid vector and saves once. Rows whose dimension differs from the
+    /// store (or, for an empty store, from the first valid row) are reported, not fatal.
+    pub fn add_batch(&mut self, vectors: Vec<Vec<f64>>) -> io::Result<BatchReport> {
+        let mut report = BatchReport::default();
+        let mut dimension = self.vectors.get(0).map(|v| v.len());
+        let mut progress = (vectors.len() >= BATCH_PROGRESS_MIN).then(|| Progress::new("Adding vectors", vectors.len()));
+
+        for (row, vector) in vectors.into_iter().enumerate() {
+            if let Some(progress) = &mut progress {
+                progress.inc();
+            }
+            if vector.is_empty() {
+                report.rejected.push((row, "no numeric values".to_string()));
+                continue;
+            }
+            let expected = *dimension.get_or_insert(vector.len());
+            if vector.len() != expected {
+                report.rejected.push((row, format!("dimension {} (expected {})", vector.len(), expected)));
+                continue;
+            }
+            let vector = if self.settings.normalized {
+                match normalize(&vector) {
+                    Some(unit) => unit,
+                    None => {
+                        report.rejected.push((row, "cannot normalize a zero vector".to_string()));
+                        continue;
+                    }
+                }
+            } else {
+                vector
+            };
+            match self.vectors.encode(vector) {
+                Ok(vector) => self.vectors.push(vector),
+                Err(e) => {
+                    report.rejected.push((row, e.to_string()));
+                    continue;
+                }
+            }
+            report.added += 1;
+        }
+
+        if let Some(progress) = &progress {
+            progress.finish();
+        }
+        if report.added > 0 {
+            self.save()?;
+        }
+        Ok(report)
+    }
+
+    /// Replaces every stored vector and persists once.
+    pub fn set_vectors(&mut self, vectors: Vec<Vec<f64>>) -> io::Result<()> {
+        self.vectors = StoredVectors::from_vectors(self.vectors.storage(), vectors)?;
+        self.save()
+    }
+
+    /// Every vector with the query's dimension, closest first. Each match borrows the stored
+    /// vector, so the store cannot change while results are alive.
+    pub fn query_similar(&self, query: &[f64], cosine: bool) -> Vec<QueryMatch<'_>> {
+        self.query_similar_within(query, cosine, None)
+    }
+
+    /// Like `query_similar`, but drops matches that fail `threshold`: for Euclidean it is the
+    /// largest distance kept, for cosine the smallest similarity kept (so `0.9` keeps
+    /// matches whose reported distance, `1 - similarity`, is at most `0.1`).
+    pub fn query_similar_within(&self, query: &[f64], cosine: bool, threshold: Option<f64>) -> Vec<QueryMatch<'_>> {
+        let mut results: Vec<QueryMatch<'_>> = self.vectors.iter().enumerate()
             .filter_map(|(i, v)| {
                 if v.len() == query.len() {
-                    let dist = if cosine {
-                        1.0 - Self::cosine_similarity(v, query)
-                    } else {
-                        Self::euclidean_distance(v, query)
-                    };
-                    Some((i, dist))
+                    let dist = v.distance(query, cosine);
+                    within_threshold(dist, cosine, threshold)
+                        .then_some(QueryMatch { index: i, distance: dist, vector: v })
                 } else {
                     None
                 }
             })
             .collect();
-        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        sort_by_distance(&mut results, |m| m.distance);
         results
     }
 
-    pub fn batch_query(&self, queries: &[Vec<f64>], cosine: bool) -> Vec<Vec<(usize, f64)>> {
-        queries.iter().map(|q| self.query_similar(q, cosine)).collect()
+    /// Caps `batch_query` at `n` worker threads; `0` restores rayon's global pool.
+    pub fn set_threads(&mut self, n: usize) {
+        self.threads = if n == 0 { None } else { Some(n) };
+    }
+
+    /// Answers each query in parallel; results stay in the same order as `queries`.
+    pub fn batch_query(&self, queries: &[Vec<f64>], cosine: bool) -> Vec<Vec<QueryMatch<'_>>> {
+        if queries.len() < PARALLEL_BATCH_MIN || self.threads == Some(1) {
+            return queries.iter().map(|q| self.query_similar(q, cosine)).collect();
+        }
+        let run = || queries.par_iter().map(|q| self.query_similar(q, cosine)).collect();
+        match self.threads {
+            Some(n) => match rayon::ThreadPoolBuilder::new().num_threads(n).build() {
+                Ok(pool) => pool.install(run),
+                Err(_) => run(),
+            },
+            None => run(),
+        }
     }
 
     pub fn delete_vector(&mut self, index: usize) -> io::Result<()> {
@@ -59,227 +655,425 @@ impl VectorDB {
         Ok(())
     }
 
-    pub fn list_vectors(&self) -> &Vec<Vec<f64>> {
-        &self.vectors
+    /// Removes indices `start..end` (end exclusive, clamped to the store) with one save.
+    pub fn delete_range(&mut self, start: usize, end: usize) -> io::Result<usize> {
+        let end = end.min(self.vectors.len());
+        if start >= end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Empty or out-of-bounds range {}..{} for {} vectors", start, end, self.vectors.len()),
+            ));
+        }
+        self.vectors.drain(start..end);
+        self.save()?;
+        Ok(end - start)
     }
 
-    pub fn save_as_binary(&self, bin_path: &str) -> io::Result<()> {
-        let mut file = fs::File::create(bin_path)?;
-        for v in &self.vectors {
-            let len = v.len() as u64;
-            file.write_all(&len.to_le_bytes())?;
-            for f in v {
-                file.write_all(&f.to_le_bytes())?;
-            }
+    /// Keeps only vectors matching `predicate` and saves once; returns how many were removed.
+    pub fn retain(&mut self, predicate: impl Fn(&Vec<f64>) -> bool) -> io::Result<usize> {
+        let before = self.vectors.len();
+        self.vectors.retain(|v| match v {
+            VectorView::Float64(v) => predicate(&v.to_vec()),
+            VectorView::Int8(q) => predicate(&q.dequantize()),
+        });
+        let removed = before - self.vectors.len();
+        if removed > 0 {
+            self.save()?;
         }
+        Ok(removed)
+    }
+
+    /// Every vector as `f64`s; an `Int8` store dequantizes them all into a new list.
+    pub fn list_vectors(&self) -> Cow<'_, [Vec<f64>]> {
+        self.vectors.as_f64()
+    }
+
+    /// The vector at `index`, in the form the store holds it.
+    pub fn get(&self, index: usize) -> Option<VectorView<'_>> {
+        self.vectors.get(index)
+    }
+
+    pub fn storage(&self) -> VectorStorage {
+        self.vectors.storage()
+    }
+
+    /// Count, dimensions and per-component range of the vectors in memory.
+    pub fn stats(&self) -> VectorStats {
+        vector_stats(&self.vectors.as_f64())
+    }
+
+    /// Problems met while opening that did not stop it, such as an unreadable settings
+    /// file, for the caller to show. Each is handed over once.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn settings(&self) -> &VectorSettings {
+        &self.settings
+    }
+
+    /// Replaces the query defaults and writes them to the store's settings sidecar.
+    pub fn set_settings(&mut self, settings: VectorSettings) -> io::Result<()> {
+        settings.save(&settings_path(&self.file_path))?;
+        self.settings = settings;
         Ok(())
     }
 
+    /// Queries with the store's defaults: its metric and threshold, and at most `top_k`
+    /// matches.
+    pub fn query_with_defaults(&self, query: &[f64]) -> Vec<QueryMatch<'_>> {
+        let mut results = self.query_similar_within(query, self.settings.default_metric.is_cosine(), self.settings.threshold);
+        results.truncate(self.settings.top_k);
+        results
+    }
+
+    /// Writes the vectors to `bin_path` and the query defaults to its settings sidecar.
+    pub fn save_as_binary(&self, bin_path: &str) -> io::Result<()> {
+        fs::write(bin_path, encode_binary(&self.vectors.as_f64()))?;
+        self.settings.save(&settings_path(bin_path))
+    }
+
+    /// Replaces the vectors with those in `bin_path`, and the query defaults with the ones
+    /// saved next to it, if any.
     pub fn load_from_binary(&mut self, bin_path: &str) -> io::Result<()> {
         let mut file = fs::File::open(bin_path)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut idx = 0;
-        let mut loaded = Vec::new();
-        while idx + 8 <= buf.len() {
-            let len = u64::from_le_bytes(buf[idx..idx+8].try_into().unwrap()) as usize;
-            idx += 8;
-            let mut v = Vec::new();
-            for _ in 0..len {
-                if idx + 8 > buf.len() { break; }
-                let f = f64::from_le_bytes(buf[idx..idx+8].try_into().unwrap());
-                v.push(f);
-                idx += 8;
-            }
-            loaded.push(v);
-        }
-        self.vectors = loaded;
+        self.vectors = StoredVectors::from_vectors(self.vectors.storage(), decode_binary(&buf)?)?;
         self.save()?;
+        let saved_settings = settings_path(bin_path);
+        if saved_settings.exists() {
+            self.set_settings(VectorSettings::load(&saved_settings)?)?;
+        }
         Ok(())
     }
 
     fn save(&self) -> io::Result<()> {
-        fs::write(&self.file_path, serde_json::to_string_pretty(&self.vectors).unwrap())?;
+        match self.format {
+            VectorFormat::Json => {
+                fs::write(&self.file_path, serde_json::to_string_pretty(&self.vectors.as_f64()).unwrap())?;
+            }
+            VectorFormat::Binary => {
+                fs::write(&self.file_path, encode_binary(&self.vectors.as_f64()))?;
+            }
+        }
         Ok(())
     }
 
-    fn euclidean_distance(a: &Vec<f64>, b: &Vec<f64>) -> f64 {
+    fn euclidean_distance(a: &[f64], b: &[f64]) -> f64 {
         a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum::<f64>().sqrt()
     }
 
-    fn cosine_similarity(a: &Vec<f64>, b: &Vec<f64>) -> f64 {
+    /// Undefined (NaN) when either vector has zero length, so a zero vector ranks last
+    /// instead of looking as close as an orthogonal one.
+    fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
         let dot: f64 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
         let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
         let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
-        if norm_a == 0.0 || norm_b == 0.0 { 0.0 } else { dot / (norm_a * norm_b) }
-    }
-}
-
-pub fn run_simse() -> io::Result<()> {
-    use std::io::{Read, Write};
-    let sils_dir = "sils";
-    if !std::path::Path::new(sils_dir).exists() {
-        fs::create_dir_all(sils_dir)?;
-    }
-    println!("Drop a file into the 'sils' directory and press Enter when ready...");
-    let mut _dummy = String::new();
-    std::io::stdin().read_line(&mut _dummy)?;
-    let files: Vec<_> = fs::read_dir(sils_dir)?.filter_map(|e| e.ok()).collect();
-    if files.is_empty() {
-        println!("No file found in 'sils'. Exiting simse mode.");
-        return Ok(());
-    }
-    let file_path = files[0].path();
-    println!("Reading file: {}", file_path.display());
-    let mut content = String::new();
-    fs::File::open(&file_path)?.read_to_string(&mut content)?;
-    let vectors: Vec<Vec<f64>> = content
-        .lines()
-        .map(|line| line.split(',').filter_map(|s| s.trim().parse::<f64>().ok()).collect())
+        if norm_a == 0.0 || norm_b == 0.0 { f64::NAN } else { dot / (norm_a * norm_b) }
+    }
+}
+
+/// Whether a match at `distance` passes the cutoff described on `query_similar_within`.
+/// NaN distances (undefined cosine) never pass a cutoff.
+fn within_threshold(distance: f64, cosine: bool, threshold: Option<f64>) -> bool {
+    match threshold {
+        None => true,
+        Some(min_similarity) if cosine => 1.0 - distance >= min_similarity,
+        Some(max_distance) => distance <= max_distance,
+    }
+}
+
+/// Sorts by ascending distance; NaN distances (zero vectors under cosine, or rows holding
+/// NaN components) go last instead of panicking the comparison.
+fn sort_by_distance<T>(results: &mut [T], distance: impl Fn(&T) -> f64) {
+    results.sort_by(|a, b| {
+        let (a, b) = (distance(a), distance(b));
+        match (a.is_nan(), b.is_nan()) {
+            (false, false) => a.total_cmp(&b),
+            (a_nan, b_nan) => a_nan.cmp(&b_nan),
+        }
+    });
+}
+
+/// Splits one line of a vector file on commas and/or whitespace.
+pub fn parse_vector_line(line: &str) -> Vec<f64> {
+    parse_vector_tokens(line).0
+}
+
+/// Parses the numeric tokens of a line and counts the ones that are not numbers.
+fn parse_vector_tokens(line: &str) -> (Vec<f64>, usize) {
+    let mut skipped = 0;
+    let values = line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| s.parse::<f64>().inspect_err(|_| skipped += 1).ok())
         .collect();
-    println!("File converted to vectors:");
-    for vec in &vectors {
-        println!("{:?}", vec);
-    }
-    // Save vectors to sils/vectors.json
-    let vectors_path = format!("{}/vectors.json", sils_dir);
-    let json = serde_json::to_string_pretty(&vectors).unwrap();
-    fs::write(&vectors_path, json)?;
-    println!("Vectors saved to {}", vectors_path);
-    // Vector DB CLI
-    vector_db_cli(&vectors_path)?;
-    Ok(())
-}
-
-fn vector_db_cli(vectors_path: &str) -> io::Result<()> {
-    let mut db = VectorDB::new(vectors_path)?;
-    loop {
-        println!("\nVector DB Options:");
-        println!("  1. Add new vector");
-        println!("  2. Query similar vectors (Euclidean)");
-        println!("  3. Query similar vectors (Cosine)");
-        println!("  4. Batch query (Euclidean)");
-        println!("  5. List all vectors");
-        println!("  6. Delete a vector");
-        println!("  7. Save/load as binary");
-        println!("  8. Exit");
-        print!("Select option (1-8): ");
-        std::io::stdout().flush()?;
-        let mut opt = String::new();
-        std::io::stdin().read_line(&mut opt)?;
-        let opt = opt.trim();
-        match opt {
-            "1" => {
-                print!("Enter vector as comma-separated numbers: ");
-                std::io::stdout().flush()?;
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-                let vec: Vec<f64> = input.trim().split(',').filter_map(|s| s.trim().parse().ok()).collect();
-                if db.add_vector(vec).is_ok() {
-                    println!("Vector added.");
-                } else {
-                    println!("Invalid vector.");
-                }
-            }
-            "2" => {
-                query_vector(&db, false)?;
-            }
-            "3" => {
-                query_vector(&db, true)?;
-            }
-            "4" => {
-                print!("Enter batch of query vectors (one per line, end with empty line):\n");
-                std::io::stdout().flush()?;
-                let mut batch = Vec::new();
-                loop {
-                    let mut input = String::new();
-                    std::io::stdin().read_line(&mut input)?;
-                    let line = input.trim();
-                    if line.is_empty() { break; }
-                    let vec: Vec<f64> = line.split(',').filter_map(|s| s.trim().parse().ok()).collect();
-                    if !vec.is_empty() { batch.push(vec); }
-                }
-                let results = db.batch_query(&batch, false);
-                for (i, result) in results.iter().enumerate() {
-                    println!("\nQuery {}:", i+1);
-                    print_top_matches(&db, &batch[i], result);
-                }
-            }
-            "5" => {
-                for (i, v) in db.list_vectors().iter().enumerate() {
-                    println!("  {}: {:?}", i, v);
-                }
-            }
-            "6" => {
-                print!("Enter index of vector to delete: ");
-                std::io::stdout().flush()?;
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-                if let Ok(idx) = input.trim().parse::<usize>() {
-                    if db.delete_vector(idx).is_ok() {
-                        println!("Vector deleted.");
-                    } else {
-                        println!("Invalid index.");
-                    }
-                } else {
-                    println!("Invalid input.");
-                }
+    (values, skipped)
+}
+
+/// Shape and value range of a set of vectors, from `VectorDB::stats`.
+#[derive(Debug, Default, PartialEq)]
+pub struct VectorStats {
+    pub count: usize,
+    /// `(dimension, vectors with it)`, most common first; more than one entry means the
+    /// store is ragged.
+    pub dimensions: Vec<(usize, usize)>,
+    /// One entry per component index, over the vectors long enough to have it.
+    pub components: Vec<ComponentStats>,
+    /// NaN and infinite values, left out of `components`.
+    pub non_finite: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComponentStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl VectorStats {
+    pub fn is_ragged(&self) -> bool {
+        self.dimensions.len() > 1
+    }
+}
+
+pub fn vector_stats(vectors: &[Vec<f64>]) -> VectorStats {
+    let mut by_dimension: HashMap<usize, usize> = HashMap::new();
+    // Per component: min, max, sum and count of finite values.
+    let mut totals: Vec<(f64, f64, f64, usize)> = Vec::new();
+    let mut non_finite = 0;
+    for vector in vectors {
+        *by_dimension.entry(vector.len()).or_default() += 1;
+        if totals.len() < vector.len() {
+            totals.resize(vector.len(), (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0));
+        }
+        for (total, &x) in totals.iter_mut().zip(vector) {
+            if !x.is_finite() {
+                non_finite += 1;
+                continue;
             }
-            "7" => {
-                println!("  a. Save as binary");
-                println!("  b. Load from binary");
-                print!("Select (a/b): ");
-                std::io::stdout().flush()?;
-                let mut sub = String::new();
-                std::io::stdin().read_line(&mut sub)?;
-                match sub.trim() {
-                    "a" => {
-                        let bin_path = format!("{}.bin", vectors_path.trim_end_matches(".json"));
-                        if db.save_as_binary(&bin_path).is_ok() {
-                            println!("Saved to {}", bin_path);
-                        }
-                    }
-                    "b" => {
-                        let bin_path = format!("{}.bin", vectors_path.trim_end_matches(".json"));
-                        if db.load_from_binary(&bin_path).is_ok() {
-                            println!("Loaded from {}", bin_path);
-                        }
-                    }
-                    _ => println!("Invalid option."),
-                }
+            total.0 = total.0.min(x);
+            total.1 = total.1.max(x);
+            total.2 += x;
+            total.3 += 1;
+        }
+    }
+    let mut dimensions: Vec<(usize, usize)> = by_dimension.into_iter().collect();
+    dimensions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let components = totals.into_iter()
+        .map(|(min, max, sum, count)| match count {
+            0 => ComponentStats { min: f64::NAN, max: f64::NAN, mean: f64::NAN },
+            _ => ComponentStats { min, max, mean: sum / count as f64 },
+        })
+        .collect();
+    VectorStats { count: vectors.len(), dimensions, components, non_finite }
+}
+
+/// What `parse_vector_file` dropped or flagged; line numbers are 1-based.
+#[derive(Debug, Default)]
+pub struct VectorFileSummary {
+    /// Dimension of the first row with numeric values.
+    pub dimension: Option<usize>,
+    /// Non-numeric tokens dropped from rows that were kept.
+    pub skipped_values: usize,
+    /// Non-blank lines without a single numeric value (headers, text).
+    pub skipped_lines: Vec<usize>,
+    /// Rows kept despite a dimension other than `dimension`, with their length.
+    pub ragged_lines: Vec<(usize, usize)>,
+}
+
+/// Parses one vector per line, dropping lines with no numeric values.
+///
+/// With `require_uniform`, a row whose dimension differs from the first row is an
+/// `InvalidData` error instead of being kept and listed in `ragged_lines`.
+pub fn parse_vector_file(content: &str, require_uniform: bool) -> io::Result<(Vec<Vec<f64>>, VectorFileSummary)> {
+    let mut summary = VectorFileSummary::default();
+    let mut vectors = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (vector, skipped) = parse_vector_tokens(line);
+        if vector.is_empty() {
+            summary.skipped_lines.push(i + 1);
+            continue;
+        }
+        summary.skipped_values += skipped;
+        let expected = *summary.dimension.get_or_insert(vector.len());
+        if vector.len() != expected {
+            if require_uniform {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {} has {} values, expected {}", i + 1, vector.len(), expected),
+                ));
             }
-            "8" => break,
-            _ => println!("Invalid option."),
+            summary.ragged_lines.push((i + 1, vector.len()));
         }
+        vectors.push(vector);
     }
-    Ok(())
+    Ok((vectors, summary))
 }
 
-fn query_vector(db: &VectorDB, cosine: bool) -> io::Result<()> {
-    print!("Enter query vector as comma-separated numbers: ");
-    std::io::stdout().flush()?;
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
-    let query: Vec<f64> = input.trim().split(',').filter_map(|s| s.trim().parse().ok()).collect();
-    if query.is_empty() {
-        println!("Invalid query vector.");
-        return Ok(());
+fn normalize(vector: &[f64]) -> Option<Vec<f64>> {
+    let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        None
+    } else {
+        Some(vector.iter().map(|x| x / norm).collect())
     }
-    let results = db.query_similar(&query, cosine);
-    print_top_matches(db, &query, &results);
-    Ok(())
 }
 
-fn print_top_matches(db: &VectorDB, query: &Vec<f64>, results: &[(usize, f64)]) {
-    println!("Top 5 closest vectors:");
-    for (i, dist) in results.iter().take(5) {
-        let vectors = db.list_vectors();
-        if *i < vectors.len() {
-            println!("  idx {}: {:?} (distance: {:.4})", i, vectors[*i], dist);
+fn encode_record(vector: &[f64], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(vector.len() as u64).to_le_bytes());
+    for f in vector {
+        out.extend_from_slice(&f.to_le_bytes());
+    }
+}
+
+fn encode_binary(vectors: &[Vec<f64>]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BINARY_HEADER_LEN);
+    out.extend_from_slice(BINARY_MAGIC);
+    out.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+    for v in vectors {
+        encode_record(v, &mut out);
+    }
+    out
+}
+
+/// Read-only view of a binary vector file that decodes records straight from a memory map.
+///
+/// Only a `(offset, len)` table is kept in RAM, so files far larger than memory can be
+/// queried. Writes still go through `VectorDB`.
+pub struct MappedVectors {
+    map: Mmap,
+    records: Vec<(usize, usize)>,
+}
+
+impl MappedVectors {
+    pub fn open(file_path: &str) -> io::Result<Self> {
+        let file = fs::File::open(file_path)?;
+        // SAFETY: the map is read-only; callers must not truncate the file while it is open.
+        let map = unsafe { Mmap::map(&file)? };
+        let records = binary_records(&map)?;
+        Ok(MappedVectors { map, records })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Vec<f64>> {
+        let mut out = Vec::new();
+        self.read_into(*self.records.get(index)?, &mut out);
+        Some(out)
+    }
+
+    fn read_into(&self, (offset, len): (usize, usize), out: &mut Vec<f64>) {
+        out.clear();
+        out.extend(
+            self.map[offset..offset + len * 8]
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap())),
+        );
+    }
+
+    /// Same ranking as `VectorDB::query_similar`, decoding one record at a time.
+    pub fn query_similar(&self, query: &[f64], cosine: bool) -> Vec<(usize, f64)> {
+        self.query_similar_within(query, cosine, None)
+    }
+
+    /// Same cutoff as `VectorDB::query_similar_within`.
+    pub fn query_similar_within(&self, query: &[f64], cosine: bool, threshold: Option<f64>) -> Vec<(usize, f64)> {
+        let mut v = Vec::with_capacity(query.len());
+        let mut results: Vec<(usize, f64)> = self.records.iter().enumerate()
+            .filter(|(_, (_, len))| *len == query.len())
+            .map(|(i, &record)| {
+                self.read_into(record, &mut v);
+                let dist = if cosine {
+                    1.0 - VectorDB::cosine_similarity(&v, query)
+                } else {
+                    VectorDB::euclidean_distance(&v, query)
+                };
+                (i, dist)
+            })
+            .filter(|&(_, dist)| within_threshold(dist, cosine, threshold))
+            .collect();
+        sort_by_distance(&mut results, |r| r.1);
+        results
+    }
+}
+
+/// Validates the optional header and returns the offset of the first record.
+fn binary_body_start(buf: &[u8]) -> io::Result<usize> {
+    if !buf.starts_with(BINARY_MAGIC) {
+        return Ok(0);
+    }
+    if buf.len() < BINARY_HEADER_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Truncated vector file header"));
+    }
+    let version = u16::from_le_bytes([buf[BINARY_MAGIC.len()], buf[BINARY_MAGIC.len() + 1]]);
+    if version != BINARY_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported vector file version {} (expected {})", version, BINARY_VERSION),
+        ));
+    }
+    Ok(BINARY_HEADER_LEN)
+}
+
+/// Decodes the headered format; files without the magic are read as the legacy
+/// headerless dump so older `.bin` exports still load.
+fn decode_binary(buf: &[u8]) -> io::Result<Vec<Vec<f64>>> {
+    Ok(binary_records(buf)?
+        .into_iter()
+        .map(|(offset, len)| {
+            buf[offset..offset + len * 8]
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                .collect()
+        })
+        .collect())
+}
+
+/// Locates every record as `(offset of first f64, length)`, checking each declared length
+/// against `MAX_VECTOR_DIM` and the bytes left so a truncated or corrupt file is an error
+/// rather than a short vector or a huge allocation.
+fn binary_records(buf: &[u8]) -> io::Result<Vec<(usize, usize)>> {
+    let mut idx = binary_body_start(buf)?;
+    let mut records = Vec::new();
+    while idx < buf.len() {
+        let record = records.len();
+        let Some(prefix) = buf.get(idx..idx + 8) else {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Vector file truncated in the length prefix of record {}", record),
+            ));
+        };
+        let len = u64::from_le_bytes(prefix.try_into().unwrap());
+        idx += 8;
+        if len > MAX_VECTOR_DIM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Record {} declares {} values (limit {}); the file is corrupt", record, len, MAX_VECTOR_DIM),
+            ));
+        }
+        let len = len as usize;
+        let available = (buf.len() - idx) / 8;
+        if len > available {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Vector file truncated: record {} declares {} values but only {} remain", record, len, available),
+            ));
         }
+        records.push((idx, len));
+        idx += len * 8;
     }
+    Ok(records)
 }
 
-pub fn run_vector_processing() -> io::Result<()> {
-    run_simse()
-} 
\ No newline at end of file
+/// Where Simse mode in the `geng` shell saves the vectors it builds from `sils`.
+pub const DEFAULT_VECTORS_PATH: &str = "sils/vectors.bin";
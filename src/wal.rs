@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::crypto::DataKey;
+use crate::error::{RedruError, Result};
+
+/// Bytes in front of every record: payload length, then checksum, both little-endian `u32`.
+const HEADER_LEN: usize = 8;
+
+/// One change made since the last full save. Replaying sets, removes and replacements in
+/// order is idempotent, so records that already reached the persistence file (a crash
+/// between the save and the log reset) can be replayed again without changing the result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum WalRecord {
+    Insert { key: String, value: Value },
+    /// Several inserts (`batch-add`, merges, imports that add) logged, and synced, as one record.
+    #[serde(rename = "insert_many")]
+    InsertMany { records: Vec<(String, Value)> },
+    Delete { key: String },
+    Clear,
+    /// A bulk change (restore, replacing import) logged as the whole resulting map.
+    Replace { data: HashMap<String, Value> },
+    /// A soft delete: `key` moved from the records to the trash, with the value it had.
+    Trash { key: String, value: Value },
+    /// `key` moved back from the trash to the records.
+    Restore { key: String, value: Value },
+    /// Everything in the trash dropped for good.
+    #[serde(rename = "empty_trash")]
+    EmptyTrash,
+}
+
+impl WalRecord {
+    /// Replays the change onto a database's records and its trash.
+    pub fn apply(self, storage: &mut HashMap<String, Value>, trash: &mut HashMap<String, Value>) {
+        match self {
+            WalRecord::Insert { key, value } => {
+                storage.insert(key, value);
+            }
+            WalRecord::InsertMany { records } => storage.extend(records),
+            WalRecord::Delete { key } => {
+                storage.remove(&key);
+            }
+            WalRecord::Clear => storage.clear(),
+            WalRecord::Replace { data } => *storage = data,
+            WalRecord::Trash { key, value } => {
+                storage.remove(&key);
+                trash.insert(key, value);
+            }
+            WalRecord::Restore { key, value } => {
+                trash.remove(&key);
+                storage.insert(key, value);
+            }
+            WalRecord::EmptyTrash => trash.clear(),
+        }
+    }
+}
+
+/// Append-only log of `WalRecord`s, each framed as length, checksum and JSON payload so a
+/// record cut short by a crash or power loss is recognised and dropped on the next open.
+/// With a key, each payload is encrypted (see `DataKey::seal`) before it is framed.
+pub struct Wal {
+    file: File,
+    key: Option<DataKey>,
+}
+
+impl Wal {
+    /// Opens (or creates) the log at `path` and returns the records it holds. A torn or
+    /// corrupt tail is cut off so new records never land behind garbage. A complete record
+    /// that cannot be read (encrypted under another key, or no key given) is an error
+    /// instead, so a wrong key never truncates the log.
+    pub fn open<P: AsRef<Path>>(path: P, key: Option<DataKey>) -> Result<(Self, Vec<WalRecord>)> {
+        let path = path.as_ref();
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let (records, valid_len) = decode_records(&bytes, key.as_ref());
+        if checked_payload(&bytes, valid_len).is_some() {
+            return Err(RedruError::Auth(format!(
+                "'{}' holds changes that cannot be read without the session's key", path.display()
+            )));
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if valid_len < bytes.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_data()?;
+        }
+        Ok((Wal { file, key }, records))
+    }
+
+    /// Writes one record and syncs it to disk. A failed write is rolled back so a partial
+    /// record cannot hide the ones appended after it.
+    pub fn append(&self, record: &WalRecord) -> Result<()> {
+        let encoded = encode_record(record, self.key.as_ref())?;
+        let len_before = self.file.metadata()?.len();
+        let written = (&self.file).write_all(&encoded).and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            let _ = self.file.set_len(len_before);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Bytes the log takes on disk.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Empties the log once everything in it is in the persistence file.
+    pub fn reset(&self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+pub fn encode_record(record: &WalRecord, key: Option<&DataKey>) -> Result<Vec<u8>> {
+    let payload = serde_json::to_vec(record)?;
+    let payload = match key {
+        Some(key) => key.seal(&payload)?,
+        None => payload,
+    };
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "write-ahead log record larger than 4 GiB")
+    })?;
+    let mut encoded = Vec::with_capacity(HEADER_LEN + payload.len());
+    encoded.extend_from_slice(&len.to_le_bytes());
+    encoded.extend_from_slice(&checksum(&payload).to_le_bytes());
+    encoded.extend_from_slice(&payload);
+    Ok(encoded)
+}
+
+/// Decodes records up to the first one that is incomplete, fails its checksum or does not
+/// decode, and returns them with the number of bytes they span. Unencrypted payloads are
+/// read even with a key, so a log written before a session was protected still replays.
+pub fn decode_records(bytes: &[u8], key: Option<&DataKey>) -> (Vec<WalRecord>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some(payload) = checked_payload(bytes, offset) {
+        let plaintext = match key {
+            Some(key) if crate::crypto::is_sealed(payload) => match key.open(payload) {
+                Ok(plaintext) => plaintext,
+                Err(_) => break,
+            },
+            _ => payload.to_vec(),
+        };
+        let Ok(record) = serde_json::from_slice(&plaintext) else { break };
+        records.push(record);
+        offset += HEADER_LEN + payload.len();
+    }
+    (records, offset)
+}
+
+/// The payload of the record framed at `offset`, if it is complete and passes its checksum.
+fn checked_payload(bytes: &[u8], offset: usize) -> Option<&[u8]> {
+    let header = bytes.get(offset..offset + HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let expected = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let payload = bytes.get(offset + HEADER_LEN..offset + HEADER_LEN + len)?;
+    (checksum(payload) == expected).then_some(payload)
+}
+
+fn checksum(payload: &[u8]) -> u32 {
+    let digest = Sha256::digest(payload);
+    u32::from_le_bytes(digest[..4].try_into().unwrap())
+}